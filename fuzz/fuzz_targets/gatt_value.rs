@@ -0,0 +1,14 @@
+#![no_main]
+
+use btmon::characteristic::{decode_battery_level, decode_known};
+use libfuzzer_sys::fuzz_target;
+
+/// Characteristics with a decoder
+const KNOWN: [&str; 4] = ["2A19", "2A29", "2A01", "2A50"];
+
+fuzz_target!(|data: &[u8]| {
+    let _ = decode_battery_level(data);
+    for uuid in KNOWN {
+        let _ = decode_known(uuid, data);
+    }
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use btmon::manufacturer::Registry;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Registry::default().decode(data);
+});
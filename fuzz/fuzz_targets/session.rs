@@ -0,0 +1,18 @@
+#![no_main]
+
+use btmon::backend::{RawSnapshot, ReplayBackend};
+use btmon::device::collect_devices;
+use btmon::locale::Locale;
+use btmon::output;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Session files come from users' bug reports, so run them through the
+    // whole pipeline rather than just the parser.
+    let Ok(snapshot) = serde_json::from_slice::<RawSnapshot>(data) else {
+        return;
+    };
+    let devices = collect_devices(&mut ReplayBackend(snapshot), None);
+    let _ = output::format_text(&devices, &Locale::default());
+    let _ = output::format_json(&devices);
+});
@@ -0,0 +1,206 @@
+//! Core Bluetooth side of `btmon scan`
+//!
+//! Scans for advertising peripherals for a fixed time. Like the battery scan
+//! in [`crate::gatt`], the delegate is called on a private serial dispatch
+//! queue; here the calling thread just sleeps until the time is up.
+
+use crate::backend::AdapterState;
+use crate::gatt::{adapter_state, peripheral_identifier};
+use crate::nearby::Advertisement;
+use dispatch2::{DispatchQueue, DispatchQueueAttr};
+use objc2::rc::Retained;
+use objc2::runtime::{AnyObject, ProtocolObject};
+use objc2::{AllocAnyThread, DefinedClass, define_class, msg_send};
+use objc2_core_bluetooth::{
+    CBAdvertisementDataIsConnectable, CBAdvertisementDataLocalNameKey,
+    CBAdvertisementDataManufacturerDataKey, CBAdvertisementDataServiceDataKey, CBCentralManager,
+    CBCentralManagerDelegate, CBManagerState, CBPeripheral, CBUUID,
+};
+use objc2_foundation::{NSData, NSDictionary, NSNumber, NSObject, NSObjectProtocol, NSString};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+use tracing::{debug, info_span, trace};
+
+/// Label of the dispatch queue Core Bluetooth calls the delegate on
+const QUEUE_LABEL: &str = "btmon.scan";
+
+/// Battery Service UUID, as Core Bluetooth prints it
+const BATTERY_SERVICE_UUID: &str = "180F";
+
+/// Internal state for the delegate
+#[derive(Default)]
+struct ScanState {
+    central: Option<Retained<CBCentralManager>>,
+    adapter: Option<AdapterState>,
+    advertisements: Vec<Advertisement>,
+}
+
+/// Ivars for the Objective-C delegate class
+struct ScanIvars {
+    state: Mutex<ScanState>,
+    /// Signalled when Bluetooth turns out to be unavailable
+    failed: Sender<()>,
+}
+
+define_class!(
+    #[unsafe(super(NSObject))]
+    #[name = "BtmonScanDelegate"]
+    #[ivars = ScanIvars]
+    struct ScanDelegate;
+
+    unsafe impl NSObjectProtocol for ScanDelegate {}
+
+    unsafe impl CBCentralManagerDelegate for ScanDelegate {
+        #[unsafe(method(centralManagerDidUpdateState:))]
+        fn central_manager_did_update_state(&self, central: &CBCentralManager) {
+            // SAFETY: central.state() is a standard Core Bluetooth API.
+            let state = unsafe { central.state() };
+            debug!(state = ?state, "Central manager state updated");
+            self.state().adapter = Some(adapter_state(state));
+            if state == CBManagerState::PoweredOn {
+                // SAFETY: scanForPeripheralsWithServices_options is a
+                // standard Core Bluetooth API; None scans for all services.
+                unsafe { central.scanForPeripheralsWithServices_options(None, None) };
+            } else if state == CBManagerState::Unauthorized
+                || state == CBManagerState::Unsupported
+                || state == CBManagerState::PoweredOff
+            {
+                let _ = self.ivars().failed.send(());
+            }
+        }
+
+        #[unsafe(method(centralManager:didDiscoverPeripheral:advertisementData:RSSI:))]
+        fn central_manager_did_discover_peripheral(
+            &self,
+            _central: &CBCentralManager,
+            peripheral: &CBPeripheral,
+            advertisement_data: &NSDictionary<NSString, AnyObject>,
+            rssi: &NSNumber,
+        ) {
+            let advertisement = Advertisement {
+                identifier: peripheral_identifier(peripheral),
+                // SAFETY: The advertisement data keys are constants of Core
+                // Bluetooth.
+                name: lookup::<NSString>(advertisement_data, unsafe {
+                    CBAdvertisementDataLocalNameKey
+                })
+                .map(|name| name.to_string()),
+                rssi: rssi.shortValue(),
+                // SAFETY: As above.
+                manufacturer_data: lookup::<NSData>(advertisement_data, unsafe {
+                    CBAdvertisementDataManufacturerDataKey
+                })
+                .map(|data| data.to_vec()),
+                // SAFETY: As above.
+                battery_data: lookup::<NSDictionary>(advertisement_data, unsafe {
+                    CBAdvertisementDataServiceDataKey
+                })
+                .and_then(|service_data| battery_service_data(&service_data)),
+                // SAFETY: As above.
+                connectable: lookup::<NSNumber>(advertisement_data, unsafe {
+                    CBAdvertisementDataIsConnectable
+                })
+                .map(|connectable| connectable.boolValue()),
+            };
+            trace!(?advertisement, "Advertisement");
+            self.state().advertisements.push(advertisement);
+        }
+    }
+);
+
+// SAFETY: The delegate's state is only accessed through its mutex, and the
+// manager it holds is only messaged from its queue.
+unsafe impl Send for ScanDelegate {}
+// SAFETY: As above.
+unsafe impl Sync for ScanDelegate {}
+
+impl ScanDelegate {
+    fn new(failed: Sender<()>) -> Retained<Self> {
+        let this = Self::alloc();
+        let this = this.set_ivars(ScanIvars {
+            state: Mutex::new(ScanState::default()),
+            failed,
+        });
+        // SAFETY: Calling [super init] on a properly allocated NSObject subclass.
+        unsafe { msg_send![super(this), init] }
+    }
+
+    /// Lock the delegate state, even if a callback panicked
+    fn state(&self) -> MutexGuard<'_, ScanState> {
+        self.ivars()
+            .state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Stop scanning and receiving callbacks
+    ///
+    /// Must be called on the manager's queue.
+    fn stop(&self) {
+        let Some(central) = self.state().central.take() else {
+            return;
+        };
+        // SAFETY: stopScan and setDelegate are standard Core Bluetooth APIs.
+        unsafe {
+            central.stopScan();
+            central.setDelegate(None);
+        }
+    }
+}
+
+/// Value of an advertisement data key, if it has the expected type
+fn lookup<T: objc2::DowncastTarget>(
+    data: &NSDictionary<NSString, AnyObject>,
+    key: &NSString,
+) -> Option<Retained<T>> {
+    data.objectForKey(key)?.downcast::<T>().ok()
+}
+
+/// Battery Service data among the service data, keyed by service UUID
+fn battery_service_data(service_data: &NSDictionary) -> Option<Vec<u8>> {
+    service_data.allKeys().iter().find_map(|key| {
+        let uuid = key.downcast_ref::<CBUUID>()?;
+        // SAFETY: UUIDString() is a standard Core Bluetooth API.
+        if unsafe { uuid.UUIDString() }.to_string() != BATTERY_SERVICE_UUID {
+            return None;
+        }
+        let data = service_data.objectForKey(&key)?.downcast::<NSData>().ok()?;
+        Some(data.to_vec())
+    })
+}
+
+/// Scan for advertising peripherals for `duration`, returning every
+/// advertisement received, or the adapter state if Bluetooth can't be used
+pub fn scan(duration: Duration) -> Result<Vec<Advertisement>, AdapterState> {
+    let _span = info_span!("advertising_scan").entered();
+    let (failed, receiver) = mpsc::channel();
+    let delegate = ScanDelegate::new(failed);
+    let queue = DispatchQueue::new(QUEUE_LABEL, DispatchQueueAttr::SERIAL);
+
+    // SAFETY: CBCentralManager initialization is a standard Core Bluetooth API.
+    // The delegate is thread-safe, so it can be called on the private queue.
+    let central = unsafe {
+        CBCentralManager::initWithDelegate_queue(
+            CBCentralManager::alloc(),
+            Some(ProtocolObject::from_ref(&*delegate)),
+            Some(&queue),
+        )
+    };
+    delegate.state().central = Some(central);
+
+    let start = Instant::now();
+    let failed = receiver.recv_timeout(duration).is_ok();
+    queue.exec_sync(|| delegate.stop());
+
+    let mut state = delegate.state();
+    debug!(
+        elapsed_ms = start.elapsed().as_millis(),
+        count = state.advertisements.len(),
+        "Scan finished"
+    );
+    if failed {
+        return Err(state.adapter.unwrap_or(AdapterState::Unknown));
+    }
+    Ok(std::mem::take(&mut state.advertisements))
+}
@@ -0,0 +1,128 @@
+//! launchd agent starting `btmon daemon` at login
+//!
+//! `btmon install-agent` writes a property list to `~/Library/LaunchAgents`
+//! and loads it into the user's GUI session with `launchctl`, so the daemon
+//! starts right away and again at every login. launchd restarts it if it
+//! exits.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// launchd label of the agent
+pub const LABEL: &str = "com.github.sivchari.btmon";
+
+/// Location of the agent's property list
+pub fn plist_path() -> Option<PathBuf> {
+    std::env::home_dir().map(|home| {
+        home.join("Library/LaunchAgents")
+            .join(format!("{LABEL}.plist"))
+    })
+}
+
+/// Escape text for an XML string element
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Property list running `program` with `arguments`, its output appended to
+/// `log`
+pub fn plist(program: &Path, arguments: &[String], log: &Path) -> String {
+    let program_arguments: String = std::iter::once(program.display().to_string())
+        .chain(arguments.iter().cloned())
+        .map(|argument| format!("        <string>{}</string>\n", escape(&argument)))
+        .collect();
+    let log = escape(&log.display().to_string());
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{LABEL}</string>
+    <key>ProgramArguments</key>
+    <array>
+{program_arguments}    </array>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <true/>
+    <key>ProcessType</key>
+    <string>Background</string>
+    <key>StandardOutPath</key>
+    <string>{log}</string>
+    <key>StandardErrorPath</key>
+    <string>{log}</string>
+</dict>
+</plist>
+"#
+    )
+}
+
+/// launchd domain of the current user's GUI session
+fn domain() -> String {
+    // SAFETY: getuid() has no preconditions and cannot fail.
+    format!("gui/{}", unsafe { libc::getuid() })
+}
+
+/// Run `launchctl` with arguments, failing on a non-zero exit
+fn launchctl(arguments: &[&str]) -> io::Result<()> {
+    let output = Command::new("/bin/launchctl").args(arguments).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "launchctl {} failed: {}",
+            arguments[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Write the agent's property list and (re)load it
+pub fn install(path: &Path, contents: &str) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // An agent installed earlier is replaced, so unload it first
+    let _ = launchctl(&["bootout", &format!("{}/{LABEL}", domain())]);
+    fs::write(path, contents)?;
+    launchctl(&["bootstrap", &domain(), &path.display().to_string()])
+}
+
+/// Unload the agent and remove its property list, returning whether it was
+/// installed
+pub fn uninstall(path: &Path) -> io::Result<bool> {
+    // Fails if the agent isn't loaded, which is fine
+    let _ = launchctl(&["bootout", &format!("{}/{LABEL}", domain())]);
+    match fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plist() {
+        let plist = plist(
+            Path::new("/opt/homebrew/bin/btmon"),
+            &[
+                "daemon".to_string(),
+                "--interval".to_string(),
+                "60".to_string(),
+            ],
+            Path::new("/Users/me/R&D/daemon.log"),
+        );
+        assert!(plist.contains(&format!("<string>{LABEL}</string>")));
+        assert!(plist.contains(
+            "        <string>/opt/homebrew/bin/btmon</string>\n        <string>daemon</string>\n"
+        ));
+        assert!(plist.contains("<string>/Users/me/R&amp;D/daemon.log</string>"));
+    }
+}
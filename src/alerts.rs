@@ -0,0 +1,577 @@
+//! Low-battery alert engine
+//!
+//! Every battery component is classified against the warning and critical
+//! thresholds. An alert is raised when a component enters a more severe state
+//! than the one last notified, and that state is persisted between runs so
+//! one-shot invocations (e.g. from cron) don't repeat the same alert.
+//!
+//! During quiet hours, warnings are queued instead of sent and delivered as a
+//! single digest once the quiet window is over. Critical alerts always go out.
+//!
+//! Optionally, alerts are repeated at an interval while the component stays
+//! low, and channels can be reserved for escalation after a number of
+//! unacknowledged notifications (`btmon ack` acknowledges them).
+
+use crate::channels::desktop::DesktopConfig;
+use crate::channels::email::EmailConfig;
+use crate::channels::gotify::GotifyConfig;
+use crate::channels::matrix::MatrixConfig;
+use crate::config::Interval;
+use crate::device::{BatteryLevel, Component, Device};
+use chrono::{NaiveDate, NaiveTime};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use tracing::debug;
+
+/// How urgent an alert is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// At or below the warning threshold
+    Warning,
+    /// At or below the critical threshold
+    Critical,
+}
+
+/// A daily quiet window such as `23:00-08:00`, which may cross midnight
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct QuietHours {
+    /// Start of the window (inclusive)
+    pub start: NaiveTime,
+    /// End of the window (exclusive)
+    pub end: NaiveTime,
+}
+
+impl QuietHours {
+    /// Whether a local time falls inside the window
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl std::str::FromStr for QuietHours {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |t: &str| NaiveTime::parse_from_str(t.trim(), "%H:%M");
+        match s
+            .split_once('-')
+            .map(|(start, end)| (parse(start), parse(end)))
+        {
+            Some((Ok(start), Ok(end))) if start != end => Ok(Self { start, end }),
+            _ => Err(format!(
+                "invalid quiet hours '{s}', expected a window like 23:00-08:00"
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for QuietHours {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// `[alerts.repeat]` configuration section
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RepeatConfig {
+    /// Re-notify interval while a component stays at the warning level
+    pub warning: Option<Interval>,
+    /// Re-notify interval while a component stays at the critical level
+    pub critical: Option<Interval>,
+}
+
+impl RepeatConfig {
+    /// Repeat interval for a severity, None if alerts aren't repeated
+    pub fn interval(&self, severity: Severity) -> Option<Interval> {
+        match severity {
+            Severity::Warning => self.warning,
+            Severity::Critical => self.critical,
+        }
+    }
+}
+
+/// `[alerts.escalation]` configuration section
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EscalationConfig {
+    /// Number of unacknowledged notifications before escalating
+    pub after: u32,
+    /// Channels (by name, e.g. `email`) only used once escalated
+    pub channels: Vec<String>,
+}
+
+/// `[alerts]` configuration section
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlertConfig {
+    /// Warning threshold in percent
+    pub warn: u8,
+    /// Critical threshold in percent
+    pub critical: u8,
+    /// Local time windows in which warnings are held back for a digest
+    pub quiet_hours: Vec<QuietHours>,
+    /// Re-notification while a component stays low
+    pub repeat: RepeatConfig,
+    /// Channels reserved for escalation
+    pub escalation: EscalationConfig,
+    /// Matrix room to post alerts to
+    pub matrix: Option<MatrixConfig>,
+    /// Gotify server to push alerts to
+    pub gotify: Option<GotifyConfig>,
+    /// SMTP server to email alerts and daily summaries through
+    pub email: Option<EmailConfig>,
+    /// Notification Center on this Mac
+    pub desktop: Option<DesktopConfig>,
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        Self {
+            warn: 20,
+            critical: 10,
+            quiet_hours: Vec::new(),
+            repeat: RepeatConfig::default(),
+            escalation: EscalationConfig::default(),
+            matrix: None,
+            gotify: None,
+            email: None,
+            desktop: None,
+        }
+    }
+}
+
+impl AlertConfig {
+    /// Classify a battery level against the thresholds
+    pub fn severity(&self, level: BatteryLevel) -> Option<Severity> {
+        let percent = level.as_percentage();
+        if percent <= self.critical {
+            Some(Severity::Critical)
+        } else if percent <= self.warn {
+            Some(Severity::Warning)
+        } else {
+            None
+        }
+    }
+
+    /// Whether a local time falls inside any quiet window
+    pub fn is_quiet(&self, time: NaiveTime) -> bool {
+        self.quiet_hours.iter().any(|window| window.contains(time))
+    }
+}
+
+/// A low-battery alert for one device component
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Alert {
+    /// Device name
+    pub device: String,
+    /// Battery component that is low
+    pub component: Component,
+    /// Current level
+    pub level: BatteryLevel,
+    /// Alert severity
+    pub severity: Severity,
+    /// Number of this notification for the same level, starting at 1
+    #[serde(default = "first_attempt")]
+    pub attempt: u32,
+}
+
+fn first_attempt() -> u32 {
+    1
+}
+
+impl Alert {
+    /// Short title, e.g. "Low battery: AirPods Pro"
+    pub fn title(&self) -> String {
+        match self.severity {
+            Severity::Warning => format!("Low battery: {}", self.device),
+            Severity::Critical => format!("Critical battery: {}", self.device),
+        }
+    }
+
+    /// One-line message, e.g. "AirPods Pro (left) is at 15%"
+    pub fn message(&self) -> String {
+        match self.component {
+            Component::Level => format!("{} is at {}", self.device, self.level),
+            component => format!(
+                "{} ({}) is at {}",
+                self.device,
+                component_name(component),
+                self.level
+            ),
+        }
+    }
+}
+
+/// Human-readable component name used in alert messages
+fn component_name(component: Component) -> &'static str {
+    match component {
+        Component::Level => "battery",
+        Component::Left => "left",
+        Component::Right => "right",
+        Component::Case => "case",
+    }
+}
+
+/// Last alert sent for a device component
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct NotifiedAlert {
+    severity: Severity,
+    /// Unix timestamp of the notification
+    notified_at: i64,
+    /// Notifications sent at this severity
+    #[serde(default = "first_attempt")]
+    count: u32,
+    /// Acknowledged with `btmon ack`, which stops repeats and escalation
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    acknowledged: bool,
+    /// No channel took the last notification, so it is retried on the next
+    /// run as the next attempt
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    undelivered: bool,
+}
+
+/// Alerts already sent, persisted between runs
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AlertState {
+    notified: BTreeMap<String, NotifiedAlert>,
+    /// Local date (YYYY-MM-DD) the last daily summary was sent on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    summary_sent_on: Option<String>,
+    /// Alerts held back during quiet hours
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    queued: Vec<Alert>,
+}
+
+impl AlertState {
+    /// Load the state file, starting fresh if it doesn't exist
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(data) => Ok(serde_json::from_str(&data)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Save the state file, creating its directory if needed
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, format!("{}\n", serde_json::to_string_pretty(self)?))
+    }
+
+    /// Remember an attempt to deliver an alert at Unix time `now`, and
+    /// whether any channel took it
+    pub fn record(&mut self, alert: &Alert, now: i64, delivered: bool) {
+        self.notified.insert(
+            state_key(&alert.device, alert.component),
+            NotifiedAlert {
+                severity: alert.severity,
+                notified_at: now,
+                count: alert.attempt,
+                acknowledged: false,
+                undelivered: !delivered,
+            },
+        );
+    }
+
+    /// Acknowledge the active alerts of devices whose name contains `filter`
+    /// (case-insensitive), or of all devices. Returns the number acknowledged.
+    pub fn acknowledge(&mut self, filter: Option<&str>) -> usize {
+        let filter = filter.map(str::to_lowercase);
+        let mut count = 0;
+        for (key, notified) in &mut self.notified {
+            let device = key.rsplit_once('/').map_or(key.as_str(), |(d, _)| d);
+            let matches = filter
+                .as_deref()
+                .is_none_or(|f| device.to_lowercase().contains(f));
+            if matches && !notified.acknowledged {
+                notified.acknowledged = true;
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Hold an alert back for the quiet-hours digest.
+    ///
+    /// The alert counts as notified so it isn't queued again on the next run;
+    /// a queued alert for the same component is replaced.
+    pub fn queue(&mut self, alert: Alert, now: i64) {
+        self.record(&alert, now, true);
+        self.queued
+            .retain(|a| a.device != alert.device || a.component != alert.component);
+        self.queued.push(alert);
+    }
+
+    /// Alerts waiting for the quiet-hours digest
+    pub fn queued(&self) -> &[Alert] {
+        &self.queued
+    }
+
+    /// Forget the queued alerts once the digest was delivered
+    pub fn clear_queue(&mut self) {
+        self.queued.clear();
+    }
+
+    /// Whether the daily summary hasn't been sent yet on `today`
+    pub fn summary_due(&self, today: NaiveDate) -> bool {
+        self.summary_sent_on.as_deref() != Some(today.to_string().as_str())
+    }
+
+    /// Remember that the daily summary was sent on `today`
+    pub fn record_summary(&mut self, today: NaiveDate) {
+        self.summary_sent_on = Some(today.to_string());
+    }
+}
+
+/// Key of a device component in persisted state, e.g. `AirPods Pro/L`
+pub(crate) fn state_key(device: &str, component: Component) -> String {
+    match component.label() {
+        Some(label) => format!("{device}/{label}"),
+        None => device.to_string(),
+    }
+}
+
+/// Evaluate devices against the thresholds at Unix time `now` and return the
+/// alerts to send.
+///
+/// Components that recovered are cleared from the state. Alerts are not
+/// recorded until [`AlertState::record`] is called after delivery. One that
+/// no channel took is retried on the next run as the next attempt, so the
+/// escalation channels take over when the others keep failing (or when all
+/// channels are reserved for escalation).
+pub fn evaluate(
+    devices: &[Device],
+    config: &AlertConfig,
+    state: &mut AlertState,
+    now: i64,
+) -> Vec<Alert> {
+    let mut alerts = Vec::new();
+
+    for device in devices {
+        for (component, level) in device.components() {
+            let key = state_key(&device.name, component);
+
+            let Some(severity) = config.severity(level) else {
+                if state.notified.remove(&key).is_some() {
+                    debug!(key = %key, "Battery recovered, clearing alert");
+                }
+                state
+                    .queued
+                    .retain(|a| a.device != device.name || a.component != component);
+                continue;
+            };
+
+            let attempt = match state.notified.get(&key) {
+                None => 1,
+                Some(previous) if previous.severity < severity => 1,
+                Some(previous) if previous.acknowledged => continue,
+                Some(previous) if previous.undelivered => previous.count + 1,
+                Some(previous) => {
+                    let due = config.repeat.interval(severity).is_some_and(|interval| {
+                        now - previous.notified_at >= interval.as_secs() as i64
+                    });
+                    if !due {
+                        continue;
+                    }
+                    previous.count + 1
+                }
+            };
+
+            alerts.push(Alert {
+                device: device.name.clone(),
+                component,
+                level,
+                severity,
+                attempt,
+            });
+        }
+    }
+
+    alerts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn airpods(left: u8, right: u8) -> Device {
+        Device {
+            battery_left: BatteryLevel::new(left),
+            battery_right: BatteryLevel::new(right),
+            ..Device::test_classic("AirPods Pro", "aa-bb-cc-dd-ee-ff")
+        }
+    }
+
+    #[test]
+    fn test_severity() {
+        let config = AlertConfig::default();
+        assert_eq!(config.severity(BatteryLevel::new(21).unwrap()), None);
+        assert_eq!(
+            config.severity(BatteryLevel::new(20).unwrap()),
+            Some(Severity::Warning)
+        );
+        assert_eq!(
+            config.severity(BatteryLevel::new(10).unwrap()),
+            Some(Severity::Critical)
+        );
+    }
+
+    #[test]
+    fn test_evaluate_alerts_once_per_severity() {
+        let config = AlertConfig::default();
+        let mut state = AlertState::default();
+
+        let mut run = |device: Device, now: i64| {
+            let alerts = evaluate(&[device], &config, &mut state, now);
+            for alert in &alerts {
+                state.record(alert, now, true);
+            }
+            alerts
+        };
+
+        let alerts = run(airpods(15, 80), 0);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].component, Component::Left);
+        assert_eq!(alerts[0].severity, Severity::Warning);
+
+        // Still low, already notified
+        assert!(run(airpods(14, 80), 60).is_empty());
+
+        // Escalates to critical
+        let alerts = run(airpods(8, 80), 120);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].severity, Severity::Critical);
+
+        // Recovers, then drops again
+        assert!(run(airpods(90, 80), 180).is_empty());
+        assert_eq!(run(airpods(19, 80), 240).len(), 1);
+    }
+
+    #[test]
+    fn test_evaluate_retries_undelivered() {
+        let config = AlertConfig::default();
+        let mut state = AlertState::default();
+
+        assert_eq!(
+            evaluate(&[airpods(15, 80)], &config, &mut state, 0).len(),
+            1
+        );
+        assert_eq!(
+            evaluate(&[airpods(15, 80)], &config, &mut state, 0).len(),
+            1
+        );
+
+        // Failed attempts count towards escalation, without a repeat interval
+        let mut attempts = Vec::new();
+        for now in [60, 120, 180] {
+            let alerts = evaluate(&[airpods(15, 80)], &config, &mut state, now);
+            attempts.extend(alerts.iter().map(|alert| alert.attempt));
+            for alert in &alerts {
+                state.record(alert, now, alert.attempt == 3);
+            }
+        }
+        assert_eq!(attempts, [1, 2, 3]);
+        assert!(evaluate(&[airpods(15, 80)], &config, &mut state, 240).is_empty());
+    }
+
+    #[test]
+    fn test_repeat_and_acknowledge() {
+        let mut config = AlertConfig::default();
+        config.repeat.critical = Some("30m".parse().unwrap());
+        let mut state = AlertState::default();
+
+        let run = |state: &mut AlertState, left: u8, now: i64| {
+            let alerts = evaluate(&[airpods(left, 80)], &config, state, now);
+            for alert in &alerts {
+                state.record(alert, now, true);
+            }
+            alerts.iter().map(|a| a.attempt).collect::<Vec<_>>()
+        };
+
+        assert_eq!(run(&mut state, 8, 0), [1]);
+        assert!(run(&mut state, 8, 600).is_empty());
+        assert_eq!(run(&mut state, 7, 1800), [2]);
+        assert_eq!(run(&mut state, 7, 3600), [3]);
+
+        assert_eq!(state.acknowledge(Some("airpods")), 1);
+        assert!(run(&mut state, 6, 7200).is_empty());
+
+        // Warnings aren't repeated without a warning interval
+        assert_eq!(run(&mut state, 90, 7300), Vec::<u32>::new());
+        assert_eq!(run(&mut state, 15, 7400), [1]);
+        assert!(run(&mut state, 15, 99999).is_empty());
+    }
+
+    #[test]
+    fn test_quiet_hours() {
+        let time = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+
+        let overnight: QuietHours = "23:00-08:00".parse().unwrap();
+        assert!(overnight.contains(time(23, 0)));
+        assert!(overnight.contains(time(3, 30)));
+        assert!(!overnight.contains(time(8, 0)));
+        assert!(!overnight.contains(time(12, 0)));
+
+        let lunch: QuietHours = "12:00-13:00".parse().unwrap();
+        assert!(lunch.contains(time(12, 30)));
+        assert!(!lunch.contains(time(13, 30)));
+
+        assert!("23:00".parse::<QuietHours>().is_err());
+        assert!("25:00-08:00".parse::<QuietHours>().is_err());
+        assert!("08:00-08:00".parse::<QuietHours>().is_err());
+    }
+
+    #[test]
+    fn test_queue_replaces_and_clears_on_recovery() {
+        let config = AlertConfig::default();
+        let mut state = AlertState::default();
+
+        for alert in evaluate(&[airpods(18, 80)], &config, &mut state, 0) {
+            state.queue(alert, 0);
+        }
+        assert_eq!(state.queued().len(), 1);
+        assert!(evaluate(&[airpods(17, 80)], &config, &mut state, 60).is_empty());
+
+        // Recovered before the digest went out
+        evaluate(&[airpods(90, 80)], &config, &mut state, 120);
+        assert!(state.queued().is_empty());
+    }
+
+    #[test]
+    fn test_summary_due_once_per_day() {
+        let mut state = AlertState::default();
+        let today = NaiveDate::from_ymd_opt(2026, 3, 14).unwrap();
+        assert!(state.summary_due(today));
+
+        state.record_summary(today);
+        assert!(!state.summary_due(today));
+        assert!(state.summary_due(today.succ_opt().unwrap()));
+    }
+
+    #[test]
+    fn test_alert_message() {
+        let alert = Alert {
+            device: "AirPods Pro".to_string(),
+            component: Component::Left,
+            level: BatteryLevel::new(15).unwrap(),
+            severity: Severity::Warning,
+            attempt: 1,
+        };
+        assert_eq!(alert.title(), "Low battery: AirPods Pro");
+        assert_eq!(alert.message(), "AirPods Pro (left) is at 15%");
+    }
+}
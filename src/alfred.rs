@@ -0,0 +1,142 @@
+//! Alfred Script Filter output for `--format alfred`
+//!
+//! A Script Filter lists the `items` of the JSON its script prints. Each
+//! device becomes an item with its batteries as the subtitle and its name
+//! as the argument passed on, e.g. to `btmon connect`.
+
+use crate::alerts::AlertConfig;
+use crate::device::Device;
+use crate::locale::Locale;
+use crate::output::{device_icon, device_kind};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// `[alfred]` configuration section
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlfredConfig {
+    /// Icon files by device type (`headphones`, `keyboard`, `mouse`,
+    /// `controller`, `other`); the workflow's icon if not set
+    pub icons: BTreeMap<String, PathBuf>,
+}
+
+/// Script Filter output
+#[derive(Debug, Serialize)]
+pub struct ScriptFilter {
+    pub items: Vec<Item>,
+}
+
+/// One row of the Script Filter
+#[derive(Debug, Serialize)]
+pub struct Item {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uid: Option<String>,
+    pub title: String,
+    pub subtitle: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arg: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<Icon>,
+    pub valid: bool,
+}
+
+/// Icon of an item
+#[derive(Debug, Serialize)]
+pub struct Icon {
+    pub path: PathBuf,
+}
+
+/// Build the Script Filter items, one per device
+pub fn script_filter(
+    devices: &[Device],
+    alerts: &AlertConfig,
+    config: &AlfredConfig,
+    locale: &Locale,
+) -> ScriptFilter {
+    let mut items: Vec<Item> = devices
+        .iter()
+        .map(|device| {
+            let mut subtitle = device
+                .components()
+                .map(|(component, level)| match component.label() {
+                    Some(label) => format!("{label}:{}", locale.battery(level)),
+                    None => locale.battery(level),
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+            if device
+                .effective()
+                .and_then(|level| alerts.severity(level))
+                .is_some()
+            {
+                subtitle.push_str(" (low)");
+            }
+            Item {
+                uid: Some(device.name.clone()),
+                title: format!("{} {}", device_icon(device), device.name),
+                subtitle,
+                arg: Some(device.name.clone()),
+                icon: config
+                    .icons
+                    .get(device_kind(device))
+                    .map(|path| Icon { path: path.clone() }),
+                valid: true,
+            }
+        })
+        .collect();
+    if items.is_empty() {
+        items.push(Item {
+            uid: None,
+            title: "No devices with battery info".to_string(),
+            subtitle: "Connect a Bluetooth device, or run btmon doctor".to_string(),
+            arg: None,
+            icon: None,
+            valid: false,
+        });
+    }
+    ScriptFilter { items }
+}
+
+/// Format the Script Filter JSON
+pub fn format_alfred(
+    devices: &[Device],
+    alerts: &AlertConfig,
+    config: &AlfredConfig,
+    locale: &Locale,
+) -> serde_json::Result<String> {
+    serde_json::to_string(&script_filter(devices, alerts, config, locale))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::BatteryLevel;
+
+    #[test]
+    fn test_format_alfred() {
+        let airpods = Device {
+            battery_left: BatteryLevel::new(15),
+            battery_right: BatteryLevel::new(90),
+            ..Device::test_classic("AirPods Pro", "a4-c3-37-12-34-56")
+        };
+        let mut config = AlfredConfig::default();
+        config
+            .icons
+            .insert("headphones".to_string(), PathBuf::from("icons/airpods.png"));
+        let json = format_alfred(
+            &[airpods],
+            &AlertConfig::default(),
+            &config,
+            &Locale::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            json,
+            r#"{"items":[{"uid":"AirPods Pro","title":"🎧 AirPods Pro","subtitle":"L:15% R:90% (low)","arg":"AirPods Pro","icon":{"path":"icons/airpods.png"},"valid":true}]}"#
+        );
+
+        let empty = script_filter(&[], &AlertConfig::default(), &config, &Locale::default());
+        assert!(!empty.items[0].valid);
+    }
+}
@@ -0,0 +1,579 @@
+//! HTTP JSON API
+//!
+//! `btmon serve` answers plain HTTP/1.1 requests so dashboards and scripts
+//! can read battery levels without running the binary:
+//!
+//! - `GET /devices`: all devices, the same JSON as `--json`, filtered with
+//!   the query parameters of [`DeviceQuery`]
+//! - `GET /devices/<name>`: one device by exact (case-insensitive) name, as
+//!   in the `--json` array
+//! - `GET /metrics`: battery levels for Prometheus (see [`crate::metrics`])
+//! - `GET /alerts`: the components at or below the alert thresholds, with
+//!   their severity
+//! - `GET /history?device=<name>&since=<interval>`: readings recorded with
+//!   `--history`, of the last 24 hours by default
+//! - `GET /ui`: a dashboard page built on the endpoints above, with a card
+//!   and a chart of the last day per device
+//!
+//! Requests are served one at a time and every connection is closed after
+//! its response. Scans are cached for a few seconds so that a dashboard
+//! polling several endpoints doesn't trigger a scan for each.
+//!
+//! `btmon daemon --metrics-port` serves only `/metrics`, from the daemon's
+//! latest scan instead of a backend of its own (see [`serve_metrics`]).
+
+use crate::alerts::AlertConfig;
+use crate::backend::Backend;
+use crate::config::{Config, Interval};
+use crate::device::{Device, collect_devices, hide_components, update_freshness};
+use crate::history::History;
+use crate::http;
+use crate::metrics;
+use crate::output::VersionedDevice;
+use crate::query::DeviceQuery;
+use crate::redact;
+use crate::smoothing::SmoothingState;
+use crate::socket::Snapshot;
+use chrono::{DateTime, TimeDelta, Utc};
+use serde_json::{Value, json};
+use std::borrow::Cow;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// How long a scan answers requests before the devices are scanned again
+const MAX_AGE: Duration = Duration::from_secs(5);
+
+/// Time allowed for a client to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest accepted request head
+const MAX_HEAD: u64 = 8 * 1024;
+
+/// The `/ui` page
+const UI: &str = include_str!("ui.html");
+
+/// History served when `/history` doesn't say how far back to go
+const DEFAULT_HISTORY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// An HTTP response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    fn json(status: u16, body: String) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body,
+        }
+    }
+
+    fn html(body: &str) -> Self {
+        Self {
+            status: 200,
+            content_type: "text/html; charset=utf-8",
+            body: body.to_string(),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self::json(status, json!({ "error": message }).to_string())
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            503 => "Service Unavailable",
+            _ => "Internal Server Error",
+        }
+    }
+
+    /// Write the response with its headers, closing the connection
+    fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        write!(
+            writer,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            self.reason(),
+            self.content_type,
+            self.body.len(),
+            self.body
+        )?;
+        writer.flush()
+    }
+}
+
+/// HTTP API around a backend
+pub struct ApiServer {
+    backend: Box<dyn Backend>,
+    config: Config,
+    smoothing: SmoothingState,
+    cache: Option<(Instant, Vec<Device>)>,
+    redact: bool,
+    history: Option<History>,
+}
+
+impl ApiServer {
+    /// Create a server scanning with the given backend and applying the
+    /// per-device and smoothing settings of the configuration
+    pub fn new(backend: Box<dyn Backend>, config: Config) -> Self {
+        Self {
+            backend,
+            config,
+            smoothing: SmoothingState::default(),
+            cache: None,
+            redact: false,
+            history: None,
+        }
+    }
+
+    /// Serve `/history` from this battery history
+    pub fn history(mut self, history: History) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    /// Mask the device addresses in responses, see [`redact`]
+    pub fn redact(mut self, enabled: bool) -> Self {
+        self.redact = enabled;
+        self
+    }
+
+    /// Devices of a scan no older than [`MAX_AGE`]
+    fn devices(&mut self, now: Instant) -> &[Device] {
+        let fresh = self
+            .cache
+            .as_ref()
+            .is_some_and(|(at, _)| now.saturating_duration_since(*at) <= MAX_AGE);
+        if !fresh {
+            debug!("Scanning for HTTP client");
+            let mut devices = collect_devices(self.backend.as_mut(), None);
+            hide_components(&mut devices, &self.config.devices);
+            if self.config.smoothing.is_enabled() {
+                self.smoothing.apply(&mut devices, &self.config.smoothing);
+            }
+            self.cache = Some((now, devices));
+        }
+        let Some((_, devices)) = &mut self.cache else {
+            return &[];
+        };
+        update_freshness(devices, Utc::now(), self.config.stale_after());
+        devices
+    }
+
+    /// Devices of [`Self::devices`] as clients see them, masked with `redact`
+    fn shown(&mut self, now: Instant) -> Cow<'_, [Device]> {
+        let redact = self.redact;
+        let devices = self.devices(now);
+        if redact {
+            let mut devices = devices.to_vec();
+            redact::redact_devices(&mut devices);
+            Cow::Owned(devices)
+        } else {
+            Cow::Borrowed(devices)
+        }
+    }
+
+    /// Answer a request for `target` (path and query)
+    pub fn respond(&mut self, method: &str, target: &str, now: Instant) -> Response {
+        if method != "GET" {
+            return Response::error(405, "only GET is supported");
+        }
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+        if path == "/devices" || path == "/devices/" {
+            let query = match DeviceQuery::parse(query) {
+                Ok(query) => query,
+                Err(e) => return Response::error(400, &e.to_string()),
+            };
+            return match query
+                .apply(&self.shown(now))
+                .and_then(|value| serde_json::to_string_pretty(&value))
+            {
+                Ok(body) => Response::json(200, body),
+                Err(e) => Response::error(500, &e.to_string()),
+            };
+        }
+
+        if let Some(segment) = path.strip_prefix("/devices/") {
+            let Some(name) = http::decode_query_component(segment) else {
+                return Response::error(400, "bad encoding in device name");
+            };
+            return match self
+                .shown(now)
+                .iter()
+                .find(|d| d.name.eq_ignore_ascii_case(&name))
+                .map(|device| serde_json::to_string_pretty(&VersionedDevice::from(device)))
+            {
+                Some(Ok(body)) => Response::json(200, body),
+                Some(Err(e)) => Response::error(500, &e.to_string()),
+                None => Response::error(404, &format!("no device named '{name}'")),
+            };
+        }
+
+        if path == "/alerts" {
+            let thresholds = AlertConfig {
+                warn: self.config.alerts.warn,
+                critical: self.config.alerts.critical,
+                ..AlertConfig::default()
+            };
+            let alerts: Vec<_> = self
+                .shown(now)
+                .iter()
+                .flat_map(|device| {
+                    device.components().filter_map(|(component, level)| {
+                        let severity = thresholds.severity(level)?;
+                        Some(json!({
+                            "device": device.name,
+                            "component": component,
+                            "level": level,
+                            "severity": severity,
+                        }))
+                    })
+                })
+                .collect();
+            return Response::json(200, Value::Array(alerts).to_string());
+        }
+
+        if path == "/history" {
+            return self.history_response(query);
+        }
+
+        if path == "/ui" || path == "/ui/" {
+            return Response::html(UI);
+        }
+
+        if path == "/metrics" {
+            return Response {
+                status: 200,
+                content_type: metrics::CONTENT_TYPE,
+                body: metrics::format_metrics(&self.shown(now)),
+            };
+        }
+
+        Response::error(404, &format!("unknown path '{path}'"))
+    }
+
+    /// Answer `/history` with the readings the query asks for
+    fn history_response(&self, query: &str) -> Response {
+        let mut device = None;
+        let mut since = DEFAULT_HISTORY;
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let Some(value) = http::decode_query_component(value) else {
+                return Response::error(400, &format!("bad encoding in '{pair}'"));
+            };
+            match key {
+                "device" => device = Some(value),
+                "since" => match value.parse::<Interval>() {
+                    Ok(interval) => since = interval.0,
+                    Err(e) => return Response::error(400, &e),
+                },
+                _ => return Response::error(400, &format!("unknown parameter '{key}'")),
+            }
+        }
+
+        let Some(history) = &self.history else {
+            return Response::json(200, "[]".to_string());
+        };
+        let since = TimeDelta::from_std(since)
+            .ok()
+            .and_then(|since| Utc::now().checked_sub_signed(since))
+            .unwrap_or(DateTime::<Utc>::MIN_UTC);
+        match history
+            .load(device.as_deref(), since)
+            .map_err(|e| e.to_string())
+            .and_then(|readings| serde_json::to_string(&readings).map_err(|e| e.to_string()))
+        {
+            Ok(body) => Response::json(200, body),
+            Err(e) => Response::error(500, &e),
+        }
+    }
+
+    /// Read one request from a connection and answer it
+    pub fn handle(&mut self, stream: TcpStream) -> io::Result<()> {
+        let response = match read_request(&stream)? {
+            Ok((method, target)) => self.respond(&method, &target, Instant::now()),
+            Err(response) => response,
+        };
+        response.write_to(&stream)
+    }
+}
+
+/// Read the head of a request, returning its method and target, or the
+/// error response for a malformed one
+fn read_request(stream: &TcpStream) -> io::Result<Result<(String, String), Response>> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(Read::take(stream, MAX_HEAD));
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // The headers aren't used, but are read up to the blank line so the
+    // client sees a reply rather than a reset connection
+    let mut complete = false;
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 {
+        if header.trim_end().is_empty() {
+            complete = true;
+            break;
+        }
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    Ok(match (parts.next(), parts.next()) {
+        _ if !complete => Err(Response::error(400, "incomplete or oversized request")),
+        (Some(method), Some(target)) => {
+            debug!(method, target, "HTTP request");
+            Ok((method.to_string(), target.to_string()))
+        }
+        _ => Err(Response::error(400, "malformed request")),
+    })
+}
+
+/// Answer a request to the daemon's metrics listener from its snapshot,
+/// aging the levels to `now`
+pub fn metrics_response(
+    snapshot: &Snapshot,
+    method: &str,
+    target: &str,
+    now: DateTime<Utc>,
+    stale_after: Duration,
+) -> Response {
+    if method != "GET" {
+        return Response::error(405, "only GET is supported");
+    }
+    let path = target.split_once('?').map_or(target, |(path, _)| path);
+    if path != "/metrics" {
+        return Response::error(404, &format!("unknown path '{path}'"));
+    }
+    let Some((mut scan, restarts)) = snapshot.latest() else {
+        return Response::error(503, "the daemon hasn't finished its first scan");
+    };
+    update_freshness(&mut scan.devices, now, stale_after);
+    Response {
+        status: 200,
+        content_type: metrics::CONTENT_TYPE,
+        body: metrics::format_daemon_metrics(&scan.devices, restarts),
+    }
+}
+
+/// Serve the daemon's `/metrics` on `listener` from a background thread
+pub fn serve_metrics(
+    listener: TcpListener,
+    snapshot: Snapshot,
+    stale_after: Duration,
+) -> io::Result<()> {
+    thread::Builder::new()
+        .name("daemon-metrics".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream.and_then(|stream| {
+                    let response = match read_request(&stream)? {
+                        Ok((method, target)) => {
+                            metrics_response(&snapshot, &method, &target, Utc::now(), stale_after)
+                        }
+                        Err(response) => response,
+                    };
+                    response.write_to(&stream)
+                });
+                if let Err(e) = result {
+                    debug!(error = %e, "Metrics connection failed");
+                }
+            }
+        })?;
+    Ok(())
+}
+
+/// Serve HTTP requests on `listener` until the process is stopped
+pub fn serve(listener: TcpListener, mut server: ApiServer) -> io::Result<()> {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if let Err(e) = server.handle(stream) {
+                    debug!(error = %e, "HTTP connection failed");
+                }
+            }
+            Err(e) => warn!(error = %e, "Failed to accept HTTP connection"),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{ClassicReading, GattReading, RawSnapshot, ReplayBackend};
+    use std::fs;
+
+    fn server() -> ApiServer {
+        let backend = ReplayBackend(RawSnapshot {
+            gatt: vec![GattReading {
+                name: "Keyboard".to_string(),
+                battery_level: 76,
+                read_at: None,
+            }],
+            classic: vec![ClassicReading {
+                name: "AirPods Pro".to_string(),
+                address: Some("00:0a:95:12:34:56".to_string()),
+                battery_left: 15,
+                battery_right: 90,
+                ..Default::default()
+            }],
+            adapter: None,
+        });
+        ApiServer::new(Box::new(backend), Config::default())
+    }
+
+    fn get(server: &mut ApiServer, target: &str) -> (u16, Value) {
+        let response = server.respond("GET", target, Instant::now());
+        (
+            response.status,
+            serde_json::from_str(&response.body).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_respond() {
+        let mut server = server();
+
+        let (status, devices) = get(&mut server, "/devices");
+        assert_eq!(status, 200);
+        assert_eq!(devices.as_array().unwrap().len(), 2);
+
+        let (_, devices) = get(&mut server, "/devices?below=20&fields=name");
+        assert_eq!(devices, json!([{ "name": "AirPods Pro" }]));
+
+        let (status, device) = get(&mut server, "/devices/airpods%20pro");
+        assert_eq!(status, 200);
+        assert_eq!(device["battery_left"], 15);
+        assert_eq!(device["schema_version"], crate::schema::SCHEMA_VERSION);
+
+        assert_eq!(get(&mut server, "/devices/Mouse").0, 404);
+        assert_eq!(get(&mut server, "/devices?below=x").0, 400);
+        assert_eq!(get(&mut server, "/").0, 404);
+
+        let metrics = server.respond("GET", "/metrics", Instant::now());
+        assert_eq!(metrics.content_type, metrics::CONTENT_TYPE);
+        assert!(metrics.body.contains("btmon_devices 2\n"));
+        assert_eq!(
+            server.respond("POST", "/devices", Instant::now()).status,
+            405
+        );
+    }
+
+    #[test]
+    fn test_dashboard_endpoints() {
+        let path = std::env::temp_dir().join(format!("btmon-api-{}.jsonl", std::process::id()));
+        let history = History::new(path.clone());
+        let now = Utc::now();
+        history
+            .append(
+                &crate::history::readings(&[Device::test_ble("Keyboard", 80)], now),
+                Duration::from_secs(7 * 24 * 60 * 60),
+            )
+            .unwrap();
+        let mut server = server().history(history);
+
+        let (status, alerts) = get(&mut server, "/alerts");
+        assert_eq!(status, 200);
+        assert_eq!(
+            alerts,
+            json!([{ "device": "AirPods Pro", "component": "left", "level": 15, "severity": "warning" }])
+        );
+
+        let (status, readings) = get(&mut server, "/history?device=key&since=1h");
+        assert_eq!(status, 200);
+        assert_eq!(readings[0]["level"], 80);
+        assert_eq!(get(&mut server, "/history?device=mouse").1, json!([]));
+        assert_eq!(get(&mut server, "/history?since=soon").0, 400);
+
+        let ui = server.respond("GET", "/ui", Instant::now());
+        assert_eq!(ui.content_type, "text/html; charset=utf-8");
+        assert!(ui.body.contains("/history?since=24h"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_redact() {
+        let mut server = server().redact(true);
+
+        let (_, device) = get(&mut server, "/devices/airpods%20pro");
+        assert_eq!(device["address"], "00:0a:95:XX:XX:XX");
+        let (_, devices) = get(&mut server, "/devices?fields=address");
+        assert_eq!(devices[1]["address"], "00:0a:95:XX:XX:XX");
+        // Only responses are masked, not the scan they are made from
+        let (_, devices) = server.cache.as_ref().unwrap();
+        assert_eq!(
+            devices[1].address,
+            crate::device::DeviceAddress::Classic("00:0a:95:12:34:56".to_string())
+        );
+    }
+
+    #[test]
+    fn test_daemon_metrics() {
+        let snapshot = Snapshot::default();
+        let now = Utc::now();
+        let stale_after = Duration::from_secs(600);
+        let response = |target| metrics_response(&snapshot, "GET", target, now, stale_after);
+        assert_eq!(response("/metrics").status, 503);
+
+        snapshot
+            .update(&crate::cache::ScanCache {
+                scanned_at: now,
+                devices: vec![Device {
+                    freshness: Some(crate::device::Freshness::new(now - TimeDelta::seconds(90))),
+                    ..Device::test_ble("Keyboard", 76)
+                }],
+            })
+            .unwrap();
+        snapshot.set_backend_restarts(1);
+        let metrics = response("/metrics");
+        assert_eq!(metrics.content_type, metrics::CONTENT_TYPE);
+        assert!(metrics.body.contains("component=\"level\"} 76\n"));
+        // Aged to the request, not to the scan
+        assert!(
+            metrics
+                .body
+                .contains("btmon_reading_age_seconds{device=\"Keyboard\"} 90\n")
+        );
+        assert!(metrics.body.ends_with("btmon_backend_restarts_total 1\n"));
+
+        assert_eq!(response("/devices").status, 404);
+        let post = metrics_response(&snapshot, "POST", "/metrics", now, stale_after);
+        assert_eq!(post.status, 405);
+    }
+
+    #[test]
+    fn test_handle_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream
+                .write_all(b"GET /devices/Keyboard HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        server().handle(stream).unwrap();
+        let response = client.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Type: application/json\r\n"));
+        assert!(response.ends_with("}"));
+        assert!(response.contains(r#""battery_level": 76"#));
+    }
+}
@@ -0,0 +1,372 @@
+//! Raw battery data sources
+//!
+//! A backend returns the raw values reported by the system Bluetooth APIs,
+//! before any validation or merging. Keeping this boundary small lets the
+//! rest of the pipeline run against recorded data instead of real hardware.
+
+use crate::capabilities::Capabilities;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Battery level read from a peripheral's GATT Battery Service
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GattReading {
+    /// Peripheral name as reported by Core Bluetooth
+    pub name: String,
+    /// Raw Battery Level characteristic value
+    pub battery_level: u8,
+    /// When the value was read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_at: Option<DateTime<Utc>>,
+}
+
+/// Battery values reported by a connected IOBluetooth device
+///
+/// The private battery selectors return 0 or 255 when a value is unavailable,
+/// so every field is kept exactly as reported.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClassicReading {
+    /// Device name
+    pub name: String,
+    /// Bluetooth address string, if the device reported one
+    pub address: Option<String>,
+    /// `batteryPercentSingle`
+    pub battery_single: u8,
+    /// `batteryPercentLeft`
+    pub battery_left: u8,
+    /// `batteryPercentRight`
+    pub battery_right: u8,
+    /// `batteryPercentCase`
+    pub battery_case: u8,
+    /// `batteryPercentCombined`
+    pub battery_combined: u8,
+    /// `headsetBattery`
+    pub headset_battery: u8,
+    /// Private selectors the system didn't implement, read as 0
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unsupported: Vec<String>,
+    /// When the values were read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_at: Option<DateTime<Utc>>,
+}
+
+/// State of the Bluetooth adapter as reported by Core Bluetooth
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdapterState {
+    Unknown,
+    Resetting,
+    Unsupported,
+    Unauthorized,
+    PoweredOff,
+    PoweredOn,
+}
+
+/// Bluetooth adapter status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdapterStatus {
+    pub state: AdapterState,
+    /// Whether Bluetooth is turned on
+    pub powered: bool,
+    /// Whether btmon may use Bluetooth
+    pub authorized: bool,
+}
+
+impl From<AdapterState> for AdapterStatus {
+    fn from(state: AdapterState) -> Self {
+        Self {
+            state,
+            powered: state == AdapterState::PoweredOn,
+            authorized: state != AdapterState::Unauthorized,
+        }
+    }
+}
+
+impl AdapterStatus {
+    /// Whether devices can be queried at all
+    pub fn is_usable(&self) -> bool {
+        self.state == AdapterState::PoweredOn
+    }
+}
+
+/// A source of raw battery readings
+pub trait Backend {
+    /// Read battery levels from GATT Battery Service peripherals
+    fn gatt_readings(&mut self) -> Vec<GattReading>;
+
+    /// Read battery values from connected IOBluetooth devices
+    fn classic_readings(&mut self) -> Vec<ClassicReading>;
+
+    /// Adapter status seen by the last GATT read, None if unknown
+    fn adapter(&mut self) -> Option<AdapterStatus> {
+        None
+    }
+}
+
+/// Backend using the Bluetooth APIs of the running system
+///
+/// Only the data sources that [`Capabilities`] chose for this system are
+/// queried; the others are skipped with a warning giving the reason.
+pub struct SystemBackend {
+    capabilities: Capabilities,
+    connect_if_needed: bool,
+    adapter: Option<AdapterStatus>,
+}
+
+impl SystemBackend {
+    /// Detect the available data sources and create the backend, using
+    /// private APIs only if `private_api` is set
+    pub fn detect(private_api: bool) -> Self {
+        let capabilities = Capabilities::detect(private_api);
+        tracing::debug!(
+            version = ?capabilities.version.map(|v| v.to_string()),
+            gatt = capabilities.gatt.enabled,
+            classic = capabilities.classic.enabled,
+            "Detected capabilities"
+        );
+        Self {
+            capabilities,
+            connect_if_needed: false,
+            adapter: None,
+        }
+    }
+
+    /// Also connect to bonded GATT peripherals that are idle, reading their
+    /// battery and disconnecting again
+    pub fn connect_if_needed(mut self, enabled: bool) -> Self {
+        self.connect_if_needed = enabled;
+        self
+    }
+
+    /// Data sources chosen for this system
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    /// Scan GATT peripherals, remembering their identifiers for later
+    /// on-demand connections
+    #[cfg(target_os = "macos")]
+    fn scan_gatt(&self) -> crate::gatt::GattScan {
+        use crate::peripherals::KnownPeripherals;
+
+        let path = crate::paths::cache_dir().map(|dir| dir.join("peripherals.json"));
+        let mut known = path.as_deref().map_or_else(KnownPeripherals::default, |path| {
+            KnownPeripherals::load(path).unwrap_or_else(|e| {
+                tracing::warn!(error = %e, path = %path.display(), "Failed to load known peripherals");
+                KnownPeripherals::default()
+            })
+        });
+        let connect_if_needed = if self.connect_if_needed {
+            known.identifiers()
+        } else {
+            Vec::new()
+        };
+
+        // Identifiers are learned as levels arrive, so a scan that times out
+        // still remembers the peripherals it read
+        let mut learned = false;
+        let scan = crate::gatt::get_gatt_battery_devices(&connect_if_needed, |level| {
+            tracing::debug!(name = %level.name, battery = level.level, "Read GATT level");
+            learned |= known.learn([(level.name.as_str(), level.identifier.as_str())]);
+        });
+        if learned
+            && let Some(path) = &path
+            && let Err(e) = known.save(path)
+        {
+            tracing::warn!(error = %e, path = %path.display(), "Failed to save known peripherals");
+        }
+        scan
+    }
+}
+
+impl Backend for SystemBackend {
+    fn gatt_readings(&mut self) -> Vec<GattReading> {
+        let decision = &self.capabilities.gatt;
+        if !decision.enabled {
+            tracing::warn!(reason = %decision.reason, "Skipping GATT devices");
+            return Vec::new();
+        }
+
+        #[cfg(target_os = "macos")]
+        {
+            let scan = self.scan_gatt();
+            self.adapter = scan.adapter.map(AdapterStatus::from);
+            let read_at = Some(Utc::now());
+            scan.lowest_levels()
+                .into_iter()
+                .map(|level| GattReading {
+                    name: level.name.clone(),
+                    battery_level: level.level,
+                    read_at,
+                })
+                .collect()
+        }
+        #[cfg(not(target_os = "macos"))]
+        Vec::new()
+    }
+
+    fn classic_readings(&mut self) -> Vec<ClassicReading> {
+        let decision = &self.capabilities.classic;
+        if !decision.enabled {
+            tracing::warn!(reason = %decision.reason, "Skipping Classic Bluetooth devices");
+            return Vec::new();
+        }
+
+        #[cfg(all(target_os = "macos", feature = "private-api"))]
+        {
+            crate::iobluetooth::get_connected_readings()
+        }
+        #[cfg(not(all(target_os = "macos", feature = "private-api")))]
+        Vec::new()
+    }
+
+    fn adapter(&mut self) -> Option<AdapterStatus> {
+        self.adapter
+    }
+}
+
+/// Raw backend responses captured in a single pass
+///
+/// This is the format of `--record` session files and of the test fixtures.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RawSnapshot {
+    /// GATT Battery Service readings
+    pub gatt: Vec<GattReading>,
+    /// IOBluetooth readings
+    pub classic: Vec<ClassicReading>,
+    /// Adapter status, if the backend reported one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub adapter: Option<AdapterStatus>,
+}
+
+impl RawSnapshot {
+    /// Load a snapshot from a JSON session file
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let data = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    /// Save the snapshot as a JSON session file
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, format!("{json}\n"))
+    }
+}
+
+/// Backend wrapper that keeps a copy of every response from the inner backend
+pub struct RecordingBackend {
+    inner: Box<dyn Backend>,
+    recorded: RawSnapshot,
+}
+
+impl RecordingBackend {
+    /// Wrap a backend to record its responses
+    pub fn new(inner: Box<dyn Backend>) -> Self {
+        Self {
+            inner,
+            recorded: RawSnapshot::default(),
+        }
+    }
+
+    /// Take the responses recorded so far
+    pub fn into_snapshot(self) -> RawSnapshot {
+        self.recorded
+    }
+}
+
+impl Backend for RecordingBackend {
+    fn gatt_readings(&mut self) -> Vec<GattReading> {
+        let readings = self.inner.gatt_readings();
+        self.recorded.gatt.extend(readings.iter().cloned());
+        readings
+    }
+
+    fn classic_readings(&mut self) -> Vec<ClassicReading> {
+        let readings = self.inner.classic_readings();
+        self.recorded.classic.extend(readings.iter().cloned());
+        readings
+    }
+
+    fn adapter(&mut self) -> Option<AdapterStatus> {
+        let adapter = self.inner.adapter();
+        self.recorded.adapter = adapter;
+        adapter
+    }
+}
+
+/// Backend serving responses from a recorded snapshot instead of hardware
+pub struct ReplayBackend(pub RawSnapshot);
+
+impl Backend for ReplayBackend {
+    fn gatt_readings(&mut self) -> Vec<GattReading> {
+        self.0.gatt.clone()
+    }
+
+    fn classic_readings(&mut self) -> Vec<ClassicReading> {
+        self.0.classic.clone()
+    }
+
+    fn adapter(&mut self) -> Option<AdapterStatus> {
+        self.0.adapter
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recording_backend_captures_responses() {
+        let snapshot = RawSnapshot {
+            gatt: vec![GattReading {
+                name: "Keyboard".to_string(),
+                battery_level: 76,
+                read_at: None,
+            }],
+            classic: vec![ClassicReading {
+                name: "AirPods".to_string(),
+                battery_left: 80,
+                ..Default::default()
+            }],
+            adapter: None,
+        };
+
+        let mut recorder = RecordingBackend::new(Box::new(ReplayBackend(snapshot)));
+        assert_eq!(recorder.gatt_readings().len(), 1);
+        assert_eq!(recorder.classic_readings().len(), 1);
+
+        let recorded = recorder.into_snapshot();
+        assert_eq!(recorded.gatt[0].name, "Keyboard");
+        assert_eq!(recorded.classic[0].battery_left, 80);
+    }
+
+    #[test]
+    fn test_snapshot_save_and_load() {
+        let path = std::env::temp_dir().join(format!("btmon-session-{}.json", std::process::id()));
+        let snapshot = RawSnapshot {
+            gatt: vec![GattReading {
+                name: "Keyboard".to_string(),
+                battery_level: 76,
+                read_at: None,
+            }],
+            classic: Vec::new(),
+            adapter: Some(AdapterState::PoweredOff.into()),
+        };
+
+        snapshot.save(&path).unwrap();
+        let loaded = RawSnapshot::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.gatt.len(), 1);
+        assert_eq!(loaded.gatt[0].battery_level, 76);
+        assert!(loaded.classic.is_empty());
+        let adapter = loaded.adapter.unwrap();
+        assert_eq!(adapter.state, AdapterState::PoweredOff);
+        assert!(!adapter.powered && adapter.authorized);
+    }
+}
@@ -0,0 +1,199 @@
+//! Bonjour advertisement of `btmon serve`
+//!
+//! With `--advertise`, the HTTP API is registered with mDNSResponder as a
+//! `_btmon._tcp` service, so companion apps and phones on the network can
+//! find it without knowing the Mac's address. The TXT record gives the paths
+//! of the API and dashboard. An API bound to a loopback address is only
+//! advertised on the Mac itself, as nothing else could reach it.
+//!
+//! Registration goes through the DNS-SD API of libSystem and lasts as long
+//! as the returned [`Advertisement`].
+
+use std::fmt;
+use std::net::IpAddr;
+
+/// Service type of the API
+pub const SERVICE_TYPE: &str = "_btmon._tcp";
+
+/// Why the service couldn't be advertised
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BonjourError {
+    /// mDNSResponder refused the registration, with its error code
+    Register(i32),
+    /// Not running on macOS
+    Unsupported,
+}
+
+impl fmt::Display for BonjourError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Register(code) => {
+                write!(
+                    f,
+                    "failed to advertise {SERVICE_TYPE} (DNS-SD error {code})"
+                )
+            }
+            Self::Unsupported => f.write_str("Bonjour advertisement is only supported on macOS"),
+        }
+    }
+}
+
+impl std::error::Error for BonjourError {}
+
+/// TXT record of the service: length-prefixed `key=value` strings
+pub fn txt_record() -> Vec<u8> {
+    let entries = [
+        concat!("version=", env!("CARGO_PKG_VERSION")),
+        "path=/devices",
+        "ui=/ui",
+    ];
+    let mut record = Vec::new();
+    for entry in entries {
+        // Entries are short constants, well under the 255-byte limit
+        record.push(entry.len() as u8);
+        record.extend(entry.as_bytes());
+    }
+    record
+}
+
+/// Whether the service is only advertised on the Mac itself, for an API
+/// bound to `bind`
+pub fn local_only(bind: IpAddr) -> bool {
+    bind.is_loopback()
+}
+
+/// A registered service, withdrawn when dropped
+pub struct Advertisement {
+    #[cfg(target_os = "macos")]
+    service: ffi::DNSServiceRef,
+}
+
+/// Advertise the API listening on `port` of `bind`, under the Mac's name
+#[cfg(target_os = "macos")]
+pub fn advertise(bind: IpAddr, port: u16) -> Result<Advertisement, BonjourError> {
+    use std::ffi::CString;
+    use std::ptr;
+
+    let regtype = CString::new(SERVICE_TYPE).expect("service type has no NUL");
+    let txt = txt_record();
+    let interface = if local_only(bind) {
+        ffi::INTERFACE_INDEX_LOCAL_ONLY
+    } else {
+        ffi::INTERFACE_INDEX_ANY
+    };
+    let mut service: ffi::DNSServiceRef = ptr::null_mut();
+    // SAFETY: every pointer is valid for the call, and mDNSResponder copies
+    // the strings and TXT record; without a callback no reply is delivered
+    let code = unsafe {
+        ffi::DNSServiceRegister(
+            &mut service,
+            0,
+            interface,
+            ptr::null(),
+            regtype.as_ptr(),
+            ptr::null(),
+            ptr::null(),
+            port.to_be(),
+            txt.len() as u16,
+            txt.as_ptr().cast(),
+            None,
+            ptr::null_mut(),
+        )
+    };
+    if code != 0 {
+        return Err(BonjourError::Register(code));
+    }
+    Ok(Advertisement { service })
+}
+
+/// Advertise the API listening on `port` of `bind`, under the Mac's name
+#[cfg(not(target_os = "macos"))]
+pub fn advertise(_bind: IpAddr, _port: u16) -> Result<Advertisement, BonjourError> {
+    Err(BonjourError::Unsupported)
+}
+
+#[cfg(target_os = "macos")]
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        // SAFETY: the reference came from a successful DNSServiceRegister and
+        // is deallocated only here
+        unsafe { ffi::DNSServiceRefDeallocate(self.service) };
+    }
+}
+
+/// The parts of `<dns_sd.h>` used here
+#[cfg(target_os = "macos")]
+mod ffi {
+    use std::ffi::{c_char, c_void};
+
+    #[repr(C)]
+    pub struct DNSServiceOpaque {
+        _private: [u8; 0],
+    }
+
+    pub type DNSServiceRef = *mut DNSServiceOpaque;
+
+    pub type DNSServiceRegisterReply = Option<
+        unsafe extern "C" fn(
+            service: DNSServiceRef,
+            flags: u32,
+            error: i32,
+            name: *const c_char,
+            regtype: *const c_char,
+            domain: *const c_char,
+            context: *mut c_void,
+        ),
+    >;
+
+    /// `kDNSServiceInterfaceIndexAny`
+    pub const INTERFACE_INDEX_ANY: u32 = 0;
+
+    /// `kDNSServiceInterfaceIndexLocalOnly`
+    pub const INTERFACE_INDEX_LOCAL_ONLY: u32 = u32::MAX;
+
+    unsafe extern "C" {
+        pub fn DNSServiceRegister(
+            service: *mut DNSServiceRef,
+            flags: u32,
+            interface_index: u32,
+            name: *const c_char,
+            regtype: *const c_char,
+            domain: *const c_char,
+            host: *const c_char,
+            port: u16,
+            txt_len: u16,
+            txt_record: *const c_void,
+            callback: DNSServiceRegisterReply,
+            context: *mut c_void,
+        ) -> i32;
+
+        pub fn DNSServiceRefDeallocate(service: DNSServiceRef);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_txt_record() {
+        let record = txt_record();
+        let mut entries = Vec::new();
+        let mut rest = record.as_slice();
+        while let Some((&len, tail)) = rest.split_first() {
+            let (entry, tail) = tail.split_at(usize::from(len));
+            entries.push(std::str::from_utf8(entry).unwrap());
+            rest = tail;
+        }
+        assert_eq!(entries[0], concat!("version=", env!("CARGO_PKG_VERSION")));
+        assert_eq!(entries[1..], ["path=/devices", "ui=/ui"]);
+    }
+
+    #[test]
+    fn test_local_only() {
+        assert!(local_only("127.0.0.1".parse().unwrap()));
+        assert!(local_only("::1".parse().unwrap()));
+        assert!(!local_only("0.0.0.0".parse().unwrap()));
+        assert!(!local_only("192.168.1.20".parse().unwrap()));
+    }
+}
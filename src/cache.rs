@@ -0,0 +1,63 @@
+//! Last scan kept between runs
+//!
+//! A scan takes a couple of seconds, too slow for something that runs on
+//! every shell prompt. Each system scan is saved here, and `--max-age` (or
+//! the prompt format) reuses it while it is recent enough.
+
+use crate::device::Device;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+/// Devices of the last scan and when it ran
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScanCache {
+    pub scanned_at: DateTime<Utc>,
+    pub devices: Vec<Device>,
+}
+
+impl ScanCache {
+    /// Load the cache file
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let data = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    /// Save the cache file, creating its directory if needed
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, format!("{}\n", serde_json::to_string(self)?))
+    }
+
+    /// The cached devices if the scan is at most `max_age` old
+    pub fn fresh(self, now: DateTime<Utc>, max_age: Duration) -> Option<Vec<Device>> {
+        let age = now.signed_duration_since(self.scanned_at).to_std().ok()?;
+        (age <= max_age).then_some(self.devices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fresh() {
+        let scanned_at = Utc::now();
+        let cache = || ScanCache {
+            scanned_at,
+            devices: vec![Device::test_ble("Keyboard", 76)],
+        };
+        let minute = Duration::from_secs(60);
+        let later = |secs| scanned_at + chrono::Duration::seconds(secs);
+
+        assert_eq!(cache().fresh(later(30), minute).unwrap().len(), 1);
+        assert!(cache().fresh(later(61), minute).is_none());
+        // A clock that went backwards doesn't make the cache fresh
+        assert!(cache().fresh(later(-5), minute).is_none());
+    }
+}
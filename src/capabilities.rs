@@ -0,0 +1,227 @@
+//! Data sources available on the running system
+//!
+//! Which battery sources work depends on the macOS version and on private
+//! APIs that Apple may change in any update. The decisions are made once per
+//! backend from the detected version and a runtime probe, and kept with their
+//! reasons so they can be shown to the user.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// A macOS version such as `14.5` or `10.15.7`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct OsVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl OsVersion {
+    /// Create a version from its components
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Version of the running macOS, None elsewhere or if it can't be read
+    pub fn current() -> Option<Self> {
+        if !cfg!(target_os = "macos") {
+            return None;
+        }
+        let output = std::process::Command::new("/usr/bin/sw_vers")
+            .arg("-productVersion")
+            .output()
+            .ok()?;
+        String::from_utf8(output.stdout).ok()?.trim().parse().ok()
+    }
+}
+
+impl FromStr for OsVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('.').map(str::parse::<u32>);
+        let mut next = || parts.next().transpose();
+        match (next(), next(), next()) {
+            (Ok(Some(major)), Ok(minor), Ok(patch)) if parts.next().is_none() => {
+                Ok(Self::new(major, minor.unwrap_or(0), patch.unwrap_or(0)))
+            }
+            _ => Err(format!("invalid macOS version '{s}'")),
+        }
+    }
+}
+
+impl fmt::Display for OsVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)?;
+        if self.patch > 0 {
+            write!(f, ".{}", self.patch)?;
+        }
+        Ok(())
+    }
+}
+
+/// Oldest macOS with the Core Bluetooth APIs used for GATT (`CBManagerState`)
+const GATT_MIN_VERSION: OsVersion = OsVersion::new(10, 13, 0);
+
+/// Availability of the private IOBluetooth battery selectors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrivateApi {
+    /// At least one selector is implemented
+    Available,
+    /// The probe found none of the selectors
+    Missing,
+    /// Turned off with `--no-private-api`
+    Disabled,
+    /// Built without the `private-api` feature
+    NotBuilt,
+}
+
+/// Whether a data source is used, and why
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decision {
+    pub enabled: bool,
+    pub reason: String,
+}
+
+impl Decision {
+    fn enabled(reason: impl Into<String>) -> Self {
+        Self {
+            enabled: true,
+            reason: reason.into(),
+        }
+    }
+
+    fn disabled(reason: impl Into<String>) -> Self {
+        Self {
+            enabled: false,
+            reason: reason.into(),
+        }
+    }
+}
+
+/// Data sources chosen for the running system
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Detected macOS version
+    pub version: Option<OsVersion>,
+    /// GATT Battery Service via Core Bluetooth
+    pub gatt: Decision,
+    /// Classic devices via the private IOBluetooth battery selectors
+    pub classic: Decision,
+}
+
+impl Capabilities {
+    /// Detect the capabilities of the running system, not touching private
+    /// APIs unless `private_api` is set
+    pub fn detect(private_api: bool) -> Self {
+        #[cfg(all(target_os = "macos", feature = "private-api"))]
+        let private_api = if !private_api {
+            PrivateApi::Disabled
+        } else if crate::iobluetooth::has_battery_selectors() {
+            PrivateApi::Available
+        } else {
+            PrivateApi::Missing
+        };
+        #[cfg(all(target_os = "macos", not(feature = "private-api")))]
+        let private_api = {
+            let _ = private_api;
+            PrivateApi::NotBuilt
+        };
+        #[cfg(not(target_os = "macos"))]
+        let private_api = if private_api {
+            PrivateApi::Missing
+        } else {
+            PrivateApi::Disabled
+        };
+
+        Self::decide(OsVersion::current(), private_api)
+    }
+
+    /// Choose data sources for a macOS version (None if unknown) and the
+    /// availability of the private battery selectors
+    pub fn decide(version: Option<OsVersion>, private_api: PrivateApi) -> Self {
+        if !cfg!(target_os = "macos") {
+            return Self {
+                version,
+                gatt: Decision::disabled("Core Bluetooth is only available on macOS"),
+                classic: Decision::disabled("IOBluetooth is only available on macOS"),
+            };
+        }
+
+        let gatt = match version {
+            Some(v) if v < GATT_MIN_VERSION => Decision::disabled(format!(
+                "Core Bluetooth GATT needs macOS {GATT_MIN_VERSION} or later, found {v}"
+            )),
+            Some(v) => Decision::enabled(format!("Core Bluetooth GATT on macOS {v}")),
+            None => Decision::enabled("macOS version unknown, trying Core Bluetooth GATT"),
+        };
+        let classic = match private_api {
+            PrivateApi::Available => {
+                Decision::enabled("private IOBluetooth battery selectors present")
+            }
+            PrivateApi::Missing => {
+                Decision::disabled("private IOBluetooth battery selectors missing")
+            }
+            PrivateApi::Disabled => Decision::disabled("private APIs disabled by --no-private-api"),
+            PrivateApi::NotBuilt => Decision::disabled("built without the private-api feature"),
+        };
+
+        Self {
+            version,
+            gatt,
+            classic,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!("14.5".parse(), Ok(OsVersion::new(14, 5, 0)));
+        assert_eq!("10.15.7".parse(), Ok(OsVersion::new(10, 15, 7)));
+        assert_eq!("15".parse(), Ok(OsVersion::new(15, 0, 0)));
+        assert!("".parse::<OsVersion>().is_err());
+        assert!("14.x".parse::<OsVersion>().is_err());
+        assert!("1.2.3.4".parse::<OsVersion>().is_err());
+        assert_eq!(OsVersion::new(10, 15, 7).to_string(), "10.15.7");
+        assert_eq!(OsVersion::new(14, 0, 0).to_string(), "14.0");
+    }
+
+    #[test]
+    #[cfg(target_os = "macos")]
+    fn test_decide() {
+        let caps = Capabilities::decide(Some(OsVersion::new(14, 5, 0)), PrivateApi::Available);
+        assert!(caps.gatt.enabled && caps.classic.enabled);
+
+        let caps = Capabilities::decide(Some(OsVersion::new(10, 12, 6)), PrivateApi::Missing);
+        assert!(!caps.gatt.enabled);
+        assert!(caps.gatt.reason.contains("10.13"));
+        assert!(!caps.classic.enabled);
+
+        assert!(
+            Capabilities::decide(None, PrivateApi::Available)
+                .gatt
+                .enabled
+        );
+
+        // Public APIs only: GATT stays, Classic is off
+        let caps = Capabilities::decide(Some(OsVersion::new(14, 5, 0)), PrivateApi::Disabled);
+        assert!(caps.gatt.enabled);
+        assert!(!caps.classic.enabled);
+        assert!(caps.classic.reason.contains("--no-private-api"));
+    }
+
+    #[test]
+    #[cfg(not(target_os = "macos"))]
+    fn test_decide() {
+        let caps = Capabilities::decide(None, PrivateApi::Available);
+        assert!(!caps.gatt.enabled && !caps.classic.enabled);
+    }
+}
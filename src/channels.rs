@@ -0,0 +1,252 @@
+//! Alert delivery channels
+//!
+//! Each configured destination under `[alerts]` becomes a [`Channel`].
+//! Delivery failures are logged and don't stop the other channels.
+
+use crate::alerts::{Alert, AlertConfig, Severity};
+use crate::device::Device;
+use tracing::{info, warn};
+
+pub mod desktop;
+pub mod email;
+pub mod gotify;
+pub mod matrix;
+
+/// Error returned by a channel
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// A message sent to the channels: a single alert or a digest of several
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    /// Short title
+    pub title: String,
+    /// Message body
+    pub message: String,
+    /// Highest severity of the alerts included
+    pub severity: Severity,
+    /// Number of this notification for the same alert, starting at 1
+    pub attempt: u32,
+}
+
+impl From<&Alert> for Notification {
+    fn from(alert: &Alert) -> Self {
+        Self {
+            title: alert.title(),
+            message: alert.message(),
+            severity: alert.severity,
+            attempt: alert.attempt,
+        }
+    }
+}
+
+impl Notification {
+    /// Combine alerts held back during quiet hours into one notification
+    pub fn digest(alerts: &[Alert]) -> Option<Self> {
+        let severity = alerts.iter().map(|a| a.severity).max()?;
+        Some(Self {
+            title: format!("Low battery digest ({} alerts)", alerts.len()),
+            message: alerts
+                .iter()
+                .map(Alert::message)
+                .collect::<Vec<_>>()
+                .join("\n"),
+            severity,
+            attempt: 1,
+        })
+    }
+}
+
+/// A destination for alerts
+pub trait Channel {
+    /// Channel name used in logs
+    fn name(&self) -> &'static str;
+
+    /// Whether this channel wants the notification at all
+    fn accepts(&self, _notification: &Notification) -> bool {
+        true
+    }
+
+    /// Deliver a notification
+    fn send(&self, notification: &Notification) -> Result<(), Error>;
+
+    /// Whether this channel wants the daily summary
+    fn wants_summary(&self) -> bool {
+        false
+    }
+
+    /// Deliver the daily summary of all devices
+    fn send_summary(&self, _devices: &[Device]) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Outcome of delivering to a set of channels
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Delivery {
+    /// Channels that accepted the message
+    pub sent: usize,
+    /// Channels that failed
+    pub failed: usize,
+}
+
+/// A channel that only receives notifications once they were sent `after`
+/// times without being acknowledged
+struct Escalated {
+    inner: Box<dyn Channel>,
+    after: u32,
+}
+
+impl Channel for Escalated {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn accepts(&self, notification: &Notification) -> bool {
+        notification.attempt > self.after && self.inner.accepts(notification)
+    }
+
+    fn send(&self, notification: &Notification) -> Result<(), Error> {
+        self.inner.send(notification)
+    }
+
+    fn wants_summary(&self) -> bool {
+        self.inner.wants_summary()
+    }
+
+    fn send_summary(&self, devices: &[Device]) -> Result<(), Error> {
+        self.inner.send_summary(devices)
+    }
+}
+
+/// Build the channels configured in the `[alerts]` section
+pub fn configured(config: &AlertConfig) -> Result<Vec<Box<dyn Channel>>, Error> {
+    let mut channels: Vec<Box<dyn Channel>> = Vec::new();
+    if let Some(matrix) = &config.matrix {
+        channels.push(Box::new(matrix::Matrix::new(matrix.clone())?));
+    }
+    if let Some(gotify) = &config.gotify {
+        channels.push(Box::new(gotify::Gotify::new(gotify.clone())?));
+    }
+    if let Some(email) = &config.email {
+        channels.push(Box::new(email::Email::new(email.clone())?));
+    }
+    if let Some(desktop) = &config.desktop {
+        channels.push(Box::new(desktop::Desktop::new(desktop.clone())));
+    }
+
+    let escalation = &config.escalation;
+    if let Some(unknown) = escalation
+        .channels
+        .iter()
+        .find(|name| !channels.iter().any(|c| c.name() == name.as_str()))
+    {
+        return Err(format!("alerts.escalation: channel '{unknown}' is not configured").into());
+    }
+    Ok(channels
+        .into_iter()
+        .map(|channel| -> Box<dyn Channel> {
+            if escalation.channels.iter().any(|n| n == channel.name()) {
+                Box::new(Escalated {
+                    inner: channel,
+                    after: escalation.after,
+                })
+            } else {
+                channel
+            }
+        })
+        .collect())
+}
+
+/// Send a notification to every channel that accepts it
+pub fn deliver(notification: &Notification, channels: &[Box<dyn Channel>]) -> Delivery {
+    let mut delivery = Delivery::default();
+    for channel in channels.iter().filter(|c| c.accepts(notification)) {
+        match channel.send(notification) {
+            Ok(()) => {
+                info!(channel = channel.name(), title = %notification.title, "Sent alert");
+                delivery.sent += 1;
+            }
+            Err(e) => {
+                warn!(channel = channel.name(), title = %notification.title, error = %e, "Failed to send alert");
+                delivery.failed += 1;
+            }
+        }
+    }
+    delivery
+}
+
+/// Send the daily summary to every channel that wants it
+pub fn deliver_summary(devices: &[Device], channels: &[Box<dyn Channel>]) -> Delivery {
+    let mut delivery = Delivery::default();
+    for channel in channels.iter().filter(|c| c.wants_summary()) {
+        match channel.send_summary(devices) {
+            Ok(()) => {
+                info!(channel = channel.name(), "Sent daily summary");
+                delivery.sent += 1;
+            }
+            Err(e) => {
+                warn!(channel = channel.name(), error = %e, "Failed to send daily summary");
+                delivery.failed += 1;
+            }
+        }
+    }
+    delivery
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::{BatteryLevel, Component};
+
+    fn alert(device: &str, level: u8, severity: Severity) -> Alert {
+        Alert {
+            device: device.to_string(),
+            component: Component::Level,
+            level: BatteryLevel::new(level).unwrap(),
+            severity,
+            attempt: 1,
+        }
+    }
+
+    #[test]
+    fn test_digest() {
+        assert!(Notification::digest(&[]).is_none());
+
+        let digest = Notification::digest(&[
+            alert("Keyboard", 18, Severity::Warning),
+            alert("Mouse", 15, Severity::Warning),
+        ])
+        .unwrap();
+        assert_eq!(digest.title, "Low battery digest (2 alerts)");
+        assert_eq!(digest.message, "Keyboard is at 18%\nMouse is at 15%");
+        assert_eq!(digest.severity, Severity::Warning);
+    }
+
+    struct Stub;
+
+    impl Channel for Stub {
+        fn name(&self) -> &'static str {
+            "stub"
+        }
+
+        fn send(&self, _notification: &Notification) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_escalated_channel() {
+        let channels: Vec<Box<dyn Channel>> = vec![
+            Box::new(Stub),
+            Box::new(Escalated {
+                inner: Box::new(Stub),
+                after: 2,
+            }),
+        ];
+        let mut notification = Notification::from(&alert("Keyboard", 8, Severity::Critical));
+
+        assert_eq!(deliver(&notification, &channels).sent, 1);
+        notification.attempt = 3;
+        assert_eq!(deliver(&notification, &channels).sent, 2);
+    }
+}
@@ -0,0 +1,103 @@
+//! Desktop notification channel
+//!
+//! Posts alerts to Notification Center. The UserNotifications framework only
+//! works from an app bundle, so the notification is shown through
+//! `osascript`, which is allowed to post on behalf of a command-line tool.
+
+use super::{Channel, Error, Notification};
+use crate::alerts::Severity;
+use serde::Deserialize;
+use std::process::Command;
+
+/// Script showing a notification with the title, message and optional sound
+/// passed as arguments, so that no quoting is needed
+const SCRIPT: &str = r#"on run argv
+    if (count of argv) > 2 then
+        display notification (item 2 of argv) with title (item 1 of argv) sound name (item 3 of argv)
+    else
+        display notification (item 2 of argv) with title (item 1 of argv)
+    end if
+end run"#;
+
+/// `[alerts.desktop]` configuration section
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DesktopConfig {
+    /// Sound played with critical alerts, e.g. `Submarine`
+    pub sound: Option<String>,
+}
+
+/// Notification Center channel
+pub struct Desktop {
+    config: DesktopConfig,
+}
+
+impl Desktop {
+    /// Create a desktop channel
+    pub fn new(config: DesktopConfig) -> Self {
+        Self { config }
+    }
+
+    /// Arguments of the `osascript` invocation for a notification
+    fn arguments<'a>(&'a self, notification: &'a Notification) -> Vec<&'a str> {
+        let mut arguments = vec![
+            "-e",
+            SCRIPT,
+            notification.title.as_str(),
+            notification.message.as_str(),
+        ];
+        if notification.severity == Severity::Critical
+            && let Some(sound) = &self.config.sound
+        {
+            arguments.push(sound);
+        }
+        arguments
+    }
+}
+
+impl Channel for Desktop {
+    fn name(&self) -> &'static str {
+        "desktop"
+    }
+
+    fn send(&self, notification: &Notification) -> Result<(), Error> {
+        let output = Command::new("/usr/bin/osascript")
+            .args(self.arguments(notification))
+            .output()?;
+        if !output.status.success() {
+            return Err(format!(
+                "osascript failed with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arguments() {
+        let desktop = Desktop::new(DesktopConfig {
+            sound: Some("Submarine".to_string()),
+        });
+        let mut notification = Notification {
+            title: "Low battery: AirPods Pro".to_string(),
+            message: "AirPods Pro (left) is at 15%".to_string(),
+            severity: Severity::Warning,
+            attempt: 1,
+        };
+        assert_eq!(
+            desktop.arguments(&notification)[2..],
+            ["Low battery: AirPods Pro", "AirPods Pro (left) is at 15%"]
+        );
+
+        // Only critical alerts play the sound
+        notification.severity = Severity::Critical;
+        assert_eq!(desktop.arguments(&notification).last(), Some(&"Submarine"));
+    }
+}
@@ -0,0 +1,173 @@
+//! SMTP email channel
+//!
+//! Sends alerts at or above a minimum severity (critical by default) and,
+//! optionally, a daily summary of all devices.
+
+use super::{Channel, Error, Notification};
+use crate::alerts::Severity;
+use crate::device::Device;
+use crate::locale::Locale;
+use crate::output;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use serde::Deserialize;
+
+/// Connection security for the SMTP server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpSecurity {
+    /// Plain connection upgraded with STARTTLS (usually port 587)
+    #[default]
+    Starttls,
+    /// Implicit TLS (usually port 465)
+    Tls,
+    /// Unencrypted, only for local relays
+    None,
+}
+
+/// `[alerts.email]` configuration section
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmailConfig {
+    /// SMTP server host name
+    pub host: String,
+    /// SMTP server port, defaults to the usual port for `security`
+    pub port: Option<u16>,
+    /// Connection security
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    /// SMTP user name
+    pub username: Option<String>,
+    /// SMTP password
+    pub password: Option<String>,
+    /// Sender address, e.g. `btmon <btmon@example.org>`
+    pub from: String,
+    /// Recipient addresses
+    pub to: Vec<String>,
+    /// Lowest severity that is emailed
+    #[serde(default = "default_min_severity")]
+    pub min_severity: Severity,
+    /// Also send a summary of all devices once a day
+    #[serde(default)]
+    pub daily_summary: bool,
+}
+
+fn default_min_severity() -> Severity {
+    Severity::Critical
+}
+
+/// Email channel
+pub struct Email {
+    config: EmailConfig,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+    transport: SmtpTransport,
+}
+
+impl Email {
+    /// Create an email channel, validating the addresses up front
+    pub fn new(config: EmailConfig) -> Result<Self, Error> {
+        let from: Mailbox = config.from.parse()?;
+        let to = config
+            .to
+            .iter()
+            .map(|addr| addr.parse())
+            .collect::<Result<Vec<Mailbox>, _>>()?;
+        if to.is_empty() {
+            return Err("alerts.email.to has no recipients".into());
+        }
+
+        let mut builder = match config.security {
+            SmtpSecurity::Starttls => SmtpTransport::starttls_relay(&config.host)?,
+            SmtpSecurity::Tls => SmtpTransport::relay(&config.host)?,
+            SmtpSecurity::None => SmtpTransport::builder_dangerous(&config.host),
+        };
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        Ok(Self {
+            from,
+            to,
+            transport: builder.build(),
+            config,
+        })
+    }
+
+    fn send_mail(&self, subject: &str, body: String) -> Result<(), Error> {
+        let mut message = Message::builder().from(self.from.clone()).subject(subject);
+        for to in &self.to {
+            message = message.to(to.clone());
+        }
+        self.transport.send(&message.body(body)?)?;
+        Ok(())
+    }
+}
+
+impl Channel for Email {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    fn accepts(&self, notification: &Notification) -> bool {
+        notification.severity >= self.config.min_severity
+    }
+
+    fn send(&self, notification: &Notification) -> Result<(), Error> {
+        self.send_mail(&notification.title, format!("{}\n", notification.message))
+    }
+
+    fn wants_summary(&self) -> bool {
+        self.config.daily_summary
+    }
+
+    fn send_summary(&self, devices: &[Device]) -> Result<(), Error> {
+        let locale = Locale::from_env();
+        let mut body = format!(
+            "Battery levels at {}:\n\n",
+            locale.datetime(&chrono::Local::now())
+        );
+        if devices.is_empty() {
+            body.push_str("No devices with battery info found.\n");
+        } else {
+            body.push_str(&output::format_text(devices, &locale));
+            body.push('\n');
+        }
+        self.send_mail("Bluetooth battery summary", body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_defaults() {
+        let config: EmailConfig = toml::from_str(
+            "host = \"smtp.example.org\"\nfrom = \"btmon@example.org\"\nto = [\"ops@example.org\"]",
+        )
+        .unwrap();
+        assert_eq!(config.security, SmtpSecurity::Starttls);
+        assert_eq!(config.min_severity, Severity::Critical);
+        assert!(!config.daily_summary);
+        assert!(Email::new(config).is_ok());
+    }
+
+    #[test]
+    fn test_rejects_invalid_addresses() {
+        let config: EmailConfig = toml::from_str(
+            "host = \"smtp.example.org\"\nfrom = \"not an address\"\nto = [\"ops@example.org\"]",
+        )
+        .unwrap();
+        assert!(Email::new(config).is_err());
+
+        let config: EmailConfig =
+            toml::from_str("host = \"smtp.example.org\"\nfrom = \"btmon@example.org\"\nto = []")
+                .unwrap();
+        assert!(Email::new(config).is_err());
+    }
+}
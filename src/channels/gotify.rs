@@ -0,0 +1,99 @@
+//! Gotify channel
+//!
+//! Pushes alerts to a Gotify server as application messages, with the
+//! message priority derived from the alert severity.
+
+use super::{Channel, Error, Notification};
+use crate::alerts::Severity;
+use crate::http;
+use serde::Deserialize;
+
+/// `[alerts.gotify]` configuration section
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GotifyConfig {
+    /// Server base URL, e.g. `https://gotify.example.org`
+    pub url: String,
+    /// Application token
+    pub token: String,
+    /// Message priority for warning alerts
+    #[serde(default = "default_warning_priority")]
+    pub warning_priority: u8,
+    /// Message priority for critical alerts
+    #[serde(default = "default_critical_priority")]
+    pub critical_priority: u8,
+}
+
+fn default_warning_priority() -> u8 {
+    5
+}
+
+fn default_critical_priority() -> u8 {
+    8
+}
+
+impl GotifyConfig {
+    /// Gotify priority for an alert severity
+    pub fn priority(&self, severity: Severity) -> u8 {
+        match severity {
+            Severity::Warning => self.warning_priority,
+            Severity::Critical => self.critical_priority,
+        }
+    }
+}
+
+/// Gotify channel
+pub struct Gotify {
+    config: GotifyConfig,
+    agent: ureq::Agent,
+}
+
+impl Gotify {
+    /// Create a Gotify channel
+    pub fn new(config: GotifyConfig) -> Result<Self, Error> {
+        Ok(Self {
+            config,
+            agent: http::agent()?,
+        })
+    }
+
+    fn message_url(&self) -> String {
+        format!("{}/message", self.config.url.trim_end_matches('/'))
+    }
+}
+
+impl Channel for Gotify {
+    fn name(&self) -> &'static str {
+        "gotify"
+    }
+
+    fn send(&self, notification: &Notification) -> Result<(), Error> {
+        let body = serde_json::json!({
+            "title": notification.title,
+            "message": notification.message,
+            "priority": self.config.priority(notification.severity),
+        });
+
+        self.agent
+            .post(&self.message_url())
+            .set("X-Gotify-Key", &self.config.token)
+            .send_json(body)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_priority_defaults() {
+        let config: GotifyConfig =
+            toml::from_str("url = \"https://gotify.example.org/\"\ntoken = \"abc\"").unwrap();
+        assert_eq!(config.priority(Severity::Warning), 5);
+        assert_eq!(config.priority(Severity::Critical), 8);
+
+        let gotify = Gotify::new(config).unwrap();
+        assert_eq!(gotify.message_url(), "https://gotify.example.org/message");
+    }
+}
@@ -0,0 +1,101 @@
+//! Matrix room channel
+//!
+//! Posts alerts as `m.text` messages through the client-server API.
+
+use super::{Channel, Error, Notification};
+use crate::http;
+use serde::Deserialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// `[alerts.matrix]` configuration section
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MatrixConfig {
+    /// Homeserver base URL, e.g. `https://matrix.example.org`
+    pub homeserver: String,
+    /// Access token of the account posting the alerts
+    pub access_token: String,
+    /// Room ID, e.g. `!abcdef:example.org`
+    pub room_id: String,
+}
+
+/// Matrix channel
+pub struct Matrix {
+    config: MatrixConfig,
+    agent: ureq::Agent,
+    /// Counter making transaction IDs unique within this process
+    txn_counter: AtomicU64,
+}
+
+impl Matrix {
+    /// Create a Matrix channel
+    pub fn new(config: MatrixConfig) -> Result<Self, Error> {
+        Ok(Self {
+            config,
+            agent: http::agent()?,
+            txn_counter: AtomicU64::new(0),
+        })
+    }
+
+    /// URL of the send-message endpoint for a transaction
+    fn send_url(&self, txn_id: &str) -> String {
+        format!(
+            "{}/_matrix/client/v3/rooms/{}/send/m.room.message/{}",
+            self.config.homeserver.trim_end_matches('/'),
+            http::encode_path_segment(&self.config.room_id),
+            http::encode_path_segment(txn_id)
+        )
+    }
+
+    /// Unique transaction ID so retried requests aren't posted twice
+    fn next_txn_id(&self) -> String {
+        format!(
+            "btmon-{}-{}-{}",
+            std::process::id(),
+            chrono::Utc::now().timestamp_millis(),
+            self.txn_counter.fetch_add(1, Ordering::Relaxed)
+        )
+    }
+}
+
+impl Channel for Matrix {
+    fn name(&self) -> &'static str {
+        "matrix"
+    }
+
+    fn send(&self, notification: &Notification) -> Result<(), Error> {
+        let body = serde_json::json!({
+            "msgtype": "m.text",
+            "body": format!("{}: {}", notification.title, notification.message),
+        });
+
+        self.agent
+            .put(&self.send_url(&self.next_txn_id()))
+            .set(
+                "Authorization",
+                &format!("Bearer {}", self.config.access_token),
+            )
+            .send_json(body)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_send_url() {
+        let matrix = Matrix::new(MatrixConfig {
+            homeserver: "https://matrix.example.org/".to_string(),
+            access_token: "secret".to_string(),
+            room_id: "!abc:example.org".to_string(),
+        })
+        .unwrap();
+
+        assert_eq!(
+            matrix.send_url("txn-1"),
+            "https://matrix.example.org/_matrix/client/v3/rooms/%21abc%3Aexample.org/send/m.room.message/txn-1"
+        );
+    }
+}
@@ -0,0 +1,80 @@
+//! GATT characteristic value decoding
+//!
+//! Values arrive as raw bytes from peripherals, so decoding must accept any
+//! input without panicking.
+
+/// Decode a Battery Level characteristic (0x2A19) value.
+///
+/// The characteristic is a single uint8 percentage. Extra trailing bytes are
+/// ignored, and an empty value yields None.
+pub fn decode_battery_level(value: &[u8]) -> Option<u8> {
+    value.first().copied()
+}
+
+/// Decode the value of a well-known characteristic, by its short UUID
+///
+/// Covers the Battery Level, the GAP and Device Information strings, the
+/// Appearance and the PnP ID; None for anything else or a value too short.
+pub fn decode_known(uuid: &str, value: &[u8]) -> Option<String> {
+    match uuid.to_uppercase().as_str() {
+        "2A19" => decode_battery_level(value).map(|level| format!("{level}%")),
+        // Device Name, Model, Serial, Firmware, Hardware, Software and
+        // Manufacturer strings
+        "2A00" | "2A24" | "2A25" | "2A26" | "2A27" | "2A28" | "2A29" => {
+            let text = String::from_utf8_lossy(value);
+            Some(text.trim_end_matches('\0').to_string())
+        }
+        "2A01" => {
+            let appearance = u16::from_le_bytes(value.get(..2)?.try_into().ok()?);
+            Some(format!("appearance {appearance:#06x}"))
+        }
+        "2A50" => {
+            let [source, v0, v1, p0, p1, r0, r1, ..] = *value else {
+                return None;
+            };
+            let source = match source {
+                1 => "Bluetooth SIG",
+                2 => "USB",
+                _ => "unknown",
+            };
+            Some(format!(
+                "{source} vendor {:#06x}, product {:#06x}, version {:#06x}",
+                u16::from_le_bytes([v0, v1]),
+                u16::from_le_bytes([p0, p1]),
+                u16::from_le_bytes([r0, r1])
+            ))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_battery_level() {
+        assert_eq!(decode_battery_level(&[76]), Some(76));
+        assert_eq!(decode_battery_level(&[76, 0, 1]), Some(76));
+        assert_eq!(decode_battery_level(&[]), None);
+    }
+
+    #[test]
+    fn test_decode_known() {
+        assert_eq!(decode_known("2a19", &[76]).as_deref(), Some("76%"));
+        assert_eq!(
+            decode_known("2A29", b"Logitech\0").as_deref(),
+            Some("Logitech")
+        );
+        assert_eq!(
+            decode_known("2A01", &[0xC2, 0x03]).as_deref(),
+            Some("appearance 0x03c2")
+        );
+        assert_eq!(
+            decode_known("2A50", &[2, 0x6D, 0x04, 0x23, 0xB0, 0x01, 0x00]).as_deref(),
+            Some("USB vendor 0x046d, product 0xb023, version 0x0001")
+        );
+        assert_eq!(decode_known("2A50", &[2, 0x6D]), None);
+        assert_eq!(decode_known("FFF1", &[1, 2]), None);
+    }
+}
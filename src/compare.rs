@@ -0,0 +1,269 @@
+//! Comparison of two device snapshots
+//!
+//! Snapshots are btmon JSON outputs. Devices are matched by name, and each
+//! battery component present in either snapshot gets a delta.
+
+use crate::device::{BatteryLevel, Component, Device};
+use crate::locale::Locale;
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// How a device's presence changed between snapshots
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Presence {
+    /// Present in both snapshots
+    Both,
+    /// Only present in the later snapshot
+    Appeared,
+    /// Only present in the earlier snapshot
+    Disappeared,
+}
+
+/// Change of one battery component between snapshots
+#[derive(Debug, Clone, Serialize)]
+pub struct ComponentDelta {
+    /// Battery component
+    pub component: Component,
+    /// Level in the earlier snapshot
+    pub before: Option<BatteryLevel>,
+    /// Level in the later snapshot
+    pub after: Option<BatteryLevel>,
+    /// Difference in percentage points, if present in both snapshots
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delta: Option<i16>,
+}
+
+/// Comparison result for a single device
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceComparison {
+    /// Device name
+    pub name: String,
+    /// Presence change
+    pub presence: Presence,
+    /// Per-component deltas
+    pub components: Vec<ComponentDelta>,
+}
+
+/// Load a device list from a btmon JSON output file
+pub fn load_devices(path: &Path) -> io::Result<Vec<Device>> {
+    let data = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&data)?)
+}
+
+fn component_deltas(before: Option<&Device>, after: Option<&Device>) -> Vec<ComponentDelta> {
+    Component::ALL
+        .into_iter()
+        .filter_map(|component| {
+            let before = before.and_then(|d| d.battery(component));
+            let after = after.and_then(|d| d.battery(component));
+            if before.is_none() && after.is_none() {
+                return None;
+            }
+            let delta = before
+                .zip(after)
+                .map(|(b, a)| i16::from(a.as_percentage()) - i16::from(b.as_percentage()));
+            Some(ComponentDelta {
+                component,
+                before,
+                after,
+                delta,
+            })
+        })
+        .collect()
+}
+
+pub(crate) fn find_device<'a>(devices: &'a [Device], name: &str) -> Option<&'a Device> {
+    devices.iter().find(|d| d.name == name)
+}
+
+/// Compare two snapshots.
+///
+/// Devices are listed in the order of the later snapshot, followed by the
+/// devices that disappeared.
+pub fn compare(before: &[Device], after: &[Device]) -> Vec<DeviceComparison> {
+    let mut result: Vec<DeviceComparison> = after
+        .iter()
+        .map(|device| {
+            let previous = find_device(before, &device.name);
+            DeviceComparison {
+                name: device.name.clone(),
+                presence: if previous.is_some() {
+                    Presence::Both
+                } else {
+                    Presence::Appeared
+                },
+                components: component_deltas(previous, Some(device)),
+            }
+        })
+        .collect();
+
+    result.extend(
+        before
+            .iter()
+            .filter(|device| find_device(after, &device.name).is_none())
+            .map(|device| DeviceComparison {
+                name: device.name.clone(),
+                presence: Presence::Disappeared,
+                components: component_deltas(Some(device), None),
+            }),
+    );
+
+    result
+}
+
+/// Only what changed: components whose level differs, and devices that
+/// appeared or disappeared
+pub fn changes(comparisons: Vec<DeviceComparison>) -> Vec<DeviceComparison> {
+    comparisons
+        .into_iter()
+        .filter_map(|mut device| {
+            if device.presence == Presence::Both {
+                device.components.retain(|c| c.before != c.after);
+                if device.components.is_empty() {
+                    return None;
+                }
+            }
+            Some(device)
+        })
+        .collect()
+}
+
+fn component_name(name: &str, component: Component) -> String {
+    match component.label() {
+        Some(label) => format!("{name} {label}"),
+        None => name.to_string(),
+    }
+}
+
+fn levels_summary(
+    components: &[ComponentDelta],
+    pick: fn(&ComponentDelta) -> Option<BatteryLevel>,
+    locale: &Locale,
+) -> String {
+    components
+        .iter()
+        .filter_map(|c| {
+            pick(c).map(|level| match c.component.label() {
+                Some(label) => format!("{label}:{}", locale.battery(level)),
+                None => locale.battery(level),
+            })
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn format_level(level: Option<BatteryLevel>, locale: &Locale) -> String {
+    level.map_or_else(|| "-".to_string(), |l| locale.battery(l))
+}
+
+/// Format a comparison for terminal display
+pub fn format_comparison(comparisons: &[DeviceComparison], locale: &Locale) -> String {
+    let mut lines = Vec::new();
+
+    for device in comparisons {
+        match device.presence {
+            Presence::Both => {
+                for c in &device.components {
+                    let mut line = format!(
+                        "{}: {} -> {}",
+                        component_name(&device.name, c.component),
+                        format_level(c.before, locale),
+                        format_level(c.after, locale)
+                    );
+                    if let Some(delta) = c.delta {
+                        line.push_str(&format!(" ({delta:+})"));
+                    }
+                    lines.push(line);
+                }
+            }
+            Presence::Appeared => lines.push(format!(
+                "{}: appeared ({})",
+                device.name,
+                levels_summary(&device.components, |c| c.after, locale)
+            )),
+            Presence::Disappeared => lines.push(format!(
+                "{}: disappeared (was {})",
+                device.name,
+                levels_summary(&device.components, |c| c.before, locale)
+            )),
+        }
+    }
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn airpods(left: u8, right: u8) -> Device {
+        Device {
+            battery_left: BatteryLevel::new(left),
+            battery_right: BatteryLevel::new(right),
+            ..Device::test_classic("AirPods Pro", "aa-bb-cc-dd-ee-ff")
+        }
+    }
+
+    #[test]
+    fn test_compare_deltas() {
+        let before = vec![Device::test_ble("Keyboard", 76), airpods(80, 90)];
+        let after = vec![Device::test_ble("Keyboard", 76), airpods(72, 85)];
+
+        let result = compare(&before, &after);
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].presence, Presence::Both);
+        assert_eq!(result[0].components[0].delta, Some(0));
+        assert_eq!(result[1].components[0].component, Component::Left);
+        assert_eq!(result[1].components[0].delta, Some(-8));
+        assert_eq!(result[1].components[1].delta, Some(-5));
+    }
+
+    #[test]
+    fn test_compare_appeared_and_disappeared() {
+        let before = vec![Device::test_ble("Keyboard", 76)];
+        let after = vec![Device::test_ble("Mouse", 40)];
+
+        let result = compare(&before, &after);
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].name, "Mouse");
+        assert_eq!(result[0].presence, Presence::Appeared);
+        assert_eq!(result[1].name, "Keyboard");
+        assert_eq!(result[1].presence, Presence::Disappeared);
+    }
+
+    #[test]
+    fn test_changes() {
+        let before = vec![Device::test_ble("Keyboard", 76), airpods(80, 90)];
+        let after = vec![
+            Device::test_ble("Keyboard", 76),
+            airpods(72, 90),
+            Device::test_ble("Mouse", 40),
+        ];
+
+        let result = changes(compare(&before, &after));
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].name, "AirPods Pro");
+        assert_eq!(result[0].components.len(), 1);
+        assert_eq!(result[0].components[0].component, Component::Left);
+        assert_eq!(result[1].presence, Presence::Appeared);
+
+        assert!(changes(compare(&before, &before)).is_empty());
+    }
+
+    #[test]
+    fn test_format_comparison() {
+        let before = vec![Device::test_ble("Keyboard", 76), airpods(80, 90)];
+        let after = vec![airpods(72, 90), Device::test_ble("Mouse", 40)];
+
+        assert_eq!(
+            format_comparison(&compare(&before, &after), &Locale::default()),
+            "AirPods Pro L: 80% -> 72% (-8)\n\
+             AirPods Pro R: 90% -> 90% (+0)\n\
+             Mouse: appeared (40%)\n\
+             Keyboard: disappeared (was 76%)"
+        );
+    }
+}
@@ -0,0 +1,433 @@
+//! Shell completion scripts
+//!
+//! `btmon completions <shell>` prints a script completing subcommands, flags
+//! and flag values (output formats and other fixed choices, and the paths,
+//! commands, hosts and users of clap's value hints). The scripts are
+//! generated from the clap definition of the command line, so they follow
+//! new flags without being maintained by hand. This stands in for
+//! `clap_complete`, which isn't among the crates this build can use; it maps
+//! value hints to the same shell functions.
+
+use clap::builder::PossibleValue;
+use clap::{Arg, ArgAction, Command, ValueHint};
+use std::fmt::Write;
+
+/// Shell to generate completions for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// What a flag or positional argument takes as its value
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    /// A flag without a value
+    None,
+    /// One of a fixed set of values
+    Choices(Vec<String>),
+    /// A file or any path
+    File,
+    /// A directory
+    Directory,
+    /// A command, or the path of an executable
+    Command,
+    /// A host name
+    Host,
+    /// A user name
+    User,
+    /// Anything else, which can't be completed
+    Other,
+}
+
+fn value(arg: &Arg) -> Value {
+    if !matches!(arg.get_action(), ArgAction::Set | ArgAction::Append) {
+        return Value::None;
+    }
+    let choices: Vec<String> = arg
+        .get_possible_values()
+        .iter()
+        .filter(|value| !value.is_hide_set())
+        .map(PossibleValue::get_name)
+        .map(str::to_string)
+        .collect();
+    if !choices.is_empty() {
+        return Value::Choices(choices);
+    }
+    match arg.get_value_hint() {
+        ValueHint::AnyPath | ValueHint::FilePath => Value::File,
+        ValueHint::DirPath => Value::Directory,
+        ValueHint::ExecutablePath | ValueHint::CommandName => Value::Command,
+        ValueHint::Hostname => Value::Host,
+        ValueHint::Username => Value::User,
+        _ => Value::Other,
+    }
+}
+
+/// First line of an argument's or command's help
+fn help(text: Option<&clap::builder::StyledStr>) -> String {
+    text.map(|text| text.to_string().lines().next().unwrap_or("").to_string())
+        .unwrap_or_default()
+}
+
+/// Visible flags of a command, positional arguments excluded
+fn flags(command: &Command) -> impl Iterator<Item = &Arg> {
+    command
+        .get_arguments()
+        .filter(|arg| !arg.is_positional() && !arg.is_hide_set())
+}
+
+/// Visible subcommands of a command
+fn subcommands(command: &Command) -> impl Iterator<Item = &Command> {
+    command
+        .get_subcommands()
+        .filter(|subcommand| !subcommand.is_hide_set())
+}
+
+/// Spellings of a flag, e.g. `-d` and `--device`
+fn spellings(arg: &Arg) -> Vec<String> {
+    let short = arg.get_short().map(|short| format!("-{short}"));
+    let long = arg.get_long().map(|long| format!("--{long}"));
+    short.into_iter().chain(long).collect()
+}
+
+/// Generate the completion script for `command`
+pub fn generate(shell: Shell, mut command: Command) -> String {
+    // Propagates global flags to subcommands and adds --help and --version
+    command.build();
+    match shell {
+        Shell::Bash => bash(&command),
+        Shell::Zsh => zsh(&command),
+        Shell::Fish => fish(&command),
+    }
+}
+
+/// Commands with their path of names, depth first
+fn walk<'a>(path: String, command: &'a Command, out: &mut Vec<(String, &'a Command)>) {
+    out.push((path.clone(), command));
+    for subcommand in subcommands(command) {
+        walk(
+            format!("{path}__{}", subcommand.get_name()),
+            subcommand,
+            out,
+        );
+    }
+}
+
+/// Bash completion of the current word with a `compgen` option
+fn compgen(option: &str) -> String {
+    format!("COMPREPLY=($(compgen {option} -- \"$cur\"))")
+}
+
+fn bash(root: &Command) -> String {
+    let name = root.get_name();
+    let mut commands = Vec::new();
+    walk(name.to_string(), root, &mut commands);
+
+    let mut out = String::new();
+    let _ = writeln!(out, "_{name}() {{");
+    out.push_str("    local cur prev cmd i opts\n");
+    out.push_str("    cur=\"${COMP_WORDS[COMP_CWORD]}\"\n");
+    out.push_str("    prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n");
+    let _ = writeln!(out, "    cmd=\"{name}\"");
+    out.push_str("    for ((i = 1; i < COMP_CWORD; i++)); do\n");
+    out.push_str("        case \"${cmd}__${COMP_WORDS[i]}\" in\n");
+    for (path, _) in &commands[1..] {
+        let _ = writeln!(out, "            {path}) cmd=\"{path}\" ;;");
+    }
+    out.push_str("        esac\n    done\n\n");
+
+    // Values of the flag before the cursor, the same for every command
+    let mut seen = Vec::new();
+    out.push_str("    case \"$prev\" in\n");
+    for (_, command) in &commands {
+        for arg in flags(command) {
+            let spellings = spellings(arg);
+            if seen.contains(&spellings) {
+                continue;
+            }
+            let action = match value(arg) {
+                Value::None => continue,
+                Value::Choices(choices) => format!(
+                    "COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))",
+                    choices.join(" ")
+                ),
+                Value::File => compgen("-f"),
+                Value::Directory => compgen("-d"),
+                Value::Command => compgen("-c"),
+                Value::Host => compgen("-A hostname"),
+                Value::User => compgen("-u"),
+                Value::Other => "COMPREPLY=()".to_string(),
+            };
+            let _ = writeln!(out, "        {}) {action}; return ;;", spellings.join("|"));
+            seen.push(spellings);
+        }
+    }
+    out.push_str("    esac\n\n");
+
+    out.push_str("    case \"$cmd\" in\n");
+    for (path, command) in &commands {
+        let words: Vec<String> = subcommands(command)
+            .map(|subcommand| subcommand.get_name().to_string())
+            .chain(flags(command).flat_map(spellings))
+            .collect();
+        let _ = writeln!(out, "        {path}) opts=\"{}\" ;;", words.join(" "));
+    }
+    out.push_str("    esac\n");
+    out.push_str("    COMPREPLY=($(compgen -W \"$opts\" -- \"$cur\"))\n");
+    let _ = writeln!(
+        out,
+        "}}\n\ncomplete -F _{name} -o bashdefault -o default {name}"
+    );
+    out
+}
+
+/// Quote text for a single-quoted zsh `_arguments` spec
+fn zsh_quote(text: &str) -> String {
+    text.replace('\\', r"\\")
+        .replace('\'', r"'\''")
+        .replace('[', r"\[")
+        .replace(']', r"\]")
+        .replace(':', r"\:")
+}
+
+/// `_arguments` spec of a flag or positional argument
+fn zsh_spec(arg: &Arg) -> String {
+    let help = zsh_quote(&help(arg.get_help()));
+    let action = match value(arg) {
+        Value::None => String::new(),
+        Value::Choices(choices) => format!(":{}:({})", arg.get_id(), choices.join(" ")),
+        Value::File => format!(":{}:_files", arg.get_id()),
+        Value::Directory => format!(":{}:_files -/", arg.get_id()),
+        Value::Command => format!(":{}:_command_names -e", arg.get_id()),
+        Value::Host => format!(":{}:_hosts", arg.get_id()),
+        Value::User => format!(":{}:_users", arg.get_id()),
+        Value::Other => format!(":{}:", arg.get_id()),
+    };
+    if arg.is_positional() {
+        return format!("'{}{action}'", arg.get_index().unwrap_or(1));
+    }
+    let repeat = if matches!(arg.get_action(), ArgAction::Append | ArgAction::Count) {
+        "*"
+    } else {
+        ""
+    };
+    match spellings(arg).as_slice() {
+        [one] => format!("'{repeat}{one}[{help}]{action}'"),
+        many => format!(
+            "'({})'{repeat}{{{}}}'[{help}]{action}'",
+            many.join(" "),
+            many.join(",")
+        ),
+    }
+}
+
+fn zsh(root: &Command) -> String {
+    let name = root.get_name();
+    let mut commands = Vec::new();
+    walk(format!("_{name}"), root, &mut commands);
+
+    let mut out = format!("#compdef {name}\n");
+    for (function, command) in &commands {
+        let _ = write!(out, "\n{function}() {{\n    _arguments -s -S -C");
+        let args = command
+            .get_arguments()
+            .filter(|arg| !arg.is_hide_set())
+            .map(zsh_spec);
+        for spec in args {
+            let _ = write!(out, " \\\n        {spec}");
+        }
+
+        let children: Vec<&Command> = subcommands(command).collect();
+        if children.is_empty() {
+            out.push_str("\n}\n");
+            continue;
+        }
+        out.push_str(" \\\n        ': :->command' \\\n        '*:: :->args'\n");
+        out.push_str("    case $state in\n        command)\n");
+        out.push_str("            local -a commands\n            commands=(\n");
+        for child in &children {
+            let help = help(child.get_about())
+                .replace('\'', r"'\''")
+                .replace(':', r"\:");
+            let _ = writeln!(out, "                '{}:{help}'", child.get_name());
+        }
+        out.push_str("            )\n            _describe -t commands command commands\n");
+        out.push_str("            ;;\n        args)\n            case $line[1] in\n");
+        for child in &children {
+            let _ = writeln!(
+                out,
+                "                {0}) {function}__{0} ;;",
+                child.get_name()
+            );
+        }
+        out.push_str("            esac\n            ;;\n    esac\n}\n");
+    }
+    let _ = write!(out, "\n_{name} \"$@\"\n");
+    out
+}
+
+/// Quote text for a single-quoted fish argument
+fn fish_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\\', r"\\").replace('\'', r"\'"))
+}
+
+fn fish(root: &Command) -> String {
+    let name = root.get_name();
+    let mut out = String::new();
+    fish_command(name, root, &[], &mut out);
+    out
+}
+
+/// Completions of a command reached through `path` (the subcommand names
+/// after the program's)
+fn fish_command(name: &str, command: &Command, path: &[&str], out: &mut String) {
+    let children: Vec<&Command> = subcommands(command).collect();
+    let child_names: Vec<&str> = children.iter().map(|child| child.get_name()).collect();
+
+    // Completions for this command apply once its name was typed and none
+    // of its subcommands was
+    let mut conditions = Vec::new();
+    match path.last() {
+        Some(last) => conditions.push(format!("__fish_seen_subcommand_from {last}")),
+        None if child_names.is_empty() => {}
+        None => conditions.push("__fish_use_subcommand".to_string()),
+    }
+    if !path.is_empty() && !child_names.is_empty() {
+        conditions.push(format!(
+            "not __fish_seen_subcommand_from {}",
+            child_names.join(" ")
+        ));
+    }
+    let condition = if conditions.is_empty() {
+        String::new()
+    } else {
+        format!(" -n {}", fish_quote(&conditions.join("; and ")))
+    };
+
+    for arg in flags(command) {
+        let _ = write!(out, "complete -c {name}{condition}");
+        if let Some(short) = arg.get_short() {
+            let _ = write!(out, " -s {short}");
+        }
+        if let Some(long) = arg.get_long() {
+            let _ = write!(out, " -l {long}");
+        }
+        match value(arg) {
+            Value::None => {}
+            Value::Choices(choices) => {
+                let _ = write!(out, " -x -a {}", fish_quote(&choices.join(" ")));
+            }
+            Value::File => out.push_str(" -r -F"),
+            Value::Directory => out.push_str(" -x -a '(__fish_complete_directories)'"),
+            Value::Command => out.push_str(" -x -a '(__fish_complete_command)'"),
+            Value::Host => out.push_str(" -x -a '(__fish_print_hostnames)'"),
+            Value::User => out.push_str(" -x -a '(__fish_complete_users)'"),
+            Value::Other => out.push_str(" -x"),
+        }
+        let help = help(arg.get_help());
+        if !help.is_empty() {
+            let _ = write!(out, " -d {}", fish_quote(&help));
+        }
+        out.push('\n');
+    }
+    for child in &children {
+        let _ = writeln!(
+            out,
+            "complete -c {name}{condition} -f -a {} -d {}",
+            child.get_name(),
+            fish_quote(&help(child.get_about()))
+        );
+    }
+
+    for child in children {
+        let mut path = path.to_vec();
+        path.push(child.get_name());
+        fish_command(name, child, &path, out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{Parser, Subcommand};
+    use std::path::PathBuf;
+
+    #[derive(Parser)]
+    #[command(name = "btmon")]
+    struct Cli {
+        #[command(subcommand)]
+        command: Option<Sub>,
+        /// Output format
+        #[arg(long, value_enum, global = true)]
+        format: Option<Shell>,
+        /// Path to the config file
+        #[arg(long, value_name = "PATH")]
+        config: Option<PathBuf>,
+        /// Filter by device name
+        #[arg(short, long)]
+        device: Option<String>,
+        /// Directory the [logs] go to, e.g. 'C:\logs'
+        #[arg(long, value_hint = clap::ValueHint::DirPath)]
+        log_dir: Option<PathBuf>,
+        /// Broker to publish to
+        #[arg(long, value_hint = clap::ValueHint::Hostname)]
+        broker: Option<String>,
+    }
+
+    #[derive(Subcommand)]
+    enum Sub {
+        /// Serve the device list over HTTP
+        Serve {
+            /// Port to listen on
+            #[arg(long)]
+            port: u16,
+        },
+    }
+
+    #[test]
+    fn test_generate() {
+        use clap::CommandFactory;
+
+        let bash = generate(Shell::Bash, Cli::command());
+        assert!(bash.contains("            btmon__serve) cmd=\"btmon__serve\" ;;\n"));
+        assert!(bash.contains(
+            "        --format) COMPREPLY=($(compgen -W \"bash zsh fish\" -- \"$cur\")); return ;;\n"
+        ));
+        assert!(
+            bash.contains("        --config) COMPREPLY=($(compgen -f -- \"$cur\")); return ;;\n")
+        );
+        assert!(bash.contains("        btmon__serve) opts=\"--port --format -h --help\" ;;\n"));
+        assert!(
+            bash.contains("        --log-dir) COMPREPLY=($(compgen -d -- \"$cur\")); return ;;\n")
+        );
+        assert!(bash.contains(
+            "        --broker) COMPREPLY=($(compgen -A hostname -- \"$cur\")); return ;;\n"
+        ));
+
+        let zsh = generate(Shell::Zsh, Cli::command());
+        assert!(zsh.starts_with("#compdef btmon\n"));
+        assert!(zsh.contains("'(-d --device)'{-d,--device}'[Filter by device name]:device:'"));
+        assert!(zsh.contains("'--config[Path to the config file]:config:_files'"));
+        assert!(zsh.contains("                serve) _btmon__serve ;;\n"));
+        assert!(zsh.contains(
+            r"'--log-dir[Directory the \[logs\] go to, e.g. '\''C\:\\logs'\'']:log_dir:_files -/'"
+        ));
+        assert!(zsh.contains("'--broker[Broker to publish to]:broker:_hosts'"));
+
+        let fish = generate(Shell::Fish, Cli::command());
+        assert!(fish.contains(
+            "complete -c btmon -n '__fish_use_subcommand' -l format -x -a 'bash zsh fish' -d 'Output format'\n"
+        ));
+        assert!(fish.contains(
+            "complete -c btmon -n '__fish_use_subcommand' -f -a serve -d 'Serve the device list over HTTP'\n"
+        ));
+        assert!(
+            fish.contains("complete -c btmon -n '__fish_seen_subcommand_from serve' -l port -x")
+        );
+        assert!(fish.contains(
+            r"-l log-dir -x -a '(__fish_complete_directories)' -d 'Directory the [logs] go to, e.g. \'C:\\logs\''"
+        ));
+    }
+}
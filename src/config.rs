@@ -0,0 +1,345 @@
+//! Configuration file
+//!
+//! The config file is TOML, read from `--config` or the default location
+//! (see [`crate::paths::config_file`]). A missing default file is the same
+//! as an empty one; unknown keys are rejected so typos don't go unnoticed.
+
+use crate::alerts::AlertConfig;
+use crate::alfred::AlfredConfig;
+use crate::device::DeviceConfig;
+use crate::events::EventsConfig;
+use crate::history::HistoryConfig;
+use crate::icons::IconsConfig;
+use crate::mqtt::MqttConfig;
+use crate::sketchybar::SketchybarConfig;
+use crate::smoothing::SmoothingConfig;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Default age after which readings are reported as stale
+pub const DEFAULT_STALE_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// Starting point written by `btmon config init`
+///
+/// Every setting is commented out, so the file behaves like no file at all
+/// until something is uncommented. Settings are written `#key = value`
+/// and explanations `# text`.
+pub const TEMPLATE: &str = r#"# btmon configuration
+#
+# Uncomment a setting to change it; the values shown are examples, and the
+# defaults apply to everything left commented out.
+
+# Age after which readings are reported as stale
+#stale_after = "5m"
+
+# Per-device settings, by device name
+#[devices."Galaxy Buds"]
+#hide = ["case"]
+
+# Smoothing of jittery readings
+#[smoothing]
+#window = 3
+#monotonic = true
+#charge_step = 5
+
+# Low-battery alerts, sent to the channels configured below
+#[alerts]
+#warn = 20
+#critical = 10
+#quiet_hours = ["23:00-08:00"]
+
+#[alerts.repeat]
+#critical = "30m"
+#warning = "4h"
+
+#[alerts.escalation]
+#after = 2
+#channels = ["email"]
+
+#[alerts.desktop]
+#sound = "Submarine"
+
+#[alerts.matrix]
+#homeserver = "https://matrix.example.org"
+#access_token = "syt_..."
+#room_id = "!abcdef:example.org"
+
+#[alerts.gotify]
+#url = "https://gotify.example.org"
+#token = "A1b2C3..."
+
+#[alerts.email]
+#host = "smtp.example.org"
+#username = "btmon@example.org"
+#password = "..."
+#from = "btmon <btmon@example.org>"
+#to = ["it-ops@example.org"]
+
+# CloudEvents sent on device and threshold changes
+#[events]
+#sinks = ["https://events.example.org/btmon"]
+
+# Battery history for `btmon history`
+#[history]
+#record = true
+#retention = "90d"
+#encrypt = true
+
+# btmon mqtt
+#[mqtt]
+#broker = "mqtt://homeassistant.local"
+#username = "btmon"
+#password = "secret"
+
+# --format sketchybar: icons by device type and 0xAARRGGBB colors
+#[sketchybar]
+#normal_color = "0xffffffff"
+#warning_color = "0xffeed49f"
+#critical_color = "0xffed8796"
+
+#[sketchybar.icons]
+#headphones = "󰋋"
+#keyboard = "󰌌"
+#mouse = "󰍽"
+
+# --format alfred: item icons by device type
+#[alfred.icons]
+#headphones = "icons/headphones.png"
+
+# --icons: glyphs by device type, and battery glyphs from empty to full
+#[icons.nerd]
+#controller = "󰊗"
+#battery = ["󰂎", "󰁻", "󰁽", "󰁿", "󰂁", "󰁹"]
+"#;
+
+/// Top-level configuration
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Age after which readings are reported as stale
+    pub stale_after: Option<Interval>,
+    /// Low-battery alerts and their destinations
+    pub alerts: AlertConfig,
+    /// CloudEvents output
+    pub events: EventsConfig,
+    /// Per-device settings keyed by device name
+    pub devices: HashMap<String, DeviceConfig>,
+    /// Smoothing of jittery readings
+    pub smoothing: SmoothingConfig,
+    /// Battery history recording
+    pub history: HistoryConfig,
+    /// MQTT publishing for `btmon mqtt`
+    pub mqtt: Option<MqttConfig>,
+    /// Icons and colors of `--format sketchybar`
+    pub sketchybar: SketchybarConfig,
+    /// Icons of `--format alfred`
+    pub alfred: AlfredConfig,
+    /// Glyph overrides of `--icons`
+    pub icons: IconsConfig,
+}
+
+/// A time interval written with a unit suffix, e.g. `90s`, `30m`, `4h` or `1d`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Interval(pub Duration);
+
+impl Interval {
+    /// Length of the interval in whole seconds
+    pub fn as_secs(self) -> u64 {
+        self.0.as_secs()
+    }
+}
+
+impl FromStr for Interval {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (digits, unit) = s.split_at(split);
+        let multiplier = match unit {
+            "s" | "" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            _ => 0,
+        };
+        match digits.parse::<u64>() {
+            Ok(n) if n > 0 && multiplier > 0 => Ok(Self(Duration::from_secs(n * multiplier))),
+            _ => Err(format!(
+                "invalid interval '{s}', expected a number with s, m, h or d (e.g. 30m)"
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for Interval {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Error loading the configuration file
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file could not be read
+    Io(PathBuf, io::Error),
+    /// The file is not valid configuration
+    Parse(PathBuf, toml::de::Error),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(path, e) => write!(f, "failed to read {}: {e}", path.display()),
+            Self::Parse(path, e) => write!(f, "invalid config {}: {e}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Age after which readings are reported as stale
+    pub fn stale_after(&self) -> Duration {
+        self.stale_after
+            .map_or(DEFAULT_STALE_AFTER, |interval| interval.0)
+    }
+
+    /// Parse configuration from TOML text
+    pub fn parse(text: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    /// Load the configuration from a file
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let text = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+        Self::parse(&text).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    /// Settings that parse but can't work as intended
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let alerts = &self.alerts;
+        if alerts.warn > 100 || alerts.critical > 100 {
+            problems.push("alert thresholds must be at most 100".to_string());
+        }
+        if alerts.critical > alerts.warn {
+            problems.push(format!(
+                "alerts.critical ({}) is above alerts.warn ({}), so warnings are never sent",
+                alerts.critical, alerts.warn
+            ));
+        }
+        for sink in &self.events.sinks {
+            if !sink.starts_with("http://") && !sink.starts_with("https://") {
+                problems.push(format!("events sink '{sink}' is not an http(s) URL"));
+            }
+        }
+        problems
+    }
+
+    /// Write [`TEMPLATE`] to `path`, creating its directory, unless a file
+    /// is already there and `overwrite` isn't set
+    pub fn init(path: &Path, overwrite: bool) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = fs::File::options()
+            .write(true)
+            .create(true)
+            .create_new(!overwrite)
+            .truncate(true)
+            .open(path)?;
+        io::Write::write_all(&mut file, TEMPLATE.as_bytes())
+    }
+
+    /// Load the configuration from an explicit path, or from the default
+    /// location if it exists
+    pub fn load_or_default(path: Option<&Path>) -> Result<Self, ConfigError> {
+        if let Some(path) = path {
+            return Self::load(path);
+        }
+        match crate::paths::config_file() {
+            Some(path) if path.exists() => Self::load(&path),
+            _ => Ok(Self::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::Component;
+
+    #[test]
+    fn test_parse_empty() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(config.alerts.warn, 20);
+        assert_eq!(config.alerts.critical, 10);
+        assert!(config.alerts.matrix.is_none());
+        assert!(config.events.sinks.is_empty());
+        assert_eq!(config.stale_after(), DEFAULT_STALE_AFTER);
+    }
+
+    #[test]
+    fn test_interval() {
+        assert_eq!("90s".parse(), Ok(Interval(Duration::from_secs(90))));
+        assert_eq!("30m".parse(), Ok(Interval(Duration::from_secs(1800))));
+        assert_eq!("4h".parse::<Interval>().unwrap().as_secs(), 4 * 3600);
+        assert_eq!("1d".parse::<Interval>().unwrap().as_secs(), 86400);
+        assert_eq!("45".parse::<Interval>().unwrap().as_secs(), 45);
+        assert!("0m".parse::<Interval>().is_err());
+        assert!("m".parse::<Interval>().is_err());
+        assert!("5 minutes".parse::<Interval>().is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_keys() {
+        assert!(Config::parse("[alerts]\nwarning = 30\n").is_err());
+        assert!(Config::parse("[alert]\n").is_err());
+        assert!(Config::parse("[devices.Buds]\nhide = [\"lid\"]\n").is_err());
+    }
+
+    #[test]
+    fn test_template() {
+        let config = Config::parse(TEMPLATE).unwrap();
+        assert!(config.problems().is_empty());
+        assert!(config.alerts.desktop.is_none() && config.mqtt.is_none());
+
+        // The commented-out settings are valid too
+        let uncommented: String = TEMPLATE
+            .lines()
+            .map(|line| match line.strip_prefix('#') {
+                Some(setting) if !setting.is_empty() && !setting.starts_with(' ') => setting,
+                _ => line,
+            })
+            .map(|line| format!("{line}\n"))
+            .collect();
+        let config = Config::parse(&uncommented).unwrap();
+        assert!(config.problems().is_empty());
+        assert!(config.alerts.email.is_some() && config.mqtt.is_some());
+    }
+
+    #[test]
+    fn test_problems() {
+        let config = Config::parse("[alerts]\nwarn = 10\ncritical = 15\n").unwrap();
+        assert!(config.problems()[0].contains("above alerts.warn"));
+        let config = Config::parse("[events]\nsinks = [\"events.example.org\"]\n").unwrap();
+        assert_eq!(config.problems().len(), 1);
+    }
+
+    #[test]
+    fn test_parse_devices() {
+        let config = Config::parse("[devices.\"Galaxy Buds\"]\nhide = [\"case\"]\n").unwrap();
+        assert_eq!(config.devices["Galaxy Buds"].hide, [Component::Case]);
+
+        let config = Config::parse("stale_after = \"90s\"\n").unwrap();
+        assert_eq!(config.stale_after(), Duration::from_secs(90));
+    }
+}
@@ -0,0 +1,159 @@
+//! Connecting and disconnecting paired Classic Bluetooth devices
+//!
+//! `btmon connect` and `btmon disconnect` open and close the baseband
+//! connection through IOBluetooth's public `openConnection` and
+//! `closeConnection`, which is what reconnecting a headset from the
+//! Bluetooth menu does.
+
+use std::fmt;
+
+/// Why a device couldn't be connected or disconnected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionError {
+    /// No paired device matches the name
+    NotFound(String),
+    /// More than one paired device matches the name
+    Ambiguous(Vec<String>),
+    /// IOBluetooth returned an error code
+    Failed(i32),
+    /// Not running on macOS
+    Unsupported,
+}
+
+impl fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(name) => write!(f, "no paired device matching '{name}'"),
+            Self::Ambiguous(names) => {
+                write!(f, "several paired devices match: {}", names.join(", "))
+            }
+            Self::Failed(code) => write!(f, "IOBluetooth returned error {code:#010x}"),
+            Self::Unsupported => f.write_str("connecting devices is only supported on macOS"),
+        }
+    }
+}
+
+impl std::error::Error for ConnectionError {}
+
+/// Outcome of a connect or disconnect request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Done,
+    /// The device already was in the requested state
+    Unchanged,
+}
+
+/// Index of the device name matching `filter`: an exact match
+/// (case-insensitive), or else the only one containing it
+pub fn pick(names: &[String], filter: &str) -> Result<usize, ConnectionError> {
+    let lowercase = filter.to_lowercase();
+    if let Some(index) = names
+        .iter()
+        .position(|name| name.to_lowercase() == lowercase)
+    {
+        return Ok(index);
+    }
+    let matching: Vec<usize> = (0..names.len())
+        .filter(|&i| names[i].to_lowercase().contains(&lowercase))
+        .collect();
+    match matching[..] {
+        [index] => Ok(index),
+        [] => Err(ConnectionError::NotFound(filter.to_string())),
+        _ => Err(ConnectionError::Ambiguous(
+            matching.iter().map(|&i| names[i].clone()).collect(),
+        )),
+    }
+}
+
+/// Connect (`connect` true) or disconnect the paired device matching
+/// `device`, returning its name
+#[cfg(target_os = "macos")]
+pub fn set_connected(device: &str, connect: bool) -> Result<(String, Outcome), ConnectionError> {
+    use objc2::msg_send;
+    use objc2::runtime::AnyObject;
+    use objc2_foundation::{NSArray, NSString};
+    use objc2_io_bluetooth::IOBluetoothDevice;
+    use tracing::debug;
+
+    // SAFETY: pairedDevices() returns a valid NSArray or nil.
+    let paired: Option<objc2::rc::Retained<NSArray<AnyObject>>> =
+        unsafe { IOBluetoothDevice::pairedDevices() };
+    let paired = paired.ok_or_else(|| ConnectionError::NotFound(device.to_string()))?;
+
+    let mut devices = Vec::new();
+    let mut names = Vec::new();
+    for i in 0..paired.count() {
+        // SAFETY: objectAtIndex returns a valid pointer for a valid index.
+        let object: *const AnyObject = unsafe { msg_send![&paired, objectAtIndex: i] };
+        if object.is_null() {
+            continue;
+        }
+        // SAFETY: The pointer was checked for null above, and the NSArray
+        // retains the object while it is used.
+        let object = unsafe { &*object };
+        // SAFETY: name returns NSString or nil.
+        let name: *const NSString = unsafe { msg_send![object, name] };
+        if name.is_null() {
+            continue;
+        }
+        // SAFETY: name was checked for null above.
+        names.push(unsafe { (*name).to_string() });
+        devices.push(object);
+    }
+
+    let index = pick(&names, device)?;
+    let object = devices[index];
+    let name = names.swap_remove(index);
+    // SAFETY: isConnected takes no arguments and returns a BOOL.
+    let connected: bool = unsafe { msg_send![object, isConnected] };
+    if connected == connect {
+        return Ok((name, Outcome::Unchanged));
+    }
+
+    debug!(name = %name, connect, "Changing device connection");
+    // SAFETY: openConnection and closeConnection take no arguments and
+    // return an IOReturn; openConnection blocks until the page completes.
+    let result: i32 = if connect {
+        unsafe { msg_send![object, openConnection] }
+    } else {
+        unsafe { msg_send![object, closeConnection] }
+    };
+    match result {
+        0 => Ok((name, Outcome::Done)),
+        code => Err(ConnectionError::Failed(code)),
+    }
+}
+
+/// Connect (`connect` true) or disconnect the paired device matching
+/// `device`, returning its name
+#[cfg(not(target_os = "macos"))]
+pub fn set_connected(_device: &str, _connect: bool) -> Result<(String, Outcome), ConnectionError> {
+    Err(ConnectionError::Unsupported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pick() {
+        let names = [
+            "AirPods Pro".to_string(),
+            "AirPods".to_string(),
+            "WH-1000XM4".to_string(),
+        ];
+        assert_eq!(pick(&names, "airpods"), Ok(1));
+        assert_eq!(pick(&names, "1000xm"), Ok(2));
+        assert_eq!(
+            pick(&names, "Air"),
+            Err(ConnectionError::Ambiguous(vec![
+                "AirPods Pro".to_string(),
+                "AirPods".to_string()
+            ]))
+        );
+        assert_eq!(
+            pick(&names, "Mouse"),
+            Err(ConnectionError::NotFound("Mouse".to_string()))
+        );
+    }
+}
@@ -0,0 +1,221 @@
+//! Information about the Mac's own Bluetooth controller
+//!
+//! The controller details (chipset, firmware, discoverable state) aren't
+//! available through public APIs, so they are taken from the JSON output of
+//! `system_profiler SPBluetoothDataType`, which also lists the paired devices
+//! split by whether they are connected. Core Bluetooth adds whether Bluetooth
+//! Low Energy is supported.
+
+use crate::backend::AdapterState;
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+use std::io;
+
+/// JSON output of `system_profiler SPBluetoothDataType`
+pub fn system_profiler() -> io::Result<String> {
+    if !cfg!(target_os = "macos") {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Bluetooth information is only available on macOS",
+        ));
+    }
+    let output = std::process::Command::new("/usr/sbin/system_profiler")
+        .args(["SPBluetoothDataType", "-json"])
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "system_profiler failed with {}",
+            output.status
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Local Bluetooth controller details
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ControllerInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chipset: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub firmware_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vendor_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub product_id: Option<String>,
+    /// Bus the controller is attached to, e.g. `PCIe` or `UART`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transport: Option<String>,
+    /// Whether Bluetooth is turned on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub powered: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discoverable: Option<bool>,
+    /// Whether the controller supports Bluetooth Low Energy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub low_energy: Option<bool>,
+    /// Paired devices, connected or not
+    pub paired: usize,
+    pub connected: usize,
+}
+
+impl ControllerInfo {
+    /// Query the controller through `system_profiler`
+    pub fn query() -> io::Result<Self> {
+        Self::parse(&system_profiler()?)
+    }
+
+    /// Parse the JSON output of `system_profiler SPBluetoothDataType -json`
+    pub fn parse(json: &str) -> io::Result<Self> {
+        let value: Value = serde_json::from_str(json)?;
+        let data = value
+            .get("SPBluetoothDataType")
+            .and_then(|data| data.get(0))
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "no Bluetooth data reported")
+            })?;
+        let properties = &data["controller_properties"];
+        let text = |key: &str| properties.get(key)?.as_str().map(str::to_string);
+        // Switches are reported as `attrib_on` and `attrib_off`
+        let switch = |key: &str| match properties.get(key)?.as_str()? {
+            "attrib_on" => Some(true),
+            "attrib_off" => Some(false),
+            _ => None,
+        };
+        let count = |key: &str| data.get(key).and_then(Value::as_array).map_or(0, Vec::len);
+        let connected = count("device_connected");
+
+        Ok(Self {
+            address: text("controller_address"),
+            chipset: text("controller_chipset"),
+            firmware_version: text("controller_firmwareVersion"),
+            vendor_id: text("controller_vendorID"),
+            product_id: text("controller_productID"),
+            transport: text("controller_transport"),
+            powered: switch("controller_state"),
+            discoverable: switch("controller_discoverable"),
+            low_energy: None,
+            paired: connected + count("device_not_connected"),
+            connected,
+        })
+    }
+
+    /// Add what Core Bluetooth reported: Low Energy support, and the power
+    /// state if `system_profiler` didn't report it
+    pub fn apply_adapter(&mut self, state: AdapterState) {
+        match state {
+            AdapterState::Unsupported => self.low_energy = Some(false),
+            AdapterState::PoweredOn | AdapterState::PoweredOff => {
+                self.low_energy = Some(true);
+                self.powered.get_or_insert(state == AdapterState::PoweredOn);
+            }
+            // Says nothing about the hardware
+            AdapterState::Unknown | AdapterState::Resetting | AdapterState::Unauthorized => {}
+        }
+    }
+}
+
+impl fmt::Display for ControllerInfo {
+    /// One `label: value` line per known field
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let on_off = |value: bool| if value { "on" } else { "off" };
+        let fields = [
+            ("Address", self.address.clone()),
+            ("Chipset", self.chipset.clone()),
+            ("Firmware", self.firmware_version.clone()),
+            ("Vendor ID", self.vendor_id.clone()),
+            ("Product ID", self.product_id.clone()),
+            ("Transport", self.transport.clone()),
+            ("Power", self.powered.map(|v| on_off(v).to_string())),
+            (
+                "Discoverable",
+                self.discoverable.map(|v| on_off(v).to_string()),
+            ),
+            (
+                "Bluetooth LE",
+                self.low_energy
+                    .map(|v| if v { "supported" } else { "not supported" }.to_string()),
+            ),
+        ];
+        for (label, value) in fields {
+            if let Some(value) = value {
+                writeln!(f, "{label}: {value}")?;
+            }
+        }
+        write!(
+            f,
+            "Devices: {} paired, {} connected",
+            self.paired, self.connected
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYSTEM_PROFILER: &str = r#"{
+      "SPBluetoothDataType" : [
+        {
+          "controller_properties" : {
+            "controller_address" : "F0:2F:4B:12:34:56",
+            "controller_chipset" : "BCM_4387",
+            "controller_discoverable" : "attrib_off",
+            "controller_firmwareVersion" : "22.1.534.4145",
+            "controller_productID" : "0x4A0A",
+            "controller_state" : "attrib_on",
+            "controller_transport" : "PCIe",
+            "controller_vendorID" : "0x004C (Apple)"
+          },
+          "device_connected" : [
+            { "AirPods Pro" : { "device_address" : "AA:BB:CC:DD:EE:FF" } }
+          ],
+          "device_not_connected" : [
+            { "Keyboard" : { "device_address" : "11:22:33:44:55:66" } },
+            { "Mouse" : { "device_address" : "11:22:33:44:55:67" } }
+          ]
+        }
+      ]
+    }"#;
+
+    #[test]
+    fn test_parse() {
+        let info = ControllerInfo::parse(SYSTEM_PROFILER).unwrap();
+        assert_eq!(info.address.as_deref(), Some("F0:2F:4B:12:34:56"));
+        assert_eq!(info.chipset.as_deref(), Some("BCM_4387"));
+        assert_eq!(info.firmware_version.as_deref(), Some("22.1.534.4145"));
+        assert_eq!(info.powered, Some(true));
+        assert_eq!(info.discoverable, Some(false));
+        assert_eq!((info.paired, info.connected), (3, 1));
+        assert!(info.to_string().contains("Devices: 3 paired, 1 connected"));
+
+        // Bluetooth off: no devices and no controller details
+        let info = ControllerInfo::parse(r#"{"SPBluetoothDataType": [{}]}"#).unwrap();
+        assert_eq!(info, ControllerInfo::default());
+        assert!(ControllerInfo::parse(r#"{"SPBluetoothDataType": []}"#).is_err());
+    }
+
+    #[test]
+    fn test_apply_adapter() {
+        let mut info = ControllerInfo::default();
+        info.apply_adapter(AdapterState::PoweredOff);
+        assert_eq!((info.powered, info.low_energy), (Some(false), Some(true)));
+        assert!(
+            info.to_string()
+                .contains("Power: off\nBluetooth LE: supported\n")
+        );
+
+        // system_profiler's power state wins
+        let mut info = ControllerInfo::parse(SYSTEM_PROFILER).unwrap();
+        info.apply_adapter(AdapterState::PoweredOff);
+        assert_eq!(info.powered, Some(true));
+
+        let mut info = ControllerInfo::default();
+        info.apply_adapter(AdapterState::Unsupported);
+        assert_eq!(info.low_energy, Some(false));
+        info.apply_adapter(AdapterState::Unknown);
+        assert_eq!(info.low_energy, Some(false));
+    }
+}
@@ -0,0 +1,260 @@
+//! Rolling CSV append log
+//!
+//! Every sample appends one timestamped row per device to a CSV file. The
+//! header is written whenever the file is created, and the file can be
+//! rolled over by date or size so long-running logs stay manageable.
+
+use crate::device::{BatteryLevel, Device};
+use chrono::{DateTime, Local, SecondsFormat};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tracing::debug;
+
+/// Header row of the CSV log
+pub const HEADER: &str = "timestamp,name,address,level,left,right,case";
+
+/// When to move the current log file aside and start a new one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rollover {
+    /// Start a new file when the local date changes
+    Daily,
+    /// Start a new file once the current one reaches this many bytes
+    Size(u64),
+}
+
+impl FromStr for Rollover {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("daily") {
+            return Ok(Self::Daily);
+        }
+
+        let upper = s.to_ascii_uppercase();
+        let (digits, multiplier) = if let Some(n) = upper.strip_suffix("G") {
+            (n, 1024 * 1024 * 1024)
+        } else if let Some(n) = upper.strip_suffix("M") {
+            (n, 1024 * 1024)
+        } else if let Some(n) = upper.strip_suffix("K") {
+            (n, 1024)
+        } else {
+            (upper.as_str(), 1)
+        };
+
+        match digits.parse::<u64>() {
+            Ok(n) if n > 0 => Ok(Self::Size(n * multiplier)),
+            _ => Err(format!(
+                "invalid rollover '{s}', expected 'daily' or a size like 10M"
+            )),
+        }
+    }
+}
+
+/// Quote a CSV field if it contains a separator, quote or newline
+pub fn escape_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn level_field(level: Option<BatteryLevel>) -> String {
+    level
+        .map(|l| l.as_percentage().to_string())
+        .unwrap_or_default()
+}
+
+/// Format one CSV row for a device
+pub fn format_row(device: &Device, timestamp: &DateTime<Local>) -> String {
+    [
+        timestamp.to_rfc3339_opts(SecondsFormat::Secs, false),
+        escape_field(&device.name),
+        escape_field(&device.address.to_string()),
+        level_field(device.battery_level),
+        level_field(device.battery_left),
+        level_field(device.battery_right),
+        level_field(device.battery_case),
+    ]
+    .join(",")
+}
+
+/// A CSV log file with optional rollover
+pub struct CsvLog {
+    path: PathBuf,
+    rollover: Option<Rollover>,
+}
+
+impl CsvLog {
+    /// Create a log writing to `path`
+    pub fn new(path: PathBuf, rollover: Option<Rollover>) -> Self {
+        Self { path, rollover }
+    }
+
+    /// Path of the archived file for a rollover at `suffix`
+    fn archive_path(&self, suffix: &str) -> PathBuf {
+        let stem = self
+            .path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let name = match self.path.extension() {
+            Some(ext) => format!("{stem}-{suffix}.{}", ext.to_string_lossy()),
+            None => format!("{stem}-{suffix}"),
+        };
+        self.path.with_file_name(name)
+    }
+
+    /// Move the current file aside if the rollover policy says so
+    fn roll_over(&self, now: &DateTime<Local>) -> io::Result<()> {
+        let Some(rollover) = self.rollover else {
+            return Ok(());
+        };
+        let metadata = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        let archive = match rollover {
+            Rollover::Daily => {
+                let modified: DateTime<Local> = metadata.modified()?.into();
+                if modified.date_naive() == now.date_naive() {
+                    return Ok(());
+                }
+                self.archive_path(&modified.format("%Y-%m-%d").to_string())
+            }
+            Rollover::Size(max) => {
+                if metadata.len() < max {
+                    return Ok(());
+                }
+                self.archive_path(&now.format("%Y%m%dT%H%M%S").to_string())
+            }
+        };
+
+        debug!(path = %self.path.display(), archive = %archive.display(), "Rolling over CSV log");
+        fs::rename(&self.path, archive)
+    }
+
+    /// Append one row per device, creating the file with a header if needed
+    pub fn append(&self, devices: &[Device], now: &DateTime<Local>) -> io::Result<()> {
+        if devices.is_empty() {
+            return Ok(());
+        }
+
+        self.roll_over(now)?;
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+
+        let mut data = String::new();
+        if file.metadata()?.len() == 0 {
+            data.push_str(HEADER);
+            data.push('\n');
+        }
+        for device in devices {
+            data.push_str(&format_row(device, now));
+            data.push('\n');
+        }
+
+        // A single write keeps rows from concurrent writers from interleaving
+        file.write_all(data.as_bytes())
+    }
+
+    /// Path of the current log file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("btmon-csvlog-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn devices() -> Vec<Device> {
+        vec![
+            Device::test_ble("Keyboard", 76),
+            Device {
+                battery_left: BatteryLevel::new(80),
+                battery_right: BatteryLevel::new(90),
+                ..Device::test_classic("Buds, \"Pro\"", "aa-bb-cc-dd-ee-ff")
+            },
+        ]
+    }
+
+    #[test]
+    fn test_rollover_parse() {
+        assert_eq!("daily".parse(), Ok(Rollover::Daily));
+        assert_eq!("512".parse(), Ok(Rollover::Size(512)));
+        assert_eq!("10k".parse(), Ok(Rollover::Size(10 * 1024)));
+        assert_eq!("2M".parse(), Ok(Rollover::Size(2 * 1024 * 1024)));
+        assert!("0".parse::<Rollover>().is_err());
+        assert!("weekly".parse::<Rollover>().is_err());
+    }
+
+    #[test]
+    fn test_append_writes_header_once() {
+        let dir = temp_dir("header");
+        let log = CsvLog::new(dir.join("battery.csv"), None);
+        let now = Local.with_ymd_and_hms(2026, 1, 2, 3, 4, 5).unwrap();
+
+        log.append(&devices(), &now).unwrap();
+        log.append(&devices(), &now).unwrap();
+
+        let data = fs::read_to_string(log.path()).unwrap();
+        let lines: Vec<&str> = data.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], HEADER);
+        assert!(lines[1].ends_with(",Keyboard,BLE,76,,,"));
+        assert!(lines[2].ends_with(",\"Buds, \"\"Pro\"\"\",aa-bb-cc-dd-ee-ff,,80,90,"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_size_rollover() {
+        let dir = temp_dir("size");
+        let log = CsvLog::new(dir.join("battery.csv"), Some(Rollover::Size(10)));
+        let now = Local.with_ymd_and_hms(2026, 1, 2, 3, 4, 5).unwrap();
+
+        log.append(&devices(), &now).unwrap();
+        log.append(&devices(), &now).unwrap();
+
+        assert!(dir.join("battery-20260102T030405.csv").exists());
+        let data = fs::read_to_string(log.path()).unwrap();
+        assert_eq!(data.lines().count(), 3);
+        assert!(data.starts_with(HEADER));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_daily_rollover() {
+        let dir = temp_dir("daily");
+        let log = CsvLog::new(dir.join("battery.csv"), Some(Rollover::Daily));
+        let today = Local::now();
+
+        log.append(&devices(), &today).unwrap();
+        log.append(&devices(), &today).unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        let tomorrow = today + chrono::Duration::days(1);
+        log.append(&devices(), &tomorrow).unwrap();
+
+        let archive = dir.join(format!("battery-{}.csv", today.format("%Y-%m-%d")));
+        assert_eq!(fs::read_to_string(archive).unwrap().lines().count(), 5);
+        assert_eq!(fs::read_to_string(log.path()).unwrap().lines().count(), 3);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
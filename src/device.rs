@@ -0,0 +1,640 @@
+//! Device model and the merge/filter pipeline
+//!
+//! Raw readings from the backends are validated, filtered by name and merged
+//! into a single list of devices here, independent of where they came from.
+
+use crate::backend::Backend;
+use crate::vendor;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Duration;
+use tracing::{debug, info, info_span};
+
+/// Battery level percentage (0-100)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "u8", into = "u8")]
+pub struct BatteryLevel(u8);
+
+impl BatteryLevel {
+    /// Create a new BatteryLevel from a raw value.
+    /// Returns None if value is 0 or > 100 (invalid/unavailable).
+    pub fn new(value: u8) -> Option<Self> {
+        if value > 0 && value <= 100 {
+            Some(Self(value))
+        } else {
+            None
+        }
+    }
+
+    /// Get the battery level as a percentage
+    pub fn as_percentage(self) -> u8 {
+        self.0
+    }
+}
+
+impl std::fmt::Display for BatteryLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}%", self.0)
+    }
+}
+
+/// Error for a battery level outside 1-100
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidBatteryLevel(pub u8);
+
+impl std::fmt::Display for InvalidBatteryLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid battery level {}, expected 1-100", self.0)
+    }
+}
+
+impl std::error::Error for InvalidBatteryLevel {}
+
+impl TryFrom<u8> for BatteryLevel {
+    type Error = InvalidBatteryLevel;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Self::new(value).ok_or(InvalidBatteryLevel(value))
+    }
+}
+
+impl From<BatteryLevel> for u8 {
+    fn from(level: BatteryLevel) -> Self {
+        level.0
+    }
+}
+
+/// Bluetooth device address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceAddress {
+    /// Classic Bluetooth MAC address
+    Classic(String),
+    /// BLE device (address not exposed for privacy)
+    Ble,
+}
+
+impl std::fmt::Display for DeviceAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Classic(addr) => write!(f, "{addr}"),
+            Self::Ble => write!(f, "BLE"),
+        }
+    }
+}
+
+impl Serialize for DeviceAddress {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            Self::Classic(addr) => serializer.serialize_str(addr),
+            Self::Ble => serializer.serialize_str("BLE"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for DeviceAddress {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let addr = String::deserialize(deserializer)?;
+        if addr == "BLE" {
+            Ok(Self::Ble)
+        } else {
+            Ok(Self::Classic(addr))
+        }
+    }
+}
+
+/// A battery component of a device
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Component {
+    /// Single battery of a standard device
+    Level,
+    /// Left earbud
+    Left,
+    /// Right earbud
+    Right,
+    /// Charging case
+    Case,
+}
+
+impl Component {
+    /// All components in display order
+    pub const ALL: [Self; 4] = [Self::Level, Self::Left, Self::Right, Self::Case];
+
+    /// Name used in JSON output, e.g. `left`
+    pub fn key(self) -> &'static str {
+        match self {
+            Self::Level => "level",
+            Self::Left => "left",
+            Self::Right => "right",
+            Self::Case => "case",
+        }
+    }
+
+    /// Short label used in text output, None for the single battery
+    pub fn label(self) -> Option<&'static str> {
+        match self {
+            Self::Level => None,
+            Self::Left => Some("L"),
+            Self::Right => Some("R"),
+            Self::Case => Some("Case"),
+        }
+    }
+}
+
+/// When a device's levels were read, relative to when they are reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Freshness {
+    /// Time the levels were read
+    pub read_at: DateTime<Utc>,
+    /// Seconds between the read and the report
+    #[serde(default)]
+    pub age_seconds: u64,
+    /// Whether the levels are older than the freshness window
+    #[serde(default)]
+    pub stale: bool,
+}
+
+impl Freshness {
+    /// Levels read at `read_at`, not aged yet
+    pub fn new(read_at: DateTime<Utc>) -> Self {
+        Self {
+            read_at,
+            age_seconds: 0,
+            stale: false,
+        }
+    }
+
+    /// Age the levels to `now`, marking them stale after `stale_after`
+    pub fn update(&mut self, now: DateTime<Utc>, stale_after: Duration) {
+        self.age_seconds = (now - self.read_at).num_seconds().max(0).unsigned_abs();
+        self.stale = self.age_seconds > stale_after.as_secs();
+    }
+}
+
+/// Represents a Bluetooth device with battery information
+///
+/// Serialized with the derived `effective` level as an extra field, which is
+/// ignored when deserializing.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Device {
+    /// Human-readable device name
+    pub name: String,
+    /// Bluetooth address
+    pub address: DeviceAddress,
+    /// Manufacturer, when it can be resolved from the address
+    #[serde(default)]
+    pub vendor: Option<String>,
+    /// Single battery level for standard devices
+    pub battery_level: Option<BatteryLevel>,
+    /// Left earbud battery (AirPods, etc.)
+    pub battery_left: Option<BatteryLevel>,
+    /// Right earbud battery (AirPods, etc.)
+    pub battery_right: Option<BatteryLevel>,
+    /// Charging case battery (AirPods, etc.)
+    pub battery_case: Option<BatteryLevel>,
+    /// Levels as read, for components whose level was smoothed
+    #[serde(default)]
+    pub raw: BTreeMap<Component, BatteryLevel>,
+    /// Change of each component's level since the previous pass of `--watch`
+    #[serde(default)]
+    pub delta: BTreeMap<Component, i16>,
+    /// When the levels were read, if the backend reported it
+    #[serde(default, flatten)]
+    pub freshness: Option<Freshness>,
+}
+
+impl Serialize for Device {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("Device", 13)?;
+        state.serialize_field("name", &self.name)?;
+        state.serialize_field("address", &self.address)?;
+        let optional = [
+            ("battery_level", self.battery_level),
+            ("battery_left", self.battery_left),
+            ("battery_right", self.battery_right),
+            ("battery_case", self.battery_case),
+            ("effective", self.effective()),
+        ];
+        match &self.vendor {
+            Some(vendor) => state.serialize_field("vendor", vendor)?,
+            None => state.skip_field("vendor")?,
+        }
+        for (key, level) in optional {
+            match level {
+                Some(level) => state.serialize_field(key, &level)?,
+                None => state.skip_field(key)?,
+            }
+        }
+        if self.raw.is_empty() {
+            state.skip_field("raw")?;
+        } else {
+            state.serialize_field("raw", &self.raw)?;
+        }
+        if self.delta.is_empty() {
+            state.skip_field("delta")?;
+        } else {
+            state.serialize_field("delta", &self.delta)?;
+        }
+        match &self.freshness {
+            Some(freshness) => {
+                state.serialize_field("read_at", &freshness.read_at)?;
+                state.serialize_field("age_seconds", &freshness.age_seconds)?;
+                state.serialize_field("stale", &freshness.stale)?;
+            }
+            None => {
+                state.skip_field("read_at")?;
+                state.skip_field("age_seconds")?;
+                state.skip_field("stale")?;
+            }
+        }
+        state.end()
+    }
+}
+
+impl Device {
+    /// Check if device has any battery information
+    pub fn has_battery_info(&self) -> bool {
+        self.battery_level.is_some()
+            || self.battery_left.is_some()
+            || self.battery_right.is_some()
+            || self.battery_case.is_some()
+    }
+
+    /// Get the battery level of a single component
+    pub fn battery(&self, component: Component) -> Option<BatteryLevel> {
+        match component {
+            Component::Level => self.battery_level,
+            Component::Left => self.battery_left,
+            Component::Right => self.battery_right,
+            Component::Case => self.battery_case,
+        }
+    }
+
+    /// Mutable battery level of a single component
+    pub fn battery_mut(&mut self, component: Component) -> &mut Option<BatteryLevel> {
+        match component {
+            Component::Level => &mut self.battery_level,
+            Component::Left => &mut self.battery_left,
+            Component::Right => &mut self.battery_right,
+            Component::Case => &mut self.battery_case,
+        }
+    }
+
+    /// Single comparable level: the lowest component in use, i.e. excluding
+    /// the charging case
+    pub fn effective(&self) -> Option<BatteryLevel> {
+        self.components()
+            .filter(|(component, _)| *component != Component::Case)
+            .map(|(_, level)| level)
+            .min_by_key(|level| level.as_percentage())
+    }
+
+    /// Iterate over the components that have a battery level
+    pub fn components(&self) -> impl Iterator<Item = (Component, BatteryLevel)> + '_ {
+        Component::ALL
+            .into_iter()
+            .filter_map(|c| self.battery(c).map(|level| (c, level)))
+    }
+}
+
+/// Per-device settings, from a `[devices."<name>"]` config section
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceConfig {
+    /// Components to ignore, e.g. a case that always reports 0%
+    pub hide: Vec<Component>,
+}
+
+/// Remove the components hidden in the per-device settings, dropping devices
+/// that have no battery level left
+pub fn hide_components(devices: &mut Vec<Device>, config: &HashMap<String, DeviceConfig>) {
+    if config.is_empty() {
+        return;
+    }
+    devices.retain_mut(|device| {
+        let Some(settings) = config.get(&device.name) else {
+            return true;
+        };
+        for component in &settings.hide {
+            *device.battery_mut(*component) = None;
+        }
+        let keep = device.has_battery_info();
+        if !keep {
+            debug!(name = %device.name, "All components hidden");
+        }
+        keep
+    });
+}
+
+/// Age the devices' levels to `now`, marking those older than `stale_after`
+pub fn update_freshness(devices: &mut [Device], now: DateTime<Utc>, stale_after: Duration) {
+    for freshness in devices.iter_mut().filter_map(|d| d.freshness.as_mut()) {
+        freshness.update(now, stale_after);
+    }
+}
+
+/// Check a device name against a lowercase name filter
+pub(crate) fn matches_filter(name: &str, name_filter: Option<&str>) -> bool {
+    name_filter.is_none_or(|filter| name.to_lowercase().contains(filter))
+}
+
+/// Get all connected Bluetooth devices with battery information
+pub fn collect_devices(backend: &mut dyn Backend, name_filter: Option<&str>) -> Vec<Device> {
+    let _span = info_span!("collect_devices").entered();
+
+    // Pre-convert filter to lowercase for efficiency
+    let filter_lower = name_filter.map(|f| f.to_lowercase());
+    let filter_ref = filter_lower.as_deref();
+
+    // First, get GATT Battery Service devices via Core Bluetooth
+    let mut devices: Vec<Device> = backend
+        .gatt_readings()
+        .into_iter()
+        .filter_map(|reading| {
+            if !matches_filter(&reading.name, filter_ref) {
+                return None;
+            }
+
+            let battery_level = BatteryLevel::new(reading.battery_level);
+            if battery_level.is_none() {
+                debug!(
+                    name = %reading.name,
+                    raw_value = reading.battery_level,
+                    "Invalid battery level from GATT"
+                );
+                return None;
+            }
+
+            info!(name = %reading.name, battery = reading.battery_level, "Found GATT device");
+
+            Some(Device {
+                name: reading.name,
+                address: DeviceAddress::Ble,
+                vendor: None,
+                battery_level,
+                battery_left: None,
+                battery_right: None,
+                battery_case: None,
+                raw: BTreeMap::new(),
+                delta: BTreeMap::new(),
+                freshness: reading.read_at.map(Freshness::new),
+            })
+        })
+        .collect();
+
+    // Track seen device names to avoid duplicates
+    let seen_names: HashSet<String> = devices.iter().map(|d| d.name.clone()).collect();
+
+    // Then get IOBluetooth devices
+    for reading in backend.classic_readings() {
+        // Skip if already got battery from GATT
+        if seen_names.contains(&reading.name) {
+            debug!(name = %reading.name, "Skipping device already found via GATT");
+            continue;
+        }
+
+        if !matches_filter(&reading.name, filter_ref) {
+            continue;
+        }
+
+        let address = reading.address.unwrap_or_else(|| "unknown".to_string());
+        let device = Device {
+            vendor: vendor::address_vendor(&address).map(str::to_string),
+            address: DeviceAddress::Classic(address),
+            battery_level: BatteryLevel::new(reading.battery_single),
+            battery_left: BatteryLevel::new(reading.battery_left),
+            battery_right: BatteryLevel::new(reading.battery_right),
+            battery_case: BatteryLevel::new(reading.battery_case),
+            raw: BTreeMap::new(),
+            delta: BTreeMap::new(),
+            freshness: reading.read_at.map(Freshness::new),
+            name: reading.name,
+        };
+
+        // Skip devices with no battery info
+        if !device.has_battery_info() {
+            debug!(name = %device.name, "No battery info available");
+            continue;
+        }
+
+        info!(
+            name = %device.name,
+            battery_level = ?device.battery_level.map(|b| b.as_percentage()),
+            battery_left = ?device.battery_left.map(|b| b.as_percentage()),
+            battery_right = ?device.battery_right.map(|b| b.as_percentage()),
+            battery_case = ?device.battery_case.map(|b| b.as_percentage()),
+            "Found IOBluetooth device"
+        );
+
+        devices.push(device);
+    }
+
+    devices
+}
+
+#[cfg(test)]
+impl Device {
+    /// A Bluetooth LE device with a single battery, none for 0
+    pub fn test_ble(name: &str, level: u8) -> Self {
+        Self {
+            name: name.to_string(),
+            address: DeviceAddress::Ble,
+            vendor: None,
+            battery_level: BatteryLevel::new(level),
+            battery_left: None,
+            battery_right: None,
+            battery_case: None,
+            raw: BTreeMap::new(),
+            delta: BTreeMap::new(),
+            freshness: None,
+        }
+    }
+
+    /// A Classic device without batteries, to set the ones a test needs
+    pub fn test_classic(name: &str, address: &str) -> Self {
+        Self {
+            address: DeviceAddress::Classic(address.to_string()),
+            ..Self::test_ble(name, 0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_battery_level_valid() {
+        assert!(BatteryLevel::new(1).is_some());
+        assert!(BatteryLevel::new(50).is_some());
+        assert!(BatteryLevel::new(100).is_some());
+    }
+
+    #[test]
+    fn test_battery_level_invalid() {
+        assert!(BatteryLevel::new(0).is_none());
+        assert!(BatteryLevel::new(101).is_none());
+        assert!(BatteryLevel::new(255).is_none());
+    }
+
+    #[test]
+    fn test_battery_level_display() {
+        let level = BatteryLevel::new(75).unwrap();
+        assert_eq!(format!("{level}"), "75%");
+    }
+
+    #[test]
+    fn test_device_has_battery_info() {
+        let device_with_single = Device::test_ble("Test", 50);
+        assert!(device_with_single.has_battery_info());
+
+        let device_with_left_right = Device {
+            battery_left: BatteryLevel::new(80),
+            battery_right: BatteryLevel::new(90),
+            ..Device::test_classic("AirPods", "aa:bb:cc:dd:ee:ff")
+        };
+        assert!(device_with_left_right.has_battery_info());
+
+        let device_without_battery = Device::test_ble("Mouse", 0);
+        assert!(!device_without_battery.has_battery_info());
+    }
+
+    #[test]
+    fn test_hide_components() {
+        let mut devices = vec![
+            Device {
+                battery_left: BatteryLevel::new(80),
+                battery_right: BatteryLevel::new(90),
+                battery_case: BatteryLevel::new(1),
+                ..Device::test_ble("Buds", 0)
+            },
+            Device::test_ble("Dongle", 1),
+        ];
+        let hide = |components: &[Component]| DeviceConfig {
+            hide: components.to_vec(),
+        };
+        let config = HashMap::from([
+            ("Buds".to_string(), hide(&[Component::Case])),
+            ("Dongle".to_string(), hide(&[Component::Level])),
+        ]);
+
+        hide_components(&mut devices, &config);
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].battery_case, None);
+        assert_eq!(devices[0].battery_left, BatteryLevel::new(80));
+    }
+
+    #[test]
+    fn test_battery_level_deserialize_validates() {
+        assert_eq!(
+            serde_json::from_str::<BatteryLevel>("42").unwrap(),
+            BatteryLevel::new(42).unwrap()
+        );
+        assert!(serde_json::from_str::<BatteryLevel>("0").is_err());
+        assert!(serde_json::from_str::<BatteryLevel>("101").is_err());
+        assert!(serde_json::from_str::<BatteryLevel>("-1").is_err());
+    }
+
+    #[test]
+    fn test_device_round_trip() {
+        let devices = vec![
+            Device::test_ble("Keyboard", 76),
+            Device {
+                battery_left: BatteryLevel::new(80),
+                battery_right: BatteryLevel::new(90),
+                battery_case: BatteryLevel::new(100),
+                ..Device::test_classic("AirPods Pro", "aa-bb-cc-dd-ee-ff")
+            },
+        ];
+
+        let json = serde_json::to_string(&devices).unwrap();
+        let parsed: Vec<Device> = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].address, DeviceAddress::Ble);
+        assert_eq!(parsed[0].battery_level, BatteryLevel::new(76));
+        assert_eq!(
+            parsed[1].address,
+            DeviceAddress::Classic("aa-bb-cc-dd-ee-ff".to_string())
+        );
+        assert_eq!(parsed[1].battery_case, BatteryLevel::new(100));
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), json);
+    }
+
+    #[test]
+    fn test_freshness() {
+        let read_at = DateTime::parse_from_rfc3339("2026-03-14T15:00:00Z")
+            .unwrap()
+            .to_utc();
+        let mut devices = vec![Device {
+            freshness: Some(Freshness::new(read_at)),
+            ..Device::test_ble("Keyboard", 76)
+        }];
+
+        let window = Duration::from_secs(300);
+        update_freshness(&mut devices, read_at + Duration::from_secs(60), window);
+        let json = serde_json::to_value(&devices[0]).unwrap();
+        assert_eq!(json["read_at"], "2026-03-14T15:00:00Z");
+        assert_eq!(json["age_seconds"], 60);
+        assert_eq!(json["stale"], false);
+
+        update_freshness(&mut devices, read_at + Duration::from_secs(301), window);
+        assert!(devices[0].freshness.unwrap().stale);
+
+        // Survives a round trip, and is absent when the backend had no time
+        let parsed: Device = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.freshness.unwrap().read_at, read_at);
+        let parsed: Device =
+            serde_json::from_str(r#"{"name":"Mouse","address":"BLE","battery_level":50}"#).unwrap();
+        assert_eq!(parsed.freshness, None);
+    }
+
+    #[test]
+    fn test_effective() {
+        let mut device = Device {
+            battery_left: BatteryLevel::new(80),
+            battery_right: BatteryLevel::new(35),
+            battery_case: BatteryLevel::new(5),
+            ..Device::test_ble("AirPods Pro", 0)
+        };
+        assert_eq!(device.effective(), BatteryLevel::new(35));
+        assert_eq!(
+            serde_json::to_value(&device).unwrap()["effective"],
+            serde_json::json!(35)
+        );
+
+        device.battery_left = None;
+        device.battery_right = None;
+        assert_eq!(device.effective(), None);
+        assert!(
+            serde_json::to_value(&device)
+                .unwrap()
+                .get("effective")
+                .is_none()
+        );
+    }
+}
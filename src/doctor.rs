@@ -0,0 +1,230 @@
+//! Diagnostics for `btmon doctor`
+//!
+//! A scan that finds nothing prints nothing, whether there are no devices or
+//! Bluetooth can't be used at all. The doctor goes through everything a scan
+//! depends on and says how to fix what doesn't work.
+
+use crate::backend::{AdapterState, AdapterStatus};
+use crate::capabilities::{Capabilities, Decision};
+use serde::Serialize;
+use std::fmt::{self, Write};
+
+/// Outcome of a check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Ok,
+    /// Works, with reduced coverage
+    Warning,
+    /// Keeps btmon from reading batteries
+    Failed,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Ok => "ok",
+            Self::Warning => "warn",
+            Self::Failed => "FAIL",
+        })
+    }
+}
+
+/// One diagnostic check
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+    /// What to do about a failed or degraded check
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remedy: Option<String>,
+}
+
+impl Check {
+    fn new(name: &'static str, status: Status, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+            remedy: None,
+        }
+    }
+
+    fn remedy(mut self, remedy: impl Into<String>) -> Self {
+        self.remedy = Some(remedy.into());
+        self
+    }
+
+    fn remedy_if(self, condition: bool, remedy: impl Into<String>) -> Self {
+        if condition { self.remedy(remedy) } else { self }
+    }
+}
+
+/// Run the checks for the detected capabilities, the adapter status seen by
+/// a GATT scan (None if no scan ran), and the terminal app btmon runs in
+/// (`$TERM_PROGRAM`)
+pub fn run_checks(
+    capabilities: &Capabilities,
+    adapter: Option<AdapterStatus>,
+    terminal: Option<&str>,
+) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    checks.push(match capabilities.version {
+        Some(version) => Check::new("macos", Status::Ok, format!("macOS {version}")),
+        None if cfg!(target_os = "macos") => {
+            Check::new("macos", Status::Warning, "macOS version could not be read")
+        }
+        None => Check::new("macos", Status::Failed, "not running on macOS")
+            .remedy("btmon reads batteries through macOS Bluetooth frameworks only"),
+    });
+
+    // Off macOS, the first check already says all there is to say
+    let on_macos = cfg!(target_os = "macos");
+    checks.push(
+        source_check("gatt", &capabilities.gatt, Status::Failed).remedy_if(
+            on_macos && !capabilities.gatt.enabled,
+            "Update macOS; BLE devices are read through Core Bluetooth",
+        ),
+    );
+
+    // The terminal app, not btmon, is what macOS asks for permission
+    let app = terminal.unwrap_or("your terminal app");
+    checks.push(match adapter.map(|adapter| adapter.state) {
+        _ if !capabilities.gatt.enabled => Check::new(
+            "bluetooth",
+            Status::Warning,
+            "not checked, Core Bluetooth is not used",
+        ),
+        None => Check::new(
+            "bluetooth",
+            Status::Warning,
+            "Core Bluetooth reported no state",
+        )
+        .remedy("Run `btmon doctor` again; the first check after login can time out"),
+        Some(AdapterState::PoweredOn) => Check::new("bluetooth", Status::Ok, "powered on"),
+        Some(AdapterState::PoweredOff) => Check::new("bluetooth", Status::Failed, "turned off")
+            .remedy("Turn Bluetooth on in Control Center or System Settings > Bluetooth"),
+        Some(AdapterState::Unauthorized) => Check::new(
+            "bluetooth",
+            Status::Failed,
+            format!("{app} is not allowed to use Bluetooth"),
+        )
+        .remedy(format!(
+            "Allow {app} in System Settings > Privacy & Security > Bluetooth, then restart it"
+        )),
+        Some(AdapterState::Unsupported) => Check::new(
+            "bluetooth",
+            Status::Failed,
+            "Bluetooth Low Energy is not supported on this Mac",
+        ),
+        Some(AdapterState::Resetting) => {
+            Check::new("bluetooth", Status::Warning, "Bluetooth is resetting")
+                .remedy("Wait a moment and run `btmon doctor` again")
+        }
+        Some(AdapterState::Unknown) => {
+            Check::new("bluetooth", Status::Warning, "Bluetooth state is unknown")
+                .remedy("Toggle Bluetooth off and on, or restart the Mac if this persists")
+        }
+    });
+
+    checks.push(
+        source_check("classic", &capabilities.classic, Status::Warning).remedy_if(
+            on_macos && !capabilities.classic.enabled,
+            classic_remedy(&capabilities.classic),
+        ),
+    );
+
+    checks
+}
+
+/// Check for a data source, `disabled` being the status when it is off
+fn source_check(name: &'static str, decision: &Decision, disabled: Status) -> Check {
+    let status = if decision.enabled {
+        Status::Ok
+    } else {
+        disabled
+    };
+    Check::new(name, status, decision.reason.clone())
+}
+
+/// Remedy for Classic devices (AirPods, Beats, headsets) not being read
+fn classic_remedy(decision: &Decision) -> String {
+    if decision.reason.contains("--no-private-api") {
+        "Run without --no-private-api to read AirPods and other Classic devices".to_string()
+    } else if decision.reason.contains("private-api feature") {
+        "Rebuild with the private-api feature to read AirPods and other Classic devices".to_string()
+    } else {
+        "This macOS release removed the private battery selectors; only BLE devices can be read"
+            .to_string()
+    }
+}
+
+/// Whether any check failed outright
+pub fn has_failures(checks: &[Check]) -> bool {
+    checks.iter().any(|check| check.status == Status::Failed)
+}
+
+/// Format the checks as text, one per line with its remedy indented below
+pub fn format_report(checks: &[Check]) -> String {
+    let mut out = String::new();
+    for check in checks {
+        let _ = writeln!(
+            out,
+            "[{:>4}] {:<9} {}",
+            check.status, check.name, check.detail
+        );
+        if let Some(remedy) = &check.remedy {
+            let _ = writeln!(out, "       {:<9} → {remedy}", "");
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capabilities::PrivateApi;
+
+    #[test]
+    #[cfg(target_os = "macos")]
+    fn test_run_checks() {
+        use crate::capabilities::OsVersion;
+
+        let capabilities =
+            Capabilities::decide(Some(OsVersion::new(14, 5, 0)), PrivateApi::Disabled);
+        let checks = run_checks(
+            &capabilities,
+            Some(AdapterState::Unauthorized.into()),
+            Some("iTerm.app"),
+        );
+        assert!(has_failures(&checks));
+        let bluetooth = checks.iter().find(|c| c.name == "bluetooth").unwrap();
+        assert_eq!(bluetooth.status, Status::Failed);
+        assert!(
+            bluetooth
+                .remedy
+                .as_ref()
+                .unwrap()
+                .contains("Allow iTerm.app")
+        );
+        let classic = checks.iter().find(|c| c.name == "classic").unwrap();
+        assert_eq!(classic.status, Status::Warning);
+
+        let checks = run_checks(&capabilities, Some(AdapterState::PoweredOn.into()), None);
+        assert!(!has_failures(&checks));
+        assert!(format_report(&checks).contains("[  ok] bluetooth powered on\n"));
+    }
+
+    #[test]
+    #[cfg(not(target_os = "macos"))]
+    fn test_run_checks() {
+        let capabilities = Capabilities::decide(None, PrivateApi::Available);
+        let checks = run_checks(&capabilities, None, None);
+        assert!(has_failures(&checks));
+        let report = format_report(&checks);
+        assert!(report.starts_with("[FAIL] macos     not running on macOS\n"));
+        assert!(report.contains("[warn] bluetooth not checked"));
+    }
+}
@@ -0,0 +1,400 @@
+//! Encryption at rest of the battery history
+//!
+//! With `encrypt = true` under `[history]`, every line written to the history
+//! files is encrypted with AES-256-CBC and authenticated with HMAC-SHA256
+//! (encrypt-then-MAC), both from CommonCrypto. An encrypted line is
+//! `enc1:` followed by the hex of the IV, the ciphertext and the tag, so the
+//! files stay line-oriented and new readings can still be appended.
+//!
+//! The 64-byte key (32 bytes for AES, 32 for HMAC) is generated on first use
+//! and kept in the login Keychain as the generic password `btmon`/`history`.
+//! Plain lines are still read, so readings recorded before encryption was
+//! turned on are kept, and are encrypted when the files are next rewritten.
+
+use std::fmt::{self, Write};
+use std::io;
+use tracing::info;
+
+/// Start of every encrypted line, naming the format
+pub const PREFIX: &str = "enc1:";
+
+/// Keychain service the key is stored under
+pub const KEYCHAIN_SERVICE: &str = "btmon";
+
+/// Keychain account the key is stored under
+pub const KEYCHAIN_ACCOUNT: &str = "history";
+
+/// Length of the AES IV
+const IV_LEN: usize = 16;
+
+/// Length of the AES block, which the ciphertext is a multiple of
+const BLOCK_LEN: usize = 16;
+
+/// Length of the HMAC-SHA256 tag
+const TAG_LEN: usize = 32;
+
+/// Length of each of the two halves of the key
+const KEY_LEN: usize = 32;
+
+/// Why a line couldn't be encrypted or decrypted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncryptionError {
+    /// The Keychain refused to store or return the key, with its status
+    Keychain(i32),
+    /// The Keychain item isn't a btmon key
+    InvalidKey,
+    /// CommonCrypto failed, with its status
+    Crypto(i32),
+    /// The line isn't encrypted, is truncated or was modified
+    Corrupt,
+    /// Not running on macOS
+    Unsupported,
+}
+
+impl fmt::Display for EncryptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Keychain(status) => {
+                write!(
+                    f,
+                    "cannot access the history key in the Keychain (status {status})"
+                )
+            }
+            Self::InvalidKey => f.write_str("the history key in the Keychain is invalid"),
+            Self::Crypto(status) => write!(f, "CommonCrypto failed (status {status})"),
+            Self::Corrupt => f.write_str("encrypted history line is corrupt"),
+            Self::Unsupported => f.write_str("history encryption is only supported on macOS"),
+        }
+    }
+}
+
+impl std::error::Error for EncryptionError {}
+
+impl From<EncryptionError> for io::Error {
+    fn from(error: EncryptionError) -> Self {
+        io::Error::other(error)
+    }
+}
+
+/// Key the history is encrypted with
+#[derive(Clone)]
+pub struct Key {
+    cipher: [u8; KEY_LEN],
+    mac: [u8; KEY_LEN],
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+impl Key {
+    /// Key from the bytes stored in the Keychain
+    fn from_bytes(bytes: &[u8]) -> Result<Self, EncryptionError> {
+        if bytes.len() != 2 * KEY_LEN {
+            return Err(EncryptionError::InvalidKey);
+        }
+        let (cipher, mac) = bytes.split_at(KEY_LEN);
+        Ok(Self {
+            cipher: cipher.try_into().map_err(|_| EncryptionError::InvalidKey)?,
+            mac: mac.try_into().map_err(|_| EncryptionError::InvalidKey)?,
+        })
+    }
+
+    /// The history key from the Keychain, generated and stored first if
+    /// there is none and `create` is set
+    pub fn load(create: bool) -> Result<Option<Self>, EncryptionError> {
+        if let Some(bytes) = sys::keychain_get()? {
+            return Self::from_bytes(&bytes).map(Some);
+        }
+        if !create {
+            return Ok(None);
+        }
+        let mut bytes = [0u8; 2 * KEY_LEN];
+        sys::random(&mut bytes)?;
+        sys::keychain_set(&bytes)?;
+        info!("Stored a new history key in the Keychain");
+        Self::from_bytes(&bytes).map(Some)
+    }
+}
+
+/// Whether a history line is encrypted
+pub fn is_sealed(line: &str) -> bool {
+    line.starts_with(PREFIX)
+}
+
+/// Encrypt a line of the history
+pub fn seal(key: &Key, plaintext: &[u8]) -> Result<String, EncryptionError> {
+    let mut iv = [0u8; IV_LEN];
+    sys::random(&mut iv)?;
+    let ciphertext = sys::crypt(sys::ENCRYPT, &key.cipher, &iv, plaintext)?;
+    let tag = sys::hmac(&key.mac, &[&iv, &ciphertext])?;
+    Ok(encode(&iv, &ciphertext, &tag))
+}
+
+/// Decrypt a line written by [`seal`], checking that it wasn't modified
+pub fn open(key: &Key, line: &str) -> Result<Vec<u8>, EncryptionError> {
+    let (iv, ciphertext, tag) = decode(line).ok_or(EncryptionError::Corrupt)?;
+    let expected = sys::hmac(&key.mac, &[&iv, &ciphertext])?;
+    if !constant_time_eq(&expected, &tag) {
+        return Err(EncryptionError::Corrupt);
+    }
+    sys::crypt(sys::DECRYPT, &key.cipher, &iv, &ciphertext)
+}
+
+/// An encrypted line: the prefix and the hex of the IV, ciphertext and tag
+fn encode(iv: &[u8], ciphertext: &[u8], tag: &[u8]) -> String {
+    let mut line =
+        String::with_capacity(PREFIX.len() + 2 * (iv.len() + ciphertext.len() + tag.len()));
+    line.push_str(PREFIX);
+    for byte in iv.iter().chain(ciphertext).chain(tag) {
+        let _ = write!(line, "{byte:02x}");
+    }
+    line
+}
+
+/// The IV, ciphertext and tag of an encrypted line, None if it is malformed
+fn decode(line: &str) -> Option<([u8; IV_LEN], Vec<u8>, [u8; TAG_LEN])> {
+    let hex = line.strip_prefix(PREFIX)?.as_bytes();
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    let bytes = hex
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    let ciphertext_len = bytes.len().checked_sub(IV_LEN + TAG_LEN)?;
+    if ciphertext_len == 0 || ciphertext_len % BLOCK_LEN != 0 {
+        return None;
+    }
+    let (iv, rest) = bytes.split_at(IV_LEN);
+    let (ciphertext, tag) = rest.split_at(ciphertext_len);
+    Some((
+        iv.try_into().ok()?,
+        ciphertext.to_vec(),
+        tag.try_into().ok()?,
+    ))
+}
+
+/// Compare two tags without leaking where they differ
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// CommonCrypto and the Keychain
+#[cfg(target_os = "macos")]
+mod sys {
+    use super::{BLOCK_LEN, EncryptionError, KEYCHAIN_ACCOUNT, KEYCHAIN_SERVICE, TAG_LEN};
+    use security_framework::passwords::{get_generic_password, set_generic_password};
+    use std::ffi::c_void;
+
+    /// `kCCEncrypt`
+    pub const ENCRYPT: u32 = 0;
+
+    /// `kCCDecrypt`
+    pub const DECRYPT: u32 = 1;
+
+    /// `kCCAlgorithmAES`
+    const ALGORITHM_AES: u32 = 0;
+
+    /// `kCCOptionPKCS7Padding`
+    const OPTION_PKCS7_PADDING: u32 = 1;
+
+    /// `kCCHmacAlgSHA256`
+    const HMAC_SHA256: u32 = 2;
+
+    /// `errSecItemNotFound`
+    const ITEM_NOT_FOUND: i32 = -25300;
+
+    // The parts of `<CommonCrypto/CommonCrypto.h>` and
+    // `<CommonCrypto/CommonRandom.h>` used here
+    unsafe extern "C" {
+        fn CCCrypt(
+            op: u32,
+            alg: u32,
+            options: u32,
+            key: *const c_void,
+            key_length: usize,
+            iv: *const c_void,
+            data_in: *const c_void,
+            data_in_length: usize,
+            data_out: *mut c_void,
+            data_out_available: usize,
+            data_out_moved: *mut usize,
+        ) -> i32;
+
+        fn CCHmac(
+            algorithm: u32,
+            key: *const c_void,
+            key_length: usize,
+            data: *const c_void,
+            data_length: usize,
+            mac_out: *mut c_void,
+        );
+
+        fn CCRandomGenerateBytes(bytes: *mut c_void, count: usize) -> i32;
+    }
+
+    /// The stored key, None if there is none
+    pub fn keychain_get() -> Result<Option<Vec<u8>>, EncryptionError> {
+        match get_generic_password(KEYCHAIN_SERVICE, KEYCHAIN_ACCOUNT) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.code() == ITEM_NOT_FOUND => Ok(None),
+            Err(e) => Err(EncryptionError::Keychain(e.code())),
+        }
+    }
+
+    /// Store the key
+    pub fn keychain_set(bytes: &[u8]) -> Result<(), EncryptionError> {
+        set_generic_password(KEYCHAIN_SERVICE, KEYCHAIN_ACCOUNT, bytes)
+            .map_err(|e| EncryptionError::Keychain(e.code()))
+    }
+
+    /// Fill `bytes` from the system's secure random number generator
+    pub fn random(bytes: &mut [u8]) -> Result<(), EncryptionError> {
+        // SAFETY: the buffer is valid for writes of its length
+        let status = unsafe { CCRandomGenerateBytes(bytes.as_mut_ptr().cast(), bytes.len()) };
+        match status {
+            0 => Ok(()),
+            status => Err(EncryptionError::Crypto(status)),
+        }
+    }
+
+    /// Encrypt or decrypt `data` with AES-256-CBC and PKCS#7 padding
+    pub fn crypt(op: u32, key: &[u8], iv: &[u8], data: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        let mut out = vec![0u8; data.len() + BLOCK_LEN];
+        let mut moved = 0;
+        // SAFETY: every pointer is valid for the length passed with it, and
+        // the output has room for the input plus a block of padding
+        let status = unsafe {
+            CCCrypt(
+                op,
+                ALGORITHM_AES,
+                OPTION_PKCS7_PADDING,
+                key.as_ptr().cast(),
+                key.len(),
+                iv.as_ptr().cast(),
+                data.as_ptr().cast(),
+                data.len(),
+                out.as_mut_ptr().cast(),
+                out.len(),
+                &mut moved,
+            )
+        };
+        match status {
+            0 => {
+                out.truncate(moved);
+                Ok(out)
+            }
+            // Bad padding of an authenticated line means the key is wrong
+            _ if op == DECRYPT => Err(EncryptionError::Corrupt),
+            status => Err(EncryptionError::Crypto(status)),
+        }
+    }
+
+    /// HMAC-SHA256 of the concatenated `parts`
+    pub fn hmac(key: &[u8], parts: &[&[u8]]) -> Result<[u8; TAG_LEN], EncryptionError> {
+        let data = parts.concat();
+        let mut tag = [0u8; TAG_LEN];
+        // SAFETY: every pointer is valid for the length passed with it, and
+        // the tag has room for a SHA-256 digest
+        unsafe {
+            CCHmac(
+                HMAC_SHA256,
+                key.as_ptr().cast(),
+                key.len(),
+                data.as_ptr().cast(),
+                data.len(),
+                tag.as_mut_ptr().cast(),
+            );
+        }
+        Ok(tag)
+    }
+}
+
+/// CommonCrypto and the Keychain, which only exist on macOS
+#[cfg(not(target_os = "macos"))]
+mod sys {
+    use super::{EncryptionError, TAG_LEN};
+
+    pub const ENCRYPT: u32 = 0;
+
+    pub const DECRYPT: u32 = 1;
+
+    pub fn keychain_get() -> Result<Option<Vec<u8>>, EncryptionError> {
+        Err(EncryptionError::Unsupported)
+    }
+
+    pub fn keychain_set(_bytes: &[u8]) -> Result<(), EncryptionError> {
+        Err(EncryptionError::Unsupported)
+    }
+
+    pub fn random(_bytes: &mut [u8]) -> Result<(), EncryptionError> {
+        Err(EncryptionError::Unsupported)
+    }
+
+    pub fn crypt(
+        _op: u32,
+        _key: &[u8],
+        _iv: &[u8],
+        _data: &[u8],
+    ) -> Result<Vec<u8>, EncryptionError> {
+        Err(EncryptionError::Unsupported)
+    }
+
+    pub fn hmac(_key: &[u8], _parts: &[&[u8]]) -> Result<[u8; TAG_LEN], EncryptionError> {
+        Err(EncryptionError::Unsupported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let iv = [1u8; IV_LEN];
+        let ciphertext = [2u8; 2 * BLOCK_LEN];
+        let tag = [3u8; TAG_LEN];
+        let line = encode(&iv, &ciphertext, &tag);
+        assert!(is_sealed(&line));
+        assert!(!is_sealed(r#"{"device":"Keyboard"}"#));
+        assert_eq!(decode(&line), Some((iv, ciphertext.to_vec(), tag)));
+
+        // Truncated, not whole blocks, or not hex
+        assert_eq!(decode(&line[..line.len() - 2]), None);
+        assert_eq!(decode(&encode(&iv, &[2u8; 5], &tag)), None);
+        assert_eq!(decode(&encode(&iv, &[], &tag)), None);
+        assert_eq!(decode(&line.replace("01", "zz")), None);
+
+        assert!(constant_time_eq(&tag, &[3u8; TAG_LEN]));
+        assert!(!constant_time_eq(&tag, &[4u8; TAG_LEN]));
+        assert!(Key::from_bytes(&[0u8; 2 * KEY_LEN]).is_ok());
+        assert_eq!(
+            Key::from_bytes(&[0u8; KEY_LEN]).unwrap_err(),
+            EncryptionError::InvalidKey
+        );
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn test_seal_open() {
+        let key = Key::from_bytes(&[7u8; 2 * KEY_LEN]).unwrap();
+        let line = seal(&key, b"{\"level\":80}").unwrap();
+        assert_eq!(open(&key, &line).unwrap(), b"{\"level\":80}");
+
+        // A different IV every time
+        assert_ne!(seal(&key, b"{\"level\":80}").unwrap(), line);
+        // Any change to the line fails the tag
+        let last = line.chars().last().unwrap();
+        let tampered = format!(
+            "{}{}",
+            &line[..line.len() - 1],
+            if last == '0' { '1' } else { '0' }
+        );
+        assert_eq!(open(&key, &tampered), Err(EncryptionError::Corrupt));
+        let other = Key::from_bytes(&[8u8; 2 * KEY_LEN]).unwrap();
+        assert_eq!(open(&other, &line), Err(EncryptionError::Corrupt));
+    }
+}
@@ -0,0 +1,105 @@
+//! Machine-readable errors for `--json`
+//!
+//! With `--json`, failures are reported on stderr as one JSON object per
+//! line, e.g. `{"code":"bluetooth_off","message":"Bluetooth is turned off"}`,
+//! so scripts can branch on `code` instead of matching the message. Codes
+//! are never renamed; messages may change.
+
+use crate::backend::AdapterState;
+use crate::explore::ExploreError;
+use serde::Serialize;
+
+/// Kind of failure
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// Bluetooth is turned off
+    BluetoothOff,
+    /// btmon is not allowed to use Bluetooth
+    PermissionDenied,
+    /// This Mac has no Bluetooth Low Energy, or the command needs macOS
+    Unsupported,
+    /// The adapter is resetting or in an unknown state
+    AdapterUnavailable,
+    /// No device matched, or none reports a battery
+    NotFound,
+    /// A wait ran out of time
+    Timeout,
+    /// The config file couldn't be read
+    Config,
+    /// A recorded session couldn't be read or written
+    Session,
+    /// The output couldn't be formatted or written
+    Output,
+    /// Options that don't go together, or a format the command can't print
+    Usage,
+    /// A Bluetooth operation on a device or the controller failed
+    Bluetooth,
+    /// A file btmon keeps (history, alert state, agent, log) couldn't be
+    /// read or written
+    Storage,
+    /// A socket couldn't be opened, or serving or publishing failed
+    Network,
+    /// Alerts or events couldn't be delivered
+    Delivery,
+}
+
+impl From<AdapterState> for ErrorCode {
+    fn from(state: AdapterState) -> Self {
+        match state {
+            AdapterState::PoweredOff => Self::BluetoothOff,
+            AdapterState::Unauthorized => Self::PermissionDenied,
+            AdapterState::Unsupported => Self::Unsupported,
+            AdapterState::Resetting | AdapterState::Unknown | AdapterState::PoweredOn => {
+                Self::AdapterUnavailable
+            }
+        }
+    }
+}
+
+impl From<&ExploreError> for ErrorCode {
+    fn from(error: &ExploreError) -> Self {
+        match error {
+            ExploreError::NotFound(_) | ExploreError::Ambiguous(_) => Self::NotFound,
+            ExploreError::Adapter(state) => Self::from(*state),
+            ExploreError::Failed(_) => Self::Bluetooth,
+            ExploreError::Timeout => Self::Timeout,
+            ExploreError::Unsupported => Self::Unsupported,
+        }
+    }
+}
+
+/// Format a failure as a one-line JSON object
+pub fn format_error(code: ErrorCode, message: &str) -> String {
+    serde_json::json!({ "code": code, "message": message }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_error() {
+        assert_eq!(
+            format_error(AdapterState::PoweredOff.into(), "Bluetooth is turned off"),
+            r#"{"code":"bluetooth_off","message":"Bluetooth is turned off"}"#
+        );
+        assert_eq!(
+            format_error(ErrorCode::Timeout, "timed out waiting for \"Keyboard\""),
+            r#"{"code":"timeout","message":"timed out waiting for \"Keyboard\""}"#
+        );
+        assert_eq!(
+            format_error(ErrorCode::Storage, "cannot determine cache directory"),
+            r#"{"code":"storage","message":"cannot determine cache directory"}"#
+        );
+        assert_eq!(
+            ErrorCode::from(AdapterState::Unauthorized),
+            ErrorCode::PermissionDenied
+        );
+        assert_eq!(
+            ErrorCode::from(&ExploreError::Adapter(AdapterState::PoweredOff)),
+            ErrorCode::BluetoothOff
+        );
+        assert_eq!(ErrorCode::from(&ExploreError::Timeout), ErrorCode::Timeout);
+    }
+}
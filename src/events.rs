@@ -0,0 +1,358 @@
+//! Battery events in CloudEvents format
+//!
+//! Each run is compared with the device list from the previous run (kept in
+//! the cache directory) to derive events: a device connected or disconnected,
+//! or a battery component crossed the warning or critical threshold. Events
+//! are wrapped in a CloudEvents 1.0 envelope so event-bus and serverless
+//! consumers can route them without knowing btmon's own format.
+
+use crate::alerts::{AlertConfig, Severity};
+use crate::compare::{self, Presence};
+use crate::device::{BatteryLevel, Component, Device, DeviceAddress, matches_filter};
+use crate::http;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// CloudEvents specification version
+pub const SPEC_VERSION: &str = "1.0";
+
+/// Media type for CloudEvents in structured content mode
+pub const CONTENT_TYPE: &str = "application/cloudevents+json";
+
+/// `[events]` configuration section
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EventsConfig {
+    /// HTTP endpoints every event is POSTed to
+    pub sinks: Vec<String>,
+    /// CloudEvents `source`, defaults to `urn:btmon:<hostname>`
+    pub source: Option<String>,
+}
+
+/// Kind of battery event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// A component moved to a different alert level
+    ThresholdCrossed,
+    /// A device appeared
+    Connected,
+    /// A device disappeared
+    Disconnected,
+}
+
+impl EventKind {
+    /// CloudEvents `type` attribute
+    pub fn event_type(self) -> &'static str {
+        match self {
+            Self::ThresholdCrossed => "dev.btmon.battery.threshold_crossed",
+            Self::Connected => "dev.btmon.device.connected",
+            Self::Disconnected => "dev.btmon.device.disconnected",
+        }
+    }
+}
+
+/// Payload of a battery event
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    /// Event kind
+    #[serde(skip)]
+    pub kind: EventKind,
+    /// Device name
+    pub device: String,
+    /// Bluetooth address
+    pub address: DeviceAddress,
+    /// Component that crossed a threshold
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub component: Option<Component>,
+    /// Level before the change
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous: Option<BatteryLevel>,
+    /// Level after the change
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<BatteryLevel>,
+    /// Alert level after the change, absent when back above the thresholds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub severity: Option<Severity>,
+}
+
+/// An event in a CloudEvents 1.0 envelope
+#[derive(Debug, Clone, Serialize)]
+pub struct CloudEvent {
+    /// Specification version
+    pub specversion: &'static str,
+    /// Event ID, unique within `source`
+    pub id: String,
+    /// Event producer
+    pub source: String,
+    /// Event type, e.g. `dev.btmon.device.connected`
+    #[serde(rename = "type")]
+    pub event_type: &'static str,
+    /// Device the event is about
+    pub subject: String,
+    /// Time of the observation
+    pub time: String,
+    /// Media type of `data`
+    pub datacontenttype: &'static str,
+    /// Event payload
+    pub data: Event,
+}
+
+impl CloudEvent {
+    /// Wrap events observed at `now`, numbering their IDs from the timestamp
+    pub fn wrap(events: Vec<Event>, source: &str, now: DateTime<Utc>) -> Vec<Self> {
+        let time = now.to_rfc3339_opts(SecondsFormat::Millis, true);
+        events
+            .into_iter()
+            .enumerate()
+            .map(|(i, event)| Self {
+                specversion: SPEC_VERSION,
+                id: format!("{}-{i}", now.timestamp_millis()),
+                source: source.to_string(),
+                event_type: event.kind.event_type(),
+                subject: event.device.clone(),
+                time: time.clone(),
+                datacontenttype: "application/json",
+                data: event,
+            })
+            .collect()
+    }
+}
+
+/// Default CloudEvents source for this machine
+pub fn default_source() -> String {
+    let host = hostname::get()
+        .map(|h| h.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| "localhost".to_string());
+    format!("urn:btmon:{host}")
+}
+
+/// Derive events from the previous and the current device list
+pub fn detect(before: &[Device], after: &[Device], config: &AlertConfig) -> Vec<Event> {
+    let mut events = Vec::new();
+
+    for comparison in compare::compare(before, after) {
+        let device = match comparison.presence {
+            Presence::Disappeared => compare::find_device(before, &comparison.name),
+            _ => compare::find_device(after, &comparison.name),
+        };
+        let Some(device) = device else {
+            continue;
+        };
+        let event = |kind| Event {
+            kind,
+            device: device.name.clone(),
+            address: device.address.clone(),
+            component: None,
+            previous: None,
+            level: None,
+            severity: None,
+        };
+
+        match comparison.presence {
+            Presence::Appeared => events.push(event(EventKind::Connected)),
+            Presence::Disappeared => events.push(event(EventKind::Disconnected)),
+            Presence::Both => {
+                for delta in comparison.components {
+                    let (Some(previous), Some(level)) = (delta.before, delta.after) else {
+                        continue;
+                    };
+                    let severity = config.severity(level);
+                    if config.severity(previous) == severity {
+                        continue;
+                    }
+                    events.push(Event {
+                        component: Some(delta.component),
+                        previous: Some(previous),
+                        level: Some(level),
+                        severity,
+                        ..event(EventKind::ThresholdCrossed)
+                    });
+                }
+            }
+        }
+    }
+
+    events
+}
+
+/// Load the device list saved by the previous run, empty if there is none
+pub fn load_snapshot(path: &Path) -> io::Result<Vec<Device>> {
+    match compare::load_devices(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        result => result,
+    }
+}
+
+/// Split the previous device list into the devices covered by a name filter
+/// and the ones that weren't queried this run
+pub fn partition(previous: Vec<Device>, name_filter: Option<&str>) -> (Vec<Device>, Vec<Device>) {
+    let filter_lower = name_filter.map(|f| f.to_lowercase());
+    previous
+        .into_iter()
+        .partition(|d| matches_filter(&d.name, filter_lower.as_deref()))
+}
+
+/// Save the device list for the next run, carrying over the devices that
+/// weren't queried so a filtered run doesn't forget them
+pub fn save_snapshot(path: &Path, unqueried: &[Device], current: &[Device]) -> io::Result<()> {
+    let devices: Vec<&Device> = unqueried.iter().chain(current).collect();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(
+        path,
+        format!("{}\n", serde_json::to_string_pretty(&devices)?),
+    )
+}
+
+/// POSTs events to HTTP sinks in CloudEvents structured mode
+pub struct HttpSink {
+    agent: ureq::Agent,
+    urls: Vec<String>,
+}
+
+impl HttpSink {
+    /// Create a sink for the given endpoint URLs
+    pub fn new(urls: Vec<String>) -> Result<Self, native_tls::Error> {
+        Ok(Self {
+            agent: http::agent()?,
+            urls,
+        })
+    }
+
+    /// Send an event to every endpoint, returning the number that failed
+    pub fn send(&self, event: &CloudEvent) -> usize {
+        let mut failures = 0;
+        for url in &self.urls {
+            let result = serde_json::to_string(event)
+                .map_err(|e| e.to_string())
+                .and_then(|body| {
+                    self.agent
+                        .post(url)
+                        .set("Content-Type", CONTENT_TYPE)
+                        .send_string(&body)
+                        .map_err(|e| e.to_string())
+                });
+            if let Err(e) = result {
+                tracing::warn!(url = %url, id = %event.id, error = %e, "Failed to send event");
+                failures += 1;
+            }
+        }
+        failures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_detect_events() {
+        let config = AlertConfig::default();
+        let before = vec![
+            Device::test_ble("Keyboard", 25),
+            Device::test_ble("Mouse", 50),
+        ];
+        let after = vec![
+            Device::test_ble("Keyboard", 18),
+            Device::test_ble("Trackpad", 90),
+        ];
+
+        let events = detect(&before, &after, &config);
+        let kinds: Vec<_> = events.iter().map(|e| (e.kind, e.device.as_str())).collect();
+        assert_eq!(
+            kinds,
+            [
+                (EventKind::ThresholdCrossed, "Keyboard"),
+                (EventKind::Connected, "Trackpad"),
+                (EventKind::Disconnected, "Mouse"),
+            ]
+        );
+        assert_eq!(events[0].severity, Some(Severity::Warning));
+
+        // Same level band, no event
+        assert!(
+            detect(
+                &after,
+                &[
+                    Device::test_ble("Keyboard", 15),
+                    Device::test_ble("Trackpad", 90)
+                ],
+                &config
+            )
+            .is_empty()
+        );
+
+        // Recovery crosses back above the threshold
+        let events = detect(
+            &after,
+            &[
+                Device::test_ble("Keyboard", 80),
+                Device::test_ble("Trackpad", 90),
+            ],
+            &config,
+        );
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].severity, None);
+    }
+
+    #[test]
+    fn test_cloud_event_envelope() {
+        let now = Utc.with_ymd_and_hms(2026, 1, 2, 3, 4, 5).unwrap();
+        let events = detect(
+            &[],
+            &[Device::test_ble("Keyboard", 76)],
+            &AlertConfig::default(),
+        );
+        let wrapped = CloudEvent::wrap(events, "urn:btmon:test", now);
+
+        let json = serde_json::to_value(&wrapped[0]).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "specversion": "1.0",
+                "id": "1767323045000-0",
+                "source": "urn:btmon:test",
+                "type": "dev.btmon.device.connected",
+                "subject": "Keyboard",
+                "time": "2026-01-02T03:04:05.000Z",
+                "datacontenttype": "application/json",
+                "data": { "device": "Keyboard", "address": "BLE" }
+            })
+        );
+    }
+
+    #[test]
+    fn test_filtered_run_keeps_other_devices() {
+        let dir = std::env::temp_dir().join(format!("btmon-events-{}", std::process::id()));
+        let path = dir.join("devices.json");
+
+        let previous = vec![
+            Device::test_ble("Keyboard", 76),
+            Device::test_ble("Mouse", 50),
+        ];
+        let (queried, unqueried) = partition(previous, Some("MOUSE"));
+        assert_eq!(queried.len(), 1);
+        assert!(
+            detect(
+                &queried,
+                &[Device::test_ble("Mouse", 45)],
+                &AlertConfig::default()
+            )
+            .is_empty()
+        );
+
+        save_snapshot(&path, &unqueried, &[Device::test_ble("Mouse", 45)]).unwrap();
+
+        let saved = load_snapshot(&path).unwrap();
+        let names: Vec<_> = saved.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, ["Keyboard", "Mouse"]);
+        assert_eq!(saved[1].battery_level, BatteryLevel::new(45));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,256 @@
+//! GATT explorer for `btmon gatt`
+//!
+//! Devices that don't use the standard Battery Service hide their battery
+//! somewhere else. The explorer connects to a peripheral, discovers all of
+//! its services and characteristics, and reads every readable value, so the
+//! dump shows where to look.
+
+use crate::backend::AdapterState;
+use crate::characteristic::decode_known;
+use crate::output::adapter_message;
+use serde::{Serialize, Serializer};
+use std::fmt::{self, Write};
+
+/// A characteristic and the value it returned
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Characteristic {
+    pub uuid: String,
+    pub properties: Vec<&'static str>,
+    /// Value read, None if the characteristic isn't readable or failed
+    #[serde(serialize_with = "serialize_hex")]
+    pub value: Option<Vec<u8>>,
+    /// Why reading the value failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Characteristic {
+    /// The value decoded, for well-known characteristics
+    pub fn decoded(&self) -> Option<String> {
+        decode_known(&self.uuid, self.value.as_deref()?)
+    }
+}
+
+/// A service with its characteristics
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Service {
+    pub uuid: String,
+    pub characteristics: Vec<Characteristic>,
+}
+
+/// Everything discovered on a peripheral
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Peripheral {
+    pub name: String,
+    /// Core Bluetooth identifier, stable on this Mac
+    pub identifier: String,
+    pub services: Vec<Service>,
+}
+
+/// Why a peripheral couldn't be explored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExploreError {
+    /// No connected peripheral matches the name
+    NotFound(String),
+    /// More than one connected peripheral matches the name
+    Ambiguous(Vec<String>),
+    /// Bluetooth can't be used
+    Adapter(AdapterState),
+    /// Connecting or discovering the services failed
+    Failed(String),
+    /// The peripheral stopped responding
+    Timeout,
+    /// Not running on macOS
+    Unsupported,
+}
+
+impl fmt::Display for ExploreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(name) => write!(f, "no connected BLE peripheral matching '{name}'"),
+            Self::Ambiguous(names) => {
+                write!(f, "several peripherals match: {}", names.join(", "))
+            }
+            Self::Adapter(state) => f.write_str(adapter_message(&(*state).into())),
+            Self::Failed(reason) => f.write_str(reason),
+            Self::Timeout => f.write_str("the peripheral stopped responding"),
+            Self::Unsupported => f.write_str("the GATT explorer is only supported on macOS"),
+        }
+    }
+}
+
+impl std::error::Error for ExploreError {}
+
+/// Names of the characteristic property bits, e.g. `read` and `notify`
+pub fn property_names(bits: usize) -> Vec<&'static str> {
+    [
+        (0x01, "broadcast"),
+        (0x02, "read"),
+        (0x04, "write-without-response"),
+        (0x08, "write"),
+        (0x10, "notify"),
+        (0x20, "indicate"),
+        (0x40, "signed-write"),
+        (0x80, "extended"),
+    ]
+    .into_iter()
+    .filter(|(bit, _)| bits & bit != 0)
+    .map(|(_, name)| name)
+    .collect()
+}
+
+/// Name of a well-known service
+fn service_name(uuid: &str) -> Option<&'static str> {
+    Some(match uuid.to_uppercase().as_str() {
+        "1800" => "Generic Access",
+        "1801" => "Generic Attribute",
+        "180A" => "Device Information",
+        "180F" => "Battery",
+        "1812" => "Human Interface Device",
+        "FE59" => "Nordic DFU",
+        _ => return None,
+    })
+}
+
+/// Name of a well-known characteristic
+fn characteristic_name(uuid: &str) -> Option<&'static str> {
+    Some(match uuid.to_uppercase().as_str() {
+        "2A00" => "Device Name",
+        "2A01" => "Appearance",
+        "2A19" => "Battery Level",
+        "2A24" => "Model Number",
+        "2A25" => "Serial Number",
+        "2A26" => "Firmware Revision",
+        "2A27" => "Hardware Revision",
+        "2A28" => "Software Revision",
+        "2A29" => "Manufacturer Name",
+        "2A4D" => "Report",
+        "2A50" => "PnP ID",
+        _ => return None,
+    })
+}
+
+/// Bytes as space-separated hex
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn serialize_hex<S: Serializer>(value: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(bytes) => serializer.serialize_str(&hex(bytes)),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// A UUID with its name if it is well-known
+fn labelled(uuid: &str, name: Option<&str>) -> String {
+    match name {
+        Some(name) => format!("{uuid} ({name})"),
+        None => uuid.to_string(),
+    }
+}
+
+/// Format the peripheral as a tree of services and characteristics
+pub fn format_peripheral(peripheral: &Peripheral) -> String {
+    let mut out = format!("{} [{}]\n", peripheral.name, peripheral.identifier);
+    for service in &peripheral.services {
+        let _ = writeln!(
+            out,
+            "  Service {}",
+            labelled(&service.uuid, service_name(&service.uuid))
+        );
+        for characteristic in &service.characteristics {
+            let _ = write!(
+                out,
+                "    {} [{}]",
+                labelled(
+                    &characteristic.uuid,
+                    characteristic_name(&characteristic.uuid)
+                ),
+                characteristic.properties.join(", ")
+            );
+            if let Some(value) = &characteristic.value {
+                let _ = write!(out, " = {}", hex(value));
+                if let Some(decoded) = characteristic.decoded() {
+                    let _ = write!(out, " ({decoded})");
+                }
+            }
+            if let Some(error) = &characteristic.error {
+                let _ = write!(out, " ! {error}");
+            }
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Connect to the connected BLE peripheral whose name matches `device` and
+/// read everything it exposes
+#[cfg(target_os = "macos")]
+pub fn explore(device: &str) -> Result<Peripheral, ExploreError> {
+    crate::gatt_explorer::explore(device)
+}
+
+/// Connect to the connected BLE peripheral whose name matches `device` and
+/// read everything it exposes
+#[cfg(not(target_os = "macos"))]
+pub fn explore(_device: &str) -> Result<Peripheral, ExploreError> {
+    Err(ExploreError::Unsupported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_peripheral() {
+        let peripheral = Peripheral {
+            name: "MX Master 3".to_string(),
+            identifier: "6E1F2A3B-0000-4000-8000-00805F9B34FB".to_string(),
+            services: vec![
+                Service {
+                    uuid: "180F".to_string(),
+                    characteristics: vec![Characteristic {
+                        uuid: "2A19".to_string(),
+                        properties: property_names(0x12),
+                        value: Some(vec![0x4c]),
+                        error: None,
+                    }],
+                },
+                Service {
+                    uuid: "00010000-0000-1000-8000-011F2000046D".to_string(),
+                    characteristics: vec![Characteristic {
+                        uuid: "00010001-0000-1000-8000-011F2000046D".to_string(),
+                        properties: property_names(0x0a),
+                        value: None,
+                        error: Some("Encryption is insufficient.".to_string()),
+                    }],
+                },
+            ],
+        };
+        let text = format_peripheral(&peripheral);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[1], "  Service 180F (Battery)");
+        assert_eq!(
+            lines[2],
+            "    2A19 (Battery Level) [read, notify] = 4c (76%)"
+        );
+        assert_eq!(
+            lines[4],
+            "    00010001-0000-1000-8000-011F2000046D [read, write] ! Encryption is insufficient."
+        );
+
+        let json = serde_json::to_value(&peripheral).unwrap();
+        assert_eq!(json["services"][0]["characteristics"][0]["value"], "4c");
+        assert!(json["services"][1]["characteristics"][0]["value"].is_null());
+
+        assert_eq!(
+            ExploreError::Adapter(AdapterState::PoweredOff).to_string(),
+            "Bluetooth is turned off"
+        );
+    }
+}
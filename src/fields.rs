@@ -0,0 +1,215 @@
+//! Device fields and their selection
+//!
+//! [`FIELDS`] is the one list of device fields, used by `--template`,
+//! `--fields` and the `fields=` parameter of the HTTP API.
+//!
+//! `--fields name,address,battery_left` limits the JSON, NDJSON, YAML and
+//! CSV output to those keys, in that order. Every selected key is present
+//! for every device, null (or an empty CSV field) when the device doesn't
+//! have it, so the shape of the output only changes when the selection
+//! does.
+
+use crate::backend::AdapterStatus;
+use crate::csvlog::escape_field;
+use crate::device::Device;
+use crate::output::{self, Format, device_icon, device_kind, device_source};
+use crate::sequence::Sequences;
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value, json};
+use std::fmt;
+use std::str::FromStr;
+
+/// Device fields: the keys of the JSON output plus `type`, `source` and
+/// `icon`
+pub const FIELDS: [&str; 14] = [
+    "name",
+    "address",
+    "vendor",
+    "battery_level",
+    "battery_left",
+    "battery_right",
+    "battery_case",
+    "effective",
+    "read_at",
+    "age_seconds",
+    "stale",
+    "type",
+    "source",
+    "icon",
+];
+
+/// Value of one of the [`FIELDS`] for a device, null if it doesn't have it.
+/// `json` is the device serialized, as most fields are its keys
+pub fn field_value(device: &Device, json: &Value, field: &str) -> Value {
+    match field {
+        "type" => device_kind(device).into(),
+        "source" => device_source(device).into(),
+        "icon" => device_icon(device).into(),
+        field => json[field].clone(),
+    }
+}
+
+/// A parsed field list, from `--fields` or the `fields=` query parameter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fields(Vec<&'static str>);
+
+/// Why a field list couldn't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldsError {
+    /// A name is not one of the fields
+    Unknown(String),
+    /// No field was given
+    Empty,
+}
+
+impl fmt::Display for FieldsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown(name) => write!(
+                f,
+                "unknown field '{name}', expected one of: {}",
+                FIELDS.join(", ")
+            ),
+            Self::Empty => f.write_str("no fields given"),
+        }
+    }
+}
+
+impl std::error::Error for FieldsError {}
+
+impl FromStr for Fields {
+    type Err = FieldsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = Vec::new();
+        for name in s.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            let field = FIELDS
+                .into_iter()
+                .find(|field| *field == name)
+                .ok_or_else(|| FieldsError::Unknown(name.to_string()))?;
+            if !fields.contains(&field) {
+                fields.push(field);
+            }
+        }
+        if fields.is_empty() {
+            return Err(FieldsError::Empty);
+        }
+        Ok(Self(fields))
+    }
+}
+
+impl Fields {
+    /// Whether `--fields` applies to an output format
+    pub fn supports(format: Format) -> bool {
+        matches!(
+            format,
+            Format::Json | Format::Ndjson | Format::Yaml | Format::Csv
+        )
+    }
+
+    /// The selected fields of a device, in the order given
+    pub fn select(&self, device: &Device) -> Map<String, Value> {
+        let json = serde_json::to_value(device).unwrap_or(Value::Null);
+        self.0
+            .iter()
+            .map(|field| (field.to_string(), field_value(device, &json, field)))
+            .collect()
+    }
+
+    /// Format the devices with only the selected fields. JSON and YAML get
+    /// the adapter status like the full output; NDJSON keeps its timestamp,
+    /// and the session and `seq` of a stream
+    pub fn format(
+        &self,
+        format: Format,
+        devices: &[Device],
+        adapter: Option<&AdapterStatus>,
+        now: DateTime<Utc>,
+        sequences: Option<&Sequences>,
+    ) -> serde_json::Result<String> {
+        let selected: Vec<Map<String, Value>> =
+            devices.iter().map(|device| self.select(device)).collect();
+        let document = match adapter {
+            Some(adapter) => json!({ "adapter": adapter, "devices": selected }),
+            None => json!(selected),
+        };
+        match format {
+            Format::Yaml => crate::yaml::to_string(&document),
+            Format::Ndjson => {
+                let mut lines = Vec::new();
+                for (device, fields) in devices.iter().zip(selected) {
+                    let mut object = output::ndjson_record(now, sequences, device);
+                    object.extend(fields);
+                    lines.push(serde_json::to_string(&object)?);
+                }
+                Ok(lines.join("\n"))
+            }
+            Format::Csv => Ok(self.format_csv(&selected)),
+            _ => serde_json::to_string_pretty(&document),
+        }
+    }
+
+    /// CSV with a column per selected field
+    fn format_csv(&self, selected: &[Map<String, Value>]) -> String {
+        let mut lines = vec![self.0.join(",")];
+        for fields in selected {
+            let row: Vec<String> = fields
+                .values()
+                .map(|value| match value {
+                    Value::String(s) => escape_field(s),
+                    Value::Null => String::new(),
+                    value => escape_field(&value.to_string()),
+                })
+                .collect();
+            lines.push(row.join(","));
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::BatteryLevel;
+
+    #[test]
+    fn test_format() {
+        let airpods = Device {
+            battery_left: BatteryLevel::new(80),
+            battery_right: BatteryLevel::new(90),
+            ..Device::test_classic("AirPods, Pro", "a4-c3-37-12-34-56")
+        };
+        let fields: Fields = "name, battery_left,battery_case,type".parse().unwrap();
+        let now = Utc::now();
+        let devices = [airpods];
+
+        let json = fields
+            .format(Format::Json, &devices, None, now, None)
+            .unwrap();
+        let parsed: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            parsed,
+            json!([{
+                "name": "AirPods, Pro",
+                "battery_left": 80,
+                "battery_case": null,
+                "type": "headphones"
+            }])
+        );
+        assert_eq!(
+            fields
+                .format(Format::Csv, &devices, None, now, None)
+                .unwrap(),
+            "name,battery_left,battery_case,type\n\"AirPods, Pro\",80,,headphones"
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            "name,level".parse::<Fields>(),
+            Err(FieldsError::Unknown("level".to_string()))
+        );
+        assert_eq!(" , ".parse::<Fields>(), Err(FieldsError::Empty));
+    }
+}
@@ -2,19 +2,33 @@
 //!
 //! This module handles reading battery levels from BLE devices that expose
 //! the standard GATT Battery Service (UUID: 0x180F).
+//!
+//! The central manager delivers its callbacks on a private serial dispatch
+//! queue rather than the main run loop, so a scan works from any thread. The
+//! delegate state is behind a mutex, and the scanning thread is woken through
+//! a channel whenever a level is read or a peripheral finishes, so levels are
+//! handed on as they arrive.
+//!
+//! A peripheral may have several Battery Service instances, e.g. one per half
+//! of a split keyboard. Its connection is only released once every instance
+//! was read or failed.
 
+use crate::backend::AdapterState;
+use crate::characteristic::decode_battery_level;
+use dispatch2::{DispatchQueue, DispatchQueueAttr};
 use objc2::rc::Retained;
-use objc2::runtime::{AnyObject, ProtocolObject};
-use objc2::{AllocAnyThread, DefinedClass, define_class, msg_send};
+use objc2::runtime::ProtocolObject;
+use objc2::{AllocAnyThread, DefinedClass, Message, define_class, msg_send};
 use objc2_core_bluetooth::{
     CBCentralManager, CBCentralManagerDelegate, CBCharacteristic, CBManagerState, CBPeripheral,
     CBPeripheralDelegate, CBService, CBUUID,
 };
-use objc2_foundation::{NSArray, NSError, NSObject, NSObjectProtocol, NSString};
-use std::cell::RefCell;
-use std::collections::HashMap;
+use objc2_foundation::{NSArray, NSError, NSObject, NSObjectProtocol, NSString, NSUUID};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Mutex, MutexGuard, PoisonError};
 use std::time::{Duration, Instant};
-use tracing::{debug, trace, warn};
+use tracing::{Span, debug, info_span, trace, warn};
 
 /// Battery Service UUID (0x180F)
 const BATTERY_SERVICE_UUID: &str = "180F";
@@ -25,21 +39,69 @@ const BATTERY_LEVEL_UUID: &str = "2A19";
 /// Timeout for GATT discovery operations
 const GATT_DISCOVERY_TIMEOUT: Duration = Duration::from_secs(2);
 
-/// Run loop iteration interval
-const RUN_LOOP_INTERVAL: f64 = 0.1;
+/// Timeout when connecting to idle peripherals, which may need to wake up
+const ON_DEMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Most peripherals read at the same time
+///
+/// Core Bluetooth fails connections beyond a system-wide limit, so with many
+/// peripherals the rest wait in a queue until a slot frees up.
+const MAX_CONNECTIONS: usize = 4;
+
+/// Label of the dispatch queue Core Bluetooth calls the delegate on
+const QUEUE_LABEL: &str = "btmon.gatt";
+
+/// Phase of reading a peripheral, traced as its own span
+#[derive(Clone, Copy)]
+enum Phase {
+    Connect,
+    DiscoverServices,
+    DiscoverCharacteristics,
+    Read,
+}
+
+/// Tracing spans of a peripheral that is being read.
+///
+/// The delegate callbacks arrive asynchronously on the dispatch queue, so the
+/// spans are kept here and closed by dropping them when the phase ends.
+struct PeripheralSpans {
+    peripheral: Span,
+    phase: Span,
+}
 
 /// Internal state for the delegate
 #[derive(Default)]
 struct DelegateState {
-    battery_levels: HashMap<String, u8>,
-    peripherals_to_read: Vec<Retained<CBPeripheral>>,
-    pending_reads: usize,
+    adapter: Option<AdapterState>,
+    /// Levels read so far, in the order they arrived
+    levels: Vec<GattLevel>,
+    /// Manager that connections are made with
+    central: Option<Retained<CBCentralManager>>,
+    /// Peripherals waiting for a connection slot
+    queue: VecDeque<Retained<CBPeripheral>>,
+    /// Peripherals being read
+    active: Vec<Retained<CBPeripheral>>,
+    /// Identifiers of bonded peripherals to connect to if they're idle
+    connect_if_needed: Vec<String>,
+    /// Spans of the peripherals being read, by identifier
+    spans: HashMap<String, PeripheralSpans>,
+    /// Characteristic discoveries and reads each active peripheral still
+    /// waits for, by identifier
+    operations: HashMap<String, usize>,
+    /// Peripherals that haven't finished yet
+    pending: usize,
     done: bool,
 }
 
 /// Ivars for the Objective-C delegate class
 struct DelegateIvars {
-    state: RefCell<DelegateState>,
+    state: Mutex<DelegateState>,
+    /// Sent every level as it's read, and signalled with None whenever a
+    /// peripheral finishes or the scan is done
+    progress: Sender<Option<GattLevel>>,
+    /// Span of the scan, the parent of the peripheral spans opened on the
+    /// dispatch queue
+    scan: Span,
 }
 
 define_class!(
@@ -60,6 +122,7 @@ define_class!(
             // SAFETY: peripheral.name() is a standard Core Bluetooth API.
             let name = unsafe { peripheral.name() };
             debug!(name = ?name, "Connected to peripheral");
+            self.enter_phase(peripheral, Phase::DiscoverServices);
 
             // Now discover services
             // SAFETY: discoverServices is a standard Core Bluetooth API.
@@ -81,7 +144,7 @@ define_class!(
             // SAFETY: peripheral.name() is a standard Core Bluetooth API.
             let name = unsafe { peripheral.name() };
             warn!(name = ?name, error = ?error, "Failed to connect to peripheral");
-            self.decrement_pending();
+            self.complete(peripheral);
         }
 
         #[unsafe(method(centralManagerDidUpdateState:))]
@@ -89,13 +152,16 @@ define_class!(
             // SAFETY: central.state() is a standard Core Bluetooth API.
             let state = unsafe { central.state() };
             debug!(state = ?state, "Central manager state updated");
+            self.state().adapter = Some(adapter_state(state));
 
             if state == CBManagerState::PoweredOn {
                 self.handle_powered_on(central);
-            } else if state == CBManagerState::Unauthorized || state == CBManagerState::Unsupported
+            } else if state == CBManagerState::Unauthorized
+                || state == CBManagerState::Unsupported
+                || state == CBManagerState::PoweredOff
             {
                 warn!(state = ?state, "Bluetooth not available");
-                self.ivars().state.borrow_mut().done = true;
+                self.finish();
             }
         }
     }
@@ -109,28 +175,32 @@ define_class!(
         ) {
             if let Some(e) = error {
                 warn!(error = ?e, "Error discovering services");
-                self.decrement_pending();
+                self.complete(peripheral);
                 return;
             }
 
+            self.enter_phase(peripheral, Phase::DiscoverCharacteristics);
+
             // SAFETY: peripheral.services() is a standard Core Bluetooth API.
             unsafe {
-                if let Some(services) = peripheral.services() {
-                    for i in 0..services.count() {
-                        let service: &CBService = &services.objectAtIndex(i);
-                        let uuid = service.UUID();
-                        trace!(uuid = ?uuid, "Found service");
-
-                        // Discover battery level characteristic
-                        peripheral.discoverCharacteristics_forService(
-                            Some(&NSArray::from_retained_slice(&[CBUUID::UUIDWithString(
-                                &NSString::from_str(BATTERY_LEVEL_UUID),
-                            )])),
-                            service,
-                        );
+                match peripheral.services() {
+                    Some(services) if services.count() > 0 => {
+                        self.start_operations(peripheral, services.count());
+                        for i in 0..services.count() {
+                            let service: &CBService = &services.objectAtIndex(i);
+                            let uuid = service.UUID();
+                            trace!(uuid = ?uuid, "Found service");
+
+                            // Discover battery level characteristic
+                            peripheral.discoverCharacteristics_forService(
+                                Some(&NSArray::from_retained_slice(&[CBUUID::UUIDWithString(
+                                    &NSString::from_str(BATTERY_LEVEL_UUID),
+                                )])),
+                                service,
+                            );
+                        }
                     }
-                } else {
-                    self.decrement_pending();
+                    _ => self.complete(peripheral),
                 }
             }
         }
@@ -143,14 +213,18 @@ define_class!(
             error: Option<&NSError>,
         ) {
             if let Some(e) = error {
+                // Other instances of the service may still be read
                 warn!(error = ?e, "Error discovering characteristics");
-                self.decrement_pending();
+                self.finish_operation(peripheral);
                 return;
             }
 
+            self.enter_phase(peripheral, Phase::Read);
+
             // SAFETY: service.characteristics() is a standard Core Bluetooth API.
             unsafe {
                 if let Some(characteristics) = service.characteristics() {
+                    self.start_operations(peripheral, characteristics.count());
                     for i in 0..characteristics.count() {
                         let characteristic: &CBCharacteristic = &characteristics.objectAtIndex(i);
                         trace!(uuid = ?characteristic.UUID(), "Found characteristic");
@@ -158,10 +232,10 @@ define_class!(
                         // Read the battery level
                         peripheral.readValueForCharacteristic(characteristic);
                     }
-                } else {
-                    self.decrement_pending();
                 }
             }
+            // The discovery itself is done
+            self.finish_operation(peripheral);
         }
 
         #[unsafe(method(peripheral:didUpdateValueForCharacteristic:error:))]
@@ -173,71 +247,198 @@ define_class!(
         ) {
             if let Some(e) = error {
                 warn!(error = ?e, "Error reading characteristic");
-                self.decrement_pending();
+                self.finish_operation(peripheral);
                 return;
             }
 
             // SAFETY: characteristic.value() is a standard Core Bluetooth API.
             unsafe {
-                if let Some(value) = characteristic.value() {
-                    let len = value.length();
-                    if len > 0 {
-                        // Read the first byte as battery level
-                        let mut battery_level: u8 = 0;
-                        // SAFETY: getBytes:length: copies bytes from NSData to our buffer.
-                        // We ensure the buffer is valid and the length is correct.
-                        let _: () = msg_send![&value, getBytes: &mut battery_level as *mut u8, length: 1usize];
-
-                        let name = peripheral
-                            .name()
-                            .map(|n| n.to_string())
-                            .unwrap_or_else(|| "Unknown".to_string());
-
-                        debug!(name = %name, battery_level = battery_level, "Read battery level");
-
-                        self.ivars()
-                            .state
-                            .borrow_mut()
-                            .battery_levels
-                            .insert(name, battery_level);
-                    }
+                if let Some(value) = characteristic.value()
+                    && let Some(battery_level) = decode_battery_level(&value.to_vec())
+                {
+                    let level = GattLevel {
+                        identifier: peripheral_identifier(peripheral),
+                        name: peripheral_name(peripheral),
+                        level: battery_level,
+                    };
+                    debug!(name = %level.name, battery_level, "Read battery level");
+
+                    self.state().levels.push(level.clone());
+                    let _ = self.ivars().progress.send(Some(level));
                 }
             }
 
-            self.decrement_pending();
+            self.finish_operation(peripheral);
         }
     }
 );
 
+// SAFETY: The delegate's state is only accessed through its mutex, and the
+// Core Bluetooth objects it holds are only messaged from the manager's queue
+// (see `get_gatt_battery_devices`).
+unsafe impl Send for CentralDelegate {}
+// SAFETY: As above.
+unsafe impl Sync for CentralDelegate {}
+
 impl CentralDelegate {
-    /// Create a new CentralDelegate instance
-    fn new() -> Retained<Self> {
+    /// Create a new CentralDelegate instance, with the receiving end of its
+    /// progress channel
+    fn new(connect_if_needed: &[String]) -> (Retained<Self>, Receiver<Option<GattLevel>>) {
+        let (progress, receiver) = mpsc::channel();
         let this = Self::alloc();
         let this = this.set_ivars(DelegateIvars {
-            state: RefCell::new(DelegateState::default()),
+            state: Mutex::new(DelegateState {
+                connect_if_needed: connect_if_needed.to_vec(),
+                ..Default::default()
+            }),
+            progress,
+            scan: Span::current(),
         });
         // SAFETY: Calling [super init] on a properly allocated NSObject subclass.
-        unsafe { msg_send![super(this), init] }
+        (unsafe { msg_send![super(this), init] }, receiver)
+    }
+
+    /// Lock the delegate state
+    ///
+    /// A panic in a callback can't leave the state half-updated in a way
+    /// that matters, so a poisoned lock is still used.
+    fn state(&self) -> MutexGuard<'_, DelegateState> {
+        self.ivars()
+            .state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
     }
 
     /// Check if all operations are complete
     fn is_done(&self) -> bool {
-        self.ivars().state.borrow().done
+        self.state().done
+    }
+
+    /// Mark the scan as done and wake the scanning thread
+    fn finish(&self) {
+        self.state().done = true;
+        let _ = self.ivars().progress.send(None);
     }
 
     /// Take the collected battery levels
-    fn take_results(&self) -> HashMap<String, u8> {
-        std::mem::take(&mut self.ivars().state.borrow_mut().battery_levels)
+    fn take_results(&self) -> GattScan {
+        let mut state = self.state();
+        GattScan {
+            adapter: state.adapter,
+            levels: std::mem::take(&mut state.levels),
+        }
+    }
+
+    /// Start a new phase span for a peripheral, closing the previous phase
+    fn enter_phase(&self, peripheral: &CBPeripheral, phase: Phase) {
+        let key = peripheral_identifier(peripheral);
+        let scan = &self.ivars().scan;
+        let mut state = self.state();
+        let spans = state.spans.entry(key).or_insert_with(|| PeripheralSpans {
+            peripheral: info_span!(parent: scan, "peripheral", name = %peripheral_name(peripheral)),
+            phase: Span::none(),
+        });
+        let parent = &spans.peripheral;
+        spans.phase = match phase {
+            Phase::Connect => info_span!(parent: parent, "connect"),
+            Phase::DiscoverServices => info_span!(parent: parent, "discover_services"),
+            Phase::DiscoverCharacteristics => {
+                info_span!(parent: parent, "discover_characteristics")
+            }
+            Phase::Read => info_span!(parent: parent, "read"),
+        };
+    }
+
+    /// Wait for `count` more characteristic discoveries or reads of a
+    /// peripheral
+    fn start_operations(&self, peripheral: &CBPeripheral, count: usize) {
+        *self
+            .state()
+            .operations
+            .entry(peripheral_identifier(peripheral))
+            .or_default() += count;
     }
 
-    /// Decrement pending reads counter and mark done if zero
-    fn decrement_pending(&self) {
-        let mut state = self.ivars().state.borrow_mut();
-        if state.pending_reads > 0 {
-            state.pending_reads -= 1;
+    /// Count a characteristic discovery or read of a peripheral as done,
+    /// completing the peripheral after the last one
+    fn finish_operation(&self, peripheral: &CBPeripheral) {
+        let remaining = {
+            let mut state = self.state();
+            let Some(operations) = state.operations.get_mut(&peripheral_identifier(peripheral))
+            else {
+                return;
+            };
+            *operations = operations.saturating_sub(1);
+            *operations
+        };
+        if remaining == 0 {
+            self.complete(peripheral);
         }
-        if state.pending_reads == 0 {
-            state.done = true;
+    }
+
+    /// Finish a peripheral that was read or failed: close its spans, release
+    /// its connection and start the next queued one
+    fn complete(&self, peripheral: &CBPeripheral) {
+        let key = peripheral_identifier(peripheral);
+        let central = {
+            let mut state = self.state();
+            // Later callbacks of a peripheral that already finished
+            let Some(index) = state
+                .active
+                .iter()
+                .position(|p| std::ptr::eq(&**p, peripheral))
+            else {
+                return;
+            };
+            state.active.swap_remove(index);
+            state.spans.remove(&key);
+            state.operations.remove(&key);
+            state.pending = state.pending.saturating_sub(1);
+            if state.pending == 0 {
+                state.done = true;
+            }
+            state.central.clone()
+        };
+        let _ = self.ivars().progress.send(None);
+
+        if let Some(central) = central {
+            // Peripherals that were already connected stay connected to the
+            // system, this only releases btmon's connection
+            // SAFETY: cancelPeripheralConnection is a standard Core Bluetooth API.
+            unsafe { central.cancelPeripheralConnection(peripheral) };
+            self.connect_queued(&central);
+        }
+    }
+
+    /// Connect to queued peripherals while connection slots are free
+    fn connect_queued(&self, central: &CBCentralManager) {
+        loop {
+            let peripheral = {
+                let mut state = self.state();
+                if state.active.len() >= MAX_CONNECTIONS {
+                    return;
+                }
+                let Some(peripheral) = state.queue.pop_front() else {
+                    return;
+                };
+                state.active.push(peripheral.clone());
+                peripheral
+            };
+
+            // SAFETY: peripheral.name() is a standard Core Bluetooth API.
+            let name = unsafe { peripheral.name() };
+            trace!(name = ?name, "Processing peripheral");
+
+            self.enter_phase(&peripheral, Phase::Connect);
+
+            // Set delegate and connect
+            // SAFETY: setDelegate and connectPeripheral_options are standard Core Bluetooth APIs.
+            unsafe {
+                let delegate: *const ProtocolObject<dyn CBPeripheralDelegate> =
+                    ProtocolObject::from_ref(self);
+                peripheral.setDelegate(Some(&*delegate));
+                central.connectPeripheral_options(&peripheral, None);
+            }
         }
     }
 
@@ -252,62 +453,147 @@ impl CentralDelegate {
         let connected: Retained<NSArray<CBPeripheral>> =
             unsafe { central.retrieveConnectedPeripheralsWithServices(&services) };
 
-        let count = connected.count();
         debug!(
-            count = count,
+            count = connected.count(),
             "Found connected peripherals with Battery Service"
         );
+        let mut peripherals: Vec<Retained<CBPeripheral>> = connected.to_vec();
 
-        if count == 0 {
-            self.ivars().state.borrow_mut().done = true;
-            return;
+        let idle = self.retrieve_idle(central, &peripherals);
+        if !idle.is_empty() {
+            debug!(count = idle.len(), "Connecting to idle bonded peripherals");
+            peripherals.extend(idle);
         }
 
-        self.ivars().state.borrow_mut().pending_reads = count;
+        if peripherals.is_empty() {
+            self.finish();
+            return;
+        }
 
-        for i in 0..count {
-            // SAFETY: objectAtIndex returns a valid pointer for valid index.
-            // We retain the peripheral to ensure it lives long enough.
-            let peripheral: Option<Retained<CBPeripheral>> = unsafe {
-                let p: *const CBPeripheral = msg_send![&connected, objectAtIndex: i];
-                Retained::retain(p as *mut CBPeripheral)
-            };
+        {
+            let mut state = self.state();
+            state.pending = peripherals.len();
+            state.queue.extend(peripherals);
+            state.central = Some(central.retain());
+        }
+        self.connect_queued(central);
+    }
 
-            let Some(peripheral) = peripheral else {
-                self.decrement_pending();
-                continue;
-            };
+    /// Retrieve the bonded peripherals to connect to if needed that aren't
+    /// among the connected ones
+    fn retrieve_idle(
+        &self,
+        central: &CBCentralManager,
+        connected: &[Retained<CBPeripheral>],
+    ) -> Vec<Retained<CBPeripheral>> {
+        let identifiers: Vec<Retained<NSUUID>> = self
+            .state()
+            .connect_if_needed
+            .iter()
+            .filter_map(|id| NSUUID::initWithUUIDString(NSUUID::alloc(), &NSString::from_str(id)))
+            .collect();
+        if identifiers.is_empty() {
+            return Vec::new();
+        }
 
-            // SAFETY: peripheral.name() is a standard Core Bluetooth API.
-            let name = unsafe { peripheral.name() };
-            trace!(name = ?name, "Processing peripheral");
+        let connected: HashSet<String> =
+            connected.iter().map(|p| peripheral_identifier(p)).collect();
+        // SAFETY: retrievePeripheralsWithIdentifiers is a standard Core Bluetooth API.
+        let known = unsafe {
+            central.retrievePeripheralsWithIdentifiers(&NSArray::from_retained_slice(&identifiers))
+        };
+        known
+            .to_vec()
+            .into_iter()
+            .filter(|p| !connected.contains(&peripheral_identifier(p)))
+            .collect()
+    }
 
-            // Set delegate and connect
-            // SAFETY: setDelegate and connectPeripheral_options are standard Core Bluetooth APIs.
-            unsafe {
-                let delegate: *const ProtocolObject<dyn CBPeripheralDelegate> =
-                    ProtocolObject::from_ref(self);
-                peripheral.setDelegate(Some(&*delegate));
-                central.connectPeripheral_options(&peripheral, None);
+    /// Release the connections of peripherals that never finished and stop
+    /// receiving callbacks
+    ///
+    /// Must be called on the manager's queue.
+    fn disconnect_remaining(&self) {
+        let (central, active) = {
+            let mut state = self.state();
+            state.spans.clear();
+            state.operations.clear();
+            state.queue.clear();
+            (state.central.take(), std::mem::take(&mut state.active))
+        };
+        let Some(central) = central else {
+            return;
+        };
+        // SAFETY: setDelegate and cancelPeripheralConnection are standard Core
+        // Bluetooth APIs.
+        unsafe {
+            central.setDelegate(None);
+            for peripheral in &active {
+                peripheral.setDelegate(None);
+                central.cancelPeripheralConnection(peripheral);
             }
-
-            self.ivars()
-                .state
-                .borrow_mut()
-                .peripherals_to_read
-                .push(peripheral);
         }
     }
 }
 
-/// Run the NSRunLoop for a short interval
-fn run_loop_once() {
-    // SAFETY: These are standard Foundation/AppKit APIs for running the event loop.
-    unsafe {
-        let run_loop: *const AnyObject = msg_send![objc2::class!(NSRunLoop), currentRunLoop];
-        let date: *const AnyObject =
-            msg_send![objc2::class!(NSDate), dateWithTimeIntervalSinceNow: RUN_LOOP_INTERVAL];
-        let _: () = msg_send![run_loop, runUntilDate: date];
+/// Name of a peripheral as shown to the user
+fn peripheral_name(peripheral: &CBPeripheral) -> String {
+    // SAFETY: peripheral.name() is a standard Core Bluetooth API.
+    unsafe { peripheral.name() }
+        .map(|n| n.to_string())
+        .unwrap_or_else(|| "Unknown".to_string())
+}
+
+/// Adapter state of a Core Bluetooth manager state
+pub(crate) fn adapter_state(state: CBManagerState) -> AdapterState {
+    match state {
+        CBManagerState::Resetting => AdapterState::Resetting,
+        CBManagerState::Unsupported => AdapterState::Unsupported,
+        CBManagerState::Unauthorized => AdapterState::Unauthorized,
+        CBManagerState::PoweredOff => AdapterState::PoweredOff,
+        CBManagerState::PoweredOn => AdapterState::PoweredOn,
+        _ => AdapterState::Unknown,
+    }
+}
+
+/// Core Bluetooth identifier of a peripheral, stable across connections
+pub(crate) fn peripheral_identifier(peripheral: &CBPeripheral) -> String {
+    // SAFETY: identifier() is a standard Core Bluetooth API.
+    unsafe { peripheral.identifier() }.UUIDString().to_string()
+}
+
+/// Battery level read from one Battery Service instance of a peripheral
+#[derive(Debug, Clone)]
+pub struct GattLevel {
+    /// Core Bluetooth identifier of the peripheral
+    pub identifier: String,
+    /// Peripheral name, which several peripherals may share
+    pub name: String,
+    /// Battery level (0-100)
+    pub level: u8,
+}
+
+/// Result of a GATT scan
+pub struct GattScan {
+    /// Last adapter state reported by Core Bluetooth
+    pub adapter: Option<AdapterState>,
+    /// Levels in the order they were read, one per Battery Service instance
+    pub levels: Vec<GattLevel>,
+}
+
+impl GattScan {
+    /// The lowest level of each peripheral, the one to charge first, in the
+    /// order the peripherals were first read
+    pub fn lowest_levels(&self) -> Vec<&GattLevel> {
+        let mut lowest: Vec<&GattLevel> = Vec::new();
+        for level in &self.levels {
+            match lowest.iter_mut().find(|l| l.identifier == level.identifier) {
+                Some(l) if level.level < l.level => *l = level,
+                Some(_) => {}
+                None => lowest.push(level),
+            }
+        }
+        lowest
     }
 }
 
@@ -315,33 +601,66 @@ fn run_loop_once() {
 ///
 /// This function creates a CBCentralManager, retrieves connected peripherals
 /// that advertise the Battery Service, and reads their battery levels.
+/// At most [`MAX_CONNECTIONS`] peripherals are connected at a time, each
+/// released as soon as all its levels are read. Bonded peripherals whose
+/// identifiers are in `connect_if_needed` are connected to if they're idle,
+/// and so disconnected again after the read.
 ///
-/// # Returns
+/// Each level is passed to `on_level` on the calling thread as soon as it's
+/// read, before the slower peripherals finish.
 ///
-/// A HashMap mapping device names to their battery levels (0-100).
-pub fn get_gatt_battery_devices() -> HashMap<String, u8> {
-    let delegate = CentralDelegate::new();
+/// This can be called from any thread, including several at once: each call
+/// has its own manager and dispatch queue, and blocks until its scan is over.
+pub fn get_gatt_battery_devices(
+    connect_if_needed: &[String],
+    mut on_level: impl FnMut(&GattLevel),
+) -> GattScan {
+    let _span = info_span!("gatt_scan").entered();
+    let (delegate, progress) = CentralDelegate::new(connect_if_needed);
+    let queue = DispatchQueue::new(QUEUE_LABEL, DispatchQueueAttr::SERIAL);
+    let timeout = if connect_if_needed.is_empty() {
+        GATT_DISCOVERY_TIMEOUT
+    } else {
+        ON_DEMAND_TIMEOUT
+    };
 
     // SAFETY: CBCentralManager initialization is a standard Core Bluetooth API.
-    // We pass our delegate and a nil queue (uses main queue).
-    let _central: Retained<CBCentralManager> = unsafe {
-        let delegate_obj: *const ProtocolObject<dyn CBCentralManagerDelegate> =
-            ProtocolObject::from_ref(&*delegate);
-        msg_send![CBCentralManager::alloc(), initWithDelegate: delegate_obj, queue: std::ptr::null::<AnyObject>()]
+    // The delegate is thread-safe, so it can be called on the private queue.
+    let central = unsafe {
+        CBCentralManager::initWithDelegate_queue(
+            CBCentralManager::alloc(),
+            Some(ProtocolObject::from_ref(&*delegate)),
+            Some(&queue),
+        )
     };
+    delegate.state().central = Some(central);
 
+    // The timeout runs from the last level read or peripheral finished, so a
+    // long queue of peripherals that keep responding isn't cut short
     let start = Instant::now();
-
-    while !delegate.is_done() && start.elapsed() < GATT_DISCOVERY_TIMEOUT {
-        run_loop_once();
+    while !delegate.is_done() {
+        match progress.recv_timeout(timeout) {
+            Ok(Some(level)) => on_level(&level),
+            Ok(None) => {}
+            Err(_) => break,
+        }
     }
 
     if !delegate.is_done() {
         warn!(
             elapsed_ms = start.elapsed().as_millis(),
+            pending = delegate.state().pending,
             "Timeout waiting for GATT battery levels"
         );
     }
 
+    // Close the spans and connections of peripherals that never finished, on
+    // the queue so that no callback runs at the same time
+    queue.exec_sync(|| delegate.disconnect_remaining());
+    // Levels read just before the scan was done
+    for level in progress.try_iter().flatten() {
+        on_level(&level);
+    }
+
     delegate.take_results()
 }
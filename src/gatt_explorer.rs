@@ -0,0 +1,429 @@
+//! Core Bluetooth side of the GATT explorer
+//!
+//! Like the battery scan in [`crate::gatt`], the central manager calls the
+//! delegate on a private serial dispatch queue, and the calling thread waits
+//! on a channel that is signalled whenever a discovery or read completes.
+
+use crate::connection::{ConnectionError, pick};
+use crate::explore::{Characteristic, ExploreError, Peripheral, Service, property_names};
+use crate::gatt::{adapter_state, peripheral_identifier};
+use dispatch2::{DispatchQueue, DispatchQueueAttr};
+use objc2::rc::Retained;
+use objc2::runtime::ProtocolObject;
+use objc2::{AllocAnyThread, DefinedClass, Message, define_class, msg_send};
+use objc2_core_bluetooth::{
+    CBCentralManager, CBCentralManagerDelegate, CBCharacteristic, CBCharacteristicProperties,
+    CBManagerState, CBPeripheral, CBPeripheralDelegate, CBService, CBUUID,
+};
+use objc2_foundation::{NSArray, NSError, NSObject, NSObjectProtocol, NSString};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use tracing::{debug, info_span, warn};
+
+/// Services a peripheral connected to the Mac is looked up by; Core
+/// Bluetooth only returns connected peripherals that have one of them
+const LOOKUP_SERVICES: [&str; 3] = ["180A", "180F", "1812"];
+
+/// How long the peripheral may take to answer each step
+const STEP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Label of the dispatch queue Core Bluetooth calls the delegate on
+const QUEUE_LABEL: &str = "btmon.gatt.explore";
+
+/// Internal state for the delegate
+#[derive(Default)]
+struct ExploreState {
+    /// Name filter of the peripheral to explore
+    target: String,
+    central: Option<Retained<CBCentralManager>>,
+    peripheral: Option<Retained<CBPeripheral>>,
+    name: String,
+    services: Vec<Service>,
+    /// Characteristic discoveries and reads not answered yet
+    pending: usize,
+    /// Set once exploring finished or failed
+    result: Option<Result<(), ExploreError>>,
+}
+
+/// Ivars for the Objective-C delegate class
+struct ExploreIvars {
+    state: Mutex<ExploreState>,
+    /// Signalled whenever a step completes
+    progress: Sender<()>,
+}
+
+define_class!(
+    #[unsafe(super(NSObject))]
+    #[name = "BtmonExploreDelegate"]
+    #[ivars = ExploreIvars]
+    struct ExploreDelegate;
+
+    unsafe impl NSObjectProtocol for ExploreDelegate {}
+
+    unsafe impl CBCentralManagerDelegate for ExploreDelegate {
+        #[unsafe(method(centralManagerDidUpdateState:))]
+        fn central_manager_did_update_state(&self, central: &CBCentralManager) {
+            // SAFETY: central.state() is a standard Core Bluetooth API.
+            let state = unsafe { central.state() };
+            debug!(state = ?state, "Central manager state updated");
+            if state == CBManagerState::PoweredOn {
+                self.connect_target(central);
+            } else if state == CBManagerState::Unauthorized
+                || state == CBManagerState::Unsupported
+                || state == CBManagerState::PoweredOff
+            {
+                self.finish(Err(ExploreError::Adapter(adapter_state(state))));
+            }
+        }
+
+        #[unsafe(method(centralManager:didConnectPeripheral:))]
+        fn central_manager_did_connect_peripheral(
+            &self,
+            _central: &CBCentralManager,
+            peripheral: &CBPeripheral,
+        ) {
+            debug!("Connected, discovering services");
+            // SAFETY: discoverServices is a standard Core Bluetooth API; None
+            // discovers all services.
+            unsafe { peripheral.discoverServices(None) };
+            self.progress();
+        }
+
+        #[unsafe(method(centralManager:didFailToConnectPeripheral:error:))]
+        fn central_manager_did_fail_to_connect_peripheral(
+            &self,
+            _central: &CBCentralManager,
+            _peripheral: &CBPeripheral,
+            error: Option<&NSError>,
+        ) {
+            self.finish(Err(ExploreError::Failed(format!(
+                "failed to connect: {}",
+                describe(error)
+            ))));
+        }
+    }
+
+    unsafe impl CBPeripheralDelegate for ExploreDelegate {
+        #[unsafe(method(peripheral:didDiscoverServices:))]
+        unsafe fn peripheral_did_discover_services(
+            &self,
+            peripheral: &CBPeripheral,
+            error: Option<&NSError>,
+        ) {
+            if error.is_some() {
+                self.finish(Err(ExploreError::Failed(format!(
+                    "failed to discover services: {}",
+                    describe(error)
+                ))));
+                return;
+            }
+            // SAFETY: services() is a standard Core Bluetooth API.
+            let services = unsafe { peripheral.services() }.map_or_else(Vec::new, |s| s.to_vec());
+            debug!(count = services.len(), "Discovered services");
+            if services.is_empty() {
+                self.finish(Ok(()));
+                return;
+            }
+            {
+                let mut state = self.state();
+                state.pending = services.len();
+                state.services = services
+                    .iter()
+                    .map(|service| Service {
+                        uuid: uuid_string(service_uuid(service)),
+                        characteristics: Vec::new(),
+                    })
+                    .collect();
+            }
+            for service in &services {
+                // SAFETY: discoverCharacteristics_forService is a standard
+                // Core Bluetooth API; None discovers all characteristics.
+                unsafe { peripheral.discoverCharacteristics_forService(None, service) };
+            }
+            self.progress();
+        }
+
+        #[unsafe(method(peripheral:didDiscoverCharacteristicsForService:error:))]
+        unsafe fn peripheral_did_discover_characteristics(
+            &self,
+            peripheral: &CBPeripheral,
+            service: &CBService,
+            error: Option<&NSError>,
+        ) {
+            if error.is_some() {
+                warn!(error = %describe(error), "Error discovering characteristics");
+            }
+            // SAFETY: characteristics() is a standard Core Bluetooth API.
+            let characteristics =
+                unsafe { service.characteristics() }.map_or_else(Vec::new, |c| c.to_vec());
+            let index = service_index(peripheral, service);
+            let readable: Vec<&Retained<CBCharacteristic>> = characteristics
+                .iter()
+                // SAFETY: properties() is a standard Core Bluetooth API.
+                .filter(|c| unsafe { c.properties() }.contains(CBCharacteristicProperties::Read))
+                .collect();
+            {
+                let mut state = self.state();
+                state.pending = (state.pending + readable.len()).saturating_sub(1);
+                if let Some(entry) = index.and_then(|i| state.services.get_mut(i)) {
+                    entry.characteristics = characteristics
+                        .iter()
+                        .map(|characteristic| Characteristic {
+                            uuid: uuid_string(characteristic_uuid(characteristic)),
+                            // SAFETY: properties() is a standard Core Bluetooth API.
+                            properties: property_names(unsafe { characteristic.properties() }.0),
+                            value: None,
+                            error: None,
+                        })
+                        .collect();
+                }
+            }
+            for characteristic in readable {
+                // SAFETY: readValueForCharacteristic is a standard Core
+                // Bluetooth API.
+                unsafe { peripheral.readValueForCharacteristic(characteristic) };
+            }
+            self.check_done();
+        }
+
+        #[unsafe(method(peripheral:didUpdateValueForCharacteristic:error:))]
+        unsafe fn peripheral_did_update_value(
+            &self,
+            peripheral: &CBPeripheral,
+            characteristic: &CBCharacteristic,
+            error: Option<&NSError>,
+        ) {
+            // SAFETY: service() and value() are standard Core Bluetooth APIs.
+            let service = unsafe { characteristic.service() };
+            let value = unsafe { characteristic.value() }.map(|value| value.to_vec());
+            // Services and characteristics are matched by position, not
+            // UUID, since a peripheral may have several instances of one
+            let index = service.and_then(|service| {
+                Some((
+                    service_index(peripheral, &service)?,
+                    characteristic_index(&service, characteristic)?,
+                ))
+            });
+            {
+                let mut state = self.state();
+                state.pending = state.pending.saturating_sub(1);
+                let entry = index.and_then(|(service, characteristic)| {
+                    state
+                        .services
+                        .get_mut(service)?
+                        .characteristics
+                        .get_mut(characteristic)
+                });
+                if let Some(entry) = entry {
+                    if error.is_some() {
+                        entry.error = Some(describe(error));
+                    } else {
+                        entry.value = Some(value.unwrap_or_default());
+                    }
+                }
+            }
+            self.check_done();
+        }
+    }
+);
+
+// SAFETY: The delegate's state is only accessed through its mutex, and the
+// Core Bluetooth objects it holds are only messaged from the manager's queue.
+unsafe impl Send for ExploreDelegate {}
+// SAFETY: As above.
+unsafe impl Sync for ExploreDelegate {}
+
+impl ExploreDelegate {
+    fn new(target: &str, progress: Sender<()>) -> Retained<Self> {
+        let this = Self::alloc();
+        let this = this.set_ivars(ExploreIvars {
+            state: Mutex::new(ExploreState {
+                target: target.to_string(),
+                ..Default::default()
+            }),
+            progress,
+        });
+        // SAFETY: Calling [super init] on a properly allocated NSObject subclass.
+        unsafe { msg_send![super(this), init] }
+    }
+
+    /// Lock the delegate state, even if a callback panicked
+    fn state(&self) -> MutexGuard<'_, ExploreState> {
+        self.ivars()
+            .state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Wake the waiting thread, resetting its timeout
+    fn progress(&self) {
+        let _ = self.ivars().progress.send(());
+    }
+
+    /// End exploring, unless it already ended
+    fn finish(&self, result: Result<(), ExploreError>) {
+        self.state().result.get_or_insert(result);
+        self.progress();
+    }
+
+    /// Finish once every discovery and read was answered
+    fn check_done(&self) {
+        if self.state().pending == 0 {
+            self.finish(Ok(()));
+        } else {
+            self.progress();
+        }
+    }
+
+    /// Find the target among the connected peripherals and connect to it
+    fn connect_target(&self, central: &CBCentralManager) {
+        let services: Vec<Retained<CBUUID>> = LOOKUP_SERVICES
+            .iter()
+            // SAFETY: CBUUID::UUIDWithString is a standard Core Bluetooth API.
+            .map(|uuid| unsafe { CBUUID::UUIDWithString(&NSString::from_str(uuid)) })
+            .collect();
+        // SAFETY: retrieveConnectedPeripheralsWithServices is a standard Core
+        // Bluetooth API.
+        let connected = unsafe {
+            central
+                .retrieveConnectedPeripheralsWithServices(&NSArray::from_retained_slice(&services))
+        }
+        .to_vec();
+        let names: Vec<String> = connected
+            .iter()
+            // SAFETY: peripheral.name() is a standard Core Bluetooth API.
+            .map(|p| unsafe { p.name() }.map_or_else(|| "Unknown".to_string(), |n| n.to_string()))
+            .collect();
+
+        let target = self.state().target.clone();
+        let index = match pick(&names, &target) {
+            Ok(index) => index,
+            Err(ConnectionError::Ambiguous(names)) => {
+                self.finish(Err(ExploreError::Ambiguous(names)));
+                return;
+            }
+            Err(_) => {
+                self.finish(Err(ExploreError::NotFound(target)));
+                return;
+            }
+        };
+        let peripheral = connected[index].clone();
+        debug!(name = %names[index], "Connecting to peripheral");
+        {
+            let mut state = self.state();
+            state.name = names[index].clone();
+            state.peripheral = Some(peripheral.clone());
+            state.central = Some(central.retain());
+        }
+        // SAFETY: setDelegate and connectPeripheral_options are standard Core
+        // Bluetooth APIs.
+        unsafe {
+            peripheral.setDelegate(Some(ProtocolObject::from_ref(self)));
+            central.connectPeripheral_options(&peripheral, None);
+        }
+        self.progress();
+    }
+
+    /// Release the connection and stop receiving callbacks
+    ///
+    /// Must be called on the manager's queue.
+    fn disconnect(&self) {
+        let (central, peripheral) = {
+            let mut state = self.state();
+            (state.central.take(), state.peripheral.clone())
+        };
+        let Some(central) = central else {
+            return;
+        };
+        // SAFETY: setDelegate and cancelPeripheralConnection are standard Core
+        // Bluetooth APIs.
+        unsafe {
+            central.setDelegate(None);
+            if let Some(peripheral) = &peripheral {
+                peripheral.setDelegate(None);
+                central.cancelPeripheralConnection(peripheral);
+            }
+        }
+    }
+}
+
+/// Position of a service among the peripheral's discovered services
+fn service_index(peripheral: &CBPeripheral, service: &CBService) -> Option<usize> {
+    // SAFETY: services() is a standard Core Bluetooth API.
+    let services = unsafe { peripheral.services() }?;
+    services.iter().position(|s| std::ptr::eq(&*s, service))
+}
+
+/// Position of a characteristic among its service's characteristics
+fn characteristic_index(service: &CBService, characteristic: &CBCharacteristic) -> Option<usize> {
+    // SAFETY: characteristics() is a standard Core Bluetooth API.
+    let characteristics = unsafe { service.characteristics() }?;
+    characteristics
+        .iter()
+        .position(|c| std::ptr::eq(&*c, characteristic))
+}
+
+/// UUID of a service
+fn service_uuid(service: &CBService) -> Retained<CBUUID> {
+    // SAFETY: UUID() is a standard Core Bluetooth API.
+    unsafe { service.UUID() }
+}
+
+/// UUID of a characteristic
+fn characteristic_uuid(characteristic: &CBCharacteristic) -> Retained<CBUUID> {
+    // SAFETY: UUID() is a standard Core Bluetooth API.
+    unsafe { characteristic.UUID() }
+}
+
+/// A UUID as a string, the short form for Bluetooth SIG UUIDs
+fn uuid_string(uuid: Retained<CBUUID>) -> String {
+    // SAFETY: UUIDString() is a standard Core Bluetooth API.
+    unsafe { uuid.UUIDString() }.to_string()
+}
+
+/// Description of a Core Bluetooth error
+fn describe(error: Option<&NSError>) -> String {
+    error.map_or_else(
+        || "unknown error".to_string(),
+        |e| e.localizedDescription().to_string(),
+    )
+}
+
+/// Connect to the connected peripheral matching `device`, discover all its
+/// services and characteristics, and read the readable values
+pub fn explore(device: &str) -> Result<Peripheral, ExploreError> {
+    let _span = info_span!("gatt_explore", device).entered();
+    let (progress, receiver) = mpsc::channel();
+    let delegate = ExploreDelegate::new(device, progress);
+    let queue = DispatchQueue::new(QUEUE_LABEL, DispatchQueueAttr::SERIAL);
+
+    // SAFETY: CBCentralManager initialization is a standard Core Bluetooth API.
+    // The delegate is thread-safe, so it can be called on the private queue.
+    let central = unsafe {
+        CBCentralManager::initWithDelegate_queue(
+            CBCentralManager::alloc(),
+            Some(ProtocolObject::from_ref(&*delegate)),
+            Some(&queue),
+        )
+    };
+    delegate.state().central = Some(central);
+
+    while delegate.state().result.is_none() && receiver.recv_timeout(STEP_TIMEOUT).is_ok() {}
+    queue.exec_sync(|| delegate.disconnect());
+
+    let mut state = delegate.state();
+    match state.result.take() {
+        Some(Ok(())) => Ok(Peripheral {
+            name: std::mem::take(&mut state.name),
+            identifier: state
+                .peripheral
+                .as_deref()
+                .map(peripheral_identifier)
+                .unwrap_or_default(),
+            services: std::mem::take(&mut state.services),
+        }),
+        Some(Err(e)) => Err(e),
+        None => Err(ExploreError::Timeout),
+    }
+}
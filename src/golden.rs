@@ -0,0 +1,188 @@
+//! Golden tests for the device pipeline and output formats
+//!
+//! Each case loads a raw backend snapshot from `tests/fixtures`, runs it
+//! through the merge/filter pipeline via the replay backend, and compares every
+//! output format with the expected files in `tests/golden`. Set
+//! `BTMON_UPDATE_GOLDEN=1` to rewrite the expected files after an
+//! intentional output change.
+
+use crate::alerts::AlertConfig;
+use crate::alfred::{self, AlfredConfig};
+use crate::backend::{RawSnapshot, ReplayBackend};
+use crate::device::{Device, collect_devices};
+use crate::icons::Icons;
+use crate::locale::Locale;
+use crate::sketchybar::{self, SketchybarConfig};
+use crate::{metrics, output, raycast, xbar};
+use chrono::{DateTime, Utc};
+use std::path::PathBuf;
+
+/// A pipeline run against one fixture
+struct Case {
+    /// Name of the golden files for this case
+    name: &'static str,
+    /// Fixture file in `tests/fixtures`, without extension
+    fixture: &'static str,
+    /// Name filter passed to the pipeline
+    filter: Option<&'static str>,
+}
+
+const CASES: &[Case] = &[
+    Case {
+        name: "mixed",
+        fixture: "mixed",
+        filter: None,
+    },
+    Case {
+        name: "mixed_filter_airpods",
+        fixture: "mixed",
+        filter: Some("AIRPODS"),
+    },
+    Case {
+        name: "mixed_filter_none_match",
+        fixture: "mixed",
+        filter: Some("headphones"),
+    },
+    Case {
+        name: "gatt_duplicate",
+        fixture: "gatt_duplicate",
+        filter: None,
+    },
+    Case {
+        name: "empty",
+        fixture: "empty",
+        filter: None,
+    },
+    Case {
+        name: "low",
+        fixture: "low",
+        filter: None,
+    },
+];
+
+/// Scan time of the formats that print one, so their output doesn't change
+/// from run to run
+fn scanned_at() -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339("2026-10-15T09:30:00Z")
+        .unwrap()
+        .with_timezone(&Utc)
+}
+
+/// Renders a device list in one output format
+type Formatter = fn(&[Device]) -> String;
+
+/// Output formats under golden test, keyed by golden file extension
+const FORMATS: &[(&str, Formatter)] = &[
+    ("txt", |devices| {
+        output::format_text(devices, &Locale::default())
+    }),
+    ("json", |devices| output::format_json(devices).unwrap()),
+    ("shortcuts.json", |devices| {
+        output::format_shortcuts(devices).unwrap()
+    }),
+    ("tmux", |devices| {
+        output::format_tmux(devices, &AlertConfig::default(), &Icons::default())
+    }),
+    ("prompt", |devices| {
+        output::format_prompt(devices, &AlertConfig::default(), &Icons::default())
+    }),
+    ("prom", |devices| metrics::format_metrics(devices)),
+    ("csv", |devices| output::format_csv(devices, scanned_at())),
+    ("yaml", |devices| {
+        output::format_yaml(devices, None).unwrap()
+    }),
+    ("ndjson", |devices| {
+        output::format_ndjson(devices, scanned_at(), None).unwrap()
+    }),
+    ("table", |devices| {
+        output::format_table(devices, &Locale::default(), None, None)
+    }),
+    ("md", |devices| {
+        output::format_markdown(devices, &Locale::default())
+    }),
+    ("sketchybar", |devices| {
+        sketchybar::format_sketchybar(
+            devices,
+            &AlertConfig::default(),
+            &SketchybarConfig::default(),
+        )
+    }),
+    ("xbar", |devices| {
+        xbar::format_xbar(devices, &AlertConfig::default(), &Locale::default())
+    }),
+    ("raycast", |devices| {
+        raycast::format_raycast(devices, &AlertConfig::default(), &Locale::default())
+    }),
+    ("alfred", |devices| {
+        alfred::format_alfred(
+            devices,
+            &AlertConfig::default(),
+            &AlfredConfig::default(),
+            &Locale::default(),
+        )
+        .unwrap()
+    }),
+    ("short", |devices| output::format_short(devices)),
+    ("porcelain", |devices| output::format_porcelain(devices)),
+];
+
+fn tests_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests")
+}
+
+fn load_fixture(name: &str) -> RawSnapshot {
+    let path = tests_dir().join("fixtures").join(format!("{name}.json"));
+    RawSnapshot::load(&path).unwrap_or_else(|e| panic!("failed to load {}: {e}", path.display()))
+}
+
+/// Compare output with its golden file, returning a description on mismatch
+fn check_golden(file: &str, actual: &str) -> Option<String> {
+    let path = tests_dir().join("golden").join(file);
+    let actual = format!("{actual}\n");
+
+    if std::env::var_os("BTMON_UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, &actual).unwrap();
+        return None;
+    }
+
+    match std::fs::read_to_string(&path) {
+        Ok(expected) if expected == actual => None,
+        Ok(expected) => Some(format!(
+            "{file}: output differs\n--- expected\n{expected}--- actual\n{actual}"
+        )),
+        Err(e) => Some(format!("{file}: {e}")),
+    }
+}
+
+#[test]
+fn test_golden_outputs() {
+    let mut failures = Vec::new();
+
+    for case in CASES {
+        let mut backend = ReplayBackend(load_fixture(case.fixture));
+        let devices = collect_devices(&mut backend, case.filter);
+
+        for (ext, format) in FORMATS {
+            let file = format!("{}.{ext}", case.name);
+            failures.extend(check_golden(&file, &format(&devices)));
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn test_golden_json_round_trip() {
+    for case in CASES {
+        let path = tests_dir()
+            .join("golden")
+            .join(format!("{}.json", case.name));
+        let expected = std::fs::read_to_string(&path).unwrap();
+        let devices: Vec<Device> = serde_json::from_str(&expected)
+            .unwrap_or_else(|e| panic!("failed to parse {}: {e}", path.display()));
+        assert_eq!(
+            format!("{}\n", output::format_json(&devices).unwrap()),
+            expected
+        );
+    }
+}
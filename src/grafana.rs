@@ -0,0 +1,190 @@
+//! Grafana dashboard
+//!
+//! `btmon grafana-dashboard` prints a dashboard for the metrics of
+//! [`crate::metrics`], ready for Grafana's "Import dashboard". The data source
+//! is left as an import input, and the level thresholds are the alert
+//! thresholds of the configuration.
+
+use crate::alerts::AlertConfig;
+use crate::metrics::{BATTERY_PERCENT, DEVICES, READING_AGE_SECONDS};
+use serde_json::{Value, json};
+
+/// Kind of data source the dashboard queries, selected with `--datasource`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Datasource {
+    /// Prometheus scraping `btmon serve` at `/metrics`
+    #[default]
+    Prometheus,
+}
+
+impl Datasource {
+    /// Grafana plugin ID
+    fn plugin(self) -> &'static str {
+        match self {
+            Self::Prometheus => "prometheus",
+        }
+    }
+
+    /// Grafana plugin name
+    fn name(self) -> &'static str {
+        match self {
+            Self::Prometheus => "Prometheus",
+        }
+    }
+}
+
+/// Reference to the data source chosen on import
+const DATASOURCE_INPUT: &str = "${DS_BTMON}";
+
+/// One query of a panel
+fn target(expr: &str, legend: &str, datasource: &Value) -> Value {
+    json!({
+        "datasource": datasource,
+        "expr": expr,
+        "legendFormat": legend,
+        "refId": "A"
+    })
+}
+
+/// Colour steps of a battery level: red up to `critical`, yellow up to
+/// `warn`, green above
+fn level_thresholds(alerts: &AlertConfig) -> Value {
+    json!({
+        "mode": "absolute",
+        "steps": [
+            { "color": "red", "value": null },
+            { "color": "yellow", "value": u16::from(alerts.critical) + 1 },
+            { "color": "green", "value": u16::from(alerts.warn) + 1 }
+        ]
+    })
+}
+
+/// The dashboard for `datasource`, colouring levels by `alerts`
+pub fn dashboard(datasource: Datasource, alerts: &AlertConfig) -> Value {
+    let source = json!({ "type": datasource.plugin(), "uid": DATASOURCE_INPUT });
+    let selected = format!("{BATTERY_PERCENT}{{device=~\"$device\"}}");
+    let percent = json!({
+        "unit": "percent",
+        "min": 0,
+        "max": 100,
+        "thresholds": level_thresholds(alerts)
+    });
+
+    json!({
+        "__inputs": [{
+            "name": "DS_BTMON",
+            "label": "btmon",
+            "description": "Data source scraping btmon",
+            "type": "datasource",
+            "pluginId": datasource.plugin(),
+            "pluginName": datasource.name()
+        }],
+        "title": "btmon",
+        "uid": "btmon",
+        "tags": ["btmon", "bluetooth", "battery"],
+        "timezone": "browser",
+        "schemaVersion": 39,
+        "refresh": "1m",
+        "time": { "from": "now-24h", "to": "now" },
+        "templating": {
+            "list": [{
+                "name": "device",
+                "label": "Device",
+                "type": "query",
+                "datasource": source,
+                "query": format!("label_values({BATTERY_PERCENT}, device)"),
+                "refresh": 2,
+                "multi": true,
+                "includeAll": true,
+                "current": { "text": "All", "value": "$__all" }
+            }]
+        },
+        "panels": [
+            {
+                "id": 1,
+                "type": "timeseries",
+                "title": "Battery level",
+                "gridPos": { "x": 0, "y": 0, "w": 16, "h": 10 },
+                "datasource": source,
+                "fieldConfig": {
+                    "defaults": {
+                        "unit": "percent",
+                        "min": 0,
+                        "max": 100,
+                        "thresholds": level_thresholds(alerts),
+                        "custom": { "thresholdsStyle": { "mode": "dashed" } }
+                    }
+                },
+                "targets": [target(&selected, "{{device}} {{component}}", &source)]
+            },
+            {
+                "id": 2,
+                "type": "stat",
+                "title": "Devices",
+                "gridPos": { "x": 16, "y": 0, "w": 8, "h": 4 },
+                "datasource": source,
+                "targets": [target(DEVICES, "devices", &source)]
+            },
+            {
+                "id": 3,
+                "type": "bargauge",
+                "title": "Lowest battery",
+                "gridPos": { "x": 16, "y": 4, "w": 8, "h": 6 },
+                "datasource": source,
+                "options": { "orientation": "horizontal", "displayMode": "lcd" },
+                "fieldConfig": { "defaults": percent },
+                "targets": [target(
+                    &format!("min by (device) ({selected})"),
+                    "{{device}}",
+                    &source
+                )]
+            },
+            {
+                "id": 4,
+                "type": "timeseries",
+                "title": "Reading age",
+                "gridPos": { "x": 0, "y": 10, "w": 24, "h": 8 },
+                "datasource": source,
+                "fieldConfig": { "defaults": { "unit": "s", "min": 0 } },
+                "targets": [target(
+                    &format!("{READING_AGE_SECONDS}{{device=~\"$device\"}}"),
+                    "{{device}}",
+                    &source
+                )]
+            }
+        ]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::{Device, Freshness};
+    use crate::metrics;
+
+    #[test]
+    fn test_dashboard_matches_metrics() {
+        let devices = [Device {
+            freshness: Some(Freshness::new(chrono::Utc::now())),
+            ..Device::test_ble("Keyboard", 76)
+        }];
+        let exported = metrics::format_metrics(&devices);
+
+        let dashboard = dashboard(Datasource::Prometheus, &AlertConfig::default());
+        let panels = dashboard["panels"].as_array().unwrap();
+        assert_eq!(panels.len(), 4);
+        for panel in panels {
+            let expr = panel["targets"][0]["expr"].as_str().unwrap();
+            let metric = expr
+                .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .find(|word| word.starts_with("btmon_"))
+                .unwrap();
+            // Every queried metric is one btmon exports
+            assert!(exported.contains(&format!("\n{metric}")), "{metric}");
+        }
+
+        let steps = &panels[0]["fieldConfig"]["defaults"]["thresholds"]["steps"];
+        assert_eq!(steps[1]["value"], AlertConfig::default().critical + 1);
+        assert_eq!(steps[2]["value"], AlertConfig::default().warn + 1);
+    }
+}
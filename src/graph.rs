@@ -0,0 +1,249 @@
+//! Terminal graphs of the battery history
+//!
+//! `btmon graph` draws each battery component of the matching devices as its
+//! own chart, stacked on the same time axis, so a bud that drains faster than
+//! the other stands out. Charts use braille characters, which give every
+//! character cell a 2×4 grid of dots.
+
+use crate::device::Component;
+use crate::history::Reading;
+use chrono::{DateTime, Local, Utc};
+use std::fmt::Write;
+
+/// Default chart width in characters, not counting the axis labels
+pub const DEFAULT_WIDTH: usize = 60;
+
+/// Default chart height in lines
+pub const DEFAULT_HEIGHT: usize = 8;
+
+/// Width of the percentage labels left of the axis
+const LABEL_WIDTH: usize = 5;
+
+/// Fraction of the time range a gap between readings may always span and
+/// still be drawn as a line
+const MAX_GAP_FRACTION: f64 = 0.05;
+
+/// Longer gaps are joined too if they are at most this many times the usual
+/// time between readings; anything longer (the device was off) stays empty
+const MAX_GAP_INTERVALS: i64 = 3;
+
+/// Dots of a braille cell by column and row, top to bottom
+const DOTS: [[u8; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+
+/// Readings of one device's component: (time, level) points
+type Series<'a> = (&'a str, Component, Vec<(DateTime<Utc>, u8)>);
+
+/// A grid of braille dots
+struct Canvas {
+    width: usize,
+    height: usize,
+    cells: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![0; width * height],
+        }
+    }
+
+    /// Set the dot at `x` (0 is left) and `y` (0 is the bottom)
+    fn set(&mut self, x: usize, y: usize) {
+        if x >= self.width * 2 || y >= self.height * 4 {
+            return;
+        }
+        let row = self.height * 4 - 1 - y;
+        self.cells[row / 4 * self.width + x / 2] |= DOTS[x % 2][row % 4];
+    }
+
+    /// Characters of line `row`, from the top
+    fn line(&self, row: usize) -> String {
+        self.cells[row * self.width..(row + 1) * self.width]
+            .iter()
+            .map(|&bits| char::from_u32(0x2800 + u32::from(bits)).unwrap_or(' '))
+            .collect()
+    }
+}
+
+/// Draw one series of (time, level) points
+fn plot(
+    points: &[(DateTime<Utc>, u8)],
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+    width: usize,
+    height: usize,
+) -> Canvas {
+    let mut canvas = Canvas::new(width, height);
+    let span = (until - since).num_milliseconds().max(1) as f64;
+    let max_x = (width * 2 - 1) as f64;
+    let max_y = (height * 4 - 1) as f64;
+    let x_of = |at: DateTime<Utc>| ((at - since).num_milliseconds() as f64 / span * max_x).round();
+    let y_of = |level: u8| (f64::from(level.min(100)) / 100.0 * max_y).round();
+
+    let mut intervals: Vec<i64> = points
+        .windows(2)
+        .map(|pair| (pair[1].0 - pair[0].0).num_milliseconds())
+        .collect();
+    intervals.sort_unstable();
+    let median = intervals.get(intervals.len() / 2).copied().unwrap_or(0);
+    let max_gap = (span * MAX_GAP_FRACTION).max((median * MAX_GAP_INTERVALS) as f64);
+
+    let mut previous: Option<(DateTime<Utc>, f64, f64)> = None;
+    for &(at, level) in points {
+        let (x, y) = (x_of(at), y_of(level));
+        if let Some((then, px, py)) = previous {
+            let gap = (at - then).num_milliseconds() as f64;
+            if gap <= max_gap && x > px {
+                for step in px as usize + 1..x as usize {
+                    let t = (step as f64 - px) / (x - px);
+                    canvas.set(step, (py + (y - py) * t).round() as usize);
+                }
+            }
+        }
+        canvas.set(x as usize, y as usize);
+        previous = Some((at, x, y));
+    }
+    canvas
+}
+
+/// Chart title of a device's component
+fn title(device: &str, component: Component) -> String {
+    match component {
+        Component::Level => device.to_string(),
+        _ => format!("{device} ({})", component.key()),
+    }
+}
+
+/// Render a chart per device and component of `readings`, covering the time
+/// from `since` to `until` in `width` × `height` characters
+pub fn render(
+    readings: &[Reading],
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+    width: usize,
+    height: usize,
+) -> String {
+    let (width, height) = (width.max(2), height.max(1));
+
+    // Series in order of the devices' first reading, components in display
+    // order
+    let mut series: Vec<Series> = Vec::new();
+    for reading in readings {
+        match series.iter_mut().find(|(device, component, _)| {
+            *device == reading.device && *component == reading.component
+        }) {
+            Some((_, _, points)) => points.push((reading.at, reading.level)),
+            None => series.push((
+                &reading.device,
+                reading.component,
+                vec![(reading.at, reading.level)],
+            )),
+        }
+    }
+    let order: Vec<&str> = series.iter().map(|(device, _, _)| *device).collect();
+    series
+        .sort_by_key(|(device, component, _)| (order.iter().position(|d| d == device), *component));
+
+    let axis_start = since
+        .with_timezone(&Local)
+        .format("%m-%d %H:%M")
+        .to_string();
+    let axis_end = until
+        .with_timezone(&Local)
+        .format("%m-%d %H:%M")
+        .to_string();
+
+    let mut out = String::new();
+    for (index, (device, component, mut points)) in series.into_iter().enumerate() {
+        points.sort_by_key(|(at, _)| *at);
+        let canvas = plot(&points, since, until, width, height);
+        let (_, latest) = points[points.len() - 1];
+
+        if index > 0 {
+            out.push('\n');
+        }
+        let _ = writeln!(out, "{}  {latest}%", title(device, component));
+        for row in 0..height {
+            let label = if row == 0 {
+                "100%"
+            } else if row == height - 1 {
+                "0%"
+            } else if height > 2 && row == (height - 1) / 2 {
+                "50%"
+            } else {
+                ""
+            };
+            let _ = writeln!(out, "{label:>LABEL_WIDTH$}┤{}", canvas.line(row));
+        }
+        let _ = writeln!(out, "{:LABEL_WIDTH$}└{}", "", "─".repeat(width));
+        let padding = (width + 1).saturating_sub(axis_start.len() + axis_end.len());
+        let _ = writeln!(
+            out,
+            "{:LABEL_WIDTH$}{axis_start}{:padding$}{axis_end}",
+            "", ""
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(minutes: i64, component: Component, level: u8) -> Reading {
+        Reading {
+            at: DateTime::parse_from_rfc3339("2026-10-15T12:00:00Z")
+                .unwrap()
+                .with_timezone(&Utc)
+                + chrono::Duration::minutes(minutes),
+            device: "AirPods Pro".to_string(),
+            component,
+            level,
+        }
+    }
+
+    #[test]
+    fn test_plot() {
+        // A full battery draining to empty over the whole range: a diagonal
+        // from the top left to the bottom right
+        let points = [
+            reading(0, Component::Left, 100),
+            reading(60, Component::Left, 0),
+        ]
+        .map(|r| (r.at, r.level));
+        let canvas = plot(&points, points[0].0, points[1].0, 2, 1);
+        assert_eq!(canvas.line(0), "⠑⢄");
+
+        // Readings every 10 minutes aren't joined across an hour's gap
+        let points = [
+            reading(0, Component::Left, 100),
+            reading(10, Component::Left, 100),
+            reading(20, Component::Left, 100),
+            reading(80, Component::Left, 0),
+        ]
+        .map(|r| (r.at, r.level));
+        let until = points[0].0 + chrono::Duration::hours(2);
+        let canvas = plot(&points, points[0].0, until, 8, 1);
+        assert_eq!(canvas.line(0), "⠉⠉⠀⠀⠀⡀⠀⠀");
+    }
+
+    #[test]
+    fn test_render() {
+        let readings = [
+            reading(0, Component::Right, 90),
+            reading(0, Component::Left, 80),
+            reading(30, Component::Right, 85),
+            reading(30, Component::Left, 60),
+        ];
+        let chart = render(&readings, readings[0].at, readings[2].at, 20, 4);
+        let lines: Vec<&str> = chart.lines().collect();
+        assert_eq!(lines[0], "AirPods Pro (left)  60%");
+        assert!(lines[1].starts_with(" 100%┤"));
+        assert!(lines[2].starts_with("  50%┤"));
+        assert!(lines[4].starts_with("   0%┤"));
+        assert_eq!(lines[5], format!("     └{}", "─".repeat(20)));
+        assert!(chart.contains("\n\nAirPods Pro (right)  85%\n"));
+    }
+}
@@ -0,0 +1,750 @@
+//! Battery history
+//!
+//! With `--history` (or `record = true` in `[history]`), every scan appends
+//! one reading per battery component to a JSON Lines file in the cache
+//! directory. `btmon history` lists the readings over a time range, and
+//! they are the data behind trends and graphs, and `btmon export` dumps them
+//! as CSV or JSON for analysis elsewhere. `btmon history import` merges logs
+//! kept by other tools into it (see [`crate::import`]). Readings older than
+//! the retention period are dropped as new ones are recorded.
+//!
+//! Devices connecting and disconnecting are kept next to the readings, in
+//! `history.events.jsonl`, with their battery at the time: a recorded scan
+//! compares the devices it found with the ones last recorded as connected,
+//! and `btmon events` records the changes IOBluetooth reports as they
+//! happen. `btmon history events` lists them.
+//!
+//! With `encrypt = true` in `[history]`, both files are written encrypted
+//! line by line (see [`crate::encryption`]).
+
+use crate::config::Interval;
+use crate::csvlog::escape_field;
+use crate::device::{Component, Device, matches_filter};
+use crate::encryption::{self, EncryptionError, Key};
+use crate::link::LinkEventKind;
+use crate::output::Format;
+use crate::paired::LastSeen;
+use chrono::{DateTime, Local, SecondsFormat, TimeDelta, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Header row of `btmon export --format csv`
+pub const CSV_HEADER: &str = "timestamp,device,component,level";
+
+/// Default time readings are kept for
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+
+/// How far past the retention period readings may be before the file is
+/// pruned, so it is rewritten about once a day instead of on every scan
+const PRUNE_SLACK: TimeDelta = TimeDelta::days(1);
+
+/// `[history]` configuration section
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// Record every scan, as with `--history`
+    pub record: bool,
+    /// How long readings are kept (default 90 days)
+    pub retention: Option<Interval>,
+    /// Encrypt the history files with a key kept in the Keychain
+    pub encrypt: bool,
+}
+
+impl HistoryConfig {
+    /// How long readings are kept
+    pub fn retention(&self) -> Duration {
+        self.retention
+            .map_or(DEFAULT_RETENTION, |interval| interval.0)
+    }
+}
+
+/// One battery component's level at a point in time
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reading {
+    pub at: DateTime<Utc>,
+    pub device: String,
+    pub component: Component,
+    pub level: u8,
+}
+
+/// Readings of each component of the devices
+pub fn readings(devices: &[Device], at: DateTime<Utc>) -> Vec<Reading> {
+    devices
+        .iter()
+        .flat_map(|device| {
+            device.components().map(move |(component, level)| Reading {
+                at,
+                device: device.name.clone(),
+                component,
+                level: level.as_percentage(),
+            })
+        })
+        .collect()
+}
+
+/// A device connecting or disconnecting
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Connection {
+    pub at: DateTime<Utc>,
+    pub device: String,
+    pub event: LinkEventKind,
+    /// Lowest battery level of the device at the time, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<u8>,
+}
+
+/// Lowest level of a device's components
+pub fn lowest_level(device: &Device) -> Option<u8> {
+    device
+        .components()
+        .map(|(_, level)| level.as_percentage())
+        .min()
+}
+
+/// History file of readings
+pub struct History {
+    path: PathBuf,
+    /// Whether lines are written encrypted
+    encrypt: bool,
+    /// Key of encrypted lines, looked up in the Keychain when first needed
+    key: OnceLock<Result<Option<Key>, EncryptionError>>,
+}
+
+impl History {
+    /// History kept in the given file
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            encrypt: false,
+            key: OnceLock::new(),
+        }
+    }
+
+    /// Write new lines encrypted, and encrypt the plain ones when the files
+    /// are rewritten. Encrypted lines are read either way
+    pub fn encrypt(mut self, enabled: bool) -> Self {
+        self.encrypt = enabled;
+        self
+    }
+
+    /// Location of the history in the cache directory
+    pub fn default_path() -> Option<PathBuf> {
+        crate::paths::cache_dir().map(|dir| dir.join("history.jsonl"))
+    }
+
+    /// Append readings, first dropping the ones older than `retention` once
+    /// the oldest is a day past it, or encrypting the files if encryption
+    /// was just turned on
+    pub fn append(&self, readings: &[Reading], retention: Duration) -> io::Result<()> {
+        if readings.is_empty() {
+            return Ok(());
+        }
+        let _lock = self.lock()?;
+        let cutoff = readings[0].at - retention;
+        if self
+            .oldest()?
+            .is_some_and(|oldest| oldest < cutoff - PRUNE_SLACK)
+            || self.has_plain_lines(&self.path)?
+            || self.has_plain_lines(&self.connections_path())?
+        {
+            self.prune(cutoff)?;
+        }
+        self.append_lines(&self.path, readings)
+    }
+
+    /// Connection events kept next to the readings
+    fn connections_path(&self) -> PathBuf {
+        self.path.with_extension("events.jsonl")
+    }
+
+    /// Record the devices connected and disconnected since the last recorded
+    /// scan or event: devices found but not connected as of the last event
+    /// connected, and connected devices matching `name_filter` that weren't
+    /// found disconnected, with their level in `last_seen`
+    pub fn record_scan(
+        &self,
+        devices: &[Device],
+        name_filter: Option<&str>,
+        at: DateTime<Utc>,
+        last_seen: &LastSeen,
+    ) -> io::Result<Vec<Connection>> {
+        let _lock = self.lock()?;
+        let last = self.last_events()?;
+        let connected = |name: &str| last.get(name) == Some(&LinkEventKind::Connected);
+        let mut events: Vec<Connection> = devices
+            .iter()
+            .filter(|device| device.has_battery_info() && !connected(&device.name))
+            .map(|device| Connection {
+                at,
+                device: device.name.clone(),
+                event: LinkEventKind::Connected,
+                level: lowest_level(device),
+            })
+            .collect();
+
+        let filter = name_filter.map(str::to_lowercase);
+        for (name, _) in last
+            .iter()
+            .filter(|(_, event)| **event == LinkEventKind::Connected)
+        {
+            if matches_filter(name, filter.as_deref()) && !devices.iter().any(|d| &d.name == name) {
+                events.push(Connection {
+                    at,
+                    device: name.clone(),
+                    event: LinkEventKind::Disconnected,
+                    level: last_seen.level(name),
+                });
+            }
+        }
+        self.append_lines(&self.connections_path(), &events)?;
+        Ok(events)
+    }
+
+    /// Record a connection event, unless it repeats the device's last one.
+    /// A disconnect without a level gets the one in `last_seen`. Returns
+    /// whether it was recorded
+    pub fn record_connection(
+        &self,
+        mut connection: Connection,
+        last_seen: &LastSeen,
+    ) -> io::Result<bool> {
+        let _lock = self.lock()?;
+        if self.last_events()?.get(&connection.device) == Some(&connection.event) {
+            return Ok(false);
+        }
+        if connection.level.is_none() && connection.event == LinkEventKind::Disconnected {
+            connection.level = last_seen.level(&connection.device);
+        }
+        self.append_lines(&self.connections_path(), &[connection])?;
+        Ok(true)
+    }
+
+    /// Last recorded event of each device
+    fn last_events(&self) -> io::Result<BTreeMap<String, LinkEventKind>> {
+        Ok(self
+            .load_connections(None, DateTime::<Utc>::MIN_UTC)?
+            .into_iter()
+            .map(|connection| (connection.device, connection.event))
+            .collect())
+    }
+
+    /// Connection events at or after `since`, of devices whose name contains
+    /// `device` (case-insensitive) if given, oldest first
+    pub fn load_connections(
+        &self,
+        device: Option<&str>,
+        since: DateTime<Utc>,
+    ) -> io::Result<Vec<Connection>> {
+        let device = device.map(str::to_lowercase);
+        Ok(self
+            .load_lines::<Connection>(&self.connections_path())?
+            .into_iter()
+            .filter(|c| c.at >= since && matches_filter(&c.device, device.as_deref()))
+            .collect())
+    }
+
+    /// Append lines of JSON to `path`
+    fn append_lines<T: Serialize>(&self, path: &Path, records: &[T]) -> io::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut out = String::new();
+        for record in records {
+            out.push_str(&self.encode_line(record)?);
+            out.push('\n');
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(out.as_bytes())
+    }
+
+    /// Time of the first reading, None if there is none
+    fn oldest(&self) -> io::Result<Option<DateTime<Utc>>> {
+        let Some(line) = first_line(&self.path)? else {
+            return Ok(None);
+        };
+        Ok(self
+            .decode_line::<Reading>(&line)?
+            .map(|reading| reading.at))
+    }
+
+    /// Whether lines are written encrypted but the file starts with a plain
+    /// one, written before encryption was turned on
+    fn has_plain_lines(&self, path: &Path) -> io::Result<bool> {
+        if !self.encrypt {
+            return Ok(false);
+        }
+        Ok(first_line(path)?.is_some_and(|line| !encryption::is_sealed(&line)))
+    }
+
+    /// Rewrite the files without the readings and events before `cutoff`
+    fn prune(&self, cutoff: DateTime<Utc>) -> io::Result<()> {
+        let kept = self.load(None, cutoff)?;
+        debug!(kept = kept.len(), "Pruning battery history");
+        self.rewrite(&kept)?;
+        let connections = self.connections_path();
+        if connections.exists() {
+            self.rewrite_lines(&connections, &self.load_connections(None, cutoff)?)?;
+        }
+        Ok(())
+    }
+
+    /// Merge readings from elsewhere into the history, keeping it in time
+    /// order and skipping the ones it already has. Returns how many were added
+    pub fn import(&self, readings: Vec<Reading>) -> io::Result<usize> {
+        let _lock = self.lock()?;
+        let mut all = self.load(None, DateTime::<Utc>::MIN_UTC)?;
+        let before = all.len();
+        all.extend(readings);
+        all.sort_by(|a, b| {
+            (a.at, &a.device, a.component, a.level).cmp(&(b.at, &b.device, b.component, b.level))
+        });
+        all.dedup();
+        self.rewrite(&all)?;
+        Ok(all.len() - before)
+    }
+
+    /// Replace the file's readings
+    fn rewrite(&self, readings: &[Reading]) -> io::Result<()> {
+        self.rewrite_lines(&self.path, readings)
+    }
+
+    /// Readings taken at or after `since`, of devices whose name contains
+    /// `device` (case-insensitive) if given, oldest first
+    pub fn load(&self, device: Option<&str>, since: DateTime<Utc>) -> io::Result<Vec<Reading>> {
+        let device = device.map(str::to_lowercase);
+        Ok(self
+            .load_lines::<Reading>(&self.path)?
+            .into_iter()
+            .filter(|r| r.at >= since && matches_filter(&r.device, device.as_deref()))
+            .collect())
+    }
+
+    /// Location of the history file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Take the lock every change to the files is made under, so a scan
+    /// appending while another process prunes or imports isn't lost. It is
+    /// held until the returned file is dropped
+    fn lock(&self) -> io::Result<File> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.path.with_extension("lock"))?;
+        file.lock()?;
+        Ok(file)
+    }
+
+    /// The Keychain key, created if lines are written encrypted
+    fn key(&self) -> io::Result<&Key> {
+        match self.key.get_or_init(|| Key::load(self.encrypt)) {
+            Ok(Some(key)) => Ok(key),
+            Ok(None) => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "the history is encrypted, but its key is not in the Keychain",
+            )),
+            Err(e) => Err(e.clone().into()),
+        }
+    }
+
+    /// A record as a line of the file, encrypted if enabled
+    fn encode_line<T: Serialize>(&self, record: &T) -> io::Result<String> {
+        let json = serde_json::to_string(record)?;
+        if !self.encrypt {
+            return Ok(json);
+        }
+        Ok(encryption::seal(self.key()?, json.as_bytes())?)
+    }
+
+    /// The record of a line, None if it is malformed. Fails if the line is
+    /// encrypted and the key can't be had
+    fn decode_line<T: DeserializeOwned>(&self, line: &str) -> io::Result<Option<T>> {
+        if !encryption::is_sealed(line) {
+            return Ok(serde_json::from_str(line).ok());
+        }
+        match encryption::open(self.key()?, line) {
+            Ok(json) => Ok(serde_json::from_slice(&json).ok()),
+            Err(EncryptionError::Corrupt) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Records of a JSON Lines file, skipping malformed lines; empty if
+    /// there is no file
+    fn load_lines<T: DeserializeOwned>(&self, path: &Path) -> io::Result<Vec<T>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut records = Vec::new();
+        let mut malformed = 0;
+        for line in BufReader::new(file).lines() {
+            match self.decode_line(&line?)? {
+                Some(record) => records.push(record),
+                None => malformed += 1,
+            }
+        }
+        if malformed > 0 {
+            warn!(malformed, path = %path.display(), "Skipped malformed history lines");
+        }
+        Ok(records)
+    }
+
+    /// Replace the records of a JSON Lines file
+    fn rewrite_lines<T: Serialize>(&self, path: &Path, records: &[T]) -> io::Result<()> {
+        let mut out = String::new();
+        for record in records {
+            out.push_str(&self.encode_line(record)?);
+            out.push('\n');
+        }
+        // Written aside and renamed, so a crash can't leave half a history
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, out)?;
+        fs::rename(&tmp, path)
+    }
+}
+
+/// First line of a file, None if there is no file or it is empty
+fn first_line(path: &Path) -> io::Result<Option<String>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut line = String::new();
+    BufReader::new(file).read_line(&mut line)?;
+    let line = line.trim_end();
+    Ok((!line.is_empty()).then(|| line.to_string()))
+}
+
+/// Format readings as text, one line per device and scan with its
+/// components side by side
+pub fn format_readings(readings: &[Reading]) -> String {
+    let mut out = String::new();
+    let mut current: Option<(&DateTime<Utc>, &str)> = None;
+    for reading in readings {
+        if current != Some((&reading.at, &reading.device)) {
+            if current.is_some() {
+                out.push('\n');
+            }
+            let at = reading.at.with_timezone(&Local).format("%Y-%m-%d %H:%M");
+            let _ = write!(out, "{at}  {}:", reading.device);
+            current = Some((&reading.at, &reading.device));
+        }
+        match reading.component.label() {
+            Some(label) => {
+                let _ = write!(out, " {label}:{}%", reading.level);
+            }
+            None => {
+                let _ = write!(out, " {}%", reading.level);
+            }
+        }
+    }
+    if current.is_some() {
+        out.push('\n');
+    }
+    out
+}
+
+/// Format connection events as text, one line each
+pub fn format_connections(connections: &[Connection]) -> String {
+    let mut out = String::new();
+    for connection in connections {
+        let at = connection.at.with_timezone(&Local).format("%Y-%m-%d %H:%M");
+        let event = match connection.event {
+            LinkEventKind::Connected => "connected",
+            LinkEventKind::Disconnected => "disconnected",
+        };
+        let _ = write!(out, "{at}  {} {event}", connection.device);
+        if let Some(level) = connection.level {
+            let _ = write!(out, " at {level}%");
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Whether `btmon export` can write a format: CSV (the default), JSON or an
+/// Excel workbook
+pub fn exports(format: Format) -> bool {
+    matches!(
+        format,
+        Format::Text | Format::Csv | Format::Json | Format::Xlsx
+    )
+}
+
+/// Format readings as CSV with a header row, one reading per row
+pub fn format_csv(readings: &[Reading]) -> String {
+    let mut out = format!("{CSV_HEADER}\n");
+    for reading in readings {
+        let _ = writeln!(
+            out,
+            "{},{},{},{}",
+            reading.at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            escape_field(&reading.device),
+            reading.component.key(),
+            reading.level
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(days_ago: i64, device: &str, component: Component, level: u8) -> Reading {
+        Reading {
+            at: DateTime::parse_from_rfc3339("2026-10-15T12:00:00Z")
+                .unwrap()
+                .with_timezone(&Utc)
+                - chrono::Duration::days(days_ago),
+            device: device.to_string(),
+            component,
+            level,
+        }
+    }
+
+    #[test]
+    fn test_append_and_load() {
+        let dir = std::env::temp_dir().join(format!("btmon-history-{}", std::process::id()));
+        let history = History::new(dir.join("history.jsonl"));
+        let week = Duration::from_secs(7 * 24 * 60 * 60);
+
+        history
+            .append(
+                &[
+                    reading(10, "AirPods Pro", Component::Left, 90),
+                    reading(10, "Keyboard", Component::Level, 70),
+                ],
+                Duration::from_secs(365 * 24 * 60 * 60),
+            )
+            .unwrap();
+        history
+            .append(&[reading(2, "AirPods Pro", Component::Left, 60)], week)
+            .unwrap();
+        // Only a day past the retention, so not pruned yet
+        let all = history.load(None, DateTime::<Utc>::MIN_UTC).unwrap();
+        assert_eq!(all.len(), 3);
+        history
+            .append(&[reading(1, "AirPods Pro", Component::Left, 40)], week)
+            .unwrap();
+
+        // The readings from 10 days ago were pruned
+        let all = history.load(None, DateTime::<Utc>::MIN_UTC).unwrap();
+        assert_eq!(all.len(), 2);
+
+        let since = reading(1, "", Component::Level, 0).at;
+        let recent = history.load(Some("airpods"), since).unwrap();
+        assert_eq!(recent, [reading(1, "AirPods Pro", Component::Left, 40)]);
+        assert!(history.load(Some("mouse"), since).unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_import() {
+        let dir = std::env::temp_dir().join(format!("btmon-import-{}", std::process::id()));
+        let history = History::new(dir.join("history.jsonl"));
+        let year = Duration::from_secs(365 * 24 * 60 * 60);
+        history
+            .append(&[reading(1, "Keyboard", Component::Level, 70)], year)
+            .unwrap();
+
+        let imported = [
+            reading(3, "Keyboard", Component::Level, 90),
+            reading(1, "Keyboard", Component::Level, 70),
+        ];
+        assert_eq!(history.import(imported.to_vec()).unwrap(), 1);
+        // Older readings go first, so the file stays in time order
+        let all = history.load(None, DateTime::<Utc>::MIN_UTC).unwrap();
+        assert_eq!(all, imported);
+        assert_eq!(history.import(imported.to_vec()).unwrap(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_writes() {
+        let dir = std::env::temp_dir().join(format!("btmon-locking-{}", std::process::id()));
+        let path = dir.join("history.jsonl");
+        let year = Duration::from_secs(365 * 24 * 60 * 60);
+
+        // Scans appending while imports rewrite the file lose nothing
+        let appenders: Vec<_> = (0..4)
+            .map(|thread| {
+                let history = History::new(path.clone());
+                std::thread::spawn(move || {
+                    for i in 0..25 {
+                        let name = format!("Device {thread}-{i}");
+                        history
+                            .append(&[reading(1, &name, Component::Level, 50)], year)
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        let history = History::new(path.clone());
+        for i in 0..25 {
+            let name = format!("Imported {i}");
+            history
+                .import(vec![reading(2, &name, Component::Level, 50)])
+                .unwrap();
+        }
+        for appender in appenders {
+            appender.join().unwrap();
+        }
+
+        let all = history.load(None, DateTime::<Utc>::MIN_UTC).unwrap();
+        assert_eq!(all.len(), 4 * 25 + 25);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_record_connections() {
+        let dir = std::env::temp_dir().join(format!("btmon-connections-{}", std::process::id()));
+        let history = History::new(dir.join("history.jsonl"));
+        let year = Duration::from_secs(365 * 24 * 60 * 60);
+        let at = |days_ago| reading(days_ago, "", Component::Level, 0).at;
+        let keyboard = Device::test_ble("Keyboard", 70);
+        let mouse = Device::test_ble("Mouse", 40);
+
+        let devices = [keyboard.clone(), mouse.clone()];
+        let mut seen = LastSeen::default();
+        seen.record(&devices, at(3));
+        history.append(&readings(&devices, at(3)), year).unwrap();
+        let events = history.record_scan(&devices, None, at(3), &seen).unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|e| e.event == LinkEventKind::Connected));
+        // Still connected, nothing new
+        assert!(
+            history
+                .record_scan(&devices, None, at(2), &seen)
+                .unwrap()
+                .is_empty()
+        );
+
+        // A run filtered to the keyboard doesn't disconnect the mouse
+        let filtered = history
+            .record_scan(std::slice::from_ref(&keyboard), Some("key"), at(2), &seen)
+            .unwrap();
+        assert!(filtered.is_empty());
+        let events = history
+            .record_scan(std::slice::from_ref(&keyboard), None, at(1), &seen)
+            .unwrap();
+        assert_eq!(
+            events,
+            [Connection {
+                at: at(1),
+                device: "Mouse".to_string(),
+                event: LinkEventKind::Disconnected,
+                level: Some(40),
+            }]
+        );
+
+        // IOBluetooth reporting what a scan already recorded is skipped
+        let connection = Connection {
+            at: at(0),
+            device: "Keyboard".to_string(),
+            event: LinkEventKind::Disconnected,
+            level: None,
+        };
+        assert!(
+            history
+                .record_connection(connection.clone(), &seen)
+                .unwrap()
+        );
+        assert!(!history.record_connection(connection, &seen).unwrap());
+
+        let recorded = history.load_connections(Some("keyboard"), at(5)).unwrap();
+        assert_eq!(recorded.len(), 2);
+        // The level of the last scan
+        assert_eq!(recorded[1].level, Some(70));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_format_connections() {
+        let at = reading(0, "", Component::Level, 0).at;
+        let text = format_connections(&[
+            Connection {
+                at,
+                device: "AirPods Pro".to_string(),
+                event: LinkEventKind::Disconnected,
+                level: Some(2),
+            },
+            Connection {
+                at,
+                device: "Keyboard".to_string(),
+                event: LinkEventKind::Connected,
+                level: None,
+            },
+        ]);
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines[0].ends_with("  AirPods Pro disconnected at 2%"));
+        assert!(lines[1].ends_with("  Keyboard connected"));
+    }
+
+    #[test]
+    fn test_format_readings() {
+        let readings = [
+            reading(1, "AirPods Pro", Component::Left, 80),
+            reading(1, "AirPods Pro", Component::Right, 90),
+            reading(1, "Keyboard", Component::Level, 70),
+        ];
+        let text = format_readings(&readings);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("  AirPods Pro: L:80% R:90%"));
+        assert!(lines[1].ends_with("  Keyboard: 70%"));
+    }
+
+    #[test]
+    fn test_format_csv() {
+        let readings = [
+            reading(1, "AirPods Pro", Component::Case, 100),
+            reading(0, "Desk, left", Component::Level, 55),
+        ];
+        assert_eq!(
+            format_csv(&readings),
+            "timestamp,device,component,level\n\
+             2026-10-14T12:00:00Z,AirPods Pro,case,100\n\
+             2026-10-15T12:00:00Z,\"Desk, left\",level,55\n"
+        );
+        assert_eq!(format_csv(&[]), "timestamp,device,component,level\n");
+    }
+
+    #[test]
+    fn test_exports() {
+        for format in [Format::Text, Format::Csv, Format::Json, Format::Xlsx] {
+            assert!(exports(format), "{format:?}");
+        }
+        for format in [
+            Format::Shortcuts,
+            Format::Yaml,
+            Format::Table,
+            Format::Markdown,
+            Format::Short,
+        ] {
+            assert!(!exports(format), "{format:?}");
+        }
+    }
+}
@@ -0,0 +1,77 @@
+//! Shared HTTP helpers for notification channels and the API
+
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Timeout for a single outgoing request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Build an HTTP agent using the platform TLS implementation
+pub fn agent() -> Result<ureq::Agent, native_tls::Error> {
+    let tls = native_tls::TlsConnector::new()?;
+    Ok(ureq::AgentBuilder::new()
+        .tls_connector(Arc::new(tls))
+        .timeout(REQUEST_TIMEOUT)
+        .user_agent(concat!("btmon/", env!("CARGO_PKG_VERSION")))
+        .build())
+}
+
+/// Percent-encode a string for use as a single URL path segment
+pub fn encode_path_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(char::from(byte));
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+/// Decode a percent-encoded URL query component, with `+` as a space
+pub fn decode_query_component(component: &str) -> Option<String> {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let hex = component.get(i + 1..i + 3)?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 2;
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_path_segment() {
+        assert_eq!(encode_path_segment("abc-1.2_~"), "abc-1.2_~");
+        assert_eq!(
+            encode_path_segment("!room:example.org"),
+            "%21room%3Aexample.org"
+        );
+        assert_eq!(encode_path_segment("a b/c"), "a%20b%2Fc");
+    }
+
+    #[test]
+    fn test_decode_query_component() {
+        assert_eq!(
+            decode_query_component("AirPods+Pro").unwrap(),
+            "AirPods Pro"
+        );
+        assert_eq!(decode_query_component("a%20b%2Fc").unwrap(), "a b/c");
+        assert!(decode_query_component("100%").is_none());
+        assert!(decode_query_component("%zz").is_none());
+    }
+}
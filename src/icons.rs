@@ -0,0 +1,151 @@
+//! Device and battery glyphs for `--icons`
+//!
+//! Text output is prefixed with a glyph for the kind of device and one for
+//! its battery level, and the tmux and prompt formats use the device glyph.
+//! `emoji` works in any terminal; `nerd` needs a Nerd Font. Either table
+//! can be overridden in the `[icons.emoji]` and `[icons.nerd]` config
+//! sections.
+
+use crate::device::{BatteryLevel, Device};
+use crate::output::{device_icon, device_kind};
+use serde::Deserialize;
+
+/// Glyph set selected with `--icons`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum IconStyle {
+    /// Emoji, as in the tmux and prompt formats
+    #[default]
+    Emoji,
+    /// Nerd Font glyphs
+    Nerd,
+}
+
+/// Nerd Font battery glyphs from empty to full, in steps of 10%
+const NERD_BATTERY: [&str; 11] = [
+    "\u{f008e}",
+    "\u{f007a}",
+    "\u{f007b}",
+    "\u{f007c}",
+    "\u{f007d}",
+    "\u{f007e}",
+    "\u{f007f}",
+    "\u{f0080}",
+    "\u{f0081}",
+    "\u{f0082}",
+    "\u{f0079}",
+];
+
+/// Emoji battery glyphs from empty to full, low at 20% and below
+const EMOJI_BATTERY: [&str; 5] = ["🪫", "🔋", "🔋", "🔋", "🔋"];
+
+/// `[icons]` configuration section
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IconsConfig {
+    /// Overrides of `--icons emoji`
+    pub emoji: IconTable,
+    /// Overrides of `--icons nerd`
+    pub nerd: IconTable,
+}
+
+/// Glyphs of one style; unset ones keep the built-in glyph
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IconTable {
+    pub headphones: Option<String>,
+    pub keyboard: Option<String>,
+    pub mouse: Option<String>,
+    pub controller: Option<String>,
+    pub other: Option<String>,
+    /// Battery glyphs from empty to full, spread evenly over 0-100%
+    pub battery: Option<Vec<String>>,
+}
+
+/// Glyphs of a style with the user's overrides applied
+#[derive(Debug, Clone, Default)]
+pub struct Icons {
+    style: IconStyle,
+    table: IconTable,
+}
+
+impl Icons {
+    pub fn new(style: IconStyle, config: &IconsConfig) -> Self {
+        let table = match style {
+            IconStyle::Emoji => config.emoji.clone(),
+            IconStyle::Nerd => config.nerd.clone(),
+        };
+        Self { style, table }
+    }
+
+    /// Glyph for the kind of device
+    pub fn device(&self, device: &Device) -> &str {
+        let kind = device_kind(device);
+        let custom = match kind {
+            "headphones" => &self.table.headphones,
+            "keyboard" => &self.table.keyboard,
+            "mouse" => &self.table.mouse,
+            "controller" => &self.table.controller,
+            _ => &self.table.other,
+        };
+        if let Some(glyph) = custom {
+            return glyph;
+        }
+        match self.style {
+            IconStyle::Emoji => device_icon(device),
+            IconStyle::Nerd => match kind {
+                "headphones" => "\u{f02cb}",
+                "keyboard" => "\u{f030c}",
+                "mouse" => "\u{f037d}",
+                "controller" => "\u{f02b4}",
+                _ => "\u{f00af}",
+            },
+        }
+    }
+
+    /// Glyph for a battery level
+    pub fn battery(&self, level: BatteryLevel) -> &str {
+        let builtin: &[&str] = match self.style {
+            IconStyle::Emoji => &EMOJI_BATTERY,
+            IconStyle::Nerd => &NERD_BATTERY,
+        };
+        let pick = |len: usize| usize::from(level.as_percentage()) * len / 101;
+        match &self.table.battery {
+            Some(glyphs) if !glyphs.is_empty() => &glyphs[pick(glyphs.len())],
+            _ => builtin[pick(builtin.len())],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_icons() {
+        let level = |percent| BatteryLevel::new(percent).unwrap();
+        let mouse = Device::test_ble("Magic Mouse", 50);
+
+        let nerd = Icons::new(IconStyle::Nerd, &IconsConfig::default());
+        assert_eq!(nerd.device(&mouse), "\u{f037d}");
+        assert_eq!(nerd.battery(level(5)), "\u{f008e}");
+        assert_eq!(nerd.battery(level(48)), "\u{f007e}");
+        assert_eq!(nerd.battery(level(100)), "\u{f0079}");
+
+        let emoji = Icons::default();
+        assert_eq!(emoji.device(&mouse), "🖱️");
+        assert_eq!(emoji.battery(level(20)), "🪫");
+        assert_eq!(emoji.battery(level(21)), "🔋");
+
+        let mut config = IconsConfig::default();
+        config.nerd.mouse = Some("M".to_string());
+        config.nerd.battery = Some(vec!["low".to_string(), "high".to_string()]);
+        let custom = Icons::new(IconStyle::Nerd, &config);
+        assert_eq!(custom.device(&mouse), "M");
+        assert_eq!(
+            custom.device(&Device::test_ble("Magic Keyboard", 50)),
+            "\u{f030c}"
+        );
+        assert_eq!(custom.battery(level(50)), "low");
+        assert_eq!(custom.battery(level(51)), "high");
+    }
+}
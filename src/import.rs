@@ -0,0 +1,283 @@
+//! History import
+//!
+//! `btmon history import` maps logs kept by other tools into the battery
+//! history, so trends work from the first day. CSV files are read by their
+//! header and JSON files may hold an array or one object per line; either way
+//! a record has a time, a device name and its levels:
+//!
+//! - one reading per record, with `component` (`level`, `left`, `right` or
+//!   `case`) and `level`, like `btmon export`
+//! - or a column per component, like the `--append-csv` log and `--format
+//!   ndjson`: `level`/`battery_level`, `left`/`battery_left`, and so on
+//!
+//! Times are RFC 3339, `YYYY-MM-DD HH:MM:SS` in local time, or Unix seconds.
+
+use crate::device::Component;
+use crate::history::Reading;
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Why a file couldn't be imported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportError(pub String);
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "cannot import: {}", self.0)
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+/// Names a column or key may have, by what it holds
+const TIME_KEYS: [&str; 5] = ["timestamp", "at", "time", "date", "read_at"];
+const DEVICE_KEYS: [&str; 2] = ["device", "name"];
+const LEVEL_KEYS: [&str; 4] = ["level", "battery", "percent", "battery_level"];
+
+/// Parse the readings of a CSV or JSON log, telling them apart by the first
+/// character
+pub fn parse(contents: &str) -> Result<Vec<Reading>, ImportError> {
+    match contents.trim_start().chars().next() {
+        None => Ok(Vec::new()),
+        Some('[' | '{') => parse_json(contents),
+        Some(_) => parse_csv(contents),
+    }
+}
+
+/// Parse a CSV log with a header row
+fn parse_csv(contents: &str) -> Result<Vec<Reading>, ImportError> {
+    let mut lines = contents
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty());
+    let Some((_, header)) = lines.next() else {
+        return Ok(Vec::new());
+    };
+    let header: Vec<String> = split_csv(header)
+        .into_iter()
+        .map(|column| column.trim().to_lowercase())
+        .collect();
+
+    let mut readings = Vec::new();
+    for (index, line) in lines {
+        let values = split_csv(line);
+        let record: HashMap<&str, String> = header
+            .iter()
+            .map(String::as_str)
+            .zip(values)
+            .filter(|(_, value)| !value.is_empty())
+            .collect();
+        readings.extend(record_readings(&record).map_err(|e| at_line(index, e))?);
+    }
+    Ok(readings)
+}
+
+/// Parse a JSON array of records, or one record per line
+fn parse_json(contents: &str) -> Result<Vec<Reading>, ImportError> {
+    let records: Vec<(usize, Value)> = if contents.trim_start().starts_with('[') {
+        let records: Vec<Value> = serde_json::from_str(contents)
+            .map_err(|e| ImportError(format!("invalid JSON: {e}")))?;
+        records.into_iter().enumerate().collect()
+    } else {
+        contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                serde_json::from_str(line)
+                    .map(|record| (index, record))
+                    .map_err(|e| at_line(index, format!("invalid JSON: {e}")))
+            })
+            .collect::<Result<_, _>>()?
+    };
+
+    let mut readings = Vec::new();
+    for (index, record) in records {
+        let Value::Object(object) = record else {
+            return Err(at_line(index, "expected an object".to_string()));
+        };
+        let record: HashMap<&str, String> = object
+            .iter()
+            .filter_map(|(key, value)| {
+                let value = match value {
+                    Value::String(s) => s.clone(),
+                    Value::Number(n) => n.to_string(),
+                    _ => return None,
+                };
+                Some((key.as_str(), value))
+            })
+            .collect();
+        readings.extend(record_readings(&record).map_err(|e| at_line(index, e))?);
+    }
+    Ok(readings)
+}
+
+/// Error of the record on 0-based line or element `index`
+fn at_line(index: usize, message: String) -> ImportError {
+    ImportError(format!("record {}: {message}", index + 1))
+}
+
+/// First value of the record under one of `keys`
+fn field<'a>(record: &'a HashMap<&str, String>, keys: &[&str]) -> Option<&'a str> {
+    keys.iter()
+        .find_map(|&key| record.get(key))
+        .map(|value| value.trim())
+}
+
+/// Readings of one record
+fn record_readings(record: &HashMap<&str, String>) -> Result<Vec<Reading>, String> {
+    let at = field(record, &TIME_KEYS).ok_or("no time")?;
+    let at = parse_time(at).ok_or_else(|| format!("bad time '{at}'"))?;
+    let device = field(record, &DEVICE_KEYS)
+        .ok_or("no device name")?
+        .to_string();
+
+    let levels: Vec<(Component, &str)> = match field(record, &["component"]) {
+        Some(component) => {
+            let component = Component::ALL
+                .into_iter()
+                .find(|c| c.key() == component)
+                .ok_or_else(|| format!("unknown component '{component}'"))?;
+            let level = field(record, &LEVEL_KEYS).ok_or("no level")?;
+            vec![(component, level)]
+        }
+        None => Component::ALL
+            .into_iter()
+            .filter_map(|component| {
+                let keys: &[&str] = match component {
+                    Component::Level => &LEVEL_KEYS,
+                    Component::Left => &["left", "battery_left"],
+                    Component::Right => &["right", "battery_right"],
+                    Component::Case => &["case", "battery_case"],
+                };
+                field(record, keys).map(|level| (component, level))
+            })
+            .collect(),
+    };
+    if levels.is_empty() {
+        return Err("no battery level".to_string());
+    }
+
+    levels
+        .into_iter()
+        .map(|(component, level)| {
+            let level = level
+                .trim_end_matches('%')
+                .parse::<u8>()
+                .ok()
+                .filter(|level| (1..=100).contains(level))
+                .ok_or_else(|| format!("bad level '{level}'"))?;
+            Ok(Reading {
+                at,
+                device: device.clone(),
+                component,
+                level,
+            })
+        })
+        .collect()
+}
+
+/// Parse an RFC 3339 time, a local `YYYY-MM-DD HH:MM:SS` or Unix seconds
+fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(time.with_timezone(&Utc));
+    }
+    if let Ok(seconds) = value.parse::<i64>() {
+        return DateTime::from_timestamp(seconds, 0);
+    }
+    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .and_then(|time| Local.from_local_datetime(&time).earliest())
+        .map(|time| time.with_timezone(&Utc))
+}
+
+/// Split a CSV line into its fields, unquoting quoted ones
+fn split_csv(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::Device;
+    use crate::history;
+    use crate::output;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_round_trip_export() {
+        let readings = vec![
+            Reading {
+                at: at("2026-10-14T09:00:00Z"),
+                device: "Bob's \"AirPods\", Pro".to_string(),
+                component: Component::Left,
+                level: 80,
+            },
+            Reading {
+                at: at("2026-10-14T09:00:00Z"),
+                device: "Keyboard".to_string(),
+                component: Component::Level,
+                level: 70,
+            },
+        ];
+        assert_eq!(parse(&history::format_csv(&readings)).unwrap(), readings);
+        let json = serde_json::to_string_pretty(&readings).unwrap();
+        assert_eq!(parse(&json).unwrap(), readings);
+    }
+
+    #[test]
+    fn test_wide_logs() {
+        let airpods = Device {
+            battery_left: crate::device::BatteryLevel::new(80),
+            battery_case: crate::device::BatteryLevel::new(100),
+            ..Device::test_classic("AirPods Pro", "a4-c3-37-12-34-56")
+        };
+        let now = at("2026-10-14T09:00:00Z");
+        let expected = history::readings(std::slice::from_ref(&airpods), now);
+
+        // The --append-csv log and --format ndjson output
+        let csv = output::format_csv(std::slice::from_ref(&airpods), now);
+        assert_eq!(parse(&csv).unwrap(), expected);
+        let ndjson = output::format_ndjson(&[airpods], now, None).unwrap();
+        assert_eq!(parse(&ndjson).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_shell_log() {
+        let log = "date,name,battery\n1760432400,Magic Mouse,55%\n\n1760436000,Magic Mouse,54%\n";
+        let readings = parse(log).unwrap();
+        assert_eq!(readings.len(), 2);
+        assert_eq!(readings[0].at, at("2025-10-14T09:00:00Z"));
+        assert_eq!(readings[1].level, 54);
+
+        assert_eq!(
+            parse("date,name,battery\nyesterday,Mouse,50\n").unwrap_err(),
+            ImportError("record 2: bad time 'yesterday'".to_string())
+        );
+        assert!(parse("time,name\n1760432400,Mouse\n").is_err());
+        assert!(parse("").unwrap().is_empty());
+    }
+}
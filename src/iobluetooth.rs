@@ -0,0 +1,212 @@
+//! Classic Bluetooth battery reading via IOBluetooth
+//!
+//! This module reads battery levels of connected Classic Bluetooth devices
+//! through the private battery selectors of IOBluetoothDevice. The selectors
+//! are probed with the runtime before use, since Apple can remove or rename
+//! them in any macOS update.
+
+use crate::backend::ClassicReading;
+use chrono::Utc;
+use objc2::rc::autoreleasepool;
+use objc2::runtime::{AnyObject, MessageReceiver, Sel};
+use objc2::{ClassType, msg_send, sel};
+use objc2_foundation::{NSArray, NSString};
+use objc2_io_bluetooth::IOBluetoothDevice;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use tracing::{Span, debug, info_span, warn};
+
+/// A private battery selector and whether IOBluetoothDevice implements it
+struct BatterySelector {
+    name: &'static str,
+    sel: Sel,
+    available: bool,
+}
+
+impl BatterySelector {
+    /// Read the selector's value, 0 (unavailable) if it isn't implemented
+    fn read(&self, device: &AnyObject) -> u8 {
+        if !self.available {
+            return 0;
+        }
+        // SAFETY: The selector was probed above and the private battery
+        // selectors take no arguments and return an unsigned char.
+        unsafe { MessageReceiver::send_message(device, self.sel, ()) }
+    }
+}
+
+/// Probe the private battery selectors, in `ClassicReading` field order
+fn probe_selectors() -> [BatterySelector; 6] {
+    let class = IOBluetoothDevice::class();
+    [
+        ("batteryPercentSingle", sel!(batteryPercentSingle)),
+        ("batteryPercentLeft", sel!(batteryPercentLeft)),
+        ("batteryPercentRight", sel!(batteryPercentRight)),
+        ("batteryPercentCase", sel!(batteryPercentCase)),
+        ("batteryPercentCombined", sel!(batteryPercentCombined)),
+        ("headsetBattery", sel!(headsetBattery)),
+    ]
+    .map(|(name, sel)| BatterySelector {
+        name,
+        sel,
+        available: class.responds_to(sel),
+    })
+}
+
+/// Whether any private battery selector is implemented
+pub(crate) fn has_battery_selectors() -> bool {
+    probe_selectors().iter().any(|s| s.available)
+}
+
+/// Time budget for enumerating paired devices and reading their batteries
+const ENUMERATION_BUDGET: Duration = Duration::from_secs(3);
+
+/// Set while a worker is enumerating, so a hung worker isn't joined by more
+static WORKER_BUSY: AtomicBool = AtomicBool::new(false);
+
+/// Get raw battery values from all connected IOBluetooth devices
+///
+/// Some paired devices (certain car kits) can make IOBluetooth calls block,
+/// so the enumeration runs on a worker thread and is abandoned after
+/// [`ENUMERATION_BUDGET`]. An abandoned worker keeps later calls from
+/// starting another one until it returns.
+pub fn get_connected_readings() -> Vec<ClassicReading> {
+    let _span = info_span!("classic_scan").entered();
+    if WORKER_BUSY.swap(true, Ordering::AcqRel) {
+        warn!("Previous IOBluetooth enumeration still running, skipping Classic devices");
+        return Vec::new();
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let span = Span::current();
+    let spawned = thread::Builder::new()
+        .name("iobluetooth".to_string())
+        .spawn(move || {
+            let readings = span.in_scope(|| autoreleasepool(|_| enumerate_connected()));
+            WORKER_BUSY.store(false, Ordering::Release);
+            // The receiver is gone if the budget ran out
+            let _ = tx.send(readings);
+        });
+    if let Err(e) = spawned {
+        WORKER_BUSY.store(false, Ordering::Release);
+        warn!(error = %e, "Failed to start IOBluetooth worker");
+        return Vec::new();
+    }
+
+    match rx.recv_timeout(ENUMERATION_BUDGET) {
+        Ok(readings) => readings,
+        Err(_) => {
+            warn!(
+                budget_ms = ENUMERATION_BUDGET.as_millis(),
+                "IOBluetooth enumeration timed out, skipping Classic devices"
+            );
+            Vec::new()
+        }
+    }
+}
+
+/// Read the battery values of connected devices on the calling thread
+fn enumerate_connected() -> Vec<ClassicReading> {
+    let mut readings = Vec::new();
+
+    let selectors = probe_selectors();
+    if selectors.iter().all(|s| !s.available) {
+        warn!("No private IOBluetooth battery selectors, only GATT devices are reported");
+        return readings;
+    }
+    let unsupported: Vec<String> = selectors
+        .iter()
+        .filter(|s| !s.available)
+        .map(|s| s.name.to_string())
+        .collect();
+    for name in &unsupported {
+        warn!(
+            selector = %name,
+            "Private IOBluetooth battery selector unavailable"
+        );
+    }
+    let [single, left, right, case, combined, headset] = &selectors;
+
+    // SAFETY: IOBluetoothDevice::pairedDevices() returns a valid NSArray or nil.
+    // This is a standard Objective-C API call.
+    let paired_devices: Option<objc2::rc::Retained<NSArray<AnyObject>>> =
+        unsafe { IOBluetoothDevice::pairedDevices() };
+
+    let Some(paired) = paired_devices else {
+        debug!("No paired devices found");
+        return readings;
+    };
+
+    let count = paired.count();
+    debug!(count = count, "Found paired devices");
+
+    for i in 0..count {
+        // SAFETY: objectAtIndex returns a valid pointer for valid index (0..count).
+        let device: *const AnyObject = unsafe { msg_send![&paired, objectAtIndex: i] };
+        if device.is_null() {
+            continue;
+        }
+
+        // SAFETY: device pointer was checked for null above.
+        // The object is retained by the NSArray for the duration of iteration.
+        let device_ref = unsafe { &*device };
+
+        // SAFETY: isConnected is a standard IOBluetoothDevice method returning bool.
+        let is_connected: bool = unsafe { msg_send![device_ref, isConnected] };
+        if !is_connected {
+            continue;
+        }
+
+        // SAFETY: name returns NSString or nil.
+        let name_obj: *const NSString = unsafe { msg_send![device_ref, name] };
+        let name = if name_obj.is_null() {
+            continue;
+        } else {
+            // SAFETY: name_obj was checked for null above.
+            unsafe { (*name_obj).to_string() }
+        };
+
+        let _device_span = info_span!("classic_device", name = %name).entered();
+
+        // SAFETY: addressString returns NSString or nil.
+        let addr_obj: *const NSString = unsafe { msg_send![device_ref, addressString] };
+        let address = if addr_obj.is_null() {
+            None
+        } else {
+            // SAFETY: addr_obj was checked for null above.
+            Some(unsafe { (*addr_obj).to_string() })
+        };
+
+        // The private selectors return 0 or 255 when battery info is
+        // unavailable.
+        let reading = ClassicReading {
+            name,
+            address,
+            battery_single: single.read(device_ref),
+            battery_left: left.read(device_ref),
+            battery_right: right.read(device_ref),
+            battery_case: case.read(device_ref),
+            battery_combined: combined.read(device_ref),
+            headset_battery: headset.read(device_ref),
+            unsupported: unsupported.clone(),
+            read_at: Some(Utc::now()),
+        };
+
+        debug!(
+            name = %reading.name,
+            single = reading.battery_single,
+            left = reading.battery_left,
+            right = reading.battery_right,
+            case = reading.battery_case,
+            combined = reading.battery_combined,
+            headset = reading.headset_battery,
+            "IOBluetooth battery values"
+        );
+
+        readings.push(reading);
+    }
+
+    readings
+}
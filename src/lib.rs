@@ -0,0 +1,76 @@
+//! btmon - Bluetooth battery monitor for macOS
+//!
+//! Library side of the `btmon` CLI: device collection from the system
+//! Bluetooth APIs, the merge/filter pipeline and output formatting.
+
+#[cfg(target_os = "macos")]
+mod advertising;
+pub mod agent;
+pub mod alerts;
+pub mod alfred;
+pub mod api;
+pub mod backend;
+pub mod bonjour;
+pub mod cache;
+pub mod capabilities;
+pub mod channels;
+pub mod characteristic;
+pub mod compare;
+pub mod completions;
+pub mod config;
+pub mod connection;
+pub mod controller;
+pub mod csvlog;
+pub mod device;
+pub mod doctor;
+pub mod encryption;
+pub mod errors;
+pub mod events;
+pub mod explore;
+pub mod fields;
+#[cfg(target_os = "macos")]
+mod gatt;
+#[cfg(target_os = "macos")]
+mod gatt_explorer;
+#[cfg(test)]
+mod golden;
+pub mod grafana;
+pub mod graph;
+pub mod history;
+mod http;
+pub mod icons;
+pub mod import;
+#[cfg(all(target_os = "macos", feature = "private-api"))]
+mod iobluetooth;
+pub mod link;
+#[cfg(target_os = "macos")]
+mod link_monitor;
+pub mod locale;
+pub mod manufacturer;
+#[cfg(target_os = "macos")]
+pub mod menubar;
+pub mod metrics;
+pub mod mqtt;
+pub mod nearby;
+pub mod output;
+pub mod paired;
+pub mod paths;
+pub mod peripherals;
+pub mod query;
+pub mod raycast;
+pub mod redact;
+pub mod rpc;
+pub mod schema;
+pub mod sequence;
+pub mod sketchybar;
+pub mod smoothing;
+pub mod socket;
+pub mod status;
+pub mod template;
+pub mod timeline;
+pub mod vendor;
+pub mod watch;
+pub mod watchdog;
+pub mod xbar;
+pub mod xlsx;
+pub mod yaml;
@@ -0,0 +1,136 @@
+//! Connection event stream for `btmon events`
+//!
+//! IOBluetooth notifies registered observers whenever a Classic device
+//! connects or disconnects. `btmon events` turns each notification into
+//! one JSON line, with the device's battery read right after it connects,
+//! so scripts can react to a headset or keyboard showing up.
+
+use crate::device::{Device, DeviceAddress};
+use crate::redact;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A device connected or disconnected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkChange {
+    pub connected: bool,
+    pub name: String,
+    /// Bluetooth address as IOBluetooth prints it, e.g. `a4-c3-37-12-34-56`
+    pub address: Option<String>,
+}
+
+/// Kind of connection event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkEventKind {
+    Connected,
+    Disconnected,
+}
+
+/// One line of the event stream
+#[derive(Debug, Clone, Serialize)]
+pub struct LinkEvent {
+    pub event: LinkEventKind,
+    pub at: DateTime<Utc>,
+    pub device: String,
+    pub address: DeviceAddress,
+    /// Battery read after the device connected, if it reported one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub battery: Option<Device>,
+}
+
+impl LinkEvent {
+    /// Event for a change, masking the address with `redact`
+    pub fn new(change: LinkChange, at: DateTime<Utc>, redact: bool) -> Self {
+        let address = match change.address {
+            Some(address) if redact => DeviceAddress::Classic(redact::redact_address(&address)),
+            Some(address) => DeviceAddress::Classic(address),
+            None => DeviceAddress::Ble,
+        };
+        Self {
+            event: if change.connected {
+                LinkEventKind::Connected
+            } else {
+                LinkEventKind::Disconnected
+            },
+            at,
+            device: change.name,
+            address,
+            battery: None,
+        }
+    }
+}
+
+/// Why the connection notifications couldn't be received
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkError {
+    /// IOBluetooth refused the registration
+    Register,
+    /// Not running on macOS
+    Unsupported,
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Register => {
+                f.write_str("failed to register for Bluetooth connection notifications")
+            }
+            Self::Unsupported => f.write_str("connection events are only supported on macOS"),
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+/// Call `on_change` for every device that connects or disconnects, until
+/// Ctrl-C is pressed
+#[cfg(target_os = "macos")]
+pub fn watch(on_change: impl FnMut(LinkChange)) -> Result<(), LinkError> {
+    crate::link_monitor::watch(on_change)
+}
+
+/// Call `on_change` for every device that connects or disconnects, until
+/// Ctrl-C is pressed
+#[cfg(not(target_os = "macos"))]
+pub fn watch(_on_change: impl FnMut(LinkChange)) -> Result<(), LinkError> {
+    Err(LinkError::Unsupported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_event() {
+        let at = DateTime::parse_from_rfc3339("2026-10-15T09:30:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let change = LinkChange {
+            connected: true,
+            name: "AirPods Pro".to_string(),
+            address: Some("a4-c3-37-12-34-56".to_string()),
+        };
+
+        let event = LinkEvent::new(change.clone(), at, false);
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"connected","at":"2026-10-15T09:30:00Z","device":"AirPods Pro","address":"a4-c3-37-12-34-56"}"#
+        );
+
+        let event = LinkEvent::new(
+            LinkChange {
+                connected: false,
+                ..change
+            },
+            at,
+            true,
+        );
+        assert_eq!(event.event, LinkEventKind::Disconnected);
+        assert_eq!(
+            event.address,
+            DeviceAddress::Classic(redact::redact_address("a4-c3-37-12-34-56"))
+        );
+    }
+}
@@ -0,0 +1,130 @@
+//! IOBluetooth side of `btmon events`
+//!
+//! Connect notifications are registered once for all devices; each
+//! connected device then gets its own disconnect notification. IOBluetooth
+//! delivers them on the run loop of the registering thread, so the calling
+//! thread runs its run loop in short slices and checks for Ctrl-C between
+//! them.
+
+use crate::link::{LinkChange, LinkError};
+use crate::watch;
+use objc2::rc::Retained;
+use objc2::runtime::AnyObject;
+use objc2::{AllocAnyThread, DefinedClass, define_class, msg_send, sel};
+use objc2_foundation::{NSDate, NSObject, NSObjectProtocol, NSRunLoop, NSString};
+use objc2_io_bluetooth::{IOBluetoothDevice, IOBluetoothUserNotification};
+use std::sync::mpsc::{self, Sender};
+use tracing::{debug, info_span};
+
+/// How long the run loop runs before checking for Ctrl-C, in seconds
+const RUN_LOOP_SLICE: f64 = 0.2;
+
+/// Ivars for the Objective-C observer class
+struct ObserverIvars {
+    changes: Sender<LinkChange>,
+}
+
+define_class!(
+    #[unsafe(super(NSObject))]
+    #[name = "BtmonConnectionObserver"]
+    #[ivars = ObserverIvars]
+    struct ConnectionObserver;
+
+    unsafe impl NSObjectProtocol for ConnectionObserver {}
+
+    impl ConnectionObserver {
+        #[unsafe(method(deviceConnected:device:))]
+        fn device_connected(
+            &self,
+            _notification: &IOBluetoothUserNotification,
+            device: &IOBluetoothDevice,
+        ) {
+            let observer: &AnyObject = self;
+            // SAFETY: registerForDisconnectNotification is a standard
+            // IOBluetooth API, and the observer implements the selector
+            // with the two arguments it is called with.
+            let registered = unsafe {
+                device.registerForDisconnectNotification_selector(
+                    Some(observer),
+                    Some(sel!(deviceDisconnected:device:)),
+                )
+            };
+            if registered.is_none() {
+                debug!("Failed to register for the disconnect notification");
+            }
+            self.send(device, true);
+        }
+
+        #[unsafe(method(deviceDisconnected:device:))]
+        fn device_disconnected(
+            &self,
+            notification: &IOBluetoothUserNotification,
+            device: &IOBluetoothDevice,
+        ) {
+            // SAFETY: The notification fires once per connection; a new one
+            // is registered when the device connects again.
+            unsafe { notification.unregister() };
+            self.send(device, false);
+        }
+    }
+);
+
+impl ConnectionObserver {
+    fn new(changes: Sender<LinkChange>) -> Retained<Self> {
+        let this = Self::alloc();
+        let this = this.set_ivars(ObserverIvars { changes });
+        // SAFETY: Calling [super init] on a properly allocated NSObject subclass.
+        unsafe { msg_send![super(this), init] }
+    }
+
+    fn send(&self, device: &IOBluetoothDevice, connected: bool) {
+        // SAFETY: name and addressString take no arguments and return an
+        // NSString or nil; name is nil for devices never asked for it.
+        let name: *const NSString = unsafe { msg_send![device, name] };
+        let address: *const NSString = unsafe { msg_send![device, addressString] };
+        // SAFETY: The pointers were checked for null, and the device
+        // retains the strings while they are used.
+        let string = |s: *const NSString| (!s.is_null()).then(|| unsafe { (*s).to_string() });
+        let address = string(address);
+        let change = LinkChange {
+            connected,
+            name: string(name).or_else(|| address.clone()).unwrap_or_default(),
+            address,
+        };
+        debug!(?change, "Connection changed");
+        let _ = self.ivars().changes.send(change);
+    }
+}
+
+/// Call `on_change` for every device that connects or disconnects, until
+/// Ctrl-C is pressed
+pub fn watch(mut on_change: impl FnMut(LinkChange)) -> Result<(), LinkError> {
+    let _span = info_span!("link_monitor").entered();
+    let (changes, receiver) = mpsc::channel();
+    let observer = ConnectionObserver::new(changes);
+    let target: &AnyObject = &observer;
+
+    // SAFETY: registerForConnectNotifications is a standard IOBluetooth
+    // API; the observer implements the selector with its two arguments and
+    // outlives the notification, which is unregistered below.
+    let notification = unsafe {
+        IOBluetoothDevice::registerForConnectNotifications_selector(
+            Some(target),
+            Some(sel!(deviceConnected:device:)),
+        )
+    }
+    .ok_or(LinkError::Register)?;
+
+    watch::install_interrupt_handler();
+    let run_loop = NSRunLoop::currentRunLoop();
+    while !watch::interrupted() {
+        run_loop.runUntilDate(&NSDate::dateWithTimeIntervalSinceNow(RUN_LOOP_SLICE));
+        while let Ok(change) = receiver.try_recv() {
+            on_change(change);
+        }
+    }
+
+    // SAFETY: The notification was registered above and is still valid.
+    unsafe { notification.unregister() };
+    Ok(())
+}
@@ -0,0 +1,219 @@
+//! Locale-aware formatting for human-readable output
+//!
+//! The locale is taken from the POSIX environment (`LC_ALL`, then
+//! `LC_NUMERIC` or `LC_TIME`, then `LANG`). Only the text outputs use it;
+//! JSON and CSV stay locale-independent so scripts can parse them anywhere.
+
+use crate::device::BatteryLevel;
+use chrono::{DateTime, TimeZone};
+use std::env;
+use std::fmt::Display;
+use std::time::Duration;
+
+/// Where the percent sign goes relative to the number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PercentStyle {
+    /// `76%`
+    Suffix,
+    /// `76 %` with a no-break space
+    SpacedSuffix,
+    /// `%76`
+    Prefix,
+}
+
+/// Formatting conventions of a locale
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Locale {
+    percent: PercentStyle,
+    /// strftime format for date and time
+    datetime: &'static str,
+    /// Unit labels for hours and minutes in durations
+    hours: &'static str,
+    minutes: &'static str,
+    /// Separator between a number and a duration unit
+    unit_space: &'static str,
+}
+
+/// No-break space, keeps a number and its unit on one line
+const NBSP: &str = "\u{a0}";
+
+impl Default for Locale {
+    /// The C/POSIX locale: `76%`, ISO dates
+    fn default() -> Self {
+        Self {
+            percent: PercentStyle::Suffix,
+            datetime: "%Y-%m-%d %H:%M",
+            hours: "h",
+            minutes: "m",
+            unit_space: "",
+        }
+    }
+}
+
+impl Locale {
+    /// Locale for a POSIX locale name such as `de_DE.UTF-8`
+    pub fn from_name(name: &str) -> Self {
+        let name = name.split(['.', '@']).next().unwrap_or_default();
+        let (language, region) = name.split_once(['_', '-']).unwrap_or((name, ""));
+        let c = Self::default();
+        let european = Self {
+            percent: PercentStyle::SpacedSuffix,
+            datetime: "%d.%m.%Y %H:%M",
+            hours: "h",
+            minutes: "min",
+            unit_space: NBSP,
+        };
+
+        match language {
+            "en" if region == "US" => Self {
+                datetime: "%m/%d/%Y %-I:%M %p",
+                ..c
+            },
+            "en" => Self {
+                datetime: "%d/%m/%Y %H:%M",
+                ..c
+            },
+            "de" | "ru" | "pl" | "cs" | "nb" | "no" | "da" => european,
+            "fi" => Self {
+                datetime: "%d.%m.%Y %H.%M",
+                ..european
+            },
+            "sv" => Self {
+                datetime: "%Y-%m-%d %H:%M",
+                ..european
+            },
+            "fr" | "es" => Self {
+                datetime: "%d/%m/%Y %H:%M",
+                ..european
+            },
+            "it" | "pt" => Self {
+                percent: PercentStyle::Suffix,
+                datetime: "%d/%m/%Y %H:%M",
+                ..european
+            },
+            "nl" => Self {
+                percent: PercentStyle::Suffix,
+                datetime: "%d-%m-%Y %H:%M",
+                ..european
+            },
+            "tr" => Self {
+                percent: PercentStyle::Prefix,
+                datetime: "%d.%m.%Y %H:%M",
+                ..european
+            },
+            "ja" | "zh" => Self {
+                datetime: "%Y/%m/%d %H:%M",
+                ..c
+            },
+            "ko" => Self {
+                datetime: "%Y. %m. %d. %H:%M",
+                ..c
+            },
+            _ => c,
+        }
+    }
+
+    /// Locale from the environment for a category such as `LC_NUMERIC`
+    fn from_env_category(category: &str) -> Self {
+        ["LC_ALL", category, "LANG"]
+            .into_iter()
+            .filter_map(|var| env::var(var).ok())
+            .find(|value| !value.is_empty())
+            .map_or_else(Self::default, |name| Self::from_name(&name))
+    }
+
+    /// Locale from the environment, with numbers following `LC_NUMERIC` and
+    /// dates and durations following `LC_TIME`
+    pub fn from_env() -> Self {
+        let numeric = Self::from_env_category("LC_NUMERIC");
+        Self {
+            percent: numeric.percent,
+            ..Self::from_env_category("LC_TIME")
+        }
+    }
+
+    /// Format a percentage
+    pub fn percent(&self, value: impl Display) -> String {
+        match self.percent {
+            PercentStyle::Suffix => format!("{value}%"),
+            PercentStyle::SpacedSuffix => format!("{value}{NBSP}%"),
+            PercentStyle::Prefix => format!("%{value}"),
+        }
+    }
+
+    /// Format a battery level as a percentage
+    pub fn battery(&self, level: BatteryLevel) -> String {
+        self.percent(level.as_percentage())
+    }
+
+    /// Format a date and time
+    pub fn datetime<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> String
+    where
+        Tz::Offset: Display,
+    {
+        time.format(self.datetime).to_string()
+    }
+
+    /// Format a duration in hours and minutes, e.g. `2h 5m`
+    pub fn duration(&self, duration: Duration) -> String {
+        let minutes = duration.as_secs() / 60;
+        let (hours, minutes) = (minutes / 60, minutes % 60);
+        let space = self.unit_space;
+        if hours == 0 {
+            format!("{minutes}{space}{}", self.minutes)
+        } else {
+            format!(
+                "{hours}{space}{} {minutes}{space}{}",
+                self.hours, self.minutes
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn test_percent() {
+        let level = BatteryLevel::new(76).unwrap();
+        assert_eq!(Locale::default().battery(level), "76%");
+        assert_eq!(Locale::from_name("en_US.UTF-8").battery(level), "76%");
+        assert_eq!(Locale::from_name("de_DE.UTF-8").battery(level), "76\u{a0}%");
+        assert_eq!(Locale::from_name("tr_TR").battery(level), "%76");
+        assert_eq!(Locale::from_name("C").battery(level), "76%");
+    }
+
+    #[test]
+    fn test_datetime() {
+        let time = Utc.with_ymd_and_hms(2026, 3, 14, 15, 9, 0).unwrap();
+        assert_eq!(Locale::default().datetime(&time), "2026-03-14 15:09");
+        assert_eq!(
+            Locale::from_name("en_US.UTF-8").datetime(&time),
+            "03/14/2026 3:09 PM"
+        );
+        assert_eq!(
+            Locale::from_name("de_DE").datetime(&time),
+            "14.03.2026 15:09"
+        );
+        assert_eq!(
+            Locale::from_name("ja_JP").datetime(&time),
+            "2026/03/14 15:09"
+        );
+    }
+
+    #[test]
+    fn test_duration() {
+        let d = Duration::from_secs(2 * 3600 + 5 * 60 + 30);
+        assert_eq!(Locale::default().duration(d), "2h 5m");
+        assert_eq!(
+            Locale::from_name("fr_FR").duration(d),
+            "2\u{a0}h 5\u{a0}min"
+        );
+        assert_eq!(
+            Locale::default().duration(Duration::from_secs(45 * 60)),
+            "45m"
+        );
+    }
+}
@@ -3,452 +3,2935 @@
 //! This tool monitors battery levels of connected Bluetooth devices
 //! using both IOBluetooth (Classic) and CoreBluetooth (BLE GATT) APIs.
 
-use clap::Parser;
-use objc2::msg_send;
-use objc2::runtime::AnyObject;
-use objc2_foundation::{NSArray, NSString};
-use objc2_io_bluetooth::IOBluetoothDevice;
-use serde::Serialize;
-use std::collections::HashMap;
+use btmon::agent;
+use btmon::alerts::{self, AlertConfig, AlertState, Severity};
+use btmon::alfred;
+use btmon::api::{self, ApiServer};
+use btmon::backend::{
+    AdapterStatus, Backend, RawSnapshot, RecordingBackend, ReplayBackend, SystemBackend,
+};
+use btmon::cache::ScanCache;
+use btmon::channels::desktop::DesktopConfig;
+use btmon::channels::{self, Notification};
+use btmon::compare;
+use btmon::completions::{self, Shell};
+use btmon::config::{Config, ConfigError, Interval};
+use btmon::connection::{self, Outcome};
+use btmon::controller::{self, ControllerInfo};
+use btmon::csvlog::{CsvLog, Rollover};
+use btmon::device::{Component, Device, collect_devices, hide_components, update_freshness};
+use btmon::doctor;
+use btmon::errors::{self, ErrorCode};
+use btmon::events::{self, CloudEvent, EventsConfig, HttpSink};
+use btmon::explore;
+use btmon::fields::Fields;
+use btmon::grafana::{self, Datasource};
+use btmon::graph;
+use btmon::history::{self, Connection, History};
+use btmon::icons::{IconStyle, Icons};
+use btmon::import;
+use btmon::link::{self, LinkEvent, LinkEventKind};
+use btmon::locale::Locale;
+use btmon::manufacturer::Registry;
+use btmon::mqtt::{MqttConfig, Publisher};
+use btmon::nearby;
+use btmon::output::{ColorChoice, Format, SortKey, TextStyle};
+use btmon::paired::{self, LastSeen};
+use btmon::redact;
+use btmon::sequence::Sequences;
+use btmon::sketchybar;
+use btmon::smoothing::{SmoothingConfig, SmoothingState};
+use btmon::socket::{self, Snapshot};
+use btmon::status::{RunStatus, TimedBackend, WarningCounter};
+use btmon::template::Template;
+use btmon::watch::Samples;
+use btmon::watchdog::{self, Watchdog};
+use btmon::{bonjour, output, paths, raycast, rpc, schema, timeline, watch, xbar, xlsx};
+use clap::{CommandFactory, Parser, Subcommand};
+use std::fs::{self, File};
+use std::io::{self, IsTerminal, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use tracing::{Level, debug, info, warn};
-
-mod gatt;
+use tracing_chrome::{ChromeLayerBuilder, TraceStyle};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
 
 /// CLI arguments for btmon
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(name = "btmon")]
 #[command(about = "Monitor Bluetooth device battery levels on macOS")]
 #[command(version)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Filter by device name (partial match, case-insensitive)
     #[arg(short, long)]
     device: Option<String>,
 
-    /// Output in JSON format
-    #[arg(short, long)]
+    /// Output in JSON format (same as --format json)
+    #[arg(short, long, global = true)]
     json: bool,
 
-    /// Enable debug output
+    /// Output format
+    #[arg(long, value_enum, global = true, conflicts_with = "json")]
+    format: Option<Format>,
+
+    /// Print each device with this template instead, e.g. '{name} {battery_level}%'
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        visible_alias = "format-string",
+        conflicts_with_all = ["json", "format"]
+    )]
+    template: Option<Template>,
+
+    /// Only output these fields in json, ndjson, yaml and csv, e.g. name,battery_left
+    #[arg(long, value_name = "FIELDS", conflicts_with = "template")]
+    fields: Option<Fields>,
+
+    /// Print a line with the device count, lowest battery and average level after the devices
+    #[arg(long)]
+    summary: bool,
+
+    /// Print only the summary line (or object with --format json)
+    #[arg(long, conflicts_with_all = ["summary", "template", "fields", "worst"])]
+    summary_only: bool,
+
+    /// Order of the devices (default: as found)
+    #[arg(long, value_name = "KEY", value_enum)]
+    sort: Option<SortKey>,
+
+    /// Reverse the order of --sort
+    #[arg(long, requires = "sort")]
+    reverse: bool,
+
+    /// Wrap the JSON output with the time, hostname, btmon version and data sources
+    #[arg(long, conflicts_with_all = ["template", "fields", "summary_only"])]
+    with_meta: bool,
+
+    /// Stable output for scripts: tab-separated, sorted, no header or colours
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "json",
+            "format",
+            "template",
+            "fields",
+            "sort",
+            "summary",
+            "summary_only",
+            "with_meta"
+        ]
+    )]
+    porcelain: bool,
+
+    /// Write the output to a file, replacing it in one step instead of printing it
+    #[arg(long, value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Only print the device with the lowest battery
     #[arg(long)]
+    worst: bool,
+
+    /// Prefix devices with device and battery glyphs, also used by tmux and prompt
+    #[arg(long, value_name = "STYLE", global = true)]
+    icons: Option<IconStyle>,
+
+    /// Draw each level as a gauge of WIDTH blocks, e.g. --bars=12 (default 8)
+    #[arg(
+        long,
+        value_name = "WIDTH",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "8",
+        value_parser = clap::value_parser!(u8).range(1..=50),
+        global = true
+    )]
+    bars: Option<u8>,
+
+    /// Colour levels green, yellow or red by the alert thresholds
+    #[arg(long, value_name = "WHEN", value_enum, default_value_t, global = true)]
+    color: ColorChoice,
+
+    /// Enable debug output
+    #[arg(long, global = true)]
     debug: bool,
-}
 
-/// Battery level percentage (0-100)
-#[derive(Debug, Clone, Copy, Serialize)]
-#[serde(transparent)]
-pub struct BatteryLevel(u8);
+    /// Don't print notices and warnings on stderr, only errors
+    #[arg(short, long, global = true)]
+    quiet: bool,
 
-impl BatteryLevel {
-    /// Create a new BatteryLevel from a raw value.
-    /// Returns None if value is 0 or > 100 (invalid/unavailable).
-    pub fn new(value: u8) -> Option<Self> {
-        if value > 0 && value <= 100 {
-            Some(Self(value))
-        } else {
-            None
-        }
-    }
+    /// Write a Chrome trace of the run to a file (open in Perfetto or chrome://tracing)
+    #[arg(long, value_name = "PATH", global = true)]
+    profile: Option<PathBuf>,
 
-    /// Get the battery level as a percentage
-    pub fn as_percentage(self) -> u8 {
-        self.0
-    }
-}
+    /// Path to the config file (default: ~/.config/btmon/config.toml)
+    #[arg(long, value_name = "PATH", global = true)]
+    config: Option<PathBuf>,
 
-impl std::fmt::Display for BatteryLevel {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}%", self.0)
-    }
-}
+    /// Record raw Bluetooth responses to a session file
+    #[arg(long, value_name = "PATH", conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Replay raw Bluetooth responses from a session file instead of querying devices
+    #[arg(long, value_name = "PATH")]
+    replay: Option<PathBuf>,
+
+    /// Append a timestamped row per device to a CSV file
+    #[arg(long, value_name = "PATH")]
+    append_csv: Option<PathBuf>,
+
+    /// Roll the CSV file over daily or at a size (e.g. 10M)
+    #[arg(long, value_name = "POLICY", requires = "append_csv")]
+    csv_rollover: Option<Rollover>,
 
-/// Bluetooth device address
-#[derive(Debug, Clone)]
-pub enum DeviceAddress {
-    /// Classic Bluetooth MAC address
-    Classic(String),
-    /// BLE device (address not exposed for privacy)
-    Ble,
+    /// Connect to bonded BLE devices that are idle to read their battery, then disconnect
+    #[arg(long)]
+    connect_if_needed: bool,
+
+    /// Only use public macOS APIs, skipping Classic devices that need private IOBluetooth selectors
+    #[arg(long)]
+    no_private_api: bool,
+
+    /// Reuse the last scan if it is at most this many seconds old (default 60 for --format prompt and short)
+    #[arg(long, value_name = "SECONDS", conflicts_with_all = ["record", "replay"])]
+    max_age: Option<u64>,
+
+    /// Scan even when `btmon daemon` is running, instead of using its latest scan
+    #[arg(long)]
+    no_daemon: bool,
+
+    /// Mask the device-specific part of Bluetooth addresses and peripheral identifiers in all output
+    #[arg(long, global = true)]
+    redact: bool,
+
+    /// Show low-battery alerts in Notification Center
+    #[arg(long, conflicts_with = "stdio")]
+    notify: bool,
+
+    /// Warning threshold in percent, overriding `warn` in the config
+    #[arg(long, value_name = "PERCENT", value_parser = clap::value_parser!(u8).range(1..=100))]
+    threshold: Option<u8>,
+
+    /// Keep running and refresh the readings until Ctrl-C
+    #[arg(long, conflicts_with_all = ["stdio", "record"])]
+    watch: bool,
+
+    /// Time between refreshes with --watch or menubar, e.g. 30 or 5m (default 30s)
+    #[arg(long, value_name = "INTERVAL", global = true)]
+    interval: Option<Interval>,
+
+    /// Record the readings in the battery history (see `btmon history`)
+    #[arg(long, global = true)]
+    history: bool,
+
+    /// Print a one-line JSON summary of the run on stderr
+    #[arg(long, conflicts_with = "stdio")]
+    status_json: bool,
+
+    /// Serve JSON-RPC requests on stdin/stdout (list_devices, get_device, subscribe)
+    #[arg(long, conflicts_with_all = ["record", "append_csv", "events"])]
+    stdio: bool,
+
+    /// Print battery events since the last run as CloudEvents (one JSON object per line)
+    #[arg(long, conflicts_with = "json")]
+    events: bool,
 }
 
-impl std::fmt::Display for DeviceAddress {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Classic(addr) => write!(f, "{addr}"),
-            Self::Ble => write!(f, "BLE"),
+impl Args {
+    /// Output format, with `--json` as a shorthand for `--format json`
+    fn format(&self) -> Format {
+        if self.json {
+            Format::Json
+        } else {
+            self.format.unwrap_or_default()
         }
     }
 }
 
-impl Serialize for DeviceAddress {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        match self {
-            Self::Classic(addr) => serializer.serialize_str(addr),
-            Self::Ble => serializer.serialize_str("BLE"),
-        }
-    }
-}
-
-/// Represents a Bluetooth device with battery information
-#[derive(Debug, Serialize)]
-pub struct Device {
-    /// Human-readable device name
-    name: String,
-    /// Bluetooth address
-    address: DeviceAddress,
-    /// Single battery level for standard devices
-    #[serde(skip_serializing_if = "Option::is_none")]
-    battery_level: Option<BatteryLevel>,
-    /// Left earbud battery (AirPods, etc.)
-    #[serde(skip_serializing_if = "Option::is_none")]
-    battery_left: Option<BatteryLevel>,
-    /// Right earbud battery (AirPods, etc.)
-    #[serde(skip_serializing_if = "Option::is_none")]
-    battery_right: Option<BatteryLevel>,
-    /// Charging case battery (AirPods, etc.)
-    #[serde(skip_serializing_if = "Option::is_none")]
-    battery_case: Option<BatteryLevel>,
-}
-
-impl Device {
-    /// Check if device has any battery information
-    fn has_battery_info(&self) -> bool {
-        self.battery_level.is_some()
-            || self.battery_left.is_some()
-            || self.battery_right.is_some()
-            || self.battery_case.is_some()
-    }
-}
-
-/// Get battery levels from GATT Battery Service devices
-fn get_gatt_devices(name_filter: Option<&str>) -> Vec<Device> {
-    let gatt_devices = gatt::get_gatt_battery_devices();
-
-    gatt_devices
-        .into_iter()
-        .filter_map(|(name, battery)| {
-            // Apply name filter
-            if let Some(filter) = name_filter
-                && !name.to_lowercase().contains(filter)
-            {
-                return None;
-            }
-
-            let battery_level = BatteryLevel::new(battery);
-            if battery_level.is_none() {
-                debug!(name = %name, raw_value = battery, "Invalid battery level from GATT");
-                return None;
-            }
+/// btmon subcommands
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Compare two JSON snapshots and print per-device deltas
+    Compare {
+        /// Earlier snapshot (output of `btmon --json`)
+        before: PathBuf,
+        /// Later snapshot (output of `btmon --json`)
+        after: PathBuf,
+    },
+    /// Scan and print only what changed since the previous scan
+    Diff,
+    /// Print the JSON Schema of the --json output
+    Schema,
+    /// Acknowledge active alerts, stopping their repeats and escalation
+    Ack {
+        /// Only acknowledge alerts of devices matching this name (partial match)
+        device: Option<String>,
+    },
+    /// Show the Mac's Bluetooth controller: power, address, chipset, firmware,
+    /// Bluetooth LE support and device counts
+    #[command(alias = "adapter")]
+    Controller,
+    /// Show the lowest battery in the menu bar, with all devices in its menu
+    Menubar,
+    /// Poll devices every --interval in the background, sending alerts and
+    /// keeping the last scan fresh for other invocations (see --max-age)
+    Daemon {
+        /// Log file (default: ~/.cache/btmon/daemon.log)
+        #[arg(long, value_name = "PATH")]
+        log_file: Option<PathBuf>,
+        /// Serve Prometheus metrics of the latest scan at /metrics on this port
+        #[arg(long, value_name = "PORT")]
+        metrics_port: Option<u16>,
+        /// Address the metrics are served on, e.g. 0.0.0.0 for the whole network
+        #[arg(long, default_value = "127.0.0.1", requires = "metrics_port")]
+        metrics_bind: IpAddr,
+    },
+    /// Serve the device list as JSON over HTTP (GET /devices, /devices/<name>),
+    /// with a dashboard page at /ui
+    Serve {
+        /// Port to listen on
+        #[arg(long, default_value_t = 8080)]
+        port: u16,
+        /// Address to listen on, e.g. 0.0.0.0 for the whole network
+        #[arg(long, default_value = "127.0.0.1")]
+        bind: IpAddr,
+        /// Advertise the API over Bonjour as _btmon._tcp
+        #[arg(long)]
+        advertise: bool,
+    },
+    /// Publish battery levels to an MQTT broker, with Home Assistant
+    /// discovery
+    Mqtt {
+        /// Broker URL, e.g. mqtt://homeassistant.local (overrides [mqtt])
+        #[arg(long)]
+        broker: Option<String>,
+    },
+    /// Check Bluetooth, permissions and private APIs, and explain how to fix
+    /// what keeps btmon from reading batteries
+    Doctor,
+    /// List the devices; with --all-paired, also the disconnected ones with
+    /// their last known battery
+    List {
+        /// Include every paired device, connected or not
+        #[arg(long)]
+        all_paired: bool,
+    },
+    /// Connect a paired Classic Bluetooth device, e.g. a headset
+    Connect {
+        /// Device name (partial match, case-insensitive)
+        device: String,
+    },
+    /// Disconnect a paired Classic Bluetooth device
+    Disconnect {
+        /// Device name (partial match, case-insensitive)
+        device: String,
+    },
+    /// Connect to a BLE peripheral and dump its services, characteristics
+    /// and readable values, e.g. to find where it keeps its battery level
+    Gatt {
+        /// Peripheral name (partial match, case-insensitive)
+        device: String,
+    },
+    /// Scan for nearby advertising BLE devices, with the battery levels some
+    /// of them advertise
+    Scan {
+        /// How long to scan, e.g. 10 or 1m
+        #[arg(long, value_name = "INTERVAL", default_value = "10s")]
+        duration: Interval,
+        /// Mark charging batteries with `+` instead of ⚡
+        #[arg(long)]
+        ascii: bool,
+    },
+    /// Stream Classic devices connecting and disconnecting as JSON lines,
+    /// with the battery read on connect
+    Events,
+    /// Print the lowest battery on one line, e.g. `AirPods L:15%`, for shell
+    /// prompts and status bars
+    Status,
+    /// Check a device's battery without printing anything; the exit code is
+    /// 0 when ok, 1 at the warning threshold, 2 at the critical threshold
+    /// and 3 when the device is not found
+    Check {
+        /// Device to check (partial match, case-insensitive)
+        #[arg(short, long)]
+        device: String,
+        /// Warning threshold in percent (default: `warn` in the config)
+        #[arg(long, value_name = "PERCENT", value_parser = clap::value_parser!(u8).range(1..=100))]
+        warn: Option<u8>,
+        /// Critical threshold in percent (default: `critical` in the config)
+        #[arg(long, value_name = "PERCENT", value_parser = clap::value_parser!(u8).range(1..=100))]
+        crit: Option<u8>,
+    },
+    /// Wait until a device is connected and reports its battery, then print
+    /// its reading
+    Wait {
+        /// Device to wait for (partial match, case-insensitive)
+        #[arg(short, long)]
+        device: String,
+        /// Give up after this long, e.g. 60 or 5m (default: wait until Ctrl-C)
+        #[arg(long, value_name = "INTERVAL")]
+        timeout: Option<Interval>,
+    },
+    /// Print a shell completion script, e.g. `btmon completions zsh > ~/.zfunc/_btmon`
+    Completions {
+        /// Shell to complete in
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// List battery readings recorded with --history
+    History {
+        #[command(subcommand)]
+        action: Option<HistoryAction>,
+        /// Only devices whose name contains this (case-insensitive)
+        device: Option<String>,
+        /// How far back to go, e.g. 24h or 7d
+        #[arg(long, default_value = "7d")]
+        since: Interval,
+    },
+    /// Export the readings recorded with --history as CSV (default), JSON or
+    /// an Excel workbook (`--format xlsx > battery.xlsx`)
+    Export {
+        /// Only devices whose name contains this (case-insensitive)
+        #[arg(short, long)]
+        device: Option<String>,
+        /// How far back to go, e.g. 24h or 30d
+        #[arg(long, default_value = "30d")]
+        since: Interval,
+        /// Only these components, e.g. `left,right` (default: all)
+        #[arg(long, value_enum, value_delimiter = ',')]
+        component: Vec<Component>,
+    },
+    /// Chart the recorded battery levels of a device, one chart per component
+    Graph {
+        /// Devices whose name contains this (case-insensitive)
+        device: String,
+        /// How far back to go, e.g. 24h or 7d
+        #[arg(long, default_value = "24h")]
+        since: Interval,
+        /// Chart width in characters
+        #[arg(long, default_value_t = graph::DEFAULT_WIDTH)]
+        width: usize,
+        /// Chart height in lines
+        #[arg(long, default_value_t = graph::DEFAULT_HEIGHT)]
+        height: usize,
+    },
+    /// Interleave the recorded readings, charge sessions and connects of a
+    /// device, e.g. `btmon timeline airpods --since 24h`
+    Timeline {
+        /// Devices whose name contains this (case-insensitive)
+        device: String,
+        /// How far back to go, e.g. 24h or 7d
+        #[arg(long, default_value = "24h")]
+        since: Interval,
+    },
+    /// Print a Grafana dashboard for the metrics of `btmon serve`, e.g.
+    /// `btmon grafana-dashboard > dashboard.json`
+    GrafanaDashboard {
+        /// Data source the dashboard queries
+        #[arg(long, value_enum, default_value_t)]
+        datasource: Datasource,
+    },
+    /// Create, locate or check the config file
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Install a launchd agent that starts `btmon daemon` at login
+    InstallAgent,
+    /// Stop and remove the launchd agent
+    UninstallAgent,
+}
 
-            info!(name = %name, battery = battery, "Found GATT device");
+/// `btmon history` subcommands
+#[derive(Subcommand, Debug, Clone)]
+enum HistoryAction {
+    /// Merge a log kept by another tool into the history: CSV with a header
+    /// row, a JSON array or JSON lines, e.g. from --append-csv or --format ndjson
+    Import {
+        /// Log file to read
+        file: PathBuf,
+    },
+    /// List the recorded connects and disconnects, with the battery level at
+    /// the time
+    Events {
+        /// Only devices whose name contains this (case-insensitive)
+        device: Option<String>,
+        /// How far back to go, e.g. 24h or 7d
+        #[arg(long, default_value = "7d")]
+        since: Interval,
+    },
+}
 
-            Some(Device {
-                name,
-                address: DeviceAddress::Ble,
-                battery_level,
-                battery_left: None,
-                battery_right: None,
-                battery_case: None,
-            })
-        })
-        .collect()
+/// `btmon config` subcommands, acting on `--config` or the default location
+#[derive(Subcommand, Debug, Clone)]
+enum ConfigAction {
+    /// Write a config file with every setting commented out
+    Init {
+        /// Replace an existing file
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print the location of the config file
+    Path,
+    /// Check the config file for syntax errors, unknown keys and bad values
+    Validate,
 }
 
-/// Get battery levels from IOBluetooth devices (Classic Bluetooth)
-fn get_iobluetooth_devices(
-    name_filter: Option<&str>,
-    seen_names: &HashMap<String, ()>,
-) -> Vec<Device> {
-    let mut devices = Vec::new();
+/// Default `--max-age` of the prompt format and `btmon status`
+const PROMPT_MAX_AGE: Duration = Duration::from_secs(60);
 
-    // SAFETY: IOBluetoothDevice::pairedDevices() returns a valid NSArray or nil.
-    // This is a standard Objective-C API call.
-    let paired_devices: Option<objc2::rc::Retained<NSArray<AnyObject>>> =
-        unsafe { IOBluetoothDevice::pairedDevices() };
+fn main() -> ExitCode {
+    let args = Args::parse();
 
-    let Some(paired) = paired_devices else {
-        debug!("No paired devices found");
-        return devices;
+    // Initialize tracing subscriber with JSON format, plus the Chrome trace
+    // writer when profiling and the warning counter for the run summary.
+    // The guard flushes the trace file when dropped.
+    let debug_layer = args.debug.then(|| {
+        tracing_subscriber::fmt::layer()
+            .json()
+            .with_filter(LevelFilter::from_level(Level::DEBUG))
+    });
+    let (profile_layer, _profile_guard) = match &args.profile {
+        Some(path) => {
+            let (layer, guard) = ChromeLayerBuilder::new()
+                .file(path)
+                .trace_style(TraceStyle::Async)
+                .include_args(true)
+                .build();
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+    let warnings = args.status_json.then(WarningCounter::default);
+    let log_layer = match &args.command {
+        Some(Command::Daemon { log_file, .. }) => {
+            open_log(&args, log_file.as_deref()).map(|file| {
+                tracing_subscriber::fmt::layer()
+                    .with_ansi(false)
+                    .with_writer(Mutex::new(file))
+                    .with_filter(LevelFilter::INFO)
+            })
+        }
+        _ => None,
     };
+    if debug_layer.is_some() || profile_layer.is_some() || warnings.is_some() || log_layer.is_some()
+    {
+        tracing_subscriber::registry()
+            .with(debug_layer)
+            .with(profile_layer)
+            .with(warnings.clone())
+            .with(log_layer)
+            .init();
+    }
 
-    let count = paired.count();
-    debug!(count = count, "Found paired devices");
+    debug!("Starting btmon");
 
-    for i in 0..count {
-        // SAFETY: objectAtIndex returns a valid pointer for valid index (0..count).
-        let device: *const AnyObject = unsafe { msg_send![&paired, objectAtIndex: i] };
-        if device.is_null() {
-            continue;
+    if args.fields.is_some() && !Fields::supports(args.format()) {
+        report_error(
+            &args,
+            ErrorCode::Usage,
+            "--fields only applies to --format json, ndjson, yaml or csv",
+        );
+        return ExitCode::FAILURE;
+    }
+    if args.format() == Format::Xlsx && !matches!(args.command, Some(Command::Export { .. })) {
+        report_error(
+            &args,
+            ErrorCode::Usage,
+            "--format xlsx only applies to btmon export",
+        );
+        return ExitCode::FAILURE;
+    }
+    if args.with_meta && args.format() != Format::Json {
+        report_error(
+            &args,
+            ErrorCode::Usage,
+            "--with-meta only applies to --format json",
+        );
+        return ExitCode::FAILURE;
+    }
+    if args.summary && !matches!(args.format(), Format::Text | Format::Table) {
+        report_error(
+            &args,
+            ErrorCode::Usage,
+            "--summary only applies to --format text or table",
+        );
+        return ExitCode::FAILURE;
+    }
+    if args.summary_only && !matches!(args.format(), Format::Text | Format::Json) {
+        report_error(
+            &args,
+            ErrorCode::Usage,
+            "--summary-only only applies to --format text or json",
+        );
+        return ExitCode::FAILURE;
+    }
+
+    match &args.command {
+        Some(Command::Compare { before, after }) => run_compare(&args, before, after),
+        Some(Command::Diff) => run_diff(&args),
+        Some(Command::Schema) => run_schema(&args),
+        Some(Command::Ack { device }) => run_ack(&args, device.as_deref()),
+        Some(Command::Controller) => run_controller(&args),
+        Some(Command::Menubar) => run_menubar(&args),
+        Some(Command::Daemon {
+            metrics_port,
+            metrics_bind,
+            ..
+        }) => run_daemon(
+            &args,
+            metrics_port.map(|port| SocketAddr::from((*metrics_bind, port))),
+        ),
+        Some(Command::Serve {
+            port,
+            bind,
+            advertise,
+        }) => run_serve(&args, *bind, *port, *advertise),
+        Some(Command::Mqtt { broker }) => run_mqtt(&args, broker.clone()),
+        Some(Command::Doctor) => run_doctor(&args),
+        Some(Command::List { all_paired: true }) => run_list_paired(&args),
+        Some(Command::List { all_paired: false }) => run_devices(&args, warnings.as_ref(), None),
+        Some(Command::Connect { device }) => run_connection(&args, device, true),
+        Some(Command::Disconnect { device }) => run_connection(&args, device, false),
+        Some(Command::Gatt { device }) => run_gatt(&args, device),
+        Some(Command::Scan { duration, ascii }) => run_scan(&args, *duration, *ascii),
+        Some(Command::Events) => run_link_events(&args),
+        Some(Command::Status) => run_status(&args),
+        Some(Command::Check { device, warn, crit }) => run_check(&args, device, *warn, *crit),
+        Some(Command::Wait { device, timeout }) => run_wait(&args, device, *timeout),
+        Some(Command::Completions { shell }) => {
+            print!("{}", completions::generate(*shell, Args::command()));
+            ExitCode::SUCCESS
         }
+        Some(Command::History {
+            action: Some(HistoryAction::Import { file }),
+            ..
+        }) => run_history_import(&args, file),
+        Some(Command::History {
+            action: Some(HistoryAction::Events { device, since }),
+            ..
+        }) => run_history_events(&args, device.as_deref(), *since),
+        Some(Command::History {
+            action: None,
+            device,
+            since,
+        }) => run_history(&args, device.as_deref(), *since),
+        Some(Command::Export {
+            device,
+            since,
+            component,
+        }) => run_export(&args, device.as_deref(), *since, component),
+        Some(Command::Graph {
+            device,
+            since,
+            width,
+            height,
+        }) => run_graph(&args, device, *since, *width, *height),
+        Some(Command::Timeline { device, since }) => run_timeline(&args, device, *since),
+        Some(Command::GrafanaDashboard { datasource }) => run_grafana_dashboard(&args, *datasource),
+        Some(Command::Config { action }) => run_config(&args, action),
+        Some(Command::InstallAgent) => run_install_agent(&args),
+        Some(Command::UninstallAgent) => run_uninstall_agent(&args),
+        None if args.stdio => run_stdio(&args),
+        None if args.watch => run_watch(&args, warnings.as_ref()),
+        None => run_devices(&args, warnings.as_ref(), None),
+    }
+}
+
+/// Print the deltas between two snapshot files
+fn run_compare(args: &Args, before: &Path, after: &Path) -> ExitCode {
+    let load = |path: &Path| {
+        compare::load_devices(path).map_err(|e| {
+            report_error(
+                args,
+                ErrorCode::Storage,
+                &format!("failed to load snapshot {}: {e}", path.display()),
+            )
+        })
+    };
+    let (Ok(before), Ok(after)) = (load(before), load(after)) else {
+        return ExitCode::FAILURE;
+    };
 
-        // SAFETY: device pointer was checked for null above.
-        // The object is retained by the NSArray for the duration of iteration.
-        let device_ref = unsafe { &*device };
+    let comparisons = compare::compare(&before, &after);
 
-        // SAFETY: isConnected is a standard IOBluetoothDevice method returning bool.
-        let is_connected: bool = unsafe { msg_send![device_ref, isConnected] };
-        if !is_connected {
-            continue;
+    // Comparisons have no flat Shortcuts variant, so anything but text is JSON
+    if args.format() != Format::Text {
+        match serde_json::to_string_pretty(&comparisons) {
+            Ok(json) => println!("{json}"),
+            Err(e) => {
+                report_error(
+                    args,
+                    ErrorCode::Output,
+                    &format!("Failed to serialize comparison: {e}"),
+                );
+                return ExitCode::FAILURE;
+            }
         }
+    } else if !comparisons.is_empty() {
+        println!(
+            "{}",
+            compare::format_comparison(&comparisons, &Locale::from_env())
+        );
+    }
 
-        // SAFETY: name returns NSString or nil.
-        let name_obj: *const NSString = unsafe { msg_send![device_ref, name] };
-        let name = if name_obj.is_null() {
-            continue;
-        } else {
-            // SAFETY: name_obj was checked for null above.
-            unsafe { (*name_obj).to_string() }
-        };
+    ExitCode::SUCCESS
+}
 
-        // Skip if already got battery from GATT
-        if seen_names.contains_key(&name) {
-            debug!(name = %name, "Skipping device already found via GATT");
-            continue;
+/// Print the JSON Schema of the `--json` output
+fn run_schema(args: &Args) -> ExitCode {
+    match serde_json::to_string_pretty(&schema::schema()) {
+        Ok(json) => {
+            println!("{json}");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            report_error(
+                args,
+                ErrorCode::Output,
+                &format!("Failed to serialize schema: {e}"),
+            );
+            ExitCode::FAILURE
         }
+    }
+}
 
-        // Apply name filter
-        if let Some(filter) = name_filter
-            && !name.to_lowercase().contains(filter)
-        {
-            continue;
+/// Print the Grafana dashboard, with the level thresholds of the config
+fn run_grafana_dashboard(args: &Args, datasource: Datasource) -> ExitCode {
+    let config = match load_config(args) {
+        Ok(config) => config,
+        Err(e) => {
+            report_error(args, ErrorCode::Config, &e.to_string());
+            return ExitCode::FAILURE;
+        }
+    };
+    match serde_json::to_string_pretty(&grafana::dashboard(datasource, &config.alerts)) {
+        Ok(json) => {
+            println!("{json}");
+            ExitCode::SUCCESS
         }
+        Err(e) => {
+            report_error(
+                args,
+                ErrorCode::Output,
+                &format!("Failed to serialize dashboard: {e}"),
+            );
+            ExitCode::FAILURE
+        }
+    }
+}
 
-        // SAFETY: addressString returns NSString or nil.
-        let addr_obj: *const NSString = unsafe { msg_send![device_ref, addressString] };
-        let address = if addr_obj.is_null() {
-            DeviceAddress::Classic("unknown".to_string())
-        } else {
-            // SAFETY: addr_obj was checked for null above.
-            DeviceAddress::Classic(unsafe { (*addr_obj).to_string() })
-        };
+/// Scan and print the changes against the previous scan kept in the cache
+fn run_diff(args: &Args) -> ExitCode {
+    let config = match load_config(args) {
+        Ok(config) => config,
+        Err(e) => {
+            report_error(args, ErrorCode::Config, &e.to_string());
+            return ExitCode::FAILURE;
+        }
+    };
+    // Read before scanning, which replaces it
+    let previous = scan_cache_path().and_then(|path| ScanCache::load(&path).ok());
 
-        // SAFETY: These are private IOBluetooth APIs that return u8.
-        // They return 0 or 255 when battery info is unavailable.
-        let battery_single: u8 = unsafe { msg_send![device_ref, batteryPercentSingle] };
-        let battery_left: u8 = unsafe { msg_send![device_ref, batteryPercentLeft] };
-        let battery_right: u8 = unsafe { msg_send![device_ref, batteryPercentRight] };
-        let battery_case: u8 = unsafe { msg_send![device_ref, batteryPercentCase] };
-
-        // Log additional debug info
-        // SAFETY: These are private IOBluetooth APIs that return u8.
-        let battery_combined: u8 = unsafe { msg_send![device_ref, batteryPercentCombined] };
-        let headset_battery: u8 = unsafe { msg_send![device_ref, headsetBattery] };
-
-        debug!(
-            name = %name,
-            single = battery_single,
-            left = battery_left,
-            right = battery_right,
-            case = battery_case,
-            combined = battery_combined,
-            headset = headset_battery,
-            "IOBluetooth battery values"
+    let mut status = RunStatus::default();
+    let Some((devices, _)) = scan_devices(args, &config, &mut status) else {
+        return ExitCode::FAILURE;
+    };
+    let Some(previous) = previous else {
+        notice(
+            args,
+            "No earlier scan to compare with, run btmon diff again later",
         );
+        return ExitCode::SUCCESS;
+    };
+    let mut before = previous.devices;
+    if let Some(filter) = &args.device {
+        let filter = filter.to_lowercase();
+        before.retain(|d| d.name.to_lowercase().contains(&filter));
+    }
 
-        let battery_level = BatteryLevel::new(battery_single);
-        let battery_left = BatteryLevel::new(battery_left);
-        let battery_right = BatteryLevel::new(battery_right);
-        let battery_case = BatteryLevel::new(battery_case);
-
-        let device = Device {
-            name: name.clone(),
-            address,
-            battery_level,
-            battery_left,
-            battery_right,
-            battery_case,
-        };
-
-        // Skip devices with no battery info
-        if !device.has_battery_info() {
-            debug!(name = %name, "No battery info available");
-            continue;
+    let changes = compare::changes(compare::compare(&before, &devices));
+    if args.format() != Format::Text {
+        match serde_json::to_string_pretty(&changes) {
+            Ok(json) => println!("{json}"),
+            Err(e) => {
+                report_error(
+                    args,
+                    ErrorCode::Output,
+                    &format!("Failed to serialize changes: {e}"),
+                );
+                return ExitCode::FAILURE;
+            }
         }
-
-        info!(
-            name = %name,
-            battery_level = ?battery_level.map(|b| b.as_percentage()),
-            battery_left = ?battery_left.map(|b| b.as_percentage()),
-            battery_right = ?battery_right.map(|b| b.as_percentage()),
-            battery_case = ?battery_case.map(|b| b.as_percentage()),
-            "Found IOBluetooth device"
+    } else if !changes.is_empty() {
+        println!(
+            "{}",
+            compare::format_comparison(&changes, &Locale::from_env())
         );
+    }
+    ExitCode::SUCCESS
+}
 
-        devices.push(device);
+/// Print the local Bluetooth controller details
+fn run_controller(args: &Args) -> ExitCode {
+    let mut info = match ControllerInfo::query() {
+        Ok(info) => info,
+        Err(e) => {
+            report_error(
+                args,
+                ErrorCode::Bluetooth,
+                &format!("failed to query Bluetooth controller: {e}"),
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+    // Core Bluetooth only reports the adapter state once it was asked
+    let mut backend = SystemBackend::detect(!args.no_private_api);
+    if backend.capabilities().gatt.enabled {
+        backend.gatt_readings();
+        if let Some(adapter) = backend.adapter() {
+            info.apply_adapter(adapter.state);
+        }
+    }
+    let format = args.format();
+    if args.redact {
+        info.address = info.address.as_deref().map(redact::redact_address);
     }
 
-    devices
+    // No flat Shortcuts variant, so anything but text is JSON
+    if format == Format::Text {
+        println!("{info}");
+    } else {
+        match serde_json::to_string_pretty(&info) {
+            Ok(json) => println!("{json}"),
+            Err(e) => {
+                report_error(
+                    args,
+                    ErrorCode::Output,
+                    &format!("Failed to serialize controller: {e}"),
+                );
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    ExitCode::SUCCESS
 }
 
-/// Get all connected Bluetooth devices with battery information
-fn get_connected_devices(name_filter: Option<&str>) -> Vec<Device> {
-    // Pre-convert filter to lowercase for efficiency
-    let filter_lower = name_filter.map(|f| f.to_lowercase());
-    let filter_ref = filter_lower.as_deref();
+/// List every paired device, with the current battery of the connected ones
+/// and the last known battery of the rest
+fn run_list_paired(args: &Args) -> ExitCode {
+    let config = match load_config(args) {
+        Ok(config) => config,
+        Err(e) => {
+            report_error(args, ErrorCode::Config, &e.to_string());
+            return ExitCode::FAILURE;
+        }
+    };
+    let paired = match controller::system_profiler().and_then(|json| paired::parse_paired(&json)) {
+        Ok(paired) => paired,
+        Err(e) => {
+            report_error(
+                args,
+                ErrorCode::Bluetooth,
+                &format!("failed to list paired devices: {e}"),
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut status = RunStatus::default();
+    let Some((devices, _, scanned)) = obtain_devices(args, &config, &mut status, false) else {
+        return ExitCode::FAILURE;
+    };
+    if scanned {
+        record_history(args, &config, &devices);
+    }
+    let seen = last_seen();
+
+    // Matched by the real addresses, masked only for display
+    let mut entries = paired::entries(&paired, &devices, &seen);
+    if args.redact {
+        for entry in &mut entries {
+            entry.address = entry.address.as_deref().map(redact::redact_address);
+        }
+    }
+    if let Some(filter) = &args.device {
+        let filter = filter.to_lowercase();
+        entries.retain(|entry| entry.name.to_lowercase().contains(&filter));
+    }
+    let rendered = match args.format() {
+        Format::Text => Ok(paired::format_entries(&entries, &Locale::from_env())),
+        _ => serde_json::to_string_pretty(&entries),
+    };
+    match rendered {
+        Ok(text) if text.is_empty() => {}
+        Ok(text) => println!("{text}"),
+        Err(e) => {
+            report_error(
+                args,
+                ErrorCode::Output,
+                &format!("Failed to serialize devices: {e}"),
+            );
+            return ExitCode::FAILURE;
+        }
+    }
+    status.exit_code()
+}
 
-    // First, get GATT Battery Service devices via Core Bluetooth
-    let gatt_devices = get_gatt_devices(filter_ref);
+/// Connect or disconnect a paired device
+fn run_connection(args: &Args, device: &str, connect: bool) -> ExitCode {
+    match connection::set_connected(device, connect) {
+        Ok((name, outcome)) => {
+            let state = if connect { "connected" } else { "disconnected" };
+            match outcome {
+                Outcome::Done => println!("{name}: {state}"),
+                Outcome::Unchanged => println!("{name}: already {state}"),
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            report_error(
+                args,
+                ErrorCode::Bluetooth,
+                &format!(
+                    "failed to {} '{device}': {e}",
+                    if connect { "connect" } else { "disconnect" }
+                ),
+            );
+            ExitCode::FAILURE
+        }
+    }
+}
 
-    // Track seen device names to avoid duplicates
-    let seen_names: HashMap<String, ()> =
-        gatt_devices.iter().map(|d| (d.name.clone(), ())).collect();
+/// Dump the GATT services and values of a peripheral
+fn run_gatt(args: &Args, device: &str) -> ExitCode {
+    notice(args, &format!("Reading {device}..."));
+    let mut peripheral = match explore::explore(device) {
+        Ok(peripheral) => peripheral,
+        Err(e) => {
+            report_error(
+                args,
+                ErrorCode::from(&e),
+                &format!("failed to explore '{device}': {e}"),
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+    if args.redact {
+        redact::redact_peripheral(&mut peripheral);
+    }
+    // No flat Shortcuts variant, so anything but text is JSON
+    if args.format() == Format::Text {
+        print!("{}", explore::format_peripheral(&peripheral));
+    } else {
+        match serde_json::to_string_pretty(&peripheral) {
+            Ok(json) => println!("{json}"),
+            Err(e) => {
+                report_error(
+                    args,
+                    ErrorCode::Output,
+                    &format!("Failed to serialize peripheral: {e}"),
+                );
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    ExitCode::SUCCESS
+}
 
-    // Then get IOBluetooth devices
-    let iobluetooth_devices = get_iobluetooth_devices(filter_ref, &seen_names);
+/// Time a device gets after connecting before its battery is read, so the
+/// first reading isn't missing
+const LINK_BATTERY_DELAY: Duration = Duration::from_secs(2);
 
-    // Merge results
-    let mut devices = gatt_devices;
-    devices.extend(iobluetooth_devices);
+/// Print a JSON line for every device connecting or disconnecting
+fn run_link_events(args: &Args) -> ExitCode {
+    let config = match load_config(args) {
+        Ok(config) => config,
+        Err(e) => {
+            report_error(args, ErrorCode::Config, &e.to_string());
+            return ExitCode::FAILURE;
+        }
+    };
+    let result = link::watch(|change| {
+        let mut event = LinkEvent::new(change, chrono::Utc::now(), args.redact);
+        if event.event == LinkEventKind::Connected && watch::sleep(LINK_BATTERY_DELAY) {
+            let args = Args {
+                device: Some(event.device.clone()),
+                ..args.clone()
+            };
+            event.battery = scan_devices(&args, &config, &mut RunStatus::default())
+                .and_then(|(devices, _)| devices.into_iter().find(|d| d.name == event.device));
+        }
+        if records_history(args, &config) {
+            record_connection(&config, &event);
+        }
+        match serde_json::to_string(&event) {
+            Ok(json) => println!("{json}"),
+            Err(e) => warn!(error = %e, "Failed to serialize connection event"),
+        }
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            report_error(args, ErrorCode::Bluetooth, &e.to_string());
+            ExitCode::FAILURE
+        }
+    }
+}
 
-    devices
+/// Add a connection event to the battery history
+fn record_connection(config: &Config, event: &LinkEvent) {
+    let Some(path) = History::default_path() else {
+        warn!("Cannot determine cache directory for battery history");
+        return;
+    };
+    let connection = Connection {
+        at: event.at,
+        device: event.device.clone(),
+        event: event.event,
+        level: event.battery.as_ref().and_then(history::lowest_level),
+    };
+    let history = History::new(path).encrypt(config.history.encrypt);
+    if let Err(e) = history.record_connection(connection, &last_seen()) {
+        warn!(error = %e, "Failed to record connection event");
+    }
 }
 
-/// Format device output for terminal display
-fn format_device_output(device: &Device) -> String {
-    if let Some(level) = device.battery_level {
-        format!("{}: {level}", device.name)
-    } else {
-        // AirPods-style device with multiple batteries
-        let mut parts = Vec::new();
-        if let Some(l) = device.battery_left {
-            parts.push(format!("L:{l}"));
+/// List the devices advertising nearby
+fn run_scan(args: &Args, duration: Interval, ascii: bool) -> ExitCode {
+    let format = args.format();
+    notice(args, &format!("Scanning for {}s...", duration.0.as_secs()));
+    let advertisements = match nearby::scan(duration.0) {
+        Ok(advertisements) => advertisements,
+        Err(state) => {
+            report_error(args, state.into(), output::adapter_message(&state.into()));
+            return ExitCode::FAILURE;
         }
-        if let Some(r) = device.battery_right {
-            parts.push(format!("R:{r}"));
+    };
+    let mut devices = nearby::collect(advertisements, &Registry::default());
+    if args.redact {
+        redact::redact_nearby(&mut devices);
+    }
+    // No flat Shortcuts variant, so anything but text is JSON
+    let rendered = if format == Format::Text {
+        Ok(nearby::format_nearby(&devices, &Locale::from_env(), ascii))
+    } else {
+        serde_json::to_string_pretty(&devices)
+    };
+    match rendered {
+        Ok(text) if text.is_empty() => notice(args, "no advertising devices found"),
+        Ok(text) => println!("{text}"),
+        Err(e) => {
+            report_error(
+                args,
+                ErrorCode::Output,
+                &format!("Failed to serialize devices: {e}"),
+            );
+            return ExitCode::FAILURE;
         }
-        if let Some(c) = device.battery_case {
-            parts.push(format!("Case:{c}"));
+    }
+    ExitCode::SUCCESS
+}
+
+/// Print the lowest battery across all devices as one line
+///
+/// Like the prompt format, it is meant to run on every prompt or status bar
+/// refresh: it reuses recent scans and never writes to stderr.
+fn run_status(args: &Args) -> ExitCode {
+    let config = match load_config(args) {
+        Ok(config) => config,
+        Err(e) => {
+            warn!(error = %e, "Failed to load config");
+            return ExitCode::FAILURE;
         }
-        format!("{}: {}", device.name, parts.join(" "))
+    };
+    let mut status = RunStatus::default();
+    let Some((mut devices, adapter, scanned)) = obtain_devices(args, &config, &mut status, true)
+    else {
+        return ExitCode::FAILURE;
+    };
+    if let Some(adapter) = &adapter {
+        status.fail(output::adapter_message(adapter));
+    }
+    update_freshness(&mut devices, chrono::Utc::now(), config.stale_after());
+    if scanned {
+        record_history(args, &config, &devices);
     }
+    if args.redact {
+        redact::redact_devices(&mut devices);
+    }
+
+    let lowest = output::lowest(&devices);
+    let format = args.format();
+    let rendered = match format {
+        Format::Json | Format::Shortcuts => match serde_json::to_string(&lowest) {
+            Ok(json) => json,
+            Err(e) => {
+                warn!(error = %e, "Failed to serialize status");
+                return ExitCode::FAILURE;
+            }
+        },
+        _ => {
+            let Some(lowest) = lowest else {
+                return ExitCode::SUCCESS;
+            };
+            let severity = config.alerts.severity(lowest.level);
+            let line = output::format_status_line(&lowest);
+            match (format, severity) {
+                (Format::Prompt, None) => return ExitCode::SUCCESS,
+                (Format::Tmux, Some(Severity::Critical)) => format!("#[fg=red]{line}#[default]"),
+                (Format::Tmux, Some(Severity::Warning)) => format!("#[fg=yellow]{line}#[default]"),
+                _ => line,
+            }
+        }
+    };
+    println!("{rendered}");
+    status.exit_code()
 }
 
-fn main() {
-    let args = Args::parse();
+/// Exit codes of `btmon check`, as used by Nagios-style monitoring
+const CHECK_OK: u8 = 0;
+const CHECK_WARNING: u8 = 1;
+const CHECK_CRITICAL: u8 = 2;
+const CHECK_NOT_FOUND: u8 = 3;
 
-    // Initialize tracing subscriber with JSON format
-    if args.debug {
-        tracing_subscriber::fmt()
-            .with_max_level(Level::DEBUG)
-            .json()
-            .init();
+/// Rate the lowest battery of the matching devices against the thresholds,
+/// reporting only through the exit code
+fn run_check(args: &Args, device: &str, warn: Option<u8>, crit: Option<u8>) -> ExitCode {
+    let config = match load_config(args) {
+        Ok(config) => config,
+        Err(e) => {
+            warn!(error = %e, "Failed to load config");
+            return ExitCode::from(CHECK_NOT_FOUND);
+        }
+    };
+    let thresholds = AlertConfig {
+        warn: warn.unwrap_or(config.alerts.warn),
+        critical: crit.unwrap_or(config.alerts.critical),
+        ..AlertConfig::default()
+    };
+    let args = Args {
+        device: Some(device.to_string()),
+        ..args.clone()
+    };
+    let mut status = RunStatus::default();
+    let Some((devices, _, scanned)) = obtain_devices(&args, &config, &mut status, false) else {
+        return ExitCode::from(CHECK_NOT_FOUND);
+    };
+    if scanned {
+        record_history(&args, &config, &devices);
     }
 
-    debug!("Starting btmon");
+    let Some(lowest) = output::lowest(&devices) else {
+        debug!(device, "No matching device with battery info");
+        return ExitCode::from(CHECK_NOT_FOUND);
+    };
+    let code = match thresholds.severity(lowest.level) {
+        Some(Severity::Critical) => CHECK_CRITICAL,
+        Some(Severity::Warning) => CHECK_WARNING,
+        None => CHECK_OK,
+    };
+    debug!(
+        name = lowest.name,
+        level = lowest.level.as_percentage(),
+        code,
+        "Checked battery"
+    );
+    ExitCode::from(code)
+}
 
-    let devices = get_connected_devices(args.device.as_deref());
+/// Time between scans while waiting for a device
+const WAIT_POLL_INTERVAL: Duration = Duration::from_secs(2);
 
-    if devices.is_empty() {
-        if let Some(ref filter) = args.device {
-            warn!(filter = %filter, "No devices found matching filter");
-            eprintln!("no devices found matching '{filter}'");
-        } else {
-            warn!("No devices with battery info found");
-            eprintln!("no devices with battery info found");
+/// Scan until a device matching `device` reports its battery and print it,
+/// failing after `timeout` or on Ctrl-C
+fn run_wait(args: &Args, device: &str, timeout: Option<Interval>) -> ExitCode {
+    let config = match load_config(args) {
+        Ok(config) => config,
+        Err(e) => {
+            report_error(args, ErrorCode::Config, &e.to_string());
+            return ExitCode::FAILURE;
         }
-        return;
+    };
+    let args = Args {
+        device: Some(device.to_string()),
+        ..args.clone()
+    };
+    let deadline = timeout.map(|timeout| Instant::now() + timeout.0);
+    watch::install_interrupt_handler();
+
+    let (mut devices, status) = loop {
+        let mut status = RunStatus::default();
+        if let Some((devices, _)) = scan_devices(&args, &config, &mut status) {
+            let devices: Vec<Device> = devices
+                .into_iter()
+                .filter(|device| device.components().next().is_some())
+                .collect();
+            if !devices.is_empty() {
+                break (devices, status);
+            }
+        }
+        let wait = match deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    report_error(
+                        &args,
+                        ErrorCode::Timeout,
+                        &format!("timed out waiting for '{device}'"),
+                    );
+                    return ExitCode::FAILURE;
+                }
+                remaining.min(WAIT_POLL_INTERVAL)
+            }
+            None => WAIT_POLL_INTERVAL,
+        };
+        debug!(device, "Device not reporting yet, waiting");
+        if !watch::sleep(wait) {
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if args.redact {
+        redact::redact_devices(&mut devices);
     }
+    let Some(text) = render(&args, &config, &status, devices, None, None) else {
+        return ExitCode::FAILURE;
+    };
+    let mut written = RunStatus::default();
+    emit(&args, &mut written, &text);
+    written.exit_code()
+}
+
+/// Print the diagnostic checks, failing if any check failed
+fn run_doctor(args: &Args) -> ExitCode {
+    let mut backend = SystemBackend::detect(!args.no_private_api);
+    let capabilities = backend.capabilities().clone();
+    // The adapter state is only known after Core Bluetooth was asked
+    let adapter = if capabilities.gatt.enabled {
+        notice(args, "Checking Bluetooth...");
+        backend.gatt_readings();
+        backend.adapter()
+    } else {
+        None
+    };
+    let terminal = std::env::var("TERM_PROGRAM").ok();
+    let checks = doctor::run_checks(&capabilities, adapter, terminal.as_deref());
 
-    if args.json {
-        match serde_json::to_string_pretty(&devices) {
+    if args.format() == Format::Text {
+        print!("{}", doctor::format_report(&checks));
+    } else {
+        match serde_json::to_string_pretty(&checks) {
             Ok(json) => println!("{json}"),
             Err(e) => {
-                warn!(error = %e, "Failed to serialize devices to JSON");
-                eprintln!("Failed to serialize devices: {e}");
+                report_error(
+                    args,
+                    ErrorCode::Output,
+                    &format!("Failed to serialize checks: {e}"),
+                );
+                return ExitCode::FAILURE;
             }
         }
+    }
+    if doctor::has_failures(&checks) {
+        ExitCode::FAILURE
     } else {
-        for device in &devices {
-            println!("{}", format_device_output(device));
-        }
+        ExitCode::SUCCESS
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// The replay backend if `--replay` was given, otherwise the system one,
+/// masking addresses with `--redact`
+fn open_backend(args: &Args) -> Option<Box<dyn Backend>> {
+    let backend: Box<dyn Backend> = match &args.replay {
+        Some(path) => match RawSnapshot::load(path) {
+            Ok(snapshot) => Box::new(ReplayBackend(snapshot)),
+            Err(e) => {
+                report_error(
+                    args,
+                    ErrorCode::Session,
+                    &format!("failed to load session {}: {e}", path.display()),
+                );
+                return None;
+            }
+        },
+        None => Box::new(
+            SystemBackend::detect(!args.no_private_api).connect_if_needed(args.connect_if_needed),
+        ),
+    };
+    Some(backend)
+}
 
-    #[test]
-    fn test_battery_level_valid() {
-        assert!(BatteryLevel::new(1).is_some());
-        assert!(BatteryLevel::new(50).is_some());
-        assert!(BatteryLevel::new(100).is_some());
+/// Load the config file with the command-line overrides applied
+fn load_config(args: &Args) -> Result<Config, ConfigError> {
+    let mut config = Config::load_or_default(args.config.as_deref())?;
+    if let Some(threshold) = args.threshold {
+        config.alerts.warn = threshold;
+        config.alerts.critical = config.alerts.critical.min(threshold);
     }
-
-    #[test]
-    fn test_battery_level_invalid() {
-        assert!(BatteryLevel::new(0).is_none());
-        assert!(BatteryLevel::new(101).is_none());
-        assert!(BatteryLevel::new(255).is_none());
+    if args.notify && config.alerts.desktop.is_none() {
+        config.alerts.desktop = Some(DesktopConfig::default());
     }
+    Ok(config)
+}
 
-    #[test]
-    fn test_battery_level_display() {
-        let level = BatteryLevel::new(75).unwrap();
-        assert_eq!(format!("{level}"), "75%");
-    }
+/// Print the recorded readings of the last `since`
+fn run_history(args: &Args, device: Option<&str>, since: Interval) -> ExitCode {
+    let Some(path) = History::default_path() else {
+        report_error(args, ErrorCode::Storage, "cannot determine cache directory");
+        return ExitCode::FAILURE;
+    };
+    let since = chrono::Utc::now() - since.0;
+    let readings = match History::new(path).load(device, since) {
+        Ok(readings) => readings,
+        Err(e) => {
+            report_error(
+                args,
+                ErrorCode::Storage,
+                &format!("failed to read battery history: {e}"),
+            );
+            return ExitCode::FAILURE;
+        }
+    };
 
-    #[test]
-    fn test_device_has_battery_info() {
-        let device_with_single = Device {
-            name: "Test".to_string(),
-            address: DeviceAddress::Ble,
-            battery_level: BatteryLevel::new(50),
-            battery_left: None,
-            battery_right: None,
-            battery_case: None,
-        };
-        assert!(device_with_single.has_battery_info());
-
-        let device_with_left_right = Device {
-            name: "AirPods".to_string(),
-            address: DeviceAddress::Classic("aa:bb:cc:dd:ee:ff".to_string()),
-            battery_level: None,
-            battery_left: BatteryLevel::new(80),
-            battery_right: BatteryLevel::new(90),
-            battery_case: None,
-        };
-        assert!(device_with_left_right.has_battery_info());
-
-        let device_without_battery = Device {
-            name: "Mouse".to_string(),
-            address: DeviceAddress::Ble,
-            battery_level: None,
-            battery_left: None,
-            battery_right: None,
-            battery_case: None,
-        };
-        assert!(!device_without_battery.has_battery_info());
+    if args.format() == Format::Text {
+        if readings.is_empty() {
+            notice(args, "No readings recorded (record them with --history)");
+        }
+        print!("{}", history::format_readings(&readings));
+    } else {
+        match serde_json::to_string_pretty(&readings) {
+            Ok(json) => println!("{json}"),
+            Err(e) => {
+                report_error(
+                    args,
+                    ErrorCode::Output,
+                    &format!("Failed to serialize readings: {e}"),
+                );
+                return ExitCode::FAILURE;
+            }
+        }
     }
+    ExitCode::SUCCESS
+}
 
-    #[test]
-    fn test_format_device_output_single() {
-        let device = Device {
-            name: "Keyboard".to_string(),
-            address: DeviceAddress::Ble,
-            battery_level: BatteryLevel::new(76),
-            battery_left: None,
-            battery_right: None,
-            battery_case: None,
+/// Dump the recorded readings of the last `since` for analysis elsewhere
+fn run_export(
+    args: &Args,
+    device: Option<&str>,
+    since: Interval,
+    components: &[Component],
+) -> ExitCode {
+    let format = args.format();
+    if !history::exports(format) || args.porcelain || args.template.is_some() {
+        report_error(
+            args,
+            ErrorCode::Usage,
+            "btmon export supports --format csv, json or xlsx",
+        );
+        return ExitCode::FAILURE;
+    }
+    let Some(path) = History::default_path() else {
+        report_error(args, ErrorCode::Storage, "cannot determine cache directory");
+        return ExitCode::FAILURE;
+    };
+    let since = chrono::Utc::now() - since.0;
+    let mut readings = match History::new(path).load(device, since) {
+        Ok(readings) => readings,
+        Err(e) => {
+            report_error(
+                args,
+                ErrorCode::Storage,
+                &format!("failed to read battery history: {e}"),
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+    if !components.is_empty() {
+        readings.retain(|reading| components.contains(&reading.component));
+    }
+    if readings.is_empty() {
+        notice(
+            args,
+            "No readings recorded in that time (record them with --history)",
+        );
+    }
+
+    match format {
+        Format::Json => match serde_json::to_string_pretty(&readings) {
+            Ok(json) => println!("{json}"),
+            Err(e) => {
+                report_error(
+                    args,
+                    ErrorCode::Output,
+                    &format!("Failed to serialize readings: {e}"),
+                );
+                return ExitCode::FAILURE;
+            }
+        },
+        Format::Xlsx => return write_workbook(args, &readings),
+        _ => print!("{}", history::format_csv(&readings)),
+    }
+    ExitCode::SUCCESS
+}
+
+/// Write the readings as an Excel workbook to `--output`, or to stdout when
+/// it is redirected
+fn write_workbook(args: &Args, readings: &[history::Reading]) -> ExitCode {
+    let workbook = xlsx::workbook(readings);
+    let written = match &args.output {
+        Some(path) => output::write_atomic(path, &workbook)
+            .map_err(|e| format!("failed to write {}: {e}", path.display())),
+        None if io::stdout().is_terminal() => {
+            report_error(
+                args,
+                ErrorCode::Usage,
+                "a workbook can't be printed to a terminal; redirect it to a file",
+            );
+            return ExitCode::FAILURE;
+        }
+        None => io::stdout()
+            .write_all(&workbook)
+            .map_err(|e| format!("failed to write the workbook: {e}")),
+    };
+    match written {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            report_error(args, ErrorCode::Output, &message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Chart the recorded readings of the devices matching `device`
+fn run_graph(args: &Args, device: &str, since: Interval, width: usize, height: usize) -> ExitCode {
+    let Some(path) = History::default_path() else {
+        report_error(args, ErrorCode::Storage, "cannot determine cache directory");
+        return ExitCode::FAILURE;
+    };
+    let until = chrono::Utc::now();
+    let since = until - since.0;
+    let readings = match History::new(path).load(Some(device), since) {
+        Ok(readings) => readings,
+        Err(e) => {
+            report_error(
+                args,
+                ErrorCode::Storage,
+                &format!("failed to read battery history: {e}"),
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+    if readings.is_empty() {
+        report_error(
+            args,
+            ErrorCode::NotFound,
+            &format!(
+                "No readings of '{device}' recorded in that time (record them with --history)"
+            ),
+        );
+        return ExitCode::FAILURE;
+    }
+    print!("{}", graph::render(&readings, since, until, width, height));
+    ExitCode::SUCCESS
+}
+
+/// Print the timeline of the devices matching `device`
+fn run_timeline(args: &Args, device: &str, since: Interval) -> ExitCode {
+    let Some(path) = History::default_path() else {
+        report_error(args, ErrorCode::Storage, "cannot determine cache directory");
+        return ExitCode::FAILURE;
+    };
+    let history = History::new(path);
+    let since = chrono::Utc::now() - since.0;
+    let recorded = history.load(Some(device), since).and_then(|readings| {
+        let connections = history.load_connections(Some(device), since)?;
+        Ok((readings, connections))
+    });
+    let (readings, connections) = match recorded {
+        Ok(recorded) => recorded,
+        Err(e) => {
+            report_error(
+                args,
+                ErrorCode::Storage,
+                &format!("failed to read battery history: {e}"),
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+    if readings.is_empty() && connections.is_empty() {
+        report_error(
+            args,
+            ErrorCode::NotFound,
+            &format!("Nothing of '{device}' recorded in that time (record it with --history)"),
+        );
+        return ExitCode::FAILURE;
+    }
+
+    let entries = timeline::build(&readings, &connections);
+    if args.format() == Format::Text {
+        print!("{}", timeline::format_timeline(&entries));
+    } else {
+        match serde_json::to_string_pretty(&entries) {
+            Ok(json) => println!("{json}"),
+            Err(e) => {
+                report_error(
+                    args,
+                    ErrorCode::Output,
+                    &format!("Failed to serialize timeline: {e}"),
+                );
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+/// Merge the readings of an external log into the battery history
+fn run_history_import(args: &Args, file: &Path) -> ExitCode {
+    let config = match load_config(args) {
+        Ok(config) => config,
+        Err(e) => {
+            report_error(args, ErrorCode::Config, &e.to_string());
+            return ExitCode::FAILURE;
+        }
+    };
+    let Some(path) = History::default_path() else {
+        report_error(args, ErrorCode::Storage, "cannot determine cache directory");
+        return ExitCode::FAILURE;
+    };
+    let readings = match fs::read_to_string(file) {
+        Ok(contents) => import::parse(&contents),
+        Err(e) => {
+            report_error(
+                args,
+                ErrorCode::Storage,
+                &format!("failed to read {}: {e}", file.display()),
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+    let readings = match readings {
+        Ok(readings) => readings,
+        Err(e) => {
+            report_error(args, ErrorCode::Usage, &format!("{}: {e}", file.display()));
+            return ExitCode::FAILURE;
+        }
+    };
+    let total = readings.len();
+    match History::new(path)
+        .encrypt(config.history.encrypt)
+        .import(readings)
+    {
+        Ok(added) => {
+            notice(
+                args,
+                &format!(
+                    "Imported {added} readings ({} already recorded)",
+                    total - added
+                ),
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            report_error(
+                args,
+                ErrorCode::Storage,
+                &format!("failed to write battery history: {e}"),
+            );
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Print the recorded connection events of the last `since`
+fn run_history_events(args: &Args, device: Option<&str>, since: Interval) -> ExitCode {
+    let Some(path) = History::default_path() else {
+        report_error(args, ErrorCode::Storage, "cannot determine cache directory");
+        return ExitCode::FAILURE;
+    };
+    let since = chrono::Utc::now() - since.0;
+    let connections = match History::new(path).load_connections(device, since) {
+        Ok(connections) => connections,
+        Err(e) => {
+            report_error(
+                args,
+                ErrorCode::Storage,
+                &format!("failed to read connection events: {e}"),
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if args.format() == Format::Text {
+        if connections.is_empty() {
+            notice(
+                args,
+                "No connection events recorded (record them with --history)",
+            );
+        }
+        print!("{}", history::format_connections(&connections));
+    } else {
+        match serde_json::to_string_pretty(&connections) {
+            Ok(json) => println!("{json}"),
+            Err(e) => {
+                report_error(
+                    args,
+                    ErrorCode::Output,
+                    &format!("Failed to serialize connection events: {e}"),
+                );
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+/// Whether readings and connection events are recorded, with `--history` or
+/// `[history] record`
+fn records_history(args: &Args, config: &Config) -> bool {
+    (args.history || config.history.record) && args.replay.is_none()
+}
+
+/// Append the devices' readings to the battery history, and the devices that
+/// connected or disconnected since the last scan to its events, with
+/// `--history` or `[history] record`
+fn record_history(args: &Args, config: &Config, devices: &[Device]) -> bool {
+    if !records_history(args, config) {
+        return true;
+    }
+    let Some(path) = History::default_path() else {
+        warn!("Cannot determine cache directory for battery history");
+        return false;
+    };
+    let history = History::new(path).encrypt(config.history.encrypt);
+    let now = chrono::Utc::now();
+    let readings = history::readings(devices, now);
+    if let Err(e) = history.append(&readings, config.history.retention()) {
+        warn!(error = %e, "Failed to record battery history");
+        return false;
+    }
+    match history.record_scan(devices, args.device.as_deref(), now, &last_seen()) {
+        Ok(connections) => {
+            debug!(count = connections.len(), "Recorded connection events");
+            true
+        }
+        Err(e) => {
+            warn!(error = %e, "Failed to record connection events");
+            false
+        }
+    }
+}
+
+/// Run a `btmon config` subcommand
+fn run_config(args: &Args, action: &ConfigAction) -> ExitCode {
+    let Some(path) = args.config.clone().or_else(paths::config_file) else {
+        report_error(args, ErrorCode::Storage, "cannot determine home directory");
+        return ExitCode::FAILURE;
+    };
+
+    match action {
+        ConfigAction::Init { force } => match Config::init(&path, *force) {
+            Ok(()) => {
+                println!("wrote {}", path.display());
+                ExitCode::SUCCESS
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                report_error(
+                    args,
+                    ErrorCode::Config,
+                    &format!(
+                        "{} already exists (use --force to replace it)",
+                        path.display()
+                    ),
+                );
+                ExitCode::FAILURE
+            }
+            Err(e) => {
+                report_error(
+                    args,
+                    ErrorCode::Config,
+                    &format!("failed to write {}: {e}", path.display()),
+                );
+                ExitCode::FAILURE
+            }
+        },
+        ConfigAction::Path => {
+            println!("{}", path.display());
+            if !path.exists() {
+                notice(
+                    args,
+                    "(does not exist, defaults are used; create it with `btmon config init`)",
+                );
+            }
+            ExitCode::SUCCESS
+        }
+        ConfigAction::Validate => {
+            // Without --config a missing file is valid: the defaults apply
+            if args.config.is_none() && !path.exists() {
+                println!("{} does not exist, defaults are used", path.display());
+                return ExitCode::SUCCESS;
+            }
+            let config = match Config::load(&path) {
+                Ok(config) => config,
+                Err(e) => {
+                    report_error(args, ErrorCode::Config, &e.to_string());
+                    return ExitCode::FAILURE;
+                }
+            };
+            let problems = config.problems();
+            if problems.is_empty() {
+                println!("{} is valid", path.display());
+                return ExitCode::SUCCESS;
+            }
+            for problem in problems {
+                report_error(
+                    args,
+                    ErrorCode::Config,
+                    &format!("{}: {problem}", path.display()),
+                );
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Serve JSON-RPC on stdin/stdout until stdin is closed
+fn run_stdio(args: &Args) -> ExitCode {
+    let config = match load_config(args) {
+        Ok(config) => config,
+        Err(e) => {
+            report_error(args, ErrorCode::Config, &e.to_string());
+            return ExitCode::FAILURE;
+        }
+    };
+    let Some(backend) = open_backend(args) else {
+        return ExitCode::FAILURE;
+    };
+    let input = io::BufReader::new(io::stdin());
+    let server = rpc::Server::new(backend, config).redact(args.redact);
+    match rpc::serve(server, input, io::stdout()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            report_error(args, ErrorCode::Output, &format!("stdio: {e}"));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Serve the HTTP API until the process is stopped
+fn run_serve(args: &Args, bind: IpAddr, port: u16, advertise: bool) -> ExitCode {
+    let config = match load_config(args) {
+        Ok(config) => config,
+        Err(e) => {
+            report_error(args, ErrorCode::Config, &e.to_string());
+            return ExitCode::FAILURE;
+        }
+    };
+    let Some(backend) = open_backend(args) else {
+        return ExitCode::FAILURE;
+    };
+    let listener = match TcpListener::bind((bind, port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            report_error(
+                args,
+                ErrorCode::Network,
+                &format!("failed to listen on {bind}:{port}: {e}"),
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+    notice(
+        args,
+        &format!("serving on http://{}", SocketAddr::from((bind, port))),
+    );
+    // Serving goes on without it, as clients can still be given the address
+    let _advertisement = advertise
+        .then(|| bonjour::advertise(bind, port))
+        .and_then(|advertised| match advertised {
+            Ok(advertisement) => {
+                notice(args, &format!("advertising as {}", bonjour::SERVICE_TYPE));
+                Some(advertisement)
+            }
+            Err(e) => {
+                // Not a failure of the command, so no error object with --json
+                warn!(error = %e, "Failed to advertise the API");
+                notice(args, &format!("not advertising: {e}"));
+                None
+            }
+        });
+    let mut server = ApiServer::new(backend, config).redact(args.redact);
+    if let Some(path) = History::default_path() {
+        server = server.history(History::new(path));
+    }
+    match api::serve(listener, server) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            report_error(args, ErrorCode::Network, &format!("serve: {e}"));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Query connected devices and print their battery levels, followed by the
+/// run summary with `--status-json`
+fn run_devices(
+    args: &Args,
+    warnings: Option<&WarningCounter>,
+    samples: Option<&mut Samples>,
+) -> ExitCode {
+    let start = Instant::now();
+    let warnings_before = warnings.map_or(0, WarningCounter::count);
+    let mut status = RunStatus::default();
+    list_devices(args, &mut status, samples);
+
+    if args.status_json {
+        status.duration_ms = u64::try_from(start.elapsed().as_millis()).unwrap_or(u64::MAX);
+        status.warnings = warnings.map_or(0, WarningCounter::count) - warnings_before;
+        match serde_json::to_string(&status) {
+            Ok(line) => eprintln!("{line}"),
+            Err(e) => warn!(error = %e, "Failed to serialize run status"),
+        }
+    }
+    status.exit_code()
+}
+
+/// Repeat the device listing every `--interval` until Ctrl-C
+fn run_watch(args: &Args, warnings: Option<&WarningCounter>) -> ExitCode {
+    let interval = args
+        .interval
+        .map_or(watch::DEFAULT_INTERVAL, |interval| interval.0);
+    // A terminal shows one table that is redrawn, anything else gets each
+    // pass appended
+    let redraw =
+        args.format() == Format::Text && args.output.is_none() && io::stdout().is_terminal();
+    watch::install_interrupt_handler();
+
+    let mut samples = Samples::default();
+    loop {
+        if redraw {
+            print!("\x1b[2J\x1b[H");
+        }
+        run_devices(args, warnings, Some(&mut samples));
+        if !watch::sleep(interval) {
+            debug!("Interrupted, stopping watch");
+            return ExitCode::SUCCESS;
+        }
+    }
+}
+
+/// Default daemon log file
+fn default_log_file() -> Option<PathBuf> {
+    paths::cache_dir().map(|dir| dir.join("daemon.log"))
+}
+
+/// Open the daemon log file for appending, creating its directory if needed
+fn open_log(args: &Args, path: Option<&Path>) -> Option<File> {
+    let path = path.map(Path::to_path_buf).or_else(default_log_file)?;
+    if let Some(dir) = path.parent()
+        && let Err(e) = fs::create_dir_all(dir)
+    {
+        report_error(
+            args,
+            ErrorCode::Storage,
+            &format!("failed to create {}: {e}", dir.display()),
+        );
+        return None;
+    }
+    match File::options().create(true).append(true).open(&path) {
+        Ok(file) => Some(file),
+        Err(e) => {
+            report_error(
+                args,
+                ErrorCode::Storage,
+                &format!("failed to open log file {}: {e}", path.display()),
+            );
+            None
+        }
+    }
+}
+
+/// Poll the devices every `--interval` until stopped, sending alerts and
+/// events and keeping the scan cache fresh, and with `metrics`, serving the
+/// latest scan to Prometheus
+fn run_daemon(args: &Args, metrics: Option<SocketAddr>) -> ExitCode {
+    let config = match load_config(args) {
+        Ok(config) => config,
+        Err(e) => {
+            report_error(args, ErrorCode::Config, &e.to_string());
+            return ExitCode::FAILURE;
+        }
+    };
+    let interval = args
+        .interval
+        .map_or(watch::DEFAULT_INTERVAL, |interval| interval.0);
+    info!(interval_secs = interval.as_secs(), "Starting daemon");
+    watch::install_interrupt_handler();
+
+    // Other invocations read the latest scan from the socket
+    let snapshot = Snapshot::default();
+    let socket_path = socket::socket_path();
+    if let Some(path) = &socket_path {
+        match socket::listen(path) {
+            Ok(listener) => {
+                if let Err(e) = socket::serve(listener, snapshot.clone()) {
+                    warn!(error = %e, "Failed to start daemon socket");
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+                report_error(args, ErrorCode::Network, &e.to_string());
+                return ExitCode::FAILURE;
+            }
+            Err(e) => {
+                warn!(error = %e, path = %path.display(), "Failed to listen on daemon socket")
+            }
+        }
+    }
+    // and subscribe to changes on the event socket
+    let events_path = socket::events_path();
+    if let Some(path) = &events_path {
+        let served = socket::listen(path)
+            .and_then(|listener| socket::serve_events(listener, snapshot.clone()));
+        if let Err(e) = served {
+            warn!(error = %e, path = %path.display(), "Failed to listen on daemon event socket");
+        }
+    }
+
+    // Prometheus scrapes the snapshot too, without scans of its own
+    if let Some(address) = metrics {
+        let served = TcpListener::bind(address).and_then(|listener| {
+            api::serve_metrics(listener, snapshot.clone(), config.stale_after())
+        });
+        if let Err(e) = served {
+            report_error(
+                args,
+                ErrorCode::Network,
+                &format!("failed to serve metrics on {address}: {e}"),
+            );
+            return ExitCode::FAILURE;
+        }
+        info!(%address, "Serving metrics");
+    }
+
+    // One backend for the whole run, recreated when it stops answering
+    let Some(mut backend) = open_backend(args) else {
+        return ExitCode::FAILURE;
+    };
+    let mut watchdog = Watchdog::new(watchdog::DEFAULT_LIMIT);
+
+    loop {
+        let mut status = RunStatus::default();
+        let (mut devices, adapter) = scan_with(args, &config, &mut status, backend.as_mut());
+        if watchdog.observe(watchdog::succeeded(adapter.as_ref())) {
+            warn!(
+                intervals = watchdog::DEFAULT_LIMIT,
+                restarts = watchdog.restarts(),
+                "No successful scan, recreating the Bluetooth backend"
+            );
+            snapshot.set_backend_restarts(watchdog.restarts());
+            if let Some(new) = open_backend(args) {
+                backend = new;
+            }
+        }
+        update_freshness(&mut devices, chrono::Utc::now(), config.stale_after());
+        match adapter {
+            Some(adapter) => warn!(state = ?adapter.state, "Bluetooth adapter unavailable"),
+            None => {
+                info!(devices = devices.len(), "Scanned devices");
+                let scan = ScanCache {
+                    scanned_at: chrono::Utc::now(),
+                    devices,
+                };
+                if let Err(e) = snapshot.update(&scan) {
+                    warn!(error = %e, "Failed to update daemon snapshot");
+                }
+                devices = scan.devices;
+                record_history(args, &config, &devices);
+            }
+        }
+        // The snapshot is masked by each client as it asks
+        if args.redact {
+            redact::redact_devices(&mut devices);
+        }
+        run_alerts(args, &devices, &config.alerts);
+        if !config.events.sinks.is_empty() {
+            run_events(&devices, args, &config.events, &config.alerts);
+        }
+        if !watch::sleep(interval) {
+            info!("Stopping daemon");
+            for path in [&socket_path, &events_path].into_iter().flatten() {
+                let _ = fs::remove_file(path);
+            }
+            return ExitCode::SUCCESS;
+        }
+    }
+}
+
+/// Publish battery levels to an MQTT broker every `--interval` until
+/// interrupted
+fn run_mqtt(args: &Args, broker: Option<String>) -> ExitCode {
+    let mut config = match load_config(args) {
+        Ok(config) => config,
+        Err(e) => {
+            report_error(args, ErrorCode::Config, &e.to_string());
+            return ExitCode::FAILURE;
+        }
+    };
+    let mqtt = match (broker, config.mqtt.take()) {
+        (Some(broker), Some(mqtt)) => MqttConfig { broker, ..mqtt },
+        (Some(broker), None) => MqttConfig::new(broker),
+        (None, Some(mqtt)) => mqtt,
+        (None, None) => {
+            report_error(
+                args,
+                ErrorCode::Config,
+                "no MQTT broker: pass --broker or set [mqtt] broker in the config file",
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+    let interval = args
+        .interval
+        .map_or(watch::DEFAULT_INTERVAL, |interval| interval.0);
+    let mut publisher = match Publisher::new(mqtt, interval) {
+        Ok(publisher) => publisher,
+        Err(e) => {
+            report_error(args, ErrorCode::Network, &e.to_string());
+            return ExitCode::FAILURE;
+        }
+    };
+    watch::install_interrupt_handler();
+
+    loop {
+        let mut status = RunStatus::default();
+        if let Some((mut devices, _)) = scan_devices(args, &config, &mut status) {
+            update_freshness(&mut devices, chrono::Utc::now(), config.stale_after());
+            if args.redact {
+                redact::redact_devices(&mut devices);
+            }
+            match publisher.publish(&devices) {
+                Ok(()) => debug!(devices = devices.len(), "Published to MQTT"),
+                Err(e) => warn!(error = %e, "MQTT publish failed, retrying next interval"),
+            }
+        }
+        if !watch::sleep(interval) {
+            publisher.disconnect();
+            return ExitCode::SUCCESS;
+        }
+    }
+}
+
+/// Install and load the launchd agent running the daemon with the current
+/// `--interval` and `--config`
+fn run_install_agent(args: &Args) -> ExitCode {
+    let (Some(path), Some(log)) = (agent::plist_path(), default_log_file()) else {
+        report_error(args, ErrorCode::Storage, "cannot determine home directory");
+        return ExitCode::FAILURE;
+    };
+    let program = match std::env::current_exe() {
+        Ok(program) => program,
+        Err(e) => {
+            report_error(
+                args,
+                ErrorCode::Storage,
+                &format!("cannot determine btmon's path: {e}"),
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut arguments = vec!["daemon".to_string()];
+    if let Some(interval) = args.interval {
+        arguments.extend(["--interval".to_string(), interval.as_secs().to_string()]);
+    }
+    if let Some(config) = &args.config {
+        match std::path::absolute(config) {
+            Ok(config) => {
+                arguments.extend(["--config".to_string(), config.display().to_string()]);
+            }
+            Err(e) => {
+                report_error(
+                    args,
+                    ErrorCode::Config,
+                    &format!("invalid config path {}: {e}", config.display()),
+                );
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    if let Some(dir) = log.parent()
+        && let Err(e) = fs::create_dir_all(dir)
+    {
+        report_error(
+            args,
+            ErrorCode::Storage,
+            &format!("failed to create {}: {e}", dir.display()),
+        );
+        return ExitCode::FAILURE;
+    }
+    match agent::install(&path, &agent::plist(&program, &arguments, &log)) {
+        Ok(()) => {
+            println!("installed {}", path.display());
+            println!("logging to {}", log.display());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            report_error(
+                args,
+                ErrorCode::Storage,
+                &format!("failed to install agent {}: {e}", path.display()),
+            );
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Unload and remove the launchd agent
+fn run_uninstall_agent(args: &Args) -> ExitCode {
+    let Some(path) = agent::plist_path() else {
+        report_error(args, ErrorCode::Storage, "cannot determine home directory");
+        return ExitCode::FAILURE;
+    };
+    match agent::uninstall(&path) {
+        Ok(true) => {
+            println!("removed {}", path.display());
+            ExitCode::SUCCESS
+        }
+        Ok(false) => {
+            println!("agent is not installed");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            report_error(
+                args,
+                ErrorCode::Storage,
+                &format!("failed to remove agent {}: {e}", path.display()),
+            );
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Run the menu bar app, scanning every `--interval` and sending alerts like
+/// a watch pass
+#[cfg(target_os = "macos")]
+fn run_menubar(args: &Args) -> ExitCode {
+    let config = match load_config(args) {
+        Ok(config) => config,
+        Err(e) => {
+            report_error(args, ErrorCode::Config, &e.to_string());
+            return ExitCode::FAILURE;
+        }
+    };
+    let interval = args
+        .interval
+        .map_or(watch::DEFAULT_INTERVAL, |interval| interval.0);
+    let scan_args = args.clone();
+    let scan = move || {
+        let mut status = RunStatus::default();
+        let Some((mut devices, _)) = scan_devices(&scan_args, &config, &mut status) else {
+            return Vec::new();
         };
-        assert_eq!(format_device_output(&device), "Keyboard: 76%");
+        update_freshness(&mut devices, chrono::Utc::now(), config.stale_after());
+        if scan_args.redact {
+            redact::redact_devices(&mut devices);
+        }
+        run_alerts(&scan_args, &devices, &config.alerts);
+        devices
+    };
+    match btmon::menubar::run(scan, interval) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            report_error(args, ErrorCode::Unsupported, &format!("menubar: {e}"));
+            ExitCode::FAILURE
+        }
     }
+}
 
-    #[test]
-    fn test_format_device_output_airpods() {
-        let device = Device {
-            name: "AirPods Pro".to_string(),
-            address: DeviceAddress::Classic("aa:bb:cc:dd:ee:ff".to_string()),
-            battery_level: None,
-            battery_left: BatteryLevel::new(80),
-            battery_right: BatteryLevel::new(90),
-            battery_case: BatteryLevel::new(100),
+/// The menu bar needs AppKit
+#[cfg(not(target_os = "macos"))]
+fn run_menubar(args: &Args) -> ExitCode {
+    report_error(
+        args,
+        ErrorCode::Unsupported,
+        "the menu bar app is only available on macOS",
+    );
+    ExitCode::FAILURE
+}
+
+/// Devices from the daemon or a recent enough scan, or else from a new scan
+/// (None if it failed), with whether they were scanned just now
+///
+/// `quick` output (prompts, status lines) runs all the time, so it reuses
+/// scans up to a minute old even without `--max-age`.
+fn obtain_devices(
+    args: &Args,
+    config: &Config,
+    status: &mut RunStatus,
+    quick: bool,
+) -> Option<(Vec<Device>, Option<AdapterStatus>, bool)> {
+    let max_age = args.max_age.map(Duration::from_secs).or_else(|| {
+        (quick && args.replay.is_none() && args.record.is_none()).then_some(PROMPT_MAX_AGE)
+    });
+    // A running daemon has a recent scan, only limited by an explicit
+    // --max-age
+    let from_daemon = (args.replay.is_none() && args.record.is_none() && !args.no_daemon)
+        .then(|| {
+            let scan = socket::query(&socket::socket_path()?)
+                .inspect_err(|e| debug!(error = %e, "No scan from daemon"))
+                .ok()?;
+            match args.max_age {
+                Some(secs) => scan.fresh(chrono::Utc::now(), Duration::from_secs(secs)),
+                None => Some(scan.devices),
+            }
+        })
+        .flatten();
+    let reused = from_daemon.map(|devices| ("daemon", devices)).or_else(|| {
+        let cache = ScanCache::load(&scan_cache_path()?).ok()?;
+        let devices = cache.fresh(chrono::Utc::now(), max_age?)?;
+        Some(("cache", devices))
+    });
+
+    match reused {
+        Some((source, mut devices)) => {
+            debug!(source, "Using earlier scan");
+            status.backend = Some(source);
+            if let Some(filter) = &args.device {
+                let filter = filter.to_lowercase();
+                devices.retain(|d| d.name.to_lowercase().contains(&filter));
+            }
+            Some((devices, None, false))
+        }
+        None => {
+            let (devices, adapter) = scan_devices(args, config, status)?;
+            Some((devices, adapter, true))
+        }
+    }
+}
+
+/// Glyphs selected with `--icons`, emoji by default
+fn icons(args: &Args, config: &Config) -> Icons {
+    Icons::new(args.icons.unwrap_or_default(), &config.icons)
+}
+
+/// Thresholds to colour levels by, if `--color` allows colour in the output
+fn colors<'a>(args: &Args, config: &'a Config) -> Option<&'a AlertConfig> {
+    // A file written with --output is no terminal, whatever stdout is
+    let enabled = match args.color {
+        ColorChoice::Auto if args.output.is_some() => false,
+        choice => choice.enabled(),
+    };
+    enabled.then_some(&config.alerts)
+}
+
+/// Width to fit the table to, None for a file or a pipe
+fn table_width(args: &Args) -> Option<usize> {
+    args.output.is_none().then(output::terminal_width).flatten()
+}
+
+/// Format the devices as plain text, decorated as `--icons`, `--bars` and
+/// `--color` ask
+fn render_text(args: &Args, config: &Config, devices: &[Device]) -> String {
+    let style = TextStyle {
+        icons: args.icons.map(|_| icons(args, config)),
+        bars: args.bars,
+        colors: colors(args, config),
+    };
+    output::format_styled_text(devices, &Locale::from_env(), &style)
+}
+
+/// Print an informational line on stderr, unless `--quiet`
+fn notice(args: &Args, message: &str) {
+    if !args.quiet {
+        eprintln!("{message}");
+    }
+}
+
+/// Report a failure on stderr, as a JSON object with `--json`
+fn report_error(args: &Args, code: ErrorCode, message: &str) {
+    if args.format() == Format::Json {
+        eprintln!("{}", errors::format_error(code, message));
+    } else {
+        eprintln!("{message}");
+    }
+}
+
+/// Query connected devices and print their battery levels, recording the
+/// outcome in `status`. With `samples`, the levels are compared with the
+/// previous pass of `--watch`
+fn list_devices(args: &Args, status: &mut RunStatus, samples: Option<&mut Samples>) {
+    let format = args.format();
+    let config = match load_config(args) {
+        Ok(config) => config,
+        Err(e) => {
+            report_error(args, ErrorCode::Config, &e.to_string());
+            status.fail(e.to_string());
+            return;
+        }
+    };
+
+    let Some((mut devices, adapter, scanned)) = obtain_devices(
+        args,
+        &config,
+        status,
+        matches!(format, Format::Prompt | Format::Short),
+    ) else {
+        return;
+    };
+    update_freshness(&mut devices, chrono::Utc::now(), config.stale_after());
+    let samples = samples.map(|samples| {
+        samples.apply(&mut devices);
+        &*samples
+    });
+    status.devices = devices.len();
+
+    if scanned && !record_history(args, &config, &devices) {
+        status.fail("failed to record battery history");
+    }
+    // The history keeps the real addresses, everything after is output
+    if args.redact {
+        redact::redact_devices(&mut devices);
+    }
+
+    if let Some(path) = &args.append_csv {
+        let log = CsvLog::new(path.clone(), args.csv_rollover);
+        if let Err(e) = log.append(&devices, &chrono::Local::now()) {
+            warn!(error = %e, path = %path.display(), "Failed to append to CSV log");
+            report_error(
+                args,
+                ErrorCode::Output,
+                &format!("failed to append to {}: {e}", path.display()),
+            );
+            status.fail(format!("failed to append to CSV log: {e}"));
+        }
+    }
+
+    if !run_alerts(args, &devices, &config.alerts) {
+        status.fail("alert delivery failed");
+    }
+
+    if (args.events || !config.events.sinks.is_empty())
+        && !run_events(&devices, args, &config.events, &config.alerts)
+    {
+        status.fail("event delivery failed");
+    }
+    if args.events {
+        return;
+    }
+
+    // A prompt segment is rendered on every prompt, so it stays silent
+    let quiet = args.quiet || matches!(format, Format::Prompt | Format::Short);
+
+    if let Some(adapter) = &adapter {
+        warn!(state = ?adapter.state, "Bluetooth adapter unavailable");
+        if !quiet {
+            report_error(args, adapter.state.into(), output::adapter_message(adapter));
+        }
+        status.fail(output::adapter_message(adapter));
+    }
+
+    if devices.is_empty() && adapter.is_none() && !quiet {
+        if let Some(ref filter) = args.device {
+            warn!(filter = %filter, "No devices found matching filter");
+            report_error(
+                args,
+                ErrorCode::NotFound,
+                &format!("no devices found matching '{filter}'"),
+            );
+        } else {
+            warn!("No devices with battery info found");
+            report_error(
+                args,
+                ErrorCode::NotFound,
+                "no devices with battery info found",
+            );
+        }
+    }
+    if args.summary_only {
+        let summary = output::summarize(&devices);
+        match format {
+            Format::Json => match serde_json::to_string(&summary) {
+                Ok(json) => emit(args, status, &format!("{json}\n")),
+                Err(e) => warn!(error = %e, "Failed to serialize summary"),
+            },
+            _ => emit(
+                args,
+                status,
+                &format!("{}\n", output::format_summary(&summary)),
+            ),
+        }
+        return;
+    }
+    if devices.is_empty() {
+        // Shortcuts can't parse empty input, so it still gets a dictionary,
+        // and JSON gets the adapter status
+        if matches!(
+            format,
+            Format::Text
+                | Format::Tmux
+                | Format::Prompt
+                | Format::Short
+                | Format::Ndjson
+                | Format::Table
+        ) || (matches!(format, Format::Json | Format::Yaml)
+            && adapter.is_none()
+            && !args.with_meta)
+        {
+            emit(args, status, "");
+            return;
+        }
+    }
+
+    let sequences = samples.map(Samples::sequences);
+    if let Some(text) = render(args, &config, status, devices, adapter.as_ref(), sequences) {
+        emit(args, status, &text);
+    }
+}
+
+/// Format the devices as the output options ask, with a trailing newline
+/// unless there is nothing to print. Serialization failures are reported
+/// and give None. In watch mode, `sequences` numbers the streamed records
+fn render(
+    args: &Args,
+    config: &Config,
+    status: &RunStatus,
+    mut devices: Vec<Device>,
+    adapter: Option<&AdapterStatus>,
+    sequences: Option<&Sequences>,
+) -> Option<String> {
+    let format = args.format();
+    // Of all devices, even when --worst prints only one
+    let summary = args
+        .summary
+        .then(|| output::format_summary(&output::summarize(&devices)));
+    if args.worst {
+        devices = output::worst(&devices)
+            .map(|(device, _)| vec![device.clone()])
+            .unwrap_or_default();
+    }
+    if let Some(key) = args.sort {
+        output::sort_devices(&mut devices, key, args.reverse);
+    }
+
+    let rendered = match format {
+        _ if args.porcelain => Ok(output::format_porcelain(&devices)),
+        _ if let Some(template) = &args.template => Ok(template.render_all(&devices)),
+        _ if let Some(fields) = &args.fields => {
+            fields.format(format, &devices, adapter, chrono::Utc::now(), sequences)
+        }
+        Format::Text => Ok(render_text(args, config, &devices)),
+        Format::Json if args.with_meta => {
+            let meta = output::Meta {
+                timestamp: chrono::Utc::now(),
+                hostname: hostname::get()
+                    .map(|h| h.to_string_lossy().into_owned())
+                    .unwrap_or_else(|_| "localhost".to_string()),
+                version: env!("CARGO_PKG_VERSION"),
+                backend: status.backend,
+                sources: &status.sources,
+                adapter,
+            };
+            output::format_meta_json(&meta, &devices)
+        }
+        Format::Json => match adapter {
+            Some(adapter) => output::format_adapter_json(adapter, &devices),
+            None => output::format_json(&devices),
+        },
+        Format::Shortcuts => output::format_shortcuts(&devices),
+        Format::Tmux => Ok(output::format_tmux(
+            &devices,
+            &config.alerts,
+            &icons(args, config),
+        )),
+        Format::Prompt => Ok(output::format_prompt(
+            &devices,
+            &config.alerts,
+            &icons(args, config),
+        )),
+        Format::Short => Ok(output::format_short(&devices)),
+        Format::Csv => Ok(output::format_csv(&devices, chrono::Utc::now())),
+        Format::Yaml => output::format_yaml(&devices, adapter),
+        Format::Markdown => Ok(output::format_markdown(&devices, &Locale::from_env())),
+        Format::Sketchybar => Ok(sketchybar::format_sketchybar(
+            &devices,
+            &config.alerts,
+            &config.sketchybar,
+        )),
+        Format::Xbar => Ok(xbar::format_xbar(
+            &devices,
+            &config.alerts,
+            &Locale::from_env(),
+        )),
+        Format::Raycast => Ok(raycast::format_raycast(
+            &devices,
+            &config.alerts,
+            &Locale::from_env(),
+        )),
+        Format::Alfred => alfred::format_alfred(
+            &devices,
+            &config.alerts,
+            &config.alfred,
+            &Locale::from_env(),
+        ),
+        Format::Table => Ok(output::format_table(
+            &devices,
+            &Locale::from_env(),
+            table_width(args),
+            colors(args, config),
+        )),
+        Format::Ndjson => output::format_ndjson(&devices, chrono::Utc::now(), sequences),
+        // Rejected before any command runs
+        Format::Xlsx => Ok(String::new()),
+    };
+    let mut text = match rendered {
+        // Nothing low means no prompt segment at all
+        Ok(text) if text.is_empty() && matches!(format, Format::Prompt | Format::Short) => text,
+        Ok(text) => format!("{text}\n"),
+        Err(e) => {
+            warn!(error = %e, "Failed to serialize devices to JSON");
+            report_error(
+                args,
+                ErrorCode::Output,
+                &format!("Failed to serialize devices: {e}"),
+            );
+            return None;
+        }
+    };
+    if let Some(summary) = summary {
+        text.push_str(&summary);
+        text.push('\n');
+    }
+    Some(text)
+}
+
+/// Print the output, or with `--output` replace the file with it
+fn emit(args: &Args, status: &mut RunStatus, text: &str) {
+    let Some(path) = &args.output else {
+        print!("{text}");
+        return;
+    };
+    if let Err(e) = output::write_atomic(path, text) {
+        let message = format!("failed to write {}: {e}", path.display());
+        report_error(args, ErrorCode::Output, &message);
+        status.fail(message);
+    }
+}
+
+/// Scan the devices and apply the per-device and smoothing settings,
+/// recording the outcome in `status`
+fn scan_devices(
+    args: &Args,
+    config: &Config,
+    status: &mut RunStatus,
+) -> Option<(Vec<Device>, Option<AdapterStatus>)> {
+    let Some(mut backend) = open_backend(args) else {
+        status.fail("failed to load session");
+        return None;
+    };
+    status.backend = Some(if args.replay.is_some() {
+        "replay"
+    } else {
+        "system"
+    });
+
+    let Some(path) = &args.record else {
+        return Some(scan_with(args, config, status, backend.as_mut()));
+    };
+    let (devices, adapter) = {
+        let mut recorder = RecordingBackend::new(backend);
+        let mut timed = TimedBackend::new(&mut recorder);
+        let devices = collect_devices(&mut timed, args.device.as_deref());
+        let adapter = timed.adapter();
+        status.sources = timed.into_sources();
+        let mut snapshot = recorder.into_snapshot();
+        if args.redact {
+            redact::redact_snapshot(&mut snapshot);
+        }
+        if let Err(e) = snapshot.save(path) {
+            report_error(
+                args,
+                ErrorCode::Session,
+                &format!("failed to write session {}: {e}", path.display()),
+            );
+            status.fail(format!("failed to write session: {e}"));
+            return None;
+        }
+        debug!(path = %path.display(), "Recorded session");
+        (devices, adapter)
+    };
+    Some(finish_scan(args, config, devices, adapter))
+}
+
+/// Scan with a backend the caller keeps, like the daemon does
+fn scan_with(
+    args: &Args,
+    config: &Config,
+    status: &mut RunStatus,
+    backend: &mut dyn Backend,
+) -> (Vec<Device>, Option<AdapterStatus>) {
+    let mut timed = TimedBackend::new(backend);
+    let devices = collect_devices(&mut timed, args.device.as_deref());
+    let adapter = timed.adapter();
+    status.sources = timed.into_sources();
+    finish_scan(args, config, devices, adapter)
+}
+
+/// Apply the per-device and smoothing settings to a scan and keep it for
+/// `--all-paired` and `--max-age`
+fn finish_scan(
+    args: &Args,
+    config: &Config,
+    mut devices: Vec<Device>,
+    adapter: Option<AdapterStatus>,
+) -> (Vec<Device>, Option<AdapterStatus>) {
+    // Only an adapter that can't be used is worth reporting
+    let adapter = adapter.filter(|adapter| !adapter.is_usable());
+    hide_components(&mut devices, &config.devices);
+    if config.smoothing.is_enabled() {
+        smooth(&mut devices, &config.smoothing);
+    }
+
+    // Readings of devices that disconnect later stay listed by --all-paired
+    if args.replay.is_none()
+        && !devices.is_empty()
+        && let Some(path) = LastSeen::default_path()
+    {
+        let result = LastSeen::load(&path).and_then(|mut seen| {
+            seen.record(&devices, chrono::Utc::now());
+            seen.save(&path)
+        });
+        if let Err(e) = result {
+            warn!(error = %e, path = %path.display(), "Failed to save last-seen devices");
+        }
+    }
+
+    // Complete scans of the system are kept for --max-age
+    if args.replay.is_none()
+        && args.device.is_none()
+        && adapter.is_none()
+        && let Some(path) = scan_cache_path()
+    {
+        let cache = ScanCache {
+            scanned_at: chrono::Utc::now(),
+            devices,
         };
-        assert_eq!(
-            format_device_output(&device),
-            "AirPods Pro: L:80% R:90% Case:100%"
+        if let Err(e) = cache.save(&path) {
+            warn!(error = %e, path = %path.display(), "Failed to save scan cache");
+        }
+        devices = cache.devices;
+    }
+
+    (devices, adapter)
+}
+
+/// Last reading of every device seen, empty if it can't be read
+fn last_seen() -> LastSeen {
+    let Some(path) = LastSeen::default_path() else {
+        return LastSeen::default();
+    };
+    LastSeen::load(&path).unwrap_or_else(|e| {
+        warn!(error = %e, path = %path.display(), "Failed to load last-seen devices");
+        LastSeen::default()
+    })
+}
+
+/// Location of the last scan
+fn scan_cache_path() -> Option<PathBuf> {
+    paths::cache_dir().map(|dir| dir.join("scan.json"))
+}
+
+/// Smooth the readings against the history kept in the cache directory
+fn smooth(devices: &mut [Device], config: &SmoothingConfig) {
+    let Some(path) = paths::cache_dir().map(|dir| dir.join("smoothing.json")) else {
+        warn!("Cannot determine cache directory for smoothing state");
+        return;
+    };
+    let mut state = SmoothingState::load(&path).unwrap_or_else(|e| {
+        warn!(error = %e, path = %path.display(), "Failed to load smoothing state");
+        SmoothingState::default()
+    });
+    state.apply(devices, config);
+    if let Err(e) = state.save(&path) {
+        warn!(error = %e, path = %path.display(), "Failed to save smoothing state");
+    }
+}
+
+/// Location of the persisted alert state
+fn alert_state_path() -> Option<PathBuf> {
+    paths::cache_dir().map(|dir| dir.join("alerts.json"))
+}
+
+/// Acknowledge the active alerts of matching devices
+fn run_ack(args: &Args, device: Option<&str>) -> ExitCode {
+    let Some(path) = alert_state_path() else {
+        report_error(
+            args,
+            ErrorCode::Storage,
+            "cannot determine cache directory for alert state",
+        );
+        return ExitCode::FAILURE;
+    };
+    let mut state = match AlertState::load(&path) {
+        Ok(state) => state,
+        Err(e) => {
+            report_error(
+                args,
+                ErrorCode::Storage,
+                &format!("failed to load alert state {}: {e}", path.display()),
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let count = state.acknowledge(device);
+    if let Err(e) = state.save(&path) {
+        report_error(
+            args,
+            ErrorCode::Storage,
+            &format!("failed to save alert state {}: {e}", path.display()),
         );
+        return ExitCode::FAILURE;
+    }
+    println!("acknowledged {count} alert(s)");
+    ExitCode::SUCCESS
+}
+
+/// Send alerts for low devices to the configured channels.
+///
+/// Returns false if any alert could not be delivered.
+fn run_alerts(args: &Args, devices: &[Device], config: &AlertConfig) -> bool {
+    let channels = match channels::configured(config) {
+        Ok(channels) => channels,
+        Err(e) => {
+            report_error(
+                args,
+                ErrorCode::Config,
+                &format!("failed to set up alert channels: {e}"),
+            );
+            return false;
+        }
+    };
+    if channels.is_empty() {
+        return true;
+    }
+
+    let state_path = alert_state_path();
+    let mut state = match &state_path {
+        Some(path) => AlertState::load(path).unwrap_or_else(|e| {
+            warn!(error = %e, path = %path.display(), "Failed to load alert state, starting fresh");
+            AlertState::default()
+        }),
+        None => AlertState::default(),
+    };
+
+    let now = chrono::Local::now();
+    let quiet = config.is_quiet(now.time());
+    let mut failures = 0;
+    for alert in alerts::evaluate(devices, config, &mut state, now.timestamp()) {
+        if quiet && alert.severity < Severity::Critical {
+            debug!(device = %alert.device, "Quiet hours, queueing alert for the digest");
+            state.queue(alert, now.timestamp());
+            continue;
+        }
+        let delivery = channels::deliver(&Notification::from(&alert), &channels);
+        state.record(&alert, now.timestamp(), delivery.sent > 0);
+        failures += delivery.failed;
+    }
+
+    if !quiet && let Some(digest) = Notification::digest(state.queued()) {
+        let delivery = channels::deliver(&digest, &channels);
+        if delivery.sent > 0 || delivery.failed == 0 {
+            state.clear_queue();
+        }
+        failures += delivery.failed;
+    }
+
+    let today = now.date_naive();
+    if state.summary_due(today) {
+        let delivery = channels::deliver_summary(devices, &channels);
+        if delivery.sent > 0 {
+            state.record_summary(today);
+        }
+        failures += delivery.failed;
+    }
+
+    if let Some(path) = &state_path
+        && let Err(e) = state.save(path)
+    {
+        warn!(error = %e, path = %path.display(), "Failed to save alert state");
+    }
+
+    if failures > 0 {
+        report_error(
+            args,
+            ErrorCode::Delivery,
+            &format!("failed to deliver {failures} alert(s)"),
+        );
+    }
+    failures == 0
+}
+
+/// Derive events since the last run, print them in event mode and send them
+/// to the configured sinks.
+///
+/// Returns false if any event could not be delivered.
+fn run_events(
+    devices: &[Device],
+    args: &Args,
+    config: &EventsConfig,
+    alert_config: &AlertConfig,
+) -> bool {
+    let Some(snapshot_path) = paths::cache_dir().map(|dir| dir.join("devices.json")) else {
+        report_error(
+            args,
+            ErrorCode::Storage,
+            "cannot determine cache directory for events",
+        );
+        return false;
+    };
+    let previous = events::load_snapshot(&snapshot_path).unwrap_or_else(|e| {
+        warn!(error = %e, path = %snapshot_path.display(), "Failed to load previous devices, starting fresh");
+        Vec::new()
+    });
+
+    let (queried, unqueried) = events::partition(previous, args.device.as_deref());
+
+    let source = config.source.clone().unwrap_or_else(events::default_source);
+    let detected = events::detect(&queried, devices, alert_config);
+    let cloud_events = CloudEvent::wrap(detected, &source, chrono::Utc::now());
+
+    let mut ok = true;
+    if args.events {
+        for event in &cloud_events {
+            match serde_json::to_string(event) {
+                Ok(json) => println!("{json}"),
+                Err(e) => {
+                    report_error(
+                        args,
+                        ErrorCode::Output,
+                        &format!("Failed to serialize event: {e}"),
+                    );
+                    ok = false;
+                }
+            }
+        }
+    }
+
+    if !config.sinks.is_empty() && !cloud_events.is_empty() {
+        match HttpSink::new(config.sinks.clone()) {
+            Ok(sink) => {
+                let failures: usize = cloud_events.iter().map(|e| sink.send(e)).sum();
+                if failures > 0 {
+                    report_error(
+                        args,
+                        ErrorCode::Delivery,
+                        &format!("failed to deliver {failures} event(s)"),
+                    );
+                    ok = false;
+                }
+            }
+            Err(e) => {
+                report_error(
+                    args,
+                    ErrorCode::Config,
+                    &format!("failed to set up event sinks: {e}"),
+                );
+                ok = false;
+            }
+        }
+    }
+
+    if let Err(e) = events::save_snapshot(&snapshot_path, &unqueried, devices) {
+        warn!(error = %e, path = %snapshot_path.display(), "Failed to save devices for events");
+    }
+
+    ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every visible subcommand and flag of the real command line has its
+    /// completions in each shell's script
+    #[test]
+    fn test_completions_cover_cli() {
+        Args::command().debug_assert();
+        let mut command = Args::command();
+        command.build();
+        let bash = completions::generate(Shell::Bash, Args::command());
+        let zsh = completions::generate(Shell::Zsh, Args::command());
+        let fish = completions::generate(Shell::Fish, Args::command());
+
+        let mut pending = vec![(vec!["btmon"], &command)];
+        while let Some((path, command)) = pending.pop() {
+            let bash_opts = format!("        {}) opts=\"", path.join("__"));
+            let bash_opts = bash
+                .lines()
+                .find_map(|line| line.strip_prefix(bash_opts.as_str()))
+                .unwrap_or_else(|| panic!("no bash completions for {path:?}"));
+            let zsh_function = format!("\n_{}() {{\n", path.join("__"));
+            let zsh_function = zsh
+                .split_once(zsh_function.as_str())
+                .and_then(|(_, rest)| rest.split("\n}\n").next())
+                .unwrap_or_else(|| panic!("no zsh completions for {path:?}"));
+
+            for subcommand in command.get_subcommands().filter(|c| !c.is_hide_set()) {
+                let name = subcommand.get_name();
+                assert!(
+                    bash_opts.split([' ', '"']).any(|word| word == name),
+                    "bash misses {path:?} {name}"
+                );
+                assert!(
+                    zsh_function.contains(&format!("'{name}:")),
+                    "zsh misses {path:?} {name}"
+                );
+                assert!(
+                    fish.contains(&format!(" -f -a {name} ")),
+                    "fish misses {path:?} {name}"
+                );
+                let mut path = path.clone();
+                path.push(name);
+                pending.push((path, subcommand));
+            }
+
+            let flags = command
+                .get_arguments()
+                .filter(|arg| !arg.is_positional() && !arg.is_hide_set());
+            for arg in flags {
+                let long = arg.get_long().map(|long| format!("--{long}"));
+                let short = arg.get_short().map(|short| format!("-{short}"));
+                for spelling in long.iter().chain(&short) {
+                    assert!(
+                        bash_opts.split([' ', '"']).any(|word| word == spelling),
+                        "bash misses {path:?} {spelling}"
+                    );
+                    // A single spelling or one of a brace group
+                    assert!(
+                        [
+                            format!("{spelling}["),
+                            format!("{{{spelling},"),
+                            format!(",{spelling}}}")
+                        ]
+                        .iter()
+                        .any(|spec| zsh_function.contains(spec.as_str())),
+                        "zsh misses {path:?} {spelling}"
+                    );
+                }
+                if let Some(long) = arg.get_long() {
+                    assert!(
+                        fish.contains(&format!(" -l {long}")),
+                        "fish misses {path:?} --{long}"
+                    );
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,263 @@
+//! Manufacturer-specific advertisement data decoding
+//!
+//! The manufacturer data field of a BLE advertisement starts with a
+//! little-endian Bluetooth SIG company identifier followed by a payload in a
+//! vendor-defined format. Some vendors put battery or status information in
+//! it, so decoders for recognized formats are kept in a registry keyed by
+//! company identifier. The bytes come straight from the air, so decoding
+//! must accept any input without panicking.
+
+use crate::device::{BatteryLevel, Component};
+use crate::vendor;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Apple's Bluetooth SIG company identifier
+const APPLE: u16 = 0x004C;
+
+/// Status decoded from manufacturer data
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct AdvertisedStatus {
+    /// Vendor of the company identifier
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vendor: Option<&'static str>,
+    /// Product model, if the format carries one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<&'static str>,
+    /// Single battery level
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub battery_level: Option<BatteryLevel>,
+    /// Left earbud battery
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub battery_left: Option<BatteryLevel>,
+    /// Right earbud battery
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub battery_right: Option<BatteryLevel>,
+    /// Charging case battery
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub battery_case: Option<BatteryLevel>,
+    /// Components that are charging
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub charging: Vec<Component>,
+}
+
+/// Decoder for one vendor's manufacturer data format
+pub trait Decoder: Send + Sync {
+    /// Bluetooth SIG company identifier the format belongs to
+    fn company_id(&self) -> u16;
+
+    /// Decode the payload following the company identifier
+    fn decode(&self, payload: &[u8]) -> Option<AdvertisedStatus>;
+}
+
+/// Split manufacturer data into its company identifier and payload
+pub fn split(data: &[u8]) -> Option<(u16, &[u8])> {
+    match data {
+        [low, high, payload @ ..] => Some((u16::from_le_bytes([*low, *high]), payload)),
+        _ => None,
+    }
+}
+
+/// Decoders keyed by company identifier
+pub struct Registry {
+    decoders: HashMap<u16, Box<dyn Decoder>>,
+}
+
+impl Default for Registry {
+    /// Registry with the built-in decoders
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry.register(Box::new(AppleContinuity));
+        registry
+    }
+}
+
+impl Registry {
+    /// Empty registry
+    pub fn new() -> Self {
+        Self {
+            decoders: HashMap::new(),
+        }
+    }
+
+    /// Add a decoder, replacing any previous one for the same company
+    pub fn register(&mut self, decoder: Box<dyn Decoder>) {
+        self.decoders.insert(decoder.company_id(), decoder);
+    }
+
+    /// Decode manufacturer data, None if the vendor or format is unknown
+    pub fn decode(&self, data: &[u8]) -> Option<AdvertisedStatus> {
+        let (company_id, payload) = split(data)?;
+        let status = self.decoders.get(&company_id)?.decode(payload)?;
+        Some(AdvertisedStatus {
+            vendor: status.vendor.or_else(|| vendor::company_vendor(company_id)),
+            ..status
+        })
+    }
+}
+
+/// Apple Continuity messages
+///
+/// The payload is a sequence of type-length-value messages. Proximity pairing
+/// messages (type 0x07), broadcast by AirPods and Beats while the lid is open
+/// or the buds are in use, carry 4-bit battery levels in tens of percent for
+/// each bud and the case.
+struct AppleContinuity;
+
+impl AppleContinuity {
+    /// Proximity pairing message type
+    const PROXIMITY_PAIRING: u8 = 0x07;
+
+    /// Product name of a proximity pairing model code
+    fn model(code: u16) -> Option<&'static str> {
+        match code {
+            0x0220 => Some("AirPods"),
+            0x0F20 => Some("AirPods (2nd generation)"),
+            0x1320 => Some("AirPods (3rd generation)"),
+            0x0E20 => Some("AirPods Pro"),
+            0x1420 => Some("AirPods Pro (2nd generation)"),
+            0x0A20 => Some("AirPods Max"),
+            0x0B20 => Some("Powerbeats Pro"),
+            0x1020 => Some("Beats Flex"),
+            0x1120 => Some("Beats Studio Buds"),
+            _ => None,
+        }
+    }
+
+    /// Battery level of a 4-bit value, 15 meaning unavailable
+    fn level(nibble: u8) -> Option<BatteryLevel> {
+        (nibble <= 10)
+            .then(|| BatteryLevel::new(nibble * 10))
+            .flatten()
+    }
+
+    fn decode_proximity_pairing(value: &[u8]) -> Option<AdvertisedStatus> {
+        let [_, model_high, model_low, status, pods, flags_case, ..] = *value else {
+            return None;
+        };
+        let (high, low) = (pods >> 4, pods & 0x0F);
+        let (flags, case) = (flags_case >> 4, flags_case & 0x0F);
+
+        // Which bud is reported first depends on which one is primary, given
+        // by bit 5 of the status byte
+        let flipped = status & 0x20 == 0;
+        let (left, right) = if flipped { (high, low) } else { (low, high) };
+        let (left_charging, right_charging) = if flipped {
+            (0b0010, 0b0001)
+        } else {
+            (0b0001, 0b0010)
+        };
+
+        let charging = [
+            (Component::Left, left_charging),
+            (Component::Right, right_charging),
+            (Component::Case, 0b0100),
+        ]
+        .into_iter()
+        .filter(|(_, bit)| flags & bit != 0)
+        .map(|(component, _)| component)
+        .collect();
+
+        Some(AdvertisedStatus {
+            vendor: None,
+            model: Self::model(u16::from_be_bytes([model_high, model_low])),
+            battery_level: None,
+            battery_left: Self::level(left),
+            battery_right: Self::level(right),
+            battery_case: Self::level(case),
+            charging,
+        })
+    }
+}
+
+impl Decoder for AppleContinuity {
+    fn company_id(&self) -> u16 {
+        APPLE
+    }
+
+    fn decode(&self, payload: &[u8]) -> Option<AdvertisedStatus> {
+        let mut rest = payload;
+        while let [kind, len, tail @ ..] = rest {
+            let value = tail.get(..usize::from(*len))?;
+            if *kind == Self::PROXIMITY_PAIRING {
+                return Self::decode_proximity_pairing(value);
+            }
+            rest = &tail[value.len()..];
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// AirPods Pro proximity pairing message with a nearby-info message
+    /// before it
+    const AIRPODS_PRO: [u8; 18] = [
+        0x4C, 0x00, // Apple
+        0x10, 0x02, 0x0B, 0x1C, // Nearby info
+        0x07, 0x0A, 0x01, 0x0E, 0x20, 0x2B, 0x98, 0x5A, 0x01, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_apple_proximity_pairing() {
+        let status = Registry::default().decode(&AIRPODS_PRO).unwrap();
+        assert_eq!(
+            status,
+            AdvertisedStatus {
+                vendor: Some("Apple"),
+                model: Some("AirPods Pro"),
+                battery_level: None,
+                battery_left: BatteryLevel::new(80),
+                battery_right: BatteryLevel::new(90),
+                battery_case: BatteryLevel::new(100),
+                charging: vec![Component::Left, Component::Case],
+            }
+        );
+
+        // Other bud primary: nibbles swap, and the case is unavailable
+        let mut data = AIRPODS_PRO;
+        data[11] = 0x0B;
+        data[13] = 0x0F;
+        let status = Registry::default().decode(&data).unwrap();
+        assert_eq!(status.battery_left, BatteryLevel::new(90));
+        assert_eq!(status.battery_right, BatteryLevel::new(80));
+        assert_eq!(status.battery_case, None);
+        assert!(status.charging.is_empty());
+    }
+
+    #[test]
+    fn test_rejects_unknown_and_truncated() {
+        let registry = Registry::default();
+        assert_eq!(registry.decode(&[]), None);
+        assert_eq!(registry.decode(&[0x4C]), None);
+        assert_eq!(registry.decode(&[0x06, 0x00, 0x07, 0x01]), None);
+        for len in 2..AIRPODS_PRO.len() {
+            assert_eq!(registry.decode(&AIRPODS_PRO[..len]), None, "len {len}");
+        }
+    }
+
+    #[test]
+    fn test_register_decoder() {
+        struct Fixed;
+        impl Decoder for Fixed {
+            fn company_id(&self) -> u16 {
+                0xFFFF
+            }
+            fn decode(&self, payload: &[u8]) -> Option<AdvertisedStatus> {
+                Some(AdvertisedStatus {
+                    battery_level: BatteryLevel::new(*payload.first()?),
+                    ..Default::default()
+                })
+            }
+        }
+
+        let mut registry = Registry::new();
+        assert_eq!(registry.decode(&[0xFF, 0xFF, 42]), None);
+        registry.register(Box::new(Fixed));
+        let status = registry.decode(&[0xFF, 0xFF, 42]).unwrap();
+        assert_eq!(status.battery_level, BatteryLevel::new(42));
+        assert_eq!(status.vendor, None);
+    }
+}
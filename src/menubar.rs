@@ -0,0 +1,148 @@
+//! Menu bar app
+//!
+//! `btmon menubar` keeps a status item in the menu bar showing the lowest
+//! battery, with a menu listing every device. AppKit only runs on the main
+//! thread, so scans run on a background thread (GATT scans don't need the
+//! main run loop) and each result is handed to the main queue for display.
+//!
+//! There are no AppKit bindings among the dependencies, so the few AppKit
+//! classes needed are messaged directly.
+
+use crate::device::Device;
+use crate::locale::Locale;
+use crate::output;
+use dispatch2::DispatchQueue;
+use objc2::rc::Retained;
+use objc2::runtime::{AnyObject, Sel};
+use objc2::{MainThreadMarker, class, msg_send, sel};
+use objc2_foundation::NSString;
+use std::cell::RefCell;
+use std::io;
+use std::thread;
+use std::time::Duration;
+use tracing::debug;
+
+#[link(name = "AppKit", kind = "framework")]
+unsafe extern "C" {}
+
+/// `NSApplicationActivationPolicyAccessory`: no Dock icon or app menu
+const ACTIVATION_POLICY_ACCESSORY: isize = 1;
+
+/// `NSVariableStatusItemLength`
+const VARIABLE_LENGTH: f64 = -1.0;
+
+thread_local! {
+    /// The status item, only used on the main thread
+    static STATUS_ITEM: RefCell<Option<Retained<AnyObject>>> = const { RefCell::new(None) };
+}
+
+/// Run the menu bar app until it is quit from its menu, calling `scan` on a
+/// background thread every `interval`
+///
+/// Must be called on the main thread.
+pub fn run<F>(mut scan: F, interval: Duration) -> io::Result<()>
+where
+    F: FnMut() -> Vec<Device> + Send + 'static,
+{
+    if MainThreadMarker::new().is_none() {
+        return Err(io::Error::other(
+            "the menu bar app must run on the main thread",
+        ));
+    }
+
+    // SAFETY: Standard AppKit calls on the main thread (checked above).
+    let app = unsafe {
+        let app: Retained<AnyObject> = msg_send![class!(NSApplication), sharedApplication];
+        let _: bool = msg_send![&*app, setActivationPolicy: ACTIVATION_POLICY_ACCESSORY];
+
+        let status_bar: Retained<AnyObject> = msg_send![class!(NSStatusBar), systemStatusBar];
+        let item: Retained<AnyObject> =
+            msg_send![&*status_bar, statusItemWithLength: VARIABLE_LENGTH];
+        let menu: Retained<AnyObject> = msg_send![class!(NSMenu), new];
+        // Device lines have no action, and would be greyed out otherwise
+        let _: () = msg_send![&*menu, setAutoenablesItems: false];
+        let _: () = msg_send![&*item, setMenu: &*menu];
+        STATUS_ITEM.set(Some(item));
+        app
+    };
+    show(None);
+
+    thread::Builder::new()
+        .name("menubar-scan".to_string())
+        .spawn(move || {
+            loop {
+                let devices = scan();
+                debug!(count = devices.len(), "Updating menu bar");
+                DispatchQueue::main().exec_async(move || show(Some(&devices)));
+                thread::sleep(interval);
+            }
+        })?;
+
+    // SAFETY: Standard AppKit call on the main thread; it returns once the
+    // app is terminated, which normally exits the process instead.
+    unsafe {
+        let _: () = msg_send![&*app, run];
+    }
+    Ok(())
+}
+
+/// Show the devices in the status item and its menu, `None` before the first
+/// scan finished
+fn show(devices: Option<&[Device]>) {
+    STATUS_ITEM.with_borrow(|item| {
+        let Some(item) = item else {
+            return;
+        };
+        let title = match devices {
+            Some(devices) => output::format_menubar_title(devices),
+            None => "🔋 …".to_string(),
+        };
+
+        // SAFETY: Standard AppKit calls; `show` only runs on the main thread,
+        // where the status item was created.
+        unsafe {
+            let button: Option<Retained<AnyObject>> = msg_send![&**item, button];
+            if let Some(button) = button {
+                let _: () = msg_send![&*button, setTitle: &*NSString::from_str(&title)];
+            }
+
+            let menu: Retained<AnyObject> = msg_send![&**item, menu];
+            let _: () = msg_send![&*menu, removeAllItems];
+            match devices {
+                Some([]) => add_item(&menu, "No devices with battery info", None, ""),
+                Some(devices) => {
+                    let locale = Locale::from_env();
+                    for device in devices {
+                        add_item(
+                            &menu,
+                            &output::format_device_output(device, &locale),
+                            None,
+                            "",
+                        );
+                    }
+                }
+                None => add_item(&menu, "Scanning…", None, ""),
+            }
+            let separator: Retained<AnyObject> = msg_send![class!(NSMenuItem), separatorItem];
+            let _: () = msg_send![&*menu, addItem: &*separator];
+            add_item(&menu, "Quit btmon", Some(sel!(terminate:)), "q");
+        }
+    });
+}
+
+/// Append an item to a menu; items without an action only show text
+///
+/// # Safety
+///
+/// `menu` must be an NSMenu, used on the main thread.
+unsafe fn add_item(menu: &AnyObject, title: &str, action: Option<Sel>, key: &str) {
+    // SAFETY: Guaranteed by the caller.
+    unsafe {
+        let _: Retained<AnyObject> = msg_send![
+            menu,
+            addItemWithTitle: &*NSString::from_str(title),
+            action: action,
+            keyEquivalent: &*NSString::from_str(key)
+        ];
+    }
+}
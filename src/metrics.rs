@@ -0,0 +1,108 @@
+//! Prometheus metrics
+//!
+//! The HTTP API serves the device list at `/metrics` in the Prometheus text
+//! exposition format, one gauge sample per battery component. The daemon
+//! serves its latest scan the same way with `--metrics-port`, adding how
+//! often its backend was recreated.
+
+use crate::device::Device;
+use std::fmt::Write;
+
+/// Content type of the text exposition format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Battery level of a component, labelled `device` and `component`
+pub const BATTERY_PERCENT: &str = "btmon_battery_percent";
+/// Seconds since a device's levels were read, labelled `device`
+pub const READING_AGE_SECONDS: &str = "btmon_reading_age_seconds";
+/// Number of devices with battery information
+pub const DEVICES: &str = "btmon_devices";
+/// Times the daemon recreated its Bluetooth backend
+pub const BACKEND_RESTARTS: &str = "btmon_backend_restarts_total";
+
+/// Escape a label value: backslash, double quote and newline
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+/// Format the devices as Prometheus metrics
+pub fn format_metrics(devices: &[Device]) -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(
+        out,
+        "# HELP {BATTERY_PERCENT} Battery level of a device component in percent."
+    );
+    let _ = writeln!(out, "# TYPE {BATTERY_PERCENT} gauge");
+    for device in devices {
+        let name = escape_label(&device.name);
+        for (component, level) in device.components() {
+            let _ = writeln!(
+                out,
+                "{BATTERY_PERCENT}{{device=\"{name}\",component=\"{}\"}} {}",
+                component.key(),
+                level.as_percentage()
+            );
+        }
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP {READING_AGE_SECONDS} Seconds since the device's levels were read."
+    );
+    let _ = writeln!(out, "# TYPE {READING_AGE_SECONDS} gauge");
+    for device in devices {
+        if let Some(freshness) = device.freshness {
+            let _ = writeln!(
+                out,
+                "{READING_AGE_SECONDS}{{device=\"{}\"}} {}",
+                escape_label(&device.name),
+                freshness.age_seconds
+            );
+        }
+    }
+
+    let _ = writeln!(out, "# HELP {DEVICES} Devices with battery information.");
+    let _ = writeln!(out, "# TYPE {DEVICES} gauge");
+    let _ = writeln!(out, "{DEVICES} {}", devices.len());
+    out
+}
+
+/// Format the daemon's metrics: those of its devices, and the backend
+/// restarts of its watchdog
+pub fn format_daemon_metrics(devices: &[Device], backend_restarts: u64) -> String {
+    let mut out = format_metrics(devices);
+    let _ = writeln!(
+        out,
+        "# HELP {BACKEND_RESTARTS} Times the daemon recreated its Bluetooth backend."
+    );
+    let _ = writeln!(out, "# TYPE {BACKEND_RESTARTS} counter");
+    let _ = writeln!(out, "{BACKEND_RESTARTS} {backend_restarts}");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::BatteryLevel;
+
+    #[test]
+    fn test_format_metrics() {
+        let devices = [Device {
+            battery_left: BatteryLevel::new(80),
+            battery_right: BatteryLevel::new(90),
+            ..Device::test_ble("Bob's \"AirPods\"", 0)
+        }];
+        let metrics = format_metrics(&devices);
+
+        assert!(metrics.contains(
+            "btmon_battery_percent{device=\"Bob's \\\"AirPods\\\"\",component=\"left\"} 80\n"
+        ));
+        assert!(metrics.contains("component=\"right\"} 90\n"));
+        assert!(!metrics.contains("btmon_reading_age_seconds{"));
+        assert!(metrics.ends_with("btmon_devices 1\n"));
+    }
+}
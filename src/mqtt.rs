@@ -0,0 +1,721 @@
+//! MQTT publishing with Home Assistant discovery
+//!
+//! `btmon mqtt` publishes every battery component as a retained message on
+//! `<topic_prefix>/<device>/<component>`, and announces each one once per
+//! connection under `<discovery_prefix>/sensor/.../config` so that Home
+//! Assistant adds it as a battery sensor on its own. A retained
+//! `<topic_prefix>/status` topic carries `online`, and `offline` once btmon
+//! stops or its connection is lost (as the broker's last will).
+//!
+//! Only the small part of MQTT 3.1.1 needed for this is implemented:
+//! connecting, QoS 0 publishing and keep-alive pings, over plain TCP
+//! (`mqtt://`) or TLS (`mqtts://`).
+
+use crate::device::{Component, Device};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+use tracing::{debug, info};
+
+/// Port of `mqtt://` brokers without an explicit port
+const DEFAULT_PORT: u16 = 1883;
+
+/// Port of `mqtts://` brokers without an explicit port
+const DEFAULT_TLS_PORT: u16 = 8883;
+
+/// Time allowed for connecting and for the broker's replies
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `[mqtt]` configuration section
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MqttConfig {
+    /// Broker URL, e.g. `mqtt://homeassistant.local` or `mqtts://host:8883`
+    pub broker: String,
+    /// User name for brokers requiring authentication
+    #[serde(default)]
+    pub username: Option<String>,
+    /// Password for brokers requiring authentication
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Client identifier, defaults to `btmon-<hostname>`
+    #[serde(default)]
+    pub client_id: Option<String>,
+    /// Prefix of the state topics
+    #[serde(default = "default_topic_prefix")]
+    pub topic_prefix: String,
+    /// Prefix Home Assistant watches for discovery messages
+    #[serde(default = "default_discovery_prefix")]
+    pub discovery_prefix: String,
+}
+
+fn default_topic_prefix() -> String {
+    "btmon".to_string()
+}
+
+fn default_discovery_prefix() -> String {
+    "homeassistant".to_string()
+}
+
+impl MqttConfig {
+    /// Configuration for `broker` with the default settings
+    pub fn new(broker: String) -> Self {
+        Self {
+            broker,
+            username: None,
+            password: None,
+            client_id: None,
+            topic_prefix: default_topic_prefix(),
+            discovery_prefix: default_discovery_prefix(),
+        }
+    }
+
+    /// Client identifier sent to the broker
+    fn client_id(&self) -> String {
+        self.client_id.clone().unwrap_or_else(|| {
+            let host = hostname::get()
+                .map(|h| h.to_string_lossy().into_owned())
+                .unwrap_or_else(|_| "localhost".to_string());
+            format!("btmon-{host}")
+        })
+    }
+
+    /// Topic of btmon's availability
+    fn status_topic(&self) -> String {
+        format!("{}/status", self.topic_prefix)
+    }
+}
+
+/// Broker address parsed from its URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Broker {
+    host: String,
+    port: u16,
+    tls: bool,
+}
+
+impl Broker {
+    /// Parse `mqtt://host[:port]`, `mqtts://host[:port]` or `host[:port]`
+    fn parse(url: &str) -> io::Result<Self> {
+        let (tls, rest) = match url.split_once("://") {
+            Some(("mqtt" | "tcp", rest)) => (false, rest),
+            Some(("mqtts" | "ssl", rest)) => (true, rest),
+            Some((scheme, _)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unsupported broker scheme '{scheme}', expected mqtt or mqtts"),
+                ));
+            }
+            None => (false, url),
+        };
+        let rest = rest.trim_end_matches('/');
+        let default_port = if tls { DEFAULT_TLS_PORT } else { DEFAULT_PORT };
+        let (host, port) = match rest.rsplit_once(':') {
+            Some((host, port)) => match port.parse() {
+                Ok(port) => (host, port),
+                Err(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid broker port '{port}'"),
+                    ));
+                }
+            },
+            None => (rest, default_port),
+        };
+        if host.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("missing host in broker URL '{url}'"),
+            ));
+        }
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            tls,
+        })
+    }
+}
+
+/// Append an MQTT string: its length as two bytes, then the UTF-8 bytes
+fn put_string(buf: &mut Vec<u8>, s: &[u8]) {
+    let len = u16::try_from(s.len()).unwrap_or(u16::MAX);
+    buf.extend_from_slice(&len.to_be_bytes());
+    buf.extend_from_slice(&s[..usize::from(len)]);
+}
+
+/// Prefix a packet body with its fixed header
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut buf = vec![header];
+    // Remaining length: seven bits per byte, the high bit marking more bytes
+    let mut len = body.len();
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        buf.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+    buf.extend_from_slice(body);
+    buf
+}
+
+/// A message published by the broker for the client when it disconnects
+/// without a DISCONNECT
+struct Will<'a> {
+    topic: &'a str,
+    payload: &'a [u8],
+}
+
+/// CONNECT packet with a clean session and a retained will
+///
+/// MQTT 3.1.1 only allows a password after a user name, so a password alone
+/// is left out.
+fn connect_packet(
+    client_id: &str,
+    username: Option<&str>,
+    password: Option<&str>,
+    will: &Will,
+    keep_alive: u16,
+) -> Vec<u8> {
+    let password = password.filter(|_| username.is_some());
+    let mut body = Vec::new();
+    put_string(&mut body, b"MQTT");
+    body.push(4); // protocol level 3.1.1
+
+    // Clean session, will flag and will retain
+    let mut flags = 0x02 | 0x04 | 0x20;
+    if username.is_some() {
+        flags |= 0x80;
+    }
+    if password.is_some() {
+        flags |= 0x40;
+    }
+    body.push(flags);
+    body.extend_from_slice(&keep_alive.to_be_bytes());
+
+    put_string(&mut body, client_id.as_bytes());
+    put_string(&mut body, will.topic.as_bytes());
+    put_string(&mut body, will.payload);
+    if let Some(username) = username {
+        put_string(&mut body, username.as_bytes());
+    }
+    if let Some(password) = password {
+        put_string(&mut body, password.as_bytes());
+    }
+    packet(0x10, &body)
+}
+
+/// QoS 0 PUBLISH packet
+fn publish_packet(topic: &str, payload: &[u8], retain: bool) -> Vec<u8> {
+    let mut body = Vec::new();
+    put_string(&mut body, topic.as_bytes());
+    body.extend_from_slice(payload);
+    packet(if retain { 0x31 } else { 0x30 }, &body)
+}
+
+const PINGREQ: [u8; 2] = [0xc0, 0x00];
+const PINGRESP: [u8; 2] = [0xd0, 0x00];
+const DISCONNECT: [u8; 2] = [0xe0, 0x00];
+
+/// Meaning of a CONNACK return code
+fn connack_error(code: u8) -> &'static str {
+    match code {
+        1 => "unacceptable protocol version",
+        2 => "client identifier rejected",
+        3 => "server unavailable",
+        4 => "bad user name or password",
+        5 => "not authorized",
+        _ => "unknown error",
+    }
+}
+
+/// Byte stream to the broker, plain or TLS
+trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+/// Connection to a broker
+struct Client {
+    stream: Box<dyn Stream>,
+}
+
+impl Client {
+    /// Connect and wait for the broker to accept the session
+    ///
+    /// Every address the host resolves to is tried in turn, so a broker only
+    /// listening on IPv4 is reached even if an IPv6 address comes first.
+    fn connect(config: &MqttConfig, will: &Will, keep_alive: u16) -> io::Result<Self> {
+        let broker = Broker::parse(&config.broker)?;
+        let addresses =
+            std::net::ToSocketAddrs::to_socket_addrs(&(broker.host.as_str(), broker.port))?;
+        let mut error = io::Error::other(format!("cannot resolve {}", broker.host));
+        let mut connected = None;
+        for address in addresses {
+            match TcpStream::connect_timeout(&address, TIMEOUT) {
+                Ok(tcp) => {
+                    connected = Some(tcp);
+                    break;
+                }
+                Err(e) => error = e,
+            }
+        }
+        let tcp = connected.ok_or(error)?;
+        tcp.set_read_timeout(Some(TIMEOUT))?;
+        tcp.set_write_timeout(Some(TIMEOUT))?;
+        let stream: Box<dyn Stream> = if broker.tls {
+            let connector = native_tls::TlsConnector::new().map_err(io::Error::other)?;
+            Box::new(
+                connector
+                    .connect(&broker.host, tcp)
+                    .map_err(io::Error::other)?,
+            )
+        } else {
+            Box::new(tcp)
+        };
+
+        let mut client = Self { stream };
+        client.stream.write_all(&connect_packet(
+            &config.client_id(),
+            config.username.as_deref(),
+            config.password.as_deref(),
+            will,
+            keep_alive,
+        ))?;
+
+        let mut connack = [0; 4];
+        client.stream.read_exact(&mut connack)?;
+        match connack {
+            [0x20, 0x02, _, 0] => Ok(client),
+            [0x20, 0x02, _, code] => Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("broker refused the connection: {}", connack_error(code)),
+            )),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected reply to CONNECT",
+            )),
+        }
+    }
+
+    fn publish(&mut self, topic: &str, payload: &[u8], retain: bool) -> io::Result<()> {
+        self.stream
+            .write_all(&publish_packet(topic, payload, retain))
+    }
+
+    /// Ping the broker, which both keeps the session alive and finds out
+    /// whether the connection still works
+    fn ping(&mut self) -> io::Result<()> {
+        self.stream.write_all(&PINGREQ)?;
+        let mut reply = [0; 2];
+        self.stream.read_exact(&mut reply)?;
+        if reply != PINGRESP {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected reply to PINGREQ",
+            ));
+        }
+        Ok(())
+    }
+
+    fn disconnect(mut self) {
+        let _ = self.stream.write_all(&DISCONNECT);
+    }
+}
+
+/// Topic-safe identifier of a device: lowercase ASCII letters and digits,
+/// everything else replaced by `_`
+///
+/// A name without any ASCII letter or digit, e.g. in Japanese, gets a hash of
+/// the name instead, so such devices don't all share one empty identifier.
+fn object_id(name: &str) -> String {
+    let id: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let id = id.trim_matches('_');
+    if !id.is_empty() {
+        return id.to_string();
+    }
+    // FNV-1a, which stays the same across runs and versions
+    let hash = name.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("device_{hash:016x}")
+}
+
+/// Topic a component's battery level is published on
+fn state_topic(config: &MqttConfig, device: &Device, component: Component) -> String {
+    format!(
+        "{}/{}/{}",
+        config.topic_prefix,
+        object_id(&device.name),
+        component.key()
+    )
+}
+
+/// Home Assistant discovery topic and payload of a component
+fn discovery(config: &MqttConfig, device: &Device, component: Component) -> (String, String) {
+    let id = object_id(&device.name);
+    let key = component.key();
+    let unique_id = format!("btmon_{id}_{key}");
+    let name = match component {
+        Component::Level => "Battery".to_string(),
+        _ => format!("Battery {key}"),
+    };
+    let mut device_info = json!({
+        "identifiers": [format!("btmon_{id}")],
+        "name": device.name,
+    });
+    if let Some(vendor) = &device.vendor {
+        device_info["manufacturer"] = json!(vendor);
+    }
+    let payload = json!({
+        "name": name,
+        "unique_id": unique_id,
+        "object_id": unique_id,
+        "state_topic": state_topic(config, device, component),
+        "availability_topic": config.status_topic(),
+        "device_class": "battery",
+        "state_class": "measurement",
+        "unit_of_measurement": "%",
+        "device": device_info,
+    });
+    (
+        format!("{}/sensor/{unique_id}/config", config.discovery_prefix),
+        payload.to_string(),
+    )
+}
+
+/// Publishes scans to a broker, reconnecting whenever the connection is lost
+pub struct Publisher {
+    config: MqttConfig,
+    keep_alive: u16,
+    client: Option<Client>,
+    /// Discovery topics announced on the current connection
+    announced: HashSet<String>,
+}
+
+impl Publisher {
+    /// Create a publisher that is published to every `interval`, which sets
+    /// the keep-alive the broker expects
+    pub fn new(config: MqttConfig, interval: Duration) -> io::Result<Self> {
+        // Reject a malformed URL up front rather than at every reconnect
+        Broker::parse(&config.broker)?;
+        let keep_alive =
+            u16::try_from(interval.as_secs().saturating_mul(2).max(60)).unwrap_or(u16::MAX);
+        Ok(Self {
+            config,
+            keep_alive,
+            client: None,
+            announced: HashSet::new(),
+        })
+    }
+
+    /// Connect to the broker, announcing btmon as online
+    fn connect(&mut self) -> io::Result<&mut Client> {
+        let client = match self.client.take() {
+            Some(client) => client,
+            None => {
+                let status = self.config.status_topic();
+                let will = Will {
+                    topic: &status,
+                    payload: b"offline",
+                };
+                let mut client = Client::connect(&self.config, &will, self.keep_alive)?;
+                client.publish(&status, b"online", true)?;
+                info!(broker = %self.config.broker, "Connected to MQTT broker");
+                self.announced.clear();
+                client
+            }
+        };
+        Ok(self.client.insert(client))
+    }
+
+    /// Publish the devices over the current connection
+    fn send(&mut self, devices: &[Device]) -> io::Result<()> {
+        // A new connection forgets what was announced, so connect before
+        // picking what to announce
+        self.connect()?;
+        let config = self.config.clone();
+        let mut announce = Vec::new();
+        for device in devices {
+            for (component, _) in device.components() {
+                let (topic, payload) = discovery(&config, device, component);
+                if !self.announced.contains(&topic) {
+                    announce.push((topic, payload));
+                }
+            }
+        }
+
+        let client = self.connect()?;
+        for (topic, payload) in &announce {
+            debug!(topic, "Publishing discovery config");
+            client.publish(topic, payload.as_bytes(), true)?;
+        }
+        for device in devices {
+            for (component, level) in device.components() {
+                client.publish(
+                    &state_topic(&config, device, component),
+                    level.as_percentage().to_string().as_bytes(),
+                    true,
+                )?;
+            }
+        }
+        client.ping()?;
+        self.announced
+            .extend(announce.into_iter().map(|(topic, _)| topic));
+        Ok(())
+    }
+
+    /// Publish the devices' battery levels, announcing new components to
+    /// Home Assistant first
+    ///
+    /// A connection that fails is dropped and retried once right away; if
+    /// that fails too the error is returned and the next call reconnects.
+    pub fn publish(&mut self, devices: &[Device]) -> io::Result<()> {
+        let reconnect = self.client.is_some();
+        match self.send(devices) {
+            Err(e) if reconnect => {
+                debug!(error = %e, "MQTT connection lost, reconnecting");
+                self.client = None;
+                self.send(devices).inspect_err(|_| self.client = None)
+            }
+            result => result.inspect_err(|_| self.client = None),
+        }
+    }
+
+    /// Announce btmon as offline and close the connection
+    pub fn disconnect(&mut self) {
+        if let Some(mut client) = self.client.take() {
+            let _ = client.publish(&self.config.status_topic(), b"offline", true);
+            client.disconnect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::BatteryLevel;
+
+    #[test]
+    fn test_broker_parse() {
+        let broker = |url| Broker::parse(url).unwrap();
+        assert_eq!(
+            broker("mqtt://homeassistant.local"),
+            Broker {
+                host: "homeassistant.local".to_string(),
+                port: 1883,
+                tls: false,
+            }
+        );
+        assert_eq!(broker("mqtts://broker:9000/").port, 9000);
+        assert!(broker("mqtts://broker").tls);
+        assert_eq!(broker("10.0.0.2:1884").port, 1884);
+        assert!(Broker::parse("http://broker").is_err());
+        assert!(Broker::parse("mqtt://broker:x").is_err());
+        assert!(Broker::parse("mqtt://").is_err());
+    }
+
+    #[test]
+    fn test_packets() {
+        assert_eq!(
+            publish_packet("a/b", b"42", true),
+            [0x31, 7, 0, 3, b'a', b'/', b'b', b'4', b'2']
+        );
+
+        // Bodies over 127 bytes take a second length byte
+        let long = publish_packet("t", &[0; 200], false);
+        assert_eq!(long[..3], [0x30, 0xcb, 0x01]);
+        assert_eq!(long.len(), 3 + 203);
+
+        let will = Will {
+            topic: "btmon/status",
+            payload: b"offline",
+        };
+        let connect = connect_packet("btmon-mac", Some("user"), None, &will, 60);
+        assert_eq!(connect[0], 0x10);
+        assert_eq!(usize::from(connect[1]), connect.len() - 2);
+        assert_eq!(connect[2..10], [0, 4, b'M', b'Q', b'T', b'T', 4, 0xa6]);
+        assert_eq!(connect[10..12], [0, 60]);
+        assert!(connect.ends_with(b"\x00\x04user"));
+
+        // A password needs a user name
+        let connect = connect_packet("btmon-mac", Some("user"), Some("pw"), &will, 60);
+        assert_eq!(connect[9], 0xe6);
+        assert!(connect.ends_with(b"\x00\x04user\x00\x02pw"));
+        let connect = connect_packet("btmon-mac", None, Some("pw"), &will, 60);
+        assert_eq!(connect[9], 0x26);
+        assert!(connect.ends_with(b"offline"));
+    }
+
+    #[test]
+    fn test_object_id() {
+        assert_eq!(object_id("Bob's AirPods Pro"), "bob_s_airpods_pro");
+        assert_eq!(object_id(" (MX Keys) "), "mx_keys");
+        let keyboard = object_id("キーボード");
+        assert!(keyboard.starts_with("device_"));
+        assert_eq!(keyboard, object_id("キーボード"));
+        assert_ne!(keyboard, object_id("マウス"));
+    }
+
+    /// Read one packet: its fixed header byte and its body
+    fn read_packet(stream: &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+        let mut byte = [0];
+        stream.read_exact(&mut byte)?;
+        let header = byte[0];
+        let (mut len, mut shift) = (0, 0);
+        loop {
+            stream.read_exact(&mut byte)?;
+            len |= usize::from(byte[0] & 0x7f) << shift;
+            shift += 7;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0; len];
+        stream.read_exact(&mut body)?;
+        Ok((header, body))
+    }
+
+    /// Broker accepting `connections` in turn with CONNACK `code`, logging
+    /// what each one sends. It drops every connection but the last after
+    /// the first ping, like a broker that restarted.
+    fn broker(connections: usize, code: u8) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("mqtt://{}", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let mut log = Vec::new();
+            for connection in 0..connections {
+                let (mut stream, _) = listener.accept().unwrap();
+                while let Ok((header, body)) = read_packet(&mut stream) {
+                    match header >> 4 {
+                        1 => {
+                            let id_len = usize::from(u16::from_be_bytes([body[10], body[11]]));
+                            let id = String::from_utf8_lossy(&body[12..12 + id_len]);
+                            log.push(format!("connect {id}"));
+                            stream.write_all(&[0x20, 0x02, 0, code]).unwrap();
+                        }
+                        3 => {
+                            let len = usize::from(u16::from_be_bytes([body[0], body[1]]));
+                            let topic = String::from_utf8_lossy(&body[2..2 + len]);
+                            let payload = String::from_utf8_lossy(&body[2 + len..]);
+                            let retain = if header & 1 == 1 { " (retained)" } else { "" };
+                            let payload = if payload.starts_with('{') {
+                                "{..}"
+                            } else {
+                                &payload
+                            };
+                            log.push(format!("publish {topic} {payload}{retain}"));
+                        }
+                        12 => {
+                            stream.write_all(&PINGRESP).unwrap();
+                            log.push("ping".to_string());
+                            if connection + 1 < connections {
+                                break;
+                            }
+                        }
+                        14 => log.push("disconnect".to_string()),
+                        _ => log.push(format!("packet {header:#x}")),
+                    }
+                }
+            }
+            log
+        });
+        (url, handle)
+    }
+
+    fn config(broker: String) -> MqttConfig {
+        MqttConfig {
+            client_id: Some("btmon-test".to_string()),
+            ..MqttConfig::new(broker)
+        }
+    }
+
+    #[test]
+    fn test_publisher() {
+        let (url, broker) = broker(2, 0);
+        let mut publisher = Publisher::new(config(url), Duration::from_secs(30)).unwrap();
+        publisher
+            .publish(&[Device::test_ble("Keyboard", 76)])
+            .unwrap();
+        // The broker went away: the next scan reconnects and announces the
+        // sensor to Home Assistant again
+        publisher
+            .publish(&[Device::test_ble("Keyboard", 75)])
+            .unwrap();
+        publisher.disconnect();
+
+        assert_eq!(
+            broker.join().unwrap(),
+            [
+                "connect btmon-test",
+                "publish btmon/status online (retained)",
+                "publish homeassistant/sensor/btmon_keyboard_level/config {..} (retained)",
+                "publish btmon/keyboard/level 76 (retained)",
+                "ping",
+                "connect btmon-test",
+                "publish btmon/status online (retained)",
+                "publish homeassistant/sensor/btmon_keyboard_level/config {..} (retained)",
+                "publish btmon/keyboard/level 75 (retained)",
+                "ping",
+                "publish btmon/status offline (retained)",
+                "disconnect",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_publisher_refused() {
+        let (url, broker) = broker(1, 5);
+        let mut publisher = Publisher::new(config(url), Duration::from_secs(30)).unwrap();
+        let error = publisher
+            .publish(&[Device::test_ble("Keyboard", 76)])
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+        assert_eq!(
+            error.to_string(),
+            "broker refused the connection: not authorized"
+        );
+        drop(publisher);
+        assert_eq!(broker.join().unwrap(), ["connect btmon-test"]);
+    }
+
+    #[test]
+    fn test_discovery() {
+        let device = Device {
+            vendor: Some("Apple".to_string()),
+            battery_left: BatteryLevel::new(80),
+            ..Device::test_ble("Bob's AirPods Pro", 0)
+        };
+        let config = MqttConfig::new("mqtt://broker".to_string());
+
+        assert_eq!(
+            state_topic(&config, &device, Component::Left),
+            "btmon/bob_s_airpods_pro/left"
+        );
+        let (topic, payload) = discovery(&config, &device, Component::Left);
+        assert_eq!(
+            topic,
+            "homeassistant/sensor/btmon_bob_s_airpods_pro_left/config"
+        );
+        let payload: serde_json::Value = serde_json::from_str(&payload).unwrap();
+        assert_eq!(payload["state_topic"], "btmon/bob_s_airpods_pro/left");
+        assert_eq!(payload["device_class"], "battery");
+        assert_eq!(payload["availability_topic"], "btmon/status");
+        assert_eq!(payload["device"]["manufacturer"], "Apple");
+    }
+}
@@ -0,0 +1,216 @@
+//! Nearby BLE devices for `btmon scan`
+//!
+//! A battery scan only sees peripherals that are connected to the Mac. An
+//! active scan hears every device advertising nearby, and some of them put
+//! their battery in the advertisement: in the manufacturer data (see
+//! [`crate::manufacturer`]) or as Battery Service data.
+
+use crate::backend::AdapterState;
+use crate::characteristic::decode_battery_level;
+use crate::device::{BatteryLevel, Component};
+use crate::locale::Locale;
+use crate::manufacturer::{self, AdvertisedStatus, Registry};
+use crate::vendor;
+use serde::Serialize;
+use std::time::Duration;
+
+/// One advertisement as received
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Advertisement {
+    /// Core Bluetooth identifier of the peripheral
+    pub identifier: String,
+    pub name: Option<String>,
+    /// Signal strength in dBm
+    pub rssi: i16,
+    pub manufacturer_data: Option<Vec<u8>>,
+    /// Service data of the Battery Service (0x180F)
+    pub battery_data: Option<Vec<u8>>,
+    pub connectable: Option<bool>,
+}
+
+/// A device heard during the scan
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NearbyDevice {
+    pub identifier: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Signal strength of the last advertisement in dBm
+    pub rssi: i16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connectable: Option<bool>,
+    /// Vendor, model and batteries decoded from the advertisement
+    #[serde(flatten)]
+    pub status: AdvertisedStatus,
+}
+
+impl NearbyDevice {
+    /// Whether the advertisement carried any battery level
+    pub fn has_battery(&self) -> bool {
+        let status = &self.status;
+        status.battery_level.is_some()
+            || status.battery_left.is_some()
+            || status.battery_right.is_some()
+            || status.battery_case.is_some()
+    }
+}
+
+/// Decode the advertisements into one device per peripheral, using the last
+/// advertisement of each, strongest signal first
+pub fn collect(advertisements: Vec<Advertisement>, registry: &Registry) -> Vec<NearbyDevice> {
+    let mut devices: Vec<NearbyDevice> = Vec::new();
+    for advertisement in advertisements {
+        let mut status = advertisement
+            .manufacturer_data
+            .as_deref()
+            .and_then(|data| registry.decode(data))
+            .unwrap_or_default();
+        if status.vendor.is_none() {
+            status.vendor = advertisement
+                .manufacturer_data
+                .as_deref()
+                .and_then(manufacturer::split)
+                .and_then(|(company_id, _)| vendor::company_vendor(company_id));
+        }
+        if let Some(data) = &advertisement.battery_data {
+            status.battery_level = decode_battery_level(data).and_then(BatteryLevel::new);
+        }
+
+        let index = devices
+            .iter()
+            .position(|device| device.identifier == advertisement.identifier);
+        let device = NearbyDevice {
+            // Scan responses with the name can come separately
+            name: advertisement
+                .name
+                .or_else(|| index.and_then(|index| devices[index].name.clone())),
+            identifier: advertisement.identifier,
+            rssi: advertisement.rssi,
+            connectable: advertisement.connectable,
+            status,
+        };
+        match index {
+            Some(index) => devices[index] = device,
+            None => devices.push(device),
+        }
+    }
+    devices.sort_by_key(|device| std::cmp::Reverse(device.rssi));
+    devices
+}
+
+/// Scan for advertising devices for `duration`, failing with the adapter
+/// state if Bluetooth can't be used
+#[cfg(target_os = "macos")]
+pub fn scan(duration: Duration) -> Result<Vec<Advertisement>, AdapterState> {
+    crate::advertising::scan(duration)
+}
+
+/// Scan for advertising devices for `duration`, failing with the adapter
+/// state if Bluetooth can't be used
+#[cfg(not(target_os = "macos"))]
+pub fn scan(_duration: Duration) -> Result<Vec<Advertisement>, AdapterState> {
+    Err(AdapterState::Unsupported)
+}
+
+/// Format the devices as text, one per line with the signal strength first.
+/// Charging batteries are marked with ⚡, or `+` if `ascii`
+pub fn format_nearby(devices: &[NearbyDevice], locale: &Locale, ascii: bool) -> String {
+    let charging = if ascii { "+" } else { "⚡" };
+    devices
+        .iter()
+        .map(|device| {
+            let status = &device.status;
+            let mut line = format!(
+                "{:>4} dBm  {}",
+                device.rssi,
+                device.name.as_deref().unwrap_or("(unnamed)")
+            );
+            match (status.model, status.vendor) {
+                (Some(model), _) => line.push_str(&format!(" [{model}]")),
+                (None, Some(vendor)) => line.push_str(&format!(" [{vendor}]")),
+                (None, None) => {}
+            }
+            let batteries = [
+                (Component::Level, status.battery_level),
+                (Component::Left, status.battery_left),
+                (Component::Right, status.battery_right),
+                (Component::Case, status.battery_case),
+            ];
+            for (component, level) in batteries {
+                let Some(level) = level else {
+                    continue;
+                };
+                match component.label() {
+                    Some(label) => line.push_str(&format!(" {label}:{}", locale.battery(level))),
+                    None => line.push_str(&format!(" {}", locale.battery(level))),
+                }
+                if status.charging.contains(&component) {
+                    line.push_str(charging);
+                }
+            }
+            line
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect() {
+        let airpods = Advertisement {
+            identifier: "A".to_string(),
+            rssi: -48,
+            manufacturer_data: Some(vec![
+                0x4C, 0x00, 0x07, 0x0A, 0x01, 0x0E, 0x20, 0x2B, 0x98, 0x5A, 0x01, 0x00, 0x00, 0x00,
+            ]),
+            ..Default::default()
+        };
+        let keyboard = Advertisement {
+            identifier: "B".to_string(),
+            name: Some("Keychron K2".to_string()),
+            rssi: -70,
+            battery_data: Some(vec![64]),
+            connectable: Some(true),
+            ..Default::default()
+        };
+        let beacon = Advertisement {
+            identifier: "C".to_string(),
+            rssi: -90,
+            manufacturer_data: Some(vec![0x06, 0x00, 0x01]),
+            ..Default::default()
+        };
+        let keyboard_again = Advertisement {
+            name: None,
+            rssi: -60,
+            ..keyboard.clone()
+        };
+
+        let devices = collect(
+            vec![beacon, keyboard, airpods, keyboard_again],
+            &Registry::default(),
+        );
+        let ids: Vec<&str> = devices.iter().map(|d| d.identifier.as_str()).collect();
+        assert_eq!(ids, ["A", "B", "C"]);
+        assert!(devices[0].has_battery());
+        assert_eq!(devices[1].name.as_deref(), Some("Keychron K2"));
+        assert_eq!(devices[1].rssi, -60);
+        assert_eq!(devices[2].status.vendor, Some("Microsoft"));
+        assert!(!devices[2].has_battery());
+
+        let text = format_nearby(&devices, &Locale::default(), false);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines[0],
+            " -48 dBm  (unnamed) [AirPods Pro] L:80%⚡ R:90% Case:100%⚡"
+        );
+        assert_eq!(lines[1], " -60 dBm  Keychron K2 64%");
+        assert_eq!(lines[2], " -90 dBm  (unnamed) [Microsoft]");
+        let ascii = format_nearby(&devices[..1], &Locale::default(), true);
+        assert_eq!(
+            ascii,
+            " -48 dBm  (unnamed) [AirPods Pro] L:80%+ R:90% Case:100%+"
+        );
+    }
+}
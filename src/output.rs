@@ -0,0 +1,1254 @@
+//! Output formatting for device lists
+
+use crate::alerts::{AlertConfig, Severity};
+use crate::backend::{AdapterState, AdapterStatus};
+use crate::csvlog::escape_field;
+use crate::device::{BatteryLevel, Component, Device, DeviceAddress};
+use crate::icons::Icons;
+use crate::locale::Locale;
+use crate::schema::SCHEMA_VERSION;
+use crate::sequence::Sequences;
+use crate::status::SourceStats;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::Path;
+use std::time::Duration;
+use std::{fs, io};
+
+/// Output format selected with `--format`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// One line per device
+    #[default]
+    Text,
+    /// Pretty-printed JSON array
+    Json,
+    /// Flat JSON dictionaries for Apple Shortcuts
+    Shortcuts,
+    /// One line with tmux colour codes for `status-right`
+    Tmux,
+    /// Plain shell prompt segment with only the low devices
+    Prompt,
+    /// Just the level of each device, e.g. `15% 76%`, for minimal prompts
+    Short,
+    /// Comma-separated values with a header row
+    Csv,
+    /// YAML with the same structure as the JSON output
+    Yaml,
+    /// One JSON object per device and line with a timestamp, for streaming
+    Ndjson,
+    /// Aligned table with a column per battery component
+    Table,
+    /// GitHub-flavored Markdown table
+    Markdown,
+    /// Item properties for a SketchyBar plugin
+    Sketchybar,
+    /// Menu bar line and dropdown of a SwiftBar or xbar plugin
+    Xbar,
+    /// Summary line and device list for a Raycast script command
+    Raycast,
+    /// Script Filter JSON for an Alfred workflow
+    Alfred,
+    /// Excel workbook of the recorded history, for `btmon export`
+    Xlsx,
+}
+
+/// When to colour levels, selected with `--color`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorChoice {
+    /// Only on a terminal, and not when `NO_COLOR` is set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether to colour stdout
+    pub fn enabled(self) -> bool {
+        match self {
+            Self::Always => true,
+            Self::Never => false,
+            Self::Auto => {
+                // https://no-color.org: any non-empty value disables colour
+                let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+                // SAFETY: isatty only inspects the descriptor.
+                !no_color && unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1
+            }
+        }
+    }
+}
+
+/// Colour `text` green, yellow or red by the severity of `level`
+fn paint(text: &str, level: BatteryLevel, alerts: &AlertConfig) -> String {
+    let code = match alerts.severity(level) {
+        Some(Severity::Critical) => 31,
+        Some(Severity::Warning) => 33,
+        None => 32,
+    };
+    format!("\x1b[{code}m{text}\x1b[0m")
+}
+
+/// Order of the devices, selected with `--sort`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SortKey {
+    /// By name, ignoring case
+    Name,
+    /// Lowest battery first, devices without a level last
+    Battery,
+    /// By device type, then name
+    Type,
+}
+
+/// Sort the devices by `key`, keeping the order of equal ones
+pub fn sort_devices(devices: &mut [Device], key: SortKey, reverse: bool) {
+    match key {
+        SortKey::Name => devices.sort_by_cached_key(|device| device.name.to_lowercase()),
+        SortKey::Battery => devices.sort_by_key(|device| {
+            let level = device.effective().or(device.battery_case);
+            (level.is_none(), level.map(BatteryLevel::as_percentage))
+        }),
+        SortKey::Type => {
+            devices.sort_by_cached_key(|device| (device_kind(device), device.name.to_lowercase()))
+        }
+    }
+    if reverse {
+        devices.reverse();
+    }
+}
+
+/// Format device output for terminal display
+pub fn format_device_output(device: &Device, locale: &Locale) -> String {
+    device_line(device, locale, |level| locale.battery(level))
+}
+
+/// Change of a level since the previous `--watch` pass behind its trend
+/// arrow, e.g. `↓3`
+fn trend(change: i16) -> String {
+    match change {
+        1.. => format!("↑{change}"),
+        ..0 => format!("↓{}", -change),
+        0 => "→".to_string(),
+    }
+}
+
+/// Format a device's line with each level formatted by `battery`
+fn device_line(
+    device: &Device,
+    locale: &Locale,
+    battery: impl Fn(BatteryLevel) -> String,
+) -> String {
+    let battery = |component, level| match device.delta.get(&component) {
+        Some(&change) => format!("{} {}", battery(level), trend(change)),
+        None => battery(level),
+    };
+    let line = if let Some(level) = device.battery_level {
+        format!("{}: {}", device.name, battery(Component::Level, level))
+    } else {
+        // AirPods-style device with multiple batteries
+        let mut parts = Vec::new();
+        if let Some(l) = device.battery_left {
+            parts.push(format!("L:{}", battery(Component::Left, l)));
+        }
+        if let Some(r) = device.battery_right {
+            parts.push(format!("R:{}", battery(Component::Right, r)));
+        }
+        if let Some(c) = device.battery_case {
+            parts.push(format!("Case:{}", battery(Component::Case, c)));
+        }
+        format!("{}: {}", device.name, parts.join(" "))
+    };
+
+    match device.freshness {
+        Some(freshness) if freshness.stale => {
+            let age = Duration::from_secs(freshness.age_seconds);
+            format!("{line} (stale, {} ago)", locale.duration(age))
+        }
+        _ => line,
+    }
+}
+
+/// Format all devices as plain text, one device per line
+pub fn format_text(devices: &[Device], locale: &Locale) -> String {
+    devices
+        .iter()
+        .map(|device| format_device_output(device, locale))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Decorations of the plain text output
+#[derive(Debug, Clone, Default)]
+pub struct TextStyle<'a> {
+    /// Device and battery glyphs before each line
+    pub icons: Option<Icons>,
+    /// Width of the gauge drawn before each level
+    pub bars: Option<u8>,
+    /// Thresholds to colour the levels by, None for no colour
+    pub colors: Option<&'a AlertConfig>,
+}
+
+/// Draw a level as a gauge of `width` blocks, e.g. `▮▮▮▮▯▯▯▯`
+pub fn bar(level: BatteryLevel, width: u8) -> String {
+    let width = usize::from(width);
+    let filled = (usize::from(level.as_percentage()) * width + 50) / 100;
+    format!("{}{}", "▮".repeat(filled), "▯".repeat(width - filled))
+}
+
+/// Format all devices as plain text with the decorations of `style`
+pub fn format_styled_text(devices: &[Device], locale: &Locale, style: &TextStyle) -> String {
+    devices
+        .iter()
+        .map(|device| {
+            let line = device_line(device, locale, |level| {
+                let text = match style.bars {
+                    Some(width) => format!("{} {}", bar(level, width), locale.battery(level)),
+                    None => locale.battery(level),
+                };
+                match style.colors {
+                    Some(alerts) => paint(&text, level, alerts),
+                    None => text,
+                }
+            });
+            let Some(icons) = &style.icons else {
+                return line;
+            };
+            match device.effective().or(device.battery_case) {
+                Some(level) => format!("{} {} {line}", icons.device(device), icons.battery(level)),
+                None => format!("{} {line}", icons.device(device)),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Header row of `--format csv`, kept stable for spreadsheets and scripts
+pub const CSV_HEADER: &str = "name,address,source,level,left,right,case,timestamp";
+
+/// Format all devices as CSV with a header row. The timestamp is when the
+/// levels were read, or `now` if the backend didn't report it
+pub fn format_csv(devices: &[Device], now: DateTime<Utc>) -> String {
+    let field = |level: Option<BatteryLevel>| {
+        level
+            .map(|l| l.as_percentage().to_string())
+            .unwrap_or_default()
+    };
+    let mut lines = vec![CSV_HEADER.to_string()];
+    for device in devices {
+        let source = device_source(device);
+        let read_at = device.freshness.map_or(now, |f| f.read_at);
+        lines.push(
+            [
+                escape_field(&device.name),
+                escape_field(&device.address.to_string()),
+                source.to_string(),
+                field(device.battery_level),
+                field(device.battery_left),
+                field(device.battery_right),
+                field(device.battery_case),
+                read_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            ]
+            .join(","),
+        );
+    }
+    lines.join("\n")
+}
+
+/// A device in the JSON output, tagged with the schema version; the HTTP
+/// API serves the same objects
+#[derive(Serialize)]
+pub struct VersionedDevice<'a> {
+    schema_version: u32,
+    #[serde(flatten)]
+    device: &'a Device,
+}
+
+impl<'a> From<&'a Device> for VersionedDevice<'a> {
+    fn from(device: &'a Device) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            device,
+        }
+    }
+}
+
+fn versioned(devices: &[Device]) -> Vec<VersionedDevice<'_>> {
+    devices.iter().map(VersionedDevice::from).collect()
+}
+
+/// Format all devices as a pretty-printed JSON array
+pub fn format_json(devices: &[Device]) -> serde_json::Result<String> {
+    serde_json::to_string_pretty(&versioned(devices))
+}
+
+/// Explanation of an adapter status for text output
+pub fn adapter_message(adapter: &AdapterStatus) -> &'static str {
+    match adapter.state {
+        AdapterState::PoweredOff => "Bluetooth is turned off",
+        AdapterState::Unauthorized => {
+            "btmon is not allowed to use Bluetooth (System Settings > Privacy & Security > Bluetooth)"
+        }
+        AdapterState::Unsupported => "Bluetooth Low Energy is not supported on this Mac",
+        AdapterState::Resetting => "Bluetooth is resetting, try again in a moment",
+        AdapterState::Unknown => "Bluetooth state is unknown",
+        AdapterState::PoweredOn => "Bluetooth is on",
+    }
+}
+
+/// JSON output when the adapter can't be used
+#[derive(Serialize)]
+struct AdapterOutput<'a, T> {
+    adapter: &'a AdapterStatus,
+    devices: T,
+}
+
+/// Format the devices together with the adapter status, so scripts can
+/// tell an unusable adapter apart from having no devices
+pub fn format_adapter_json(
+    adapter: &AdapterStatus,
+    devices: &[Device],
+) -> serde_json::Result<String> {
+    serde_json::to_string_pretty(&AdapterOutput {
+        adapter,
+        devices: versioned(devices),
+    })
+}
+
+/// When and where the devices were read, for `--with-meta`
+#[derive(Debug, Serialize)]
+pub struct Meta<'a> {
+    pub timestamp: DateTime<Utc>,
+    pub hostname: String,
+    /// btmon version
+    pub version: &'static str,
+    /// Where the devices came from: `system`, `replay`, `cache` or `daemon`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backend: Option<&'static str>,
+    /// Data sources queried, empty when an earlier scan was reused
+    pub sources: &'a BTreeMap<&'static str, SourceStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub adapter: Option<&'a AdapterStatus>,
+}
+
+/// JSON output with `--with-meta`
+#[derive(Serialize)]
+struct MetaOutput<'a> {
+    #[serde(flatten)]
+    meta: &'a Meta<'a>,
+    devices: Vec<VersionedDevice<'a>>,
+}
+
+/// Format the devices in an envelope with the run's metadata
+pub fn format_meta_json(meta: &Meta, devices: &[Device]) -> serde_json::Result<String> {
+    serde_json::to_string_pretty(&MetaOutput {
+        meta,
+        devices: versioned(devices),
+    })
+}
+
+/// Format the devices as newline-delimited JSON, one object per device
+/// with the time of the pass first. In a stream, `sequences` adds the
+/// `session_id` and the device's `seq`
+pub fn format_ndjson(
+    devices: &[Device],
+    now: DateTime<Utc>,
+    sequences: Option<&Sequences>,
+) -> serde_json::Result<String> {
+    let mut lines = Vec::new();
+    for device in devices {
+        let mut object = ndjson_record(now, sequences, device);
+        if let serde_json::Value::Object(fields) =
+            serde_json::to_value(VersionedDevice::from(device))?
+        {
+            object.extend(fields);
+        }
+        lines.push(serde_json::to_string(&object)?);
+    }
+    Ok(lines.join("\n"))
+}
+
+/// Start of the NDJSON record of a device: the time of the pass, then the
+/// session and the device's sequence number if it's streamed
+pub fn ndjson_record(
+    now: DateTime<Utc>,
+    sequences: Option<&Sequences>,
+    device: &Device,
+) -> serde_json::Map<String, serde_json::Value> {
+    let mut object = serde_json::Map::new();
+    let timestamp = now.to_rfc3339_opts(SecondsFormat::Secs, true);
+    object.insert("timestamp".to_string(), timestamp.into());
+    if let Some(sequences) = sequences {
+        object.insert("session_id".to_string(), sequences.session().into());
+        object.insert("seq".to_string(), sequences.seq(&device.name).into());
+    }
+    object
+}
+
+/// Format the devices as YAML, structured like the JSON output and with
+/// the adapter status if there is one
+pub fn format_yaml(
+    devices: &[Device],
+    adapter: Option<&AdapterStatus>,
+) -> serde_json::Result<String> {
+    match adapter {
+        Some(adapter) => crate::yaml::to_string(&AdapterOutput {
+            adapter,
+            devices: versioned(devices),
+        }),
+        None => crate::yaml::to_string(&versioned(devices)),
+    }
+}
+
+/// Kind of device, e.g. `keyboard`, guessed from its name and batteries
+pub fn device_kind(device: &Device) -> &'static str {
+    let name = device.name.to_lowercase();
+    let has = |words: &[&str]| words.iter().any(|word| name.contains(word));
+    if device.battery_left.is_some()
+        || device.battery_right.is_some()
+        || has(&["airpods", "buds", "beats", "headphone", "headset", "earbud"])
+    {
+        "headphones"
+    } else if has(&["keyboard", "keys"]) {
+        "keyboard"
+    } else if has(&["mouse", "trackpad", "trackball"]) {
+        "mouse"
+    } else if has(&["controller", "gamepad", "joy-con"]) {
+        "controller"
+    } else {
+        "other"
+    }
+}
+
+/// Emoji for the kind of device, guessed from its name and batteries
+pub fn device_icon(device: &Device) -> &'static str {
+    match device_kind(device) {
+        "headphones" => "🎧",
+        "keyboard" => "⌨️",
+        "mouse" => "🖱️",
+        "controller" => "🎮",
+        _ => "🔋",
+    }
+}
+
+/// Backend a device was read from, `classic` or `gatt`
+pub fn device_source(device: &Device) -> &'static str {
+    match device.address {
+        DeviceAddress::Classic(_) => "classic",
+        DeviceAddress::Ble => "gatt",
+    }
+}
+
+/// Columns of the table formats
+const TABLE_COLUMNS: [&str; 7] = ["Name", "Type", "Source", "Level", "Left", "Right", "Case"];
+
+/// Cells of a device's table row, `-` for missing components
+fn table_cells(device: &Device, locale: &Locale) -> [String; 7] {
+    let level = |component| match (device.battery(component), device.delta.get(&component)) {
+        (Some(level), Some(&change)) => format!("{} {}", locale.battery(level), trend(change)),
+        (Some(level), None) => locale.battery(level),
+        (None, _) => "-".to_string(),
+    };
+    [
+        device.name.clone(),
+        device_kind(device).to_string(),
+        device_source(device).to_string(),
+        level(Component::Level),
+        level(Component::Left),
+        level(Component::Right),
+        level(Component::Case),
+    ]
+}
+
+/// Format the devices for `--porcelain`: one line per device with the
+/// tab-separated name, address, source and the level, left, right and case
+/// percentages, empty when missing. Sorted by name and address, without a
+/// header or colours. The columns stay as they are across versions
+pub fn format_porcelain(devices: &[Device]) -> String {
+    let mut devices: Vec<&Device> = devices.iter().collect();
+    devices.sort_by(|a, b| (&a.name, a.address.to_string()).cmp(&(&b.name, b.address.to_string())));
+    let field = |level: Option<BatteryLevel>| {
+        level
+            .map(|l| l.as_percentage().to_string())
+            .unwrap_or_default()
+    };
+    devices
+        .iter()
+        .map(|device| {
+            [
+                device.name.replace(['\t', '\n', '\r'], " "),
+                device.address.to_string(),
+                device_source(device).to_string(),
+                field(device.battery_level),
+                field(device.battery_left),
+                field(device.battery_right),
+                field(device.battery_case),
+            ]
+            .join("\t")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Format the devices as a GitHub-flavored Markdown table
+pub fn format_markdown(devices: &[Device], locale: &Locale) -> String {
+    let row = |cells: &[String]| format!("| {} |", cells.join(" | "));
+    let mut lines = vec![
+        row(&TABLE_COLUMNS.map(str::to_string)),
+        "| --- | --- | --- | ---: | ---: | ---: | ---: |".to_string(),
+    ];
+    for device in devices {
+        let cells = table_cells(device, locale).map(|cell| cell.replace('|', "\\|"));
+        lines.push(row(&cells));
+    }
+    lines.join("\n")
+}
+
+/// Narrowest the name column gets when the table is truncated
+const MIN_NAME_WIDTH: usize = 8;
+
+/// Format the devices as an aligned table with a column per battery
+/// component. If `width` is given, long names are cut so that rows fit.
+/// With `colors`, levels are coloured by those thresholds.
+pub fn format_table(
+    devices: &[Device],
+    locale: &Locale,
+    width: Option<usize>,
+    colors: Option<&AlertConfig>,
+) -> String {
+    let mut rows = vec![TABLE_COLUMNS.map(str::to_uppercase)];
+    rows.extend(devices.iter().map(|device| table_cells(device, locale)));
+
+    let mut widths = [0; 7];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    if let Some(width) = width {
+        // Two spaces between columns
+        let others: usize = widths[1..].iter().map(|w| w + 2).sum();
+        widths[0] = widths[0].min(width.saturating_sub(others).max(MIN_NAME_WIDTH));
+    }
+
+    rows.iter()
+        .enumerate()
+        .map(|(r, row)| {
+            let name = truncate(&row[0], widths[0]);
+            let mut line = format!("{name:<0$}", widths[0]);
+            for (i, cell) in row.iter().enumerate().skip(1) {
+                // Text columns left-aligned, levels right-aligned
+                if i < 3 {
+                    line.push_str(&format!("  {cell:<0$}", widths[i]));
+                    continue;
+                }
+                let cell = format!("{cell:>0$}", widths[i]);
+                // Row 0 is the header
+                let level = r.checked_sub(1).and_then(|d| {
+                    let component = [
+                        Component::Level,
+                        Component::Left,
+                        Component::Right,
+                        Component::Case,
+                    ][i - 3];
+                    devices[d].battery(component)
+                });
+                match (colors, level) {
+                    (Some(alerts), Some(level)) => {
+                        line.push_str(&format!("  {}", paint(&cell, level, alerts)));
+                    }
+                    _ => line.push_str(&format!("  {cell}")),
+                }
+            }
+            line.trim_end().to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Cut `s` to at most `width` characters, ending with an ellipsis if cut
+fn truncate(s: &str, width: usize) -> String {
+    if s.chars().count() <= width {
+        return s.to_string();
+    }
+    let mut cut: String = s.chars().take(width.saturating_sub(1)).collect();
+    cut.push('…');
+    cut
+}
+
+/// Width of the terminal on stdout, None if it isn't a terminal
+pub fn terminal_width() -> Option<usize> {
+    // SAFETY: winsize is plain integers, for which zero is valid.
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    // SAFETY: TIOCGWINSZ only writes a winsize to the pointer given.
+    let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+    (ok && size.ws_col > 0).then_some(usize::from(size.ws_col))
+}
+
+/// Format all devices as one tmux status-line string
+///
+/// Devices at or below the alert thresholds are coloured yellow (warning) or
+/// red (critical); the rest use the status line's default colours.
+pub fn format_tmux(devices: &[Device], alerts: &AlertConfig, icons: &Icons) -> String {
+    devices
+        .iter()
+        .filter_map(|device| {
+            let level = device.effective().or(device.battery_case)?;
+            let segment = format!(
+                "{} {}",
+                icons.device(device),
+                Locale::default().battery(level)
+            );
+            Some(match alerts.severity(level) {
+                Some(Severity::Critical) => format!("#[fg=red]{segment}#[default]"),
+                Some(Severity::Warning) => format!("#[fg=yellow]{segment}#[default]"),
+                None => segment,
+            })
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Format the devices at or below the warning threshold as a plain prompt
+/// segment, empty if none are low
+pub fn format_prompt(devices: &[Device], alerts: &AlertConfig, icons: &Icons) -> String {
+    devices
+        .iter()
+        .filter_map(|device| {
+            let level = device.effective().or(device.battery_case)?;
+            alerts.severity(level)?;
+            Some(format!(
+                "{} {}",
+                icons.device(device),
+                Locale::default().battery(level)
+            ))
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Format the level of each device as a single token, e.g. `15% 76%`
+pub fn format_short(devices: &[Device]) -> String {
+    devices
+        .iter()
+        .filter_map(|device| device.effective().or(device.battery_case))
+        .map(|level| Locale::default().battery(level))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The device with the lowest battery and that level, the first one on a tie
+pub fn worst(devices: &[Device]) -> Option<(&Device, BatteryLevel)> {
+    devices
+        .iter()
+        .filter_map(|device| Some((device, device.effective().or(device.battery_case)?)))
+        .min_by_key(|(_, level)| level.as_percentage())
+}
+
+/// Format the lowest battery for the menu bar, e.g. `🎧 5%`
+pub fn format_menubar_title(devices: &[Device]) -> String {
+    worst(devices).map_or_else(
+        || "🔋 –".to_string(),
+        |(device, level)| {
+            format!(
+                "{} {}",
+                device_icon(device),
+                Locale::default().battery(level)
+            )
+        },
+    )
+}
+
+/// The lowest battery component across all devices
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Lowest<'a> {
+    pub name: &'a str,
+    pub component: Component,
+    pub level: BatteryLevel,
+}
+
+/// Find the lowest battery component across all devices, the first one on a
+/// tie
+pub fn lowest(devices: &[Device]) -> Option<Lowest<'_>> {
+    devices
+        .iter()
+        .flat_map(|device| {
+            device.components().map(|(component, level)| Lowest {
+                name: &device.name,
+                component,
+                level,
+            })
+        })
+        .min_by_key(|lowest| lowest.level.as_percentage())
+}
+
+/// Aggregate of all devices for `--summary`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Summary<'a> {
+    /// Number of devices
+    pub devices: usize,
+    pub lowest: Option<Lowest<'a>>,
+    /// Average of the devices' levels, the lower earbud for multi-battery
+    /// ones
+    pub average: Option<u8>,
+}
+
+/// Aggregate the devices into a summary
+pub fn summarize(devices: &[Device]) -> Summary<'_> {
+    let levels: Vec<u32> = devices
+        .iter()
+        .filter_map(|device| device.effective().or(device.battery_case))
+        .map(|level| u32::from(level.as_percentage()))
+        .collect();
+    let average = (!levels.is_empty()).then(|| {
+        let count = levels.len() as u32;
+        let average = (levels.iter().sum::<u32>() + count / 2) / count;
+        u8::try_from(average).unwrap_or(100)
+    });
+    Summary {
+        devices: devices.len(),
+        lowest: lowest(devices),
+        average,
+    }
+}
+
+/// Format a summary as one line, e.g. `3 devices, lowest AirPods L:15%,
+/// average 62%`
+pub fn format_summary(summary: &Summary) -> String {
+    let mut line = match summary.devices {
+        1 => "1 device".to_string(),
+        count => format!("{count} devices"),
+    };
+    if let Some(lowest) = &summary.lowest {
+        line.push_str(&format!(", lowest {}", format_status_line(lowest)));
+    }
+    if let Some(average) = summary.average {
+        line.push_str(&format!(", average {average}%"));
+    }
+    line
+}
+
+/// Format the lowest battery as one compact line, e.g. `AirPods L:15%`
+pub fn format_status_line(lowest: &Lowest) -> String {
+    let level = Locale::default().battery(lowest.level);
+    match lowest.component.label() {
+        Some(label) => format!("{} {label}:{level}", lowest.name),
+        None => format!("{} {level}", lowest.name),
+    }
+}
+
+/// A device as a compact payload for a Stream Deck key
+#[derive(Debug, Serialize)]
+pub struct CompactDevice<'a> {
+    pub name: &'a str,
+    /// Key title: the name and the percentage on two lines
+    pub title: String,
+    /// Single battery, or the lower earbud of multi-battery devices
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub percent: Option<u8>,
+    /// `ok`, `warning` or `critical` against the alert thresholds
+    pub severity: &'static str,
+    /// Background color for the severity
+    pub color: &'static str,
+}
+
+/// Shape a device for a Stream Deck key, rated against the alert thresholds
+pub fn compact<'a>(device: &'a Device, alerts: &AlertConfig) -> CompactDevice<'a> {
+    let level = device.effective().or(device.battery_case);
+    let (severity, color) = match level.and_then(|level| alerts.severity(level)) {
+        Some(Severity::Critical) => ("critical", "#FF3B30"),
+        Some(Severity::Warning) => ("warning", "#FFCC00"),
+        None => ("ok", "#34C759"),
+    };
+    let title = match level {
+        Some(level) => format!("{}\n{}", device.name, Locale::default().battery(level)),
+        None => device.name.clone(),
+    };
+    CompactDevice {
+        name: &device.name,
+        title,
+        percent: level.map(BatteryLevel::as_percentage),
+        severity,
+        color,
+    }
+}
+
+/// A device as a flat dictionary for Shortcuts
+#[derive(Debug, Serialize)]
+pub struct ShortcutsDevice<'a> {
+    pub name: &'a str,
+    pub address: &'a DeviceAddress,
+    /// Single battery, or the lower earbud of multi-battery devices
+    pub battery: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub left: Option<BatteryLevel>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub right: Option<BatteryLevel>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub case: Option<BatteryLevel>,
+    /// Text line, ready for "Speak Text" or "Show Notification"
+    pub summary: String,
+}
+
+/// Shape a device as a flat dictionary, for Shortcuts and the daemon's
+/// event socket
+pub fn shortcuts_device(device: &Device) -> ShortcutsDevice<'_> {
+    ShortcutsDevice {
+        name: &device.name,
+        address: &device.address,
+        battery: device
+            .effective()
+            .or(device.battery_case)
+            .map_or(0, BatteryLevel::as_percentage),
+        left: device.battery_left,
+        right: device.battery_right,
+        case: device.battery_case,
+        summary: format_device_output(device, &Locale::default()),
+    }
+}
+
+/// Top-level dictionary for Shortcuts' "Get Dictionary from Input"
+#[derive(Serialize)]
+struct ShortcutsOutput<'a> {
+    count: usize,
+    devices: Vec<ShortcutsDevice<'a>>,
+}
+
+/// Format all devices for Apple Shortcuts.
+///
+/// Shortcuts only handles flat dictionaries well, so the list is wrapped in a
+/// top-level dictionary and each device has plain number and string values.
+pub fn format_shortcuts(devices: &[Device]) -> serde_json::Result<String> {
+    let output = ShortcutsOutput {
+        count: devices.len(),
+        devices: devices.iter().map(shortcuts_device).collect(),
+    };
+    serde_json::to_string_pretty(&output)
+}
+
+/// Replace the file at `path` with `contents` in one step: they are written
+/// to a hidden file next to it, which is then renamed over it, so a status
+/// bar polling the file never reads half an output
+pub fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?;
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(name);
+    tmp_name.push(format!(".{}.tmp", std::process::id()));
+    let tmp = path.with_file_name(tmp_name);
+    let written = fs::write(&tmp, contents).and_then(|()| fs::rename(&tmp, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    written
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::{BatteryLevel, Freshness};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_format_device_output_single() {
+        let device = Device::test_ble("Keyboard", 76);
+        assert_eq!(
+            format_device_output(&device, &Locale::default()),
+            "Keyboard: 76%"
+        );
+        assert_eq!(
+            format_device_output(&device, &Locale::from_name("fr_FR.UTF-8")),
+            "Keyboard: 76\u{a0}%"
+        );
+    }
+
+    #[test]
+    fn test_format_device_output_airpods() {
+        let device = Device {
+            battery_left: BatteryLevel::new(80),
+            battery_right: BatteryLevel::new(90),
+            battery_case: BatteryLevel::new(100),
+            ..Device::test_classic("AirPods Pro", "aa:bb:cc:dd:ee:ff")
+        };
+        assert_eq!(
+            format_device_output(&device, &Locale::default()),
+            "AirPods Pro: L:80% R:90% Case:100%"
+        );
+    }
+
+    #[test]
+    fn test_format_device_output_stale() {
+        let mut freshness = Freshness::new(chrono::DateTime::UNIX_EPOCH);
+        freshness.age_seconds = 3900;
+        freshness.stale = true;
+        let device = Device {
+            freshness: Some(freshness),
+            ..Device::test_ble("Keyboard", 76)
+        };
+        assert_eq!(
+            format_device_output(&device, &Locale::default()),
+            "Keyboard: 76% (stale, 1h 5m ago)"
+        );
+    }
+
+    #[test]
+    fn test_format_shortcuts_battery_is_lower_earbud() {
+        let device = Device {
+            battery_left: BatteryLevel::new(80),
+            battery_right: BatteryLevel::new(35),
+            battery_case: BatteryLevel::new(20),
+            ..Device::test_classic("AirPods Pro", "aa:bb:cc:dd:ee:ff")
+        };
+        let json: serde_json::Value =
+            serde_json::from_str(&format_shortcuts(&[device]).unwrap()).unwrap();
+        assert_eq!(json["count"], 1);
+        assert_eq!(json["devices"][0]["battery"], 35);
+        assert_eq!(
+            json["devices"][0]["summary"],
+            "AirPods Pro: L:80% R:35% Case:20%"
+        );
+    }
+
+    #[test]
+    fn test_format_adapter_json() {
+        let adapter = AdapterStatus::from(AdapterState::Unauthorized);
+        let json = format_adapter_json(&adapter, &[]).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "adapter": {"state": "unauthorized", "powered": false, "authorized": false},
+                "devices": [],
+            })
+        );
+        assert!(adapter_message(&adapter).contains("Privacy & Security"));
+    }
+
+    #[test]
+    fn test_compact() {
+        let device = Device {
+            battery_left: BatteryLevel::new(23),
+            battery_right: BatteryLevel::new(9),
+            battery_case: BatteryLevel::new(100),
+            ..Device::test_ble("AirPods Pro", 0)
+        };
+        let compact = compact(&device, &AlertConfig::default());
+        assert_eq!(compact.title, "AirPods Pro\n9%");
+        assert_eq!(compact.percent, Some(9));
+        assert_eq!(compact.severity, "critical");
+        assert_eq!(compact.color, "#FF3B30");
+    }
+
+    #[test]
+    fn test_format_status_lines() {
+        let devices = [
+            Device::test_ble("Magic Keyboard", 80),
+            Device::test_ble("MX Master 3", 15),
+            Device::test_ble("Sony WH-1000XM4 Headphones", 5),
+        ];
+        assert_eq!(
+            format_tmux(&devices, &AlertConfig::default(), &Icons::default()),
+            "⌨️ 80% #[fg=yellow]🔋 15%#[default] #[fg=red]🎧 5%#[default]"
+        );
+        assert_eq!(
+            format_prompt(&devices, &AlertConfig::default(), &Icons::default()),
+            "🔋 15% 🎧 5%"
+        );
+        assert_eq!(
+            format_prompt(&devices[..1], &AlertConfig::default(), &Icons::default()),
+            ""
+        );
+        assert_eq!(format_short(&devices), "80% 15% 5%");
+        let style = TextStyle {
+            icons: Some(Icons::default()),
+            ..TextStyle::default()
+        };
+        assert_eq!(
+            format_styled_text(&devices[..1], &Locale::default(), &style),
+            "⌨️ 🔋 Magic Keyboard: 80%"
+        );
+        assert_eq!(
+            worst(&devices).map(|(device, _)| device.name.as_str()),
+            Some("Sony WH-1000XM4 Headphones")
+        );
+        assert_eq!(format_menubar_title(&devices), "🎧 5%");
+        assert_eq!(format_menubar_title(&[]), "🔋 –");
+    }
+
+    #[test]
+    fn test_sort_devices() {
+        let mut devices = [
+            Device::test_ble("Magic Mouse", 40),
+            Device::test_ble("Pen", 0),
+            Device::test_ble("magic Keyboard", 76),
+            Device::test_ble("AirPods", 15),
+        ];
+        let names = |devices: &[Device]| -> Vec<String> {
+            devices.iter().map(|device| device.name.clone()).collect()
+        };
+
+        sort_devices(&mut devices, SortKey::Battery, false);
+        assert_eq!(
+            names(&devices),
+            ["AirPods", "Magic Mouse", "magic Keyboard", "Pen"]
+        );
+        sort_devices(&mut devices, SortKey::Name, true);
+        assert_eq!(
+            names(&devices),
+            ["Pen", "Magic Mouse", "magic Keyboard", "AirPods"]
+        );
+        sort_devices(&mut devices, SortKey::Type, false);
+        assert_eq!(
+            names(&devices),
+            ["AirPods", "magic Keyboard", "Magic Mouse", "Pen"]
+        );
+    }
+
+    #[test]
+    fn test_format_bars() {
+        let airpods = Device {
+            battery_left: BatteryLevel::new(48),
+            battery_right: BatteryLevel::new(100),
+            ..Device::test_classic("AirPods Pro", "aa:bb:cc:dd:ee:ff")
+        };
+        let style = TextStyle {
+            bars: Some(8),
+            ..TextStyle::default()
+        };
+        let devices = [airpods];
+        assert_eq!(
+            format_styled_text(&devices, &Locale::default(), &style),
+            "AirPods Pro: L:▮▮▮▮▯▯▯▯ 48% R:▮▮▮▮▮▮▮▮ 100%"
+        );
+        let alerts = AlertConfig::default();
+        let style = TextStyle {
+            colors: Some(&alerts),
+            ..TextStyle::default()
+        };
+        assert_eq!(
+            format_styled_text(&devices, &Locale::default(), &style),
+            "AirPods Pro: L:\x1b[32m48%\x1b[0m R:\x1b[32m100%\x1b[0m"
+        );
+        assert_eq!(bar(BatteryLevel::new(4).unwrap(), 10), "▯▯▯▯▯▯▯▯▯▯");
+        assert_eq!(bar(BatteryLevel::new(5).unwrap(), 10), "▮▯▯▯▯▯▯▯▯▯");
+    }
+
+    #[test]
+    fn test_format_status_line() {
+        let airpods = Device {
+            battery_left: BatteryLevel::new(15),
+            battery_right: BatteryLevel::new(40),
+            battery_case: BatteryLevel::new(60),
+            ..Device::test_classic("AirPods Pro", "aa:bb:cc:dd:ee:ff")
+        };
+        let keyboard = Device::test_ble("Magic Keyboard", 5);
+
+        let lowest_airpods = lowest(std::slice::from_ref(&airpods)).unwrap();
+        assert_eq!(format_status_line(&lowest_airpods), "AirPods Pro L:15%");
+        let devices = [airpods, keyboard];
+        assert_eq!(
+            format_status_line(&lowest(&devices).unwrap()),
+            "Magic Keyboard 5%"
+        );
+        assert_eq!(lowest(&[]), None);
+
+        let summary = summarize(&devices);
+        assert_eq!(summary.average, Some(10));
+        assert_eq!(
+            format_summary(&summary),
+            "2 devices, lowest Magic Keyboard 5%, average 10%"
+        );
+        assert_eq!(format_summary(&summarize(&[])), "0 devices");
+    }
+
+    #[test]
+    fn test_format_csv() {
+        let now = DateTime::parse_from_rfc3339("2026-10-15T09:30:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let keyboard = Device::test_ble("Desk, Keyboard", 76);
+        let airpods = Device {
+            battery_left: BatteryLevel::new(80),
+            battery_right: BatteryLevel::new(90),
+            freshness: Some(Freshness::new(now - chrono::Duration::minutes(5))),
+            ..Device::test_classic("AirPods Pro", "a4-c3-37-12-34-56")
+        };
+        assert_eq!(
+            format_csv(&[keyboard, airpods], now),
+            "name,address,source,level,left,right,case,timestamp\n\
+             \"Desk, Keyboard\",BLE,gatt,76,,,,2026-10-15T09:30:00Z\n\
+             AirPods Pro,a4-c3-37-12-34-56,classic,,80,90,,2026-10-15T09:25:00Z"
+        );
+        assert_eq!(format_csv(&[], now), CSV_HEADER);
+    }
+
+    #[test]
+    fn test_format_meta_json() {
+        let sources = BTreeMap::from([(
+            "gatt",
+            SourceStats {
+                readings: 1,
+                duration_ms: 40,
+            },
+        )]);
+        let meta = Meta {
+            timestamp: DateTime::parse_from_rfc3339("2026-10-15T09:30:00Z")
+                .unwrap()
+                .with_timezone(&Utc),
+            hostname: "studio".to_string(),
+            version: "1.2.3",
+            backend: Some("system"),
+            sources: &sources,
+            adapter: None,
+        };
+        let json: serde_json::Value =
+            serde_json::from_str(&format_meta_json(&meta, &[]).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "timestamp": "2026-10-15T09:30:00Z",
+                "hostname": "studio",
+                "version": "1.2.3",
+                "backend": "system",
+                "sources": { "gatt": { "readings": 1, "duration_ms": 40 } },
+                "devices": []
+            })
+        );
+    }
+
+    #[test]
+    fn test_format_ndjson() {
+        let now = DateTime::parse_from_rfc3339("2026-10-15T09:30:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let text = format_ndjson(
+            &[
+                Device::test_ble("Keyboard", 76),
+                Device::test_ble("Mouse", 40),
+            ],
+            now,
+            None,
+        )
+        .unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with(
+            r#"{"timestamp":"2026-10-15T09:30:00Z","schema_version":1,"name":"Keyboard","#
+        ));
+        let mouse: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(mouse["battery_level"], 40);
+        assert!(format_ndjson(&[], now, None).unwrap().is_empty());
+
+        // Streamed records carry the session and the device's seq
+        let mut sequences = Sequences::default();
+        let devices = [Device::test_ble("Keyboard", 76)];
+        sequences.update(&devices);
+        sequences.update(&[Device::test_ble("Keyboard", 75)]);
+        let text = format_ndjson(&devices, now, Some(&sequences)).unwrap();
+        let keyboard: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(keyboard["session_id"], sequences.session());
+        assert_eq!(keyboard["seq"], 2);
+    }
+
+    #[test]
+    fn test_format_table() {
+        let keyboard = Device::test_ble("Adv360 Pro (Home Office)", 76);
+        let airpods = Device {
+            battery_left: BatteryLevel::new(80),
+            battery_right: BatteryLevel::new(90),
+            battery_case: BatteryLevel::new(100),
+            ..Device::test_classic("AirPods Pro", "a4-c3-37-12-34-56")
+        };
+        let devices = [keyboard, airpods];
+
+        assert_eq!(
+            format_table(&devices, &Locale::default(), None, None),
+            "NAME                      TYPE        SOURCE   LEVEL  LEFT  RIGHT  CASE\n\
+             Adv360 Pro (Home Office)  other       gatt       76%     -      -     -\n\
+             AirPods Pro               headphones  classic      -   80%    90%  100%"
+        );
+        let narrow = format_table(&devices, &Locale::default(), Some(60), None);
+        let lines: Vec<&str> = narrow.lines().collect();
+        assert!(lines[1].starts_with("Adv360 Pro (…  other"));
+        assert!(lines.iter().all(|line| line.chars().count() <= 60));
+
+        let alerts = AlertConfig {
+            warn: 80,
+            ..AlertConfig::default()
+        };
+        let colored = format_table(&devices[1..], &Locale::default(), None, Some(&alerts));
+        assert!(colored.ends_with(
+            "classic      -  \x1b[33m 80%\x1b[0m  \x1b[32m  90%\x1b[0m  \x1b[32m100%\x1b[0m"
+        ));
+    }
+
+    #[test]
+    fn test_format_delta() {
+        let airpods = Device {
+            battery_left: BatteryLevel::new(80),
+            battery_right: BatteryLevel::new(90),
+            delta: BTreeMap::from([(Component::Left, -3), (Component::Right, 0)]),
+            ..Device::test_classic("AirPods Pro", "a4-c3-37-12-34-56")
+        };
+        let keyboard = Device {
+            delta: BTreeMap::from([(Component::Level, 2)]),
+            ..Device::test_ble("Keyboard", 76)
+        };
+        let devices = [airpods, keyboard];
+
+        assert_eq!(
+            format_text(&devices, &Locale::default()),
+            "AirPods Pro: L:80% ↓3 R:90% →\nKeyboard: 76% ↑2"
+        );
+        assert_eq!(
+            format_table(&devices, &Locale::default(), None, None),
+            "NAME         TYPE        SOURCE    LEVEL    LEFT  RIGHT  CASE\n\
+             AirPods Pro  headphones  classic       -  80% ↓3  90% →     -\n\
+             Keyboard     keyboard    gatt     76% ↑2       -      -     -"
+        );
+    }
+
+    #[test]
+    fn test_format_porcelain() {
+        let keyboard = Device::test_ble("Magic\tKeyboard", 76);
+        let airpods = Device {
+            battery_left: BatteryLevel::new(80),
+            battery_right: BatteryLevel::new(90),
+            ..Device::test_classic("AirPods Pro", "a4-c3-37-12-34-56")
+        };
+        assert_eq!(
+            format_porcelain(&[keyboard, airpods]),
+            "AirPods Pro\ta4-c3-37-12-34-56\tclassic\t\t80\t90\t\n\
+             Magic Keyboard\tBLE\tgatt\t76\t\t\t"
+        );
+    }
+
+    #[test]
+    fn test_format_markdown() {
+        let device = Device::test_ble("Desk | Keyboard", 76);
+        assert_eq!(
+            format_markdown(&[device], &Locale::default()),
+            "| Name | Type | Source | Level | Left | Right | Case |\n\
+             | --- | --- | --- | ---: | ---: | ---: | ---: |\n\
+             | Desk \\| Keyboard | keyboard | gatt | 76% | - | - | - |"
+        );
+    }
+
+    #[test]
+    fn test_write_atomic() {
+        let dir = std::env::temp_dir().join(format!("btmon-output-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("battery.txt");
+
+        write_atomic(&path, "15% 76%\n").unwrap();
+        write_atomic(&path, "80%\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "80%\n");
+        let entries: Vec<_> = fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(entries.len(), 1);
+
+        assert!(write_atomic(&dir.join("missing").join("battery.txt"), "").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
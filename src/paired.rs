@@ -0,0 +1,298 @@
+//! Paired devices, connected or not, with their last known battery
+//!
+//! A scan only sees connected devices. `system_profiler` lists every paired
+//! device, and each system scan stores its readings in a last-seen file in
+//! the cache directory, so `btmon list --all-paired` can show what a device
+//! reported before it disconnected.
+
+use crate::device::{Device, DeviceAddress};
+use crate::locale::Locale;
+use crate::output;
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A device paired with this Mac
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PairedDevice {
+    pub name: String,
+    pub address: Option<String>,
+    pub connected: bool,
+}
+
+/// Parse the paired devices from the JSON output of
+/// `system_profiler SPBluetoothDataType -json`, connected ones first
+pub fn parse_paired(json: &str) -> io::Result<Vec<PairedDevice>> {
+    let value: Value = serde_json::from_str(json)?;
+    let data = value
+        .get("SPBluetoothDataType")
+        .and_then(|data| data.get(0))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no Bluetooth data reported"))?;
+
+    let mut devices = Vec::new();
+    for (key, connected) in [("device_connected", true), ("device_not_connected", false)] {
+        // Each entry is a single-key object of the name and its properties
+        let entries = data.get(key).and_then(Value::as_array);
+        for entry in entries.into_iter().flatten().filter_map(Value::as_object) {
+            for (name, properties) in entry {
+                devices.push(PairedDevice {
+                    name: name.clone(),
+                    address: properties["device_address"].as_str().map(str::to_string),
+                    connected,
+                });
+            }
+        }
+    }
+    Ok(devices)
+}
+
+/// Key of an address, whatever its case and separators
+fn address_key(address: &str) -> String {
+    address.to_lowercase().replace(':', "-")
+}
+
+/// Key of a device: its address, or its name for BLE devices, whose address
+/// is not exposed
+fn device_key(device: &Device) -> String {
+    match &device.address {
+        DeviceAddress::Classic(address) => address_key(address),
+        DeviceAddress::Ble => format!("name:{}", device.name),
+    }
+}
+
+/// A device's last reading and when it was taken
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Seen {
+    pub seen_at: DateTime<Utc>,
+    pub device: Device,
+}
+
+/// Last reading of every device seen, keyed by address
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LastSeen {
+    pub devices: BTreeMap<String, Seen>,
+}
+
+impl LastSeen {
+    /// Location of the last-seen file in the cache directory
+    pub fn default_path() -> Option<PathBuf> {
+        crate::paths::cache_dir().map(|dir| dir.join("last_seen.json"))
+    }
+
+    /// Load the last-seen file, empty if there is none yet
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(data) => Ok(serde_json::from_str(&data)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Save the last-seen file, creating its directory if needed
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, format!("{}\n", serde_json::to_string(self)?))
+    }
+
+    /// Store the readings of the devices, seen at `at`
+    pub fn record(&mut self, devices: &[Device], at: DateTime<Utc>) {
+        for device in devices {
+            self.devices.insert(
+                device_key(device),
+                Seen {
+                    seen_at: at,
+                    device: device.clone(),
+                },
+            );
+        }
+    }
+
+    /// Lowest level of the last reading of the device named `name`
+    pub fn level(&self, name: &str) -> Option<u8> {
+        self.devices
+            .values()
+            .filter(|seen| seen.device.name == name)
+            .max_by_key(|seen| seen.seen_at)
+            .and_then(|seen| crate::history::lowest_level(&seen.device))
+    }
+
+    /// Last reading of a paired device, by address or else by name
+    fn find(&self, paired: &PairedDevice) -> Option<&Seen> {
+        paired
+            .address
+            .as_deref()
+            .and_then(|address| self.devices.get(&address_key(address)))
+            .or_else(|| self.devices.get(&format!("name:{}", paired.name)))
+    }
+}
+
+/// A paired device with its current or last known battery
+#[derive(Debug, Serialize)]
+pub struct Entry<'a> {
+    pub name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    pub connected: bool,
+    /// Current reading, or the last one while disconnected
+    pub battery: Option<&'a Device>,
+    /// When the last reading was taken, None for current ones
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+/// Combine the paired devices with the scanned ones and the last-seen
+/// readings; scanned devices that aren't paired (BLE devices paired to
+/// another app) come last
+pub fn entries<'a>(
+    paired: &'a [PairedDevice],
+    scanned: &'a [Device],
+    seen: &'a LastSeen,
+) -> Vec<Entry<'a>> {
+    // By name too, as BLE devices have no address
+    let matches = |paired: &PairedDevice, device: &Device| {
+        paired.name == device.name
+            || matches!((&paired.address, &device.address),
+                (Some(a), DeviceAddress::Classic(b)) if address_key(a) == address_key(b))
+    };
+
+    let mut entries: Vec<Entry> = paired
+        .iter()
+        .map(|paired| {
+            let current = scanned.iter().find(|device| matches(paired, device));
+            let last = current.is_none().then(|| seen.find(paired)).flatten();
+            Entry {
+                name: &paired.name,
+                address: paired.address.clone(),
+                connected: paired.connected || current.is_some(),
+                battery: current.or(last.map(|seen| &seen.device)),
+                last_seen: last.map(|seen| seen.seen_at),
+            }
+        })
+        .collect();
+    for device in scanned {
+        if !paired.iter().any(|paired| matches(paired, device)) {
+            entries.push(Entry {
+                name: &device.name,
+                address: match &device.address {
+                    DeviceAddress::Classic(address) => Some(address.clone()),
+                    DeviceAddress::Ble => None,
+                },
+                connected: true,
+                battery: Some(device),
+                last_seen: None,
+            });
+        }
+    }
+    entries
+}
+
+/// Format the entries as text, one line per device
+pub fn format_entries(entries: &[Entry], locale: &Locale) -> String {
+    entries
+        .iter()
+        .map(|entry| {
+            let line = match entry.battery {
+                Some(device) => output::format_device_output(device, locale),
+                None => format!("{}: no battery reading", entry.name),
+            };
+            match (entry.connected, entry.last_seen) {
+                (_, Some(at)) => format!(
+                    "{line} (last seen {})",
+                    locale.datetime(&at.with_timezone(&Local))
+                ),
+                (false, None) => format!("{line} (not connected)"),
+                (true, None) => line,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::BatteryLevel;
+
+    const SYSTEM_PROFILER: &str = r#"{
+      "SPBluetoothDataType" : [
+        {
+          "device_connected" : [
+            { "Keyboard" : { "device_address" : "11:22:33:44:55:66" } }
+          ],
+          "device_not_connected" : [
+            { "AirPods Pro" : { "device_address" : "AA:BB:CC:DD:EE:FF" } },
+            { "Mouse" : { "device_address" : "11:22:33:44:55:67" } }
+          ]
+        }
+      ]
+    }"#;
+
+    fn device(name: &str, address: DeviceAddress, level: u8) -> Device {
+        Device {
+            address,
+            ..Device::test_ble(name, level)
+        }
+    }
+
+    #[test]
+    fn test_parse_paired() {
+        let paired = parse_paired(SYSTEM_PROFILER).unwrap();
+        assert_eq!(paired.len(), 3);
+        assert_eq!(
+            paired[1],
+            PairedDevice {
+                name: "AirPods Pro".to_string(),
+                address: Some("AA:BB:CC:DD:EE:FF".to_string()),
+                connected: false,
+            }
+        );
+        assert!(
+            parse_paired(r#"{"SPBluetoothDataType": [{}]}"#)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_entries() {
+        let paired = parse_paired(SYSTEM_PROFILER).unwrap();
+        let scanned = [
+            device("Keyboard", DeviceAddress::Ble, 76),
+            device("Trackpad", DeviceAddress::Ble, 50),
+        ];
+        let at = DateTime::parse_from_rfc3339("2026-10-14T09:00:00Z")
+            .unwrap()
+            .to_utc();
+        let mut seen = LastSeen::default();
+        seen.record(
+            &[device(
+                "AirPods Pro",
+                DeviceAddress::Classic("aa-bb-cc-dd-ee-ff".to_string()),
+                40,
+            )],
+            at,
+        );
+
+        let entries = entries(&paired, &scanned, &seen);
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name).collect();
+        assert_eq!(names, ["Keyboard", "AirPods Pro", "Mouse", "Trackpad"]);
+        assert_eq!(entries[1].last_seen, Some(at));
+        assert_eq!(
+            entries[1].battery.and_then(|device| device.battery_level),
+            BatteryLevel::new(40)
+        );
+
+        let text = format_entries(&entries, &Locale::default());
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "Keyboard: 76%");
+        assert!(lines[1].starts_with("AirPods Pro: 40% (last seen "));
+        assert_eq!(lines[2], "Mouse: no battery reading (not connected)");
+        assert_eq!(lines[3], "Trackpad: 50%");
+    }
+}
@@ -0,0 +1,30 @@
+//! Locations of btmon's configuration and cache files
+//!
+//! Follows the XDG base directory variables when set, falling back to
+//! `~/.config/btmon` and `~/.cache/btmon`.
+
+use std::env;
+use std::path::PathBuf;
+
+fn base_dir(xdg_var: &str, fallback: &str) -> Option<PathBuf> {
+    env::var_os(xdg_var)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::home_dir().map(|home| home.join(fallback)))
+        .map(|dir| dir.join("btmon"))
+}
+
+/// Directory holding the configuration file
+pub fn config_dir() -> Option<PathBuf> {
+    base_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// Default configuration file path
+pub fn config_file() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("config.toml"))
+}
+
+/// Directory for state kept between runs
+pub fn cache_dir() -> Option<PathBuf> {
+    base_dir("XDG_CACHE_HOME", ".cache")
+}
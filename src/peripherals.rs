@@ -0,0 +1,86 @@
+//! Bonded BLE peripherals seen by earlier scans
+//!
+//! Core Bluetooth only lists peripherals that are connected right now, and a
+//! disconnected one can only be retrieved by its identifier. Every GATT scan
+//! remembers the identifiers of the peripherals it read, so that
+//! `--connect-if-needed` can later connect to the ones that went idle.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Peripheral identifiers by device name, kept between runs
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KnownPeripherals {
+    identifiers: BTreeMap<String, String>,
+}
+
+impl KnownPeripherals {
+    /// Load the state file, empty if it doesn't exist yet
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(data) => Ok(serde_json::from_str(&data)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Save the state file, creating its directory if needed
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, format!("{}\n", serde_json::to_string_pretty(self)?))
+    }
+
+    /// Identifiers of all known peripherals
+    pub fn identifiers(&self) -> Vec<String> {
+        self.identifiers.values().cloned().collect()
+    }
+
+    /// Remember the identifiers of peripherals that were read, returning
+    /// whether anything changed
+    pub fn learn<'a>(&mut self, seen: impl IntoIterator<Item = (&'a str, &'a str)>) -> bool {
+        let mut changed = false;
+        for (name, identifier) in seen {
+            if self.identifiers.get(name).map(String::as_str) != Some(identifier) {
+                self.identifiers
+                    .insert(name.to_string(), identifier.to_string());
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEYBOARD: &str = "2F1A6C8E-0B0D-4E4B-9C43-6E1D2A9F0C11";
+
+    #[test]
+    fn test_learn() {
+        let mut known = KnownPeripherals::default();
+        assert!(known.learn([("Keyboard", KEYBOARD)]));
+        assert!(!known.learn([("Keyboard", KEYBOARD)]));
+        assert!(known.learn([("Keyboard", "other")]));
+        assert_eq!(known.identifiers(), vec!["other".to_string()]);
+    }
+
+    #[test]
+    fn test_load_save() {
+        let dir = std::env::temp_dir().join(format!("btmon-peripherals-{}", std::process::id()));
+        let path = dir.join("peripherals.json");
+        assert_eq!(KnownPeripherals::load(&path).unwrap(), Default::default());
+
+        let mut known = KnownPeripherals::default();
+        known.learn([("Keyboard", KEYBOARD)]);
+        known.save(&path).unwrap();
+        assert_eq!(KnownPeripherals::load(&path).unwrap(), known);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,165 @@
+//! Device list queries for the HTTP API
+//!
+//! Clients can filter and trim the device list server-side, e.g.
+//! `GET /devices?name=airpods&below=20&fields=name,battery_left`, instead of
+//! pulling the full list and re-filtering it in every widget. `fields=`
+//! selects like `--fields`: the keys in the order given, null when a device
+//! doesn't have them.
+
+use crate::device::Device;
+use crate::fields::Fields;
+use crate::http;
+use crate::output::VersionedDevice;
+use serde_json::Value;
+
+/// Error for an invalid query string
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryError(pub String);
+
+impl std::fmt::Display for QueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid query: {}", self.0)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+/// Filters and field selection parsed from a query string
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceQuery {
+    /// Keep devices whose name contains this (case-insensitive)
+    pub name: Option<String>,
+    /// Keep devices with any component below this percentage
+    pub below: Option<u8>,
+    /// Keep only these fields in each device
+    pub fields: Option<Fields>,
+}
+
+impl DeviceQuery {
+    /// Parse a query string such as `name=airpods&below=20`
+    pub fn parse(query: &str) -> Result<Self, QueryError> {
+        let mut result = Self::default();
+
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = http::decode_query_component(value)
+                .ok_or_else(|| QueryError(format!("bad encoding in '{pair}'")))?;
+
+            match key {
+                "name" => result.name = Some(value.to_lowercase()),
+                "below" => {
+                    let below = value
+                        .parse::<u8>()
+                        .ok()
+                        .filter(|n| *n <= 100)
+                        .ok_or_else(|| QueryError(format!("below must be 0-100, got '{value}'")))?;
+                    result.below = Some(below);
+                }
+                "fields" => {
+                    let fields = value.parse().map_err(|e| QueryError(format!("{e}")))?;
+                    result.fields = Some(fields);
+                }
+                _ => return Err(QueryError(format!("unknown parameter '{key}'"))),
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Whether a device passes the filters
+    pub fn matches(&self, device: &Device) -> bool {
+        let name_ok = self
+            .name
+            .as_deref()
+            .is_none_or(|name| device.name.to_lowercase().contains(name));
+        let below_ok = self.below.is_none_or(|below| {
+            device
+                .components()
+                .any(|(_, level)| level.as_percentage() < below)
+        });
+        name_ok && below_ok
+    }
+
+    /// Filter the devices and select the requested fields, or else give
+    /// them as in the `--json` output
+    pub fn apply(&self, devices: &[Device]) -> serde_json::Result<Value> {
+        let mut result = Vec::new();
+
+        for device in devices.iter().filter(|d| self.matches(d)) {
+            let value = match &self.fields {
+                Some(fields) => Value::Object(fields.select(device)),
+                None => serde_json::to_value(VersionedDevice::from(device))?,
+            };
+            result.push(value);
+        }
+
+        Ok(Value::Array(result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::BatteryLevel;
+    use crate::output::format_json;
+    use crate::schema::SCHEMA_VERSION;
+    use serde_json::json;
+
+    fn devices() -> Vec<Device> {
+        vec![
+            Device::test_ble("Keyboard", 76),
+            Device {
+                battery_left: BatteryLevel::new(15),
+                battery_right: BatteryLevel::new(90),
+                ..Device::test_classic("AirPods Pro", "aa-bb-cc-dd-ee-ff")
+            },
+        ]
+    }
+
+    #[test]
+    fn test_parse() {
+        let query =
+            DeviceQuery::parse("name=AirPods+Pro&below=20&fields=name,battery_left").unwrap();
+        assert_eq!(query.name.as_deref(), Some("airpods pro"));
+        assert_eq!(query.below, Some(20));
+        assert_eq!(query.fields, Some("name,battery_left".parse().unwrap()));
+        assert_eq!(DeviceQuery::parse("").unwrap(), DeviceQuery::default());
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid() {
+        assert!(DeviceQuery::parse("below=101").is_err());
+        assert!(DeviceQuery::parse("below=low").is_err());
+        assert!(DeviceQuery::parse("fields=name,colour").is_err());
+        assert!(DeviceQuery::parse("fields=").is_err());
+        assert!(DeviceQuery::parse("sort=name").is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let query = DeviceQuery::parse("name=airpods&below=20&fields=name,battery_left").unwrap();
+        assert_eq!(
+            query.apply(&devices()).unwrap(),
+            json!([{ "name": "AirPods Pro", "battery_left": 15 }])
+        );
+
+        // Missing fields are null, as with --fields
+        let query = DeviceQuery::parse("name=keyboard&fields=battery_left,type").unwrap();
+        assert_eq!(
+            query.apply(&devices()).unwrap(),
+            json!([{ "battery_left": null, "type": "keyboard" }])
+        );
+
+        let query = DeviceQuery::parse("below=20").unwrap();
+        assert_eq!(
+            query.apply(&devices()).unwrap().as_array().unwrap().len(),
+            1
+        );
+
+        let query = DeviceQuery::default();
+        let all = query.apply(&devices()).unwrap();
+        let json: Value = serde_json::from_str(&format_json(&devices()).unwrap()).unwrap();
+        assert_eq!(all, json);
+        assert_eq!(all[0]["schema_version"], SCHEMA_VERSION);
+    }
+}
@@ -0,0 +1,68 @@
+//! Raycast script command output for `--format raycast`
+//!
+//! A script command in `inline` mode shows the first line of its output
+//! next to its title and refreshes it on its own schedule; in `fullOutput`
+//! mode all of it is shown. The first line is a compact summary of every
+//! device, coloured with ANSI codes when low, followed by one line per
+//! device.
+
+use crate::alerts::{AlertConfig, Severity};
+use crate::device::Device;
+use crate::locale::Locale;
+use crate::output::{device_icon, format_device_output};
+
+/// ANSI codes Raycast renders in inline output
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+/// Format the summary line followed by the device list
+pub fn format_raycast(devices: &[Device], alerts: &AlertConfig, locale: &Locale) -> String {
+    let summary: Vec<String> = devices
+        .iter()
+        .filter_map(|device| {
+            let level = device.effective().or(device.battery_case)?;
+            let segment = format!("{} {}", device_icon(device), locale.battery(level));
+            Some(match alerts.severity(level) {
+                Some(Severity::Critical) => format!("{RED}{segment}{RESET}"),
+                Some(Severity::Warning) => format!("{YELLOW}{segment}{RESET}"),
+                None => segment,
+            })
+        })
+        .collect();
+    if summary.is_empty() {
+        return "No devices with battery info".to_string();
+    }
+
+    let mut lines = vec![summary.join("  ")];
+    lines.extend(
+        devices
+            .iter()
+            .map(|device| format_device_output(device, locale)),
+    );
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_raycast() {
+        let devices = [
+            Device::test_ble("Magic Keyboard", 76),
+            Device::test_ble("Magic Mouse", 5),
+        ];
+        let alerts = AlertConfig::default();
+        assert_eq!(
+            format_raycast(&devices, &alerts, &Locale::default()),
+            "⌨️ 76%  \x1b[31m🖱️ 5%\x1b[0m\n\
+             Magic Keyboard: 76%\n\
+             Magic Mouse: 5%"
+        );
+        assert_eq!(
+            format_raycast(&[], &alerts, &Locale::default()),
+            "No devices with battery info"
+        );
+    }
+}
@@ -0,0 +1,151 @@
+//! Redaction of hardware addresses
+//!
+//! With `--redact`, the device-specific half of every Bluetooth address is
+//! masked in the output and in recordings, so snapshots can be shared without
+//! identifying the hardware. The first three octets (the manufacturer prefix)
+//! are kept, so vendors are still recognized. Core Bluetooth peripheral
+//! identifiers are masked the same way, keeping only their first group so
+//! the devices of one listing can still be told apart. The scan cache, the last-seen
+//! readings and the history keep the real addresses, which is what devices
+//! are matched by; they are masked when they are shown.
+
+use crate::backend::RawSnapshot;
+use crate::device::{Device, DeviceAddress};
+use crate::explore::Peripheral;
+use crate::nearby::NearbyDevice;
+
+/// Replacement for addresses that aren't six octets
+const REDACTED: &str = "redacted";
+
+/// Mask the last three octets of an address, e.g. `AA:BB:CC:XX:XX:XX`
+pub fn redact_address(address: &str) -> String {
+    let separator = if address.contains('-') { '-' } else { ':' };
+    let octets: Vec<&str> = address.split([':', '-']).collect();
+    // Only the kept octets need to be hex, so masking is idempotent
+    let valid = octets.len() == 6
+        && octets.iter().all(|octet| octet.len() == 2)
+        && octets[..3]
+            .iter()
+            .all(|octet| octet.chars().all(|c| c.is_ascii_hexdigit()));
+    if !valid {
+        return REDACTED.to_string();
+    }
+    let mut masked: Vec<&str> = octets[..3].to_vec();
+    masked.extend(["XX"; 3]);
+    masked.join(&separator.to_string())
+}
+
+/// Mask all but the first group of a peripheral identifier, e.g.
+/// `6E1F2A3B-XXXX-XXXX-XXXX-XXXXXXXXXXXX`
+pub fn redact_identifier(identifier: &str) -> String {
+    let groups: Vec<&str> = identifier.split('-').collect();
+    let lengths: Vec<usize> = groups.iter().map(|group| group.len()).collect();
+    // Only the kept group needs to be hex, so masking is idempotent
+    if lengths != [8, 4, 4, 4, 12] || !groups[0].chars().all(|c| c.is_ascii_hexdigit()) {
+        return REDACTED.to_string();
+    }
+    let mut masked = vec![groups[0].to_string()];
+    masked.extend(lengths[1..].iter().map(|&length| "X".repeat(length)));
+    masked.join("-")
+}
+
+/// Mask the identifiers of peripherals heard by `btmon scan`
+pub fn redact_nearby(devices: &mut [NearbyDevice]) {
+    for device in devices {
+        device.identifier = redact_identifier(&device.identifier);
+    }
+}
+
+/// Mask the identifier of a peripheral dumped by `btmon gatt`
+pub fn redact_peripheral(peripheral: &mut Peripheral) {
+    peripheral.identifier = redact_identifier(&peripheral.identifier);
+}
+
+/// Mask the addresses of devices, e.g. ones loaded from an earlier scan
+pub fn redact_devices(devices: &mut [Device]) {
+    for device in devices {
+        if let DeviceAddress::Classic(address) = &mut device.address {
+            *address = redact_address(address);
+        }
+    }
+}
+
+/// Mask the addresses of a session's readings before it is saved with
+/// `--record`
+pub fn redact_snapshot(snapshot: &mut RawSnapshot) {
+    for reading in &mut snapshot.classic {
+        reading.address = reading.address.as_deref().map(redact_address);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{ClassicReading, ReplayBackend};
+    use crate::device::collect_devices;
+
+    #[test]
+    fn test_redact_address() {
+        assert_eq!(redact_address("00:0a:95:12:34:56"), "00:0a:95:XX:XX:XX");
+        assert_eq!(redact_address("00-0A-95-12-34-56"), "00-0A-95-XX-XX-XX");
+        // Already masked addresses stay as they are
+        assert_eq!(redact_address("00:0a:95:XX:XX:XX"), "00:0a:95:XX:XX:XX");
+        assert_eq!(redact_address("unknown"), REDACTED);
+    }
+
+    #[test]
+    fn test_redact_identifier() {
+        let masked = "6E1F2A3B-XXXX-XXXX-XXXX-XXXXXXXXXXXX";
+        assert_eq!(
+            redact_identifier("6E1F2A3B-0000-4000-8000-00805F9B34FB"),
+            masked
+        );
+        assert_eq!(redact_identifier(masked), masked);
+        assert_eq!(redact_identifier("A"), REDACTED);
+
+        let mut devices = crate::nearby::collect(
+            vec![crate::nearby::Advertisement {
+                identifier: "6E1F2A3B-0000-4000-8000-00805F9B34FB".to_string(),
+                ..Default::default()
+            }],
+            &crate::manufacturer::Registry::default(),
+        );
+        redact_nearby(&mut devices);
+        let json = serde_json::to_value(&devices).unwrap();
+        assert_eq!(json[0]["identifier"], masked);
+
+        let mut peripheral = crate::explore::Peripheral {
+            name: "MX Master 3".to_string(),
+            identifier: "6E1F2A3B-0000-4000-8000-00805F9B34FB".to_string(),
+            services: Vec::new(),
+        };
+        redact_peripheral(&mut peripheral);
+        assert_eq!(
+            crate::explore::format_peripheral(&peripheral),
+            format!("MX Master 3 [{masked}]\n")
+        );
+    }
+
+    #[test]
+    fn test_redact_snapshot() {
+        let mut snapshot = RawSnapshot {
+            gatt: Vec::new(),
+            classic: vec![ClassicReading {
+                name: "AirPods Pro".to_string(),
+                address: Some("00:0a:95:12:34:56".to_string()),
+                battery_single: 80,
+                ..Default::default()
+            }],
+            adapter: None,
+        };
+        redact_snapshot(&mut snapshot);
+        let devices = collect_devices(&mut ReplayBackend(snapshot), None);
+
+        assert_eq!(
+            devices[0].address,
+            DeviceAddress::Classic("00:0a:95:XX:XX:XX".to_string())
+        );
+        // The manufacturer prefix still identifies the vendor
+        assert!(devices[0].vendor.is_some());
+    }
+}
@@ -0,0 +1,608 @@
+//! JSON-RPC 2.0 over stdio
+//!
+//! `btmon --stdio` keeps one process alive for editor plugins and desktop
+//! apps. Requests and responses are one JSON object per line. Methods:
+//!
+//! - `list_devices {name?, max_age?, compact?}`: all devices, optionally filtered
+//! - `get_device {name}`: one device by exact (case-insensitive) name
+//! - `subscribe {name?, interval?, compact?}`: `devices` notifications on change
+//!
+//! With `compact`, devices are sent as small payloads for Stream Deck keys
+//! (title, percent, severity and color) instead of full device objects.
+//! - `unsubscribe {subscription}`
+//!
+//! Scans are cached for `max_age` seconds (default 5) so concurrent clients
+//! don't each pay the scan latency.
+//!
+//! Every device carries a `seq` that increases each time its levels change,
+//! and notifications carry the server's `session` ID (see [`Sequences`]).
+
+use crate::backend::Backend;
+use crate::config::Config;
+use crate::device::{Device, collect_devices, hide_components, update_freshness};
+use crate::output;
+use crate::redact;
+use crate::sequence::Sequences;
+use crate::smoothing::SmoothingState;
+use chrono::Utc;
+use serde_json::{Value, json};
+use std::borrow::Cow;
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Default cache age for `list_devices` and `get_device`
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(5);
+
+/// Default polling interval of a subscription
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Shortest allowed subscription interval
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// Server-defined error: no device with the requested name
+const DEVICE_NOT_FOUND: i64 = -32001;
+
+/// A JSON-RPC error object
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn invalid_params(message: impl Into<String>) -> Self {
+        Self::new(INVALID_PARAMS, message)
+    }
+}
+
+/// A client subscription to device changes
+struct Subscription {
+    id: u64,
+    name: Option<String>,
+    compact: bool,
+    interval: Duration,
+    next_at: Instant,
+    /// Sequence numbers of the devices last sent, to only notify on change
+    last: Option<Vec<(String, u64)>>,
+}
+
+/// JSON-RPC request handler around a backend
+pub struct Server {
+    backend: Box<dyn Backend>,
+    config: Config,
+    smoothing: SmoothingState,
+    sequences: Sequences,
+    cache: Option<(Instant, Vec<Device>)>,
+    subscriptions: Vec<Subscription>,
+    next_subscription: u64,
+    redact: bool,
+}
+
+impl Server {
+    /// Create a server scanning with the given backend and applying the
+    /// per-device and smoothing settings of the configuration
+    pub fn new(backend: Box<dyn Backend>, config: Config) -> Self {
+        Self {
+            backend,
+            config,
+            smoothing: SmoothingState::default(),
+            sequences: Sequences::default(),
+            cache: None,
+            subscriptions: Vec::new(),
+            next_subscription: 1,
+            redact: false,
+        }
+    }
+
+    /// Mask the device addresses in responses, see [`redact`]
+    pub fn redact(mut self, enabled: bool) -> Self {
+        self.redact = enabled;
+        self
+    }
+
+    /// A device as clients see it, masked with `redact`
+    fn shown<'a>(&self, device: &'a Device) -> Cow<'a, Device> {
+        if self.redact {
+            let mut device = device.clone();
+            redact::redact_devices(std::slice::from_mut(&mut device));
+            Cow::Owned(device)
+        } else {
+            Cow::Borrowed(device)
+        }
+    }
+
+    /// Make sure the cached scan is no older than `max_age`
+    fn refresh(&mut self, max_age: Duration, now: Instant) {
+        let fresh = self
+            .cache
+            .as_ref()
+            .is_some_and(|(at, _)| now.saturating_duration_since(*at) <= max_age);
+        if !fresh {
+            debug!("Scanning for JSON-RPC client");
+            let mut devices = collect_devices(self.backend.as_mut(), None);
+            hide_components(&mut devices, &self.config.devices);
+            if self.config.smoothing.is_enabled() {
+                self.smoothing.apply(&mut devices, &self.config.smoothing);
+            }
+            self.sequences.update(&devices);
+            self.cache = Some((now, devices));
+        }
+        if let Some((_, devices)) = &mut self.cache {
+            update_freshness(devices, Utc::now(), self.config.stale_after());
+        }
+    }
+
+    /// Devices of the cached scan
+    fn cached(&self) -> &[Device] {
+        self.cache.as_ref().map_or(&[], |(_, devices)| devices)
+    }
+
+    /// Devices matching a name filter as JSON, each with its `seq`
+    fn devices_value(&self, devices: &[Device], name: Option<&str>, compact: bool) -> Vec<Value> {
+        devices
+            .iter()
+            .filter(|d| name.is_none_or(|n| d.name.to_lowercase().contains(n)))
+            .map(|d| {
+                if compact {
+                    self.compact_value(d)
+                } else {
+                    self.device_value(d)
+                }
+            })
+            .collect()
+    }
+
+    fn compact_value(&self, device: &Device) -> Value {
+        let device = self.shown(device);
+        let compact = output::compact(&device, &self.config.alerts);
+        let mut value = serde_json::to_value(compact).unwrap_or(Value::Null);
+        if let Value::Object(object) = &mut value {
+            object.insert("seq".to_string(), json!(self.sequences.seq(&device.name)));
+        }
+        value
+    }
+
+    fn device_value(&self, device: &Device) -> Value {
+        let mut value = serde_json::to_value(self.shown(device)).unwrap_or(Value::Null);
+        if let Value::Object(object) = &mut value {
+            object.insert("seq".to_string(), json!(self.sequences.seq(&device.name)));
+        }
+        value
+    }
+
+    /// Handle one request line, returning the response line if one is due
+    pub fn handle(&mut self, line: &str, now: Instant) -> Option<String> {
+        let request: Value = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(e) => {
+                return Some(error_response(
+                    Value::Null,
+                    RpcError::new(PARSE_ERROR, e.to_string()),
+                ));
+            }
+        };
+
+        let id = request.get("id").cloned();
+        let result = self.dispatch(&request, now);
+
+        // Requests without an id are notifications and get no response
+        let id = id?;
+        Some(match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}).to_string(),
+            Err(error) => error_response(id, error),
+        })
+    }
+
+    fn dispatch(&mut self, request: &Value, now: Instant) -> Result<Value, RpcError> {
+        if request.get("jsonrpc").and_then(Value::as_str) != Some("2.0") {
+            return Err(RpcError::new(INVALID_REQUEST, "expected jsonrpc 2.0"));
+        }
+        let Some(method) = request.get("method").and_then(Value::as_str) else {
+            return Err(RpcError::new(INVALID_REQUEST, "missing method"));
+        };
+        let params = match request.get("params") {
+            None | Some(Value::Null) => &Value::Null,
+            Some(params @ Value::Object(_)) => params,
+            Some(_) => return Err(RpcError::invalid_params("params must be an object")),
+        };
+
+        match method {
+            "list_devices" => {
+                let name = string_param(params, "name")?.map(|n| n.to_lowercase());
+                let max_age = seconds_param(params, "max_age")?.unwrap_or(DEFAULT_MAX_AGE);
+                let compact = bool_param(params, "compact")?;
+                self.refresh(max_age, now);
+                Ok(Value::Array(self.devices_value(
+                    self.cached(),
+                    name.as_deref(),
+                    compact,
+                )))
+            }
+            "get_device" => {
+                let name = string_param(params, "name")?
+                    .ok_or_else(|| RpcError::invalid_params("missing name"))?;
+                let max_age = seconds_param(params, "max_age")?.unwrap_or(DEFAULT_MAX_AGE);
+                self.refresh(max_age, now);
+                self.cached()
+                    .iter()
+                    .find(|d| d.name.eq_ignore_ascii_case(&name))
+                    .map(|d| self.device_value(d))
+                    .ok_or_else(|| {
+                        RpcError::new(DEVICE_NOT_FOUND, format!("no device named '{name}'"))
+                    })
+            }
+            "subscribe" => {
+                let name = string_param(params, "name")?.map(|n| n.to_lowercase());
+                let compact = bool_param(params, "compact")?;
+                let interval = seconds_param(params, "interval")?
+                    .unwrap_or(DEFAULT_INTERVAL)
+                    .max(MIN_INTERVAL);
+                let id = self.next_subscription;
+                self.next_subscription += 1;
+                self.subscriptions.push(Subscription {
+                    id,
+                    name,
+                    compact,
+                    interval,
+                    next_at: now,
+                    last: None,
+                });
+                Ok(json!({"subscription": id, "session": self.sequences.session()}))
+            }
+            "unsubscribe" => {
+                let id = params
+                    .get("subscription")
+                    .and_then(Value::as_u64)
+                    .ok_or_else(|| RpcError::invalid_params("missing subscription"))?;
+                let before = self.subscriptions.len();
+                self.subscriptions.retain(|s| s.id != id);
+                Ok(json!(self.subscriptions.len() < before))
+            }
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method '{method}'"),
+            )),
+        }
+    }
+
+    /// Run due subscriptions, returning the notification lines to send
+    pub fn poll(&mut self, now: Instant) -> Vec<String> {
+        let due: Vec<usize> = (0..self.subscriptions.len())
+            .filter(|&i| self.subscriptions[i].next_at <= now)
+            .collect();
+        if due.is_empty() {
+            return Vec::new();
+        }
+
+        // One scan serves every subscription due now
+        let shortest = due
+            .iter()
+            .map(|&i| self.subscriptions[i].interval)
+            .min()
+            .unwrap_or(DEFAULT_INTERVAL);
+        let max_age = shortest.min(DEFAULT_MAX_AGE);
+        self.refresh(max_age, now);
+
+        let mut notifications = Vec::new();
+        for i in due {
+            let subscription = &self.subscriptions[i];
+            let devices = self.devices_value(
+                self.cached(),
+                subscription.name.as_deref(),
+                subscription.compact,
+            );
+            let sequences: Vec<(String, u64)> = devices
+                .iter()
+                .map(|d| {
+                    let name = d["name"].as_str().unwrap_or_default().to_string();
+                    (name, d["seq"].as_u64().unwrap_or_default())
+                })
+                .collect();
+
+            let subscription = &mut self.subscriptions[i];
+            subscription.next_at = now + subscription.interval;
+            if subscription.last.as_ref() == Some(&sequences) {
+                continue;
+            }
+            notifications.push(
+                json!({
+                    "jsonrpc": "2.0",
+                    "method": "devices",
+                    "params": {
+                        "subscription": subscription.id,
+                        "session": self.sequences.session(),
+                        "devices": devices,
+                    },
+                })
+                .to_string(),
+            );
+            subscription.last = Some(sequences);
+        }
+        notifications
+    }
+
+    /// When the next subscription is due
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.subscriptions.iter().map(|s| s.next_at).min()
+    }
+}
+
+fn error_response(id: Value, error: RpcError) -> String {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {"code": error.code, "message": error.message},
+    })
+    .to_string()
+}
+
+fn bool_param(params: &Value, key: &str) -> Result<bool, RpcError> {
+    match params.get(key) {
+        None | Some(Value::Null) => Ok(false),
+        Some(Value::Bool(b)) => Ok(*b),
+        Some(_) => Err(RpcError::invalid_params(format!("{key} must be a boolean"))),
+    }
+}
+
+fn string_param(params: &Value, key: &str) -> Result<Option<String>, RpcError> {
+    match params.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s.clone())),
+        Some(_) => Err(RpcError::invalid_params(format!("{key} must be a string"))),
+    }
+}
+
+fn seconds_param(params: &Value, key: &str) -> Result<Option<Duration>, RpcError> {
+    match params.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => value
+            .as_f64()
+            .filter(|s| s.is_finite() && *s >= 0.0)
+            .map(|s| Some(Duration::from_secs_f64(s)))
+            .ok_or_else(|| RpcError::invalid_params(format!("{key} must be a number of seconds"))),
+    }
+}
+
+/// Serve JSON-RPC requests from `input` until it is closed.
+///
+/// Input is read on a separate thread so subscriptions keep being served
+/// while no request arrives.
+pub fn serve<R, W>(mut server: Server, input: R, mut output: W) -> io::Result<()>
+where
+    R: BufRead + Send + 'static,
+    W: Write,
+{
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in input.lines() {
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    loop {
+        let received = match server.next_deadline() {
+            Some(deadline) => rx.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+            None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        match received {
+            Ok(line) => {
+                let line = line?;
+                if !line.trim().is_empty()
+                    && let Some(response) = server.handle(&line, Instant::now())
+                {
+                    writeln!(output, "{response}")?;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+
+        for notification in server.poll(Instant::now()) {
+            writeln!(output, "{notification}")?;
+        }
+        output.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{ClassicReading, GattReading, RawSnapshot, ReplayBackend};
+
+    fn server() -> Server {
+        let backend = ReplayBackend(RawSnapshot {
+            gatt: vec![
+                GattReading {
+                    name: "Keyboard".to_string(),
+                    battery_level: 76,
+                    read_at: None,
+                },
+                GattReading {
+                    name: "Mouse".to_string(),
+                    battery_level: 40,
+                    read_at: None,
+                },
+            ],
+            classic: Vec::new(),
+            adapter: None,
+        });
+        Server::new(Box::new(backend), Config::default())
+    }
+
+    fn call(server: &mut Server, request: &str) -> Value {
+        serde_json::from_str(&server.handle(request, Instant::now()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_list_and_get_devices() {
+        let mut server = server();
+
+        let response = call(
+            &mut server,
+            r#"{"jsonrpc":"2.0","id":1,"method":"list_devices","params":{"name":"KEY"}}"#,
+        );
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["result"][0]["name"], "Keyboard");
+        assert_eq!(response["result"].as_array().unwrap().len(), 1);
+
+        let response = call(
+            &mut server,
+            r#"{"jsonrpc":"2.0","id":"a","method":"get_device","params":{"name":"mouse"}}"#,
+        );
+        assert_eq!(response["result"]["battery_level"], 40);
+
+        let response = call(
+            &mut server,
+            r#"{"jsonrpc":"2.0","id":2,"method":"get_device","params":{"name":"Trackpad"}}"#,
+        );
+        assert_eq!(response["error"]["code"], DEVICE_NOT_FOUND);
+
+        let response = call(
+            &mut server,
+            r#"{"jsonrpc":"2.0","id":3,"method":"list_devices","params":{"name":"mouse","compact":true}}"#,
+        );
+        assert_eq!(
+            response["result"][0],
+            json!({
+                "name": "Mouse",
+                "title": "Mouse\n40%",
+                "percent": 40,
+                "severity": "ok",
+                "color": "#34C759",
+                "seq": 1,
+            })
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut server = server();
+        assert_eq!(call(&mut server, "{")["error"]["code"], PARSE_ERROR);
+        assert_eq!(
+            call(&mut server, r#"{"id":1,"method":"list_devices"}"#)["error"]["code"],
+            INVALID_REQUEST
+        );
+        assert_eq!(
+            call(&mut server, r#"{"jsonrpc":"2.0","id":1,"method":"scan"}"#)["error"]["code"],
+            METHOD_NOT_FOUND
+        );
+        assert_eq!(
+            call(
+                &mut server,
+                r#"{"jsonrpc":"2.0","id":1,"method":"get_device","params":{}}"#
+            )["error"]["code"],
+            INVALID_PARAMS
+        );
+
+        // Notifications get no response
+        assert!(
+            server
+                .handle(
+                    r#"{"jsonrpc":"2.0","method":"list_devices"}"#,
+                    Instant::now()
+                )
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_subscribe_notifies_on_change_only() {
+        let mut server = server();
+        let start = Instant::now();
+
+        let response = server
+            .handle(
+                r#"{"jsonrpc":"2.0","id":1,"method":"subscribe","params":{"interval":10}}"#,
+                start,
+            )
+            .unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        let id = response["result"]["subscription"].as_u64().unwrap();
+
+        let notifications = server.poll(start);
+        assert_eq!(notifications.len(), 1);
+        let notification: Value = serde_json::from_str(&notifications[0]).unwrap();
+        assert_eq!(notification["method"], "devices");
+        assert_eq!(notification["params"]["subscription"], id);
+
+        // Not due yet, then due but unchanged
+        assert!(server.poll(start + Duration::from_secs(5)).is_empty());
+        assert!(server.poll(start + Duration::from_secs(10)).is_empty());
+        assert_eq!(
+            server.next_deadline(),
+            Some(start + Duration::from_secs(20))
+        );
+
+        let response = call(
+            &mut server,
+            &format!(
+                r#"{{"jsonrpc":"2.0","id":2,"method":"unsubscribe","params":{{"subscription":{id}}}}}"#
+            ),
+        );
+        assert_eq!(response["result"], true);
+        assert_eq!(server.next_deadline(), None);
+    }
+
+    /// Backend reporting a keyboard whose level drops every other scan
+    struct Draining(u8);
+
+    impl Backend for Draining {
+        fn gatt_readings(&mut self) -> Vec<GattReading> {
+            self.0 += 1;
+            vec![GattReading {
+                name: "Keyboard".to_string(),
+                battery_level: 80 - self.0 / 2,
+                read_at: None,
+            }]
+        }
+
+        fn classic_readings(&mut self) -> Vec<ClassicReading> {
+            Vec::new()
+        }
+    }
+
+    #[test]
+    fn test_sequence_numbers() {
+        let mut server = Server::new(Box::new(Draining(0)), Config::default());
+        let start = Instant::now();
+        server.handle(
+            r#"{"jsonrpc":"2.0","id":1,"method":"subscribe","params":{"interval":10}}"#,
+            start,
+        );
+
+        let notify = |server: &mut Server, secs| -> Option<Value> {
+            let notifications = server.poll(start + Duration::from_secs(secs));
+            notifications
+                .first()
+                .map(|n| serde_json::from_str(n).unwrap())
+        };
+
+        // Scans report 80, 79, 79, 78: unchanged levels keep their seq
+        let first = notify(&mut server, 0).unwrap();
+        assert_eq!(first["params"]["devices"][0]["seq"], 1);
+        let second = notify(&mut server, 10).unwrap();
+        assert_eq!(second["params"]["devices"][0]["seq"], 2);
+        assert!(notify(&mut server, 20).is_none());
+        let third = notify(&mut server, 30).unwrap();
+        assert_eq!(third["params"]["devices"][0]["battery_level"], 78);
+        assert_eq!(third["params"]["devices"][0]["seq"], 3);
+        assert_eq!(first["params"]["session"], third["params"]["session"]);
+    }
+}
@@ -0,0 +1,147 @@
+//! JSON Schema of the `--json` output for `btmon schema`
+//!
+//! Every device object carries `schema_version`. It is raised whenever a
+//! field is removed, renamed or changes type; new optional fields don't
+//! raise it, so consumers should ignore keys they don't know.
+
+use serde_json::{Value, json};
+
+/// Version of the `--json` device objects
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// JSON Schema (draft 2020-12) of the `--json` output: an array of devices,
+/// an object with the adapter status and the devices when Bluetooth can't
+/// be used, or the envelope of `--with-meta`
+pub fn schema() -> Value {
+    let level = json!({ "type": "integer", "minimum": 1, "maximum": 100 });
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "btmon --json output",
+        "oneOf": [
+            { "type": "array", "items": { "$ref": "#/$defs/device" } },
+            {
+                "type": "object",
+                "required": ["adapter", "devices"],
+                "properties": {
+                    "adapter": { "$ref": "#/$defs/adapter" },
+                    "devices": { "type": "array", "items": { "$ref": "#/$defs/device" } }
+                }
+            },
+            {
+                "type": "object",
+                "required": ["timestamp", "hostname", "version", "sources", "devices"],
+                "properties": {
+                    "timestamp": { "type": "string", "format": "date-time" },
+                    "hostname": { "type": "string" },
+                    "version": { "type": "string" },
+                    "backend": { "enum": ["system", "replay", "cache", "daemon"] },
+                    "sources": {
+                        "type": "object",
+                        "additionalProperties": {
+                            "type": "object",
+                            "properties": {
+                                "readings": { "type": "integer", "minimum": 0 },
+                                "duration_ms": { "type": "integer", "minimum": 0 }
+                            }
+                        }
+                    },
+                    "adapter": { "$ref": "#/$defs/adapter" },
+                    "devices": { "type": "array", "items": { "$ref": "#/$defs/device" } }
+                }
+            }
+        ],
+        "$defs": {
+            "device": {
+                "type": "object",
+                "required": ["schema_version", "name", "address"],
+                "properties": {
+                    "schema_version": { "const": SCHEMA_VERSION },
+                    "name": { "type": "string" },
+                    "address": {
+                        "type": "string",
+                        "description": "Classic address, or BLE for Bluetooth LE devices"
+                    },
+                    "vendor": { "type": "string" },
+                    "battery_level": level,
+                    "battery_left": level,
+                    "battery_right": level,
+                    "battery_case": level,
+                    "effective": {
+                        "description": "Single battery, or the lower earbud of multi-battery devices",
+                        "type": "integer",
+                        "minimum": 1,
+                        "maximum": 100
+                    },
+                    "raw": {
+                        "description": "Levels as read, for components whose level was smoothed",
+                        "type": "object",
+                        "propertyNames": { "enum": ["level", "left", "right", "case"] },
+                        "additionalProperties": level
+                    },
+                    "delta": {
+                        "description": "Change of each component's level since the previous pass of --watch",
+                        "type": "object",
+                        "propertyNames": { "enum": ["level", "left", "right", "case"] },
+                        "additionalProperties": { "type": "integer", "minimum": -99, "maximum": 99 }
+                    },
+                    "read_at": { "type": "string", "format": "date-time" },
+                    "age_seconds": { "type": "integer", "minimum": 0 },
+                    "stale": { "type": "boolean" }
+                }
+            },
+            "adapter": {
+                "type": "object",
+                "required": ["state", "powered", "authorized"],
+                "properties": {
+                    "state": {
+                        "enum": [
+                            "unknown",
+                            "resetting",
+                            "unsupported",
+                            "unauthorized",
+                            "powered_off",
+                            "powered_on"
+                        ]
+                    },
+                    "powered": { "type": "boolean" },
+                    "authorized": { "type": "boolean" }
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::{BatteryLevel, Component, Device, Freshness};
+    use crate::output::format_json;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_schema_covers_output() {
+        let device = Device {
+            battery_level: BatteryLevel::new(50),
+            vendor: Some("Apple".to_string()),
+            battery_left: BatteryLevel::new(80),
+            battery_right: BatteryLevel::new(90),
+            battery_case: BatteryLevel::new(100),
+            raw: BTreeMap::from([(Component::Left, BatteryLevel::new(78).unwrap())]),
+            delta: BTreeMap::from([(Component::Left, -2)]),
+            freshness: Some(Freshness {
+                read_at: chrono::Utc::now(),
+                age_seconds: 5,
+                stale: false,
+            }),
+            ..Device::test_classic("AirPods Pro", "a4-c3-37-12-34-56")
+        };
+        let output: Value = serde_json::from_str(&format_json(&[device]).unwrap()).unwrap();
+        let schema = schema();
+        let properties = schema["$defs"]["device"]["properties"].as_object().unwrap();
+        let keys = output[0].as_object().unwrap().keys();
+        for key in keys {
+            assert!(properties.contains_key(key), "{key} missing from schema");
+        }
+        assert_eq!(output[0]["schema_version"], SCHEMA_VERSION);
+    }
+}
@@ -0,0 +1,94 @@
+//! Sequence numbers of streamed devices
+//!
+//! Streams (`btmon --stdio` subscriptions, `--watch --format ndjson` and the
+//! daemon's event socket) give every device a `seq` that increases each time
+//! its levels change, and tag their records with the ID of the process that
+//! sent them. A consumer can then notice updates it missed, and tell a
+//! restarted btmon, where `seq` starts over, from an old one.
+
+use crate::device::{BatteryLevel, Component, Device};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Per-device update counters of one stream
+#[derive(Debug)]
+pub struct Sequences {
+    session: String,
+    devices: HashMap<String, Sequence>,
+}
+
+#[derive(Debug)]
+struct Sequence {
+    seq: u64,
+    levels: Vec<(Component, BatteryLevel)>,
+}
+
+impl Default for Sequences {
+    fn default() -> Self {
+        Self {
+            session: session_id(),
+            devices: HashMap::new(),
+        }
+    }
+}
+
+impl Sequences {
+    /// ID of this process, the same for all its streams
+    pub fn session(&self) -> &str {
+        &self.session
+    }
+
+    /// Bump the sequence number of every device whose levels changed
+    pub fn update(&mut self, devices: &[Device]) {
+        for device in devices {
+            let levels: Vec<_> = device.components().collect();
+            match self.devices.get_mut(&device.name) {
+                Some(sequence) if sequence.levels == levels => {}
+                Some(sequence) => {
+                    sequence.seq += 1;
+                    sequence.levels = levels;
+                }
+                None => {
+                    self.devices
+                        .insert(device.name.clone(), Sequence { seq: 1, levels });
+                }
+            }
+        }
+    }
+
+    /// Sequence number of a device, 0 if it was never seen
+    pub fn seq(&self, name: &str) -> u64 {
+        self.devices.get(name).map_or(0, |s| s.seq)
+    }
+}
+
+/// ID distinguishing this process from earlier ones
+fn session_id() -> String {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis());
+    format!("{millis:x}-{:x}", std::process::id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequences() {
+        let mut sequences = Sequences::default();
+        assert_eq!(sequences.seq("Keyboard"), 0);
+
+        // 80, 80, 79: an unchanged level keeps its seq
+        for (level, seq) in [(80, 1), (80, 1), (79, 2)] {
+            sequences.update(&[Device::test_ble("Keyboard", level)]);
+            assert_eq!(sequences.seq("Keyboard"), seq);
+        }
+        // A device missing from a pass keeps its seq
+        sequences.update(&[Device::test_ble("Mouse", 50)]);
+        assert_eq!((sequences.seq("Keyboard"), sequences.seq("Mouse")), (2, 1));
+
+        let session = sequences.session();
+        assert!(session.ends_with(&format!("-{:x}", std::process::id())));
+    }
+}
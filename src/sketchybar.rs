@@ -0,0 +1,102 @@
+//! SketchyBar output for `--format sketchybar`
+//!
+//! A SketchyBar item plugin sets its item's properties with
+//! `sketchybar --set $NAME key=value ...`. This format prints those
+//! properties for the lowest battery, shell-quoted so the plugin can pass
+//! them on with `eval`, and hides the item when there is nothing to show.
+
+use crate::alerts::{AlertConfig, Severity};
+use crate::device::Device;
+use crate::locale::Locale;
+use crate::output::{self, device_icon, device_kind};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Default colors, as SketchyBar's 0xAARRGGBB
+const NORMAL_COLOR: &str = "0xffffffff";
+const WARNING_COLOR: &str = "0xffeed49f";
+const CRITICAL_COLOR: &str = "0xffed8796";
+
+/// `[sketchybar]` configuration section
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SketchybarConfig {
+    /// Icons by device type (`headphones`, `keyboard`, `mouse`,
+    /// `controller`, `other`), e.g. Nerd Font glyphs; emoji by default
+    pub icons: BTreeMap<String, String>,
+    /// Color above the warning threshold
+    pub normal_color: Option<String>,
+    /// Color at or below the warning threshold
+    pub warning_color: Option<String>,
+    /// Color at or below the critical threshold
+    pub critical_color: Option<String>,
+}
+
+/// Quote a value for a POSIX shell
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Format the item properties for the lowest battery
+pub fn format_sketchybar(
+    devices: &[Device],
+    alerts: &AlertConfig,
+    config: &SketchybarConfig,
+) -> String {
+    let Some(lowest) = output::lowest(devices) else {
+        return "drawing=off".to_string();
+    };
+    let Some(device) = devices.iter().find(|device| device.name == lowest.name) else {
+        return "drawing=off".to_string();
+    };
+
+    let icon = config
+        .icons
+        .get(device_kind(device))
+        .map_or_else(|| device_icon(device), String::as_str);
+    let level = Locale::default().battery(lowest.level);
+    let label = match lowest.component.label() {
+        Some(component) => format!("{component}:{level}"),
+        None => level,
+    };
+    let color = match alerts.severity(lowest.level) {
+        Some(Severity::Critical) => config.critical_color.as_deref().unwrap_or(CRITICAL_COLOR),
+        Some(Severity::Warning) => config.warning_color.as_deref().unwrap_or(WARNING_COLOR),
+        None => config.normal_color.as_deref().unwrap_or(NORMAL_COLOR),
+    };
+    format!(
+        "drawing=on icon={} label={} icon.color={color} label.color={color}",
+        quote(icon),
+        quote(&label)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_sketchybar() {
+        let alerts = AlertConfig::default();
+        let mut config = SketchybarConfig::default();
+        let devices = [
+            Device::test_ble("Magic Keyboard", 76),
+            Device::test_ble("Magic Mouse", 8),
+        ];
+
+        assert_eq!(
+            format_sketchybar(&devices, &alerts, &config),
+            "drawing=on icon='🖱️' label='8%' icon.color=0xffed8796 label.color=0xffed8796"
+        );
+
+        config.icons.insert("mouse".to_string(), "󰍽".to_string());
+        config.normal_color = Some("0xff00ff00".to_string());
+        assert_eq!(
+            format_sketchybar(&devices[..1], &alerts, &config),
+            "drawing=on icon='⌨️' label='76%' icon.color=0xff00ff00 label.color=0xff00ff00"
+        );
+        assert!(format_sketchybar(&devices[1..], &alerts, &config).contains("icon='󰍽'"));
+        assert_eq!(format_sketchybar(&[], &alerts, &config), "drawing=off");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+    }
+}
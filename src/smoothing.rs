@@ -0,0 +1,183 @@
+//! Smoothing of jittery battery readings
+//!
+//! Some devices report levels that bounce between reads (78, 82, 77). With
+//! smoothing enabled, each component's level is replaced by the median of its
+//! last readings and can be held from rising by less than a charging step.
+//! The readings are persisted between runs like the alert state, and the
+//! values as read stay available in the device's `raw` field.
+
+use crate::alerts::state_key;
+use crate::device::{BatteryLevel, Component, Device};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// `[smoothing]` configuration section
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SmoothingConfig {
+    /// Number of readings to take the median of, 1 to disable
+    pub window: usize,
+    /// Hold levels from rising while discharging
+    pub monotonic: bool,
+    /// Smallest rise that counts as charging rather than jitter
+    pub charge_step: u8,
+}
+
+impl Default for SmoothingConfig {
+    fn default() -> Self {
+        Self {
+            window: 1,
+            monotonic: false,
+            charge_step: 5,
+        }
+    }
+}
+
+impl SmoothingConfig {
+    /// Whether any smoothing is configured
+    pub fn is_enabled(&self) -> bool {
+        self.window > 1 || self.monotonic
+    }
+}
+
+/// Recent readings of one component
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct History {
+    /// Raw levels, oldest first
+    samples: Vec<u8>,
+    /// Smoothed level reported last time
+    last: Option<u8>,
+}
+
+/// Reading history kept between runs
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SmoothingState {
+    components: BTreeMap<String, History>,
+}
+
+impl SmoothingState {
+    /// Load the state file, empty if it doesn't exist yet
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(data) => Ok(serde_json::from_str(&data)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Save the state file, creating its directory if needed
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, format!("{}\n", serde_json::to_string_pretty(self)?))
+    }
+
+    /// Record the devices' readings and replace their levels with smoothed
+    /// ones, keeping the raw level of every component that changed
+    pub fn apply(&mut self, devices: &mut [Device], config: &SmoothingConfig) {
+        let window = config.window.max(1);
+
+        for device in devices.iter_mut() {
+            for component in Component::ALL {
+                let Some(level) = device.battery(component) else {
+                    continue;
+                };
+                let raw = level.as_percentage();
+                let history = self
+                    .components
+                    .entry(state_key(&device.name, component))
+                    .or_default();
+
+                history.samples.push(raw);
+                let excess = history.samples.len().saturating_sub(window);
+                history.samples.drain(..excess);
+
+                let mut sorted = history.samples.clone();
+                sorted.sort_unstable();
+                let mut smoothed = sorted[(sorted.len() - 1) / 2];
+
+                if config.monotonic
+                    && let Some(last) = history.last
+                    && smoothed > last
+                    && smoothed - last < config.charge_step
+                {
+                    smoothed = last;
+                }
+                history.last = Some(smoothed);
+
+                if smoothed != raw {
+                    device.raw.insert(component, level);
+                    *device.battery_mut(component) = BatteryLevel::new(smoothed);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyboard(level: u8) -> Device {
+        Device::test_ble("Keyboard", level)
+    }
+
+    fn run(state: &mut SmoothingState, config: &SmoothingConfig, levels: &[u8]) -> Vec<u8> {
+        levels
+            .iter()
+            .map(|level| {
+                let mut devices = vec![keyboard(*level)];
+                state.apply(&mut devices, config);
+                devices[0].battery_level.unwrap().as_percentage()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_median() {
+        let config = SmoothingConfig {
+            window: 3,
+            ..Default::default()
+        };
+        let mut state = SmoothingState::default();
+        assert_eq!(
+            run(&mut state, &config, &[78, 82, 77, 76]),
+            [78, 78, 78, 77]
+        );
+
+        let mut devices = vec![keyboard(90)];
+        state.apply(&mut devices, &config);
+        assert_eq!(devices[0].battery_level, BatteryLevel::new(77));
+        assert_eq!(
+            devices[0].raw[&Component::Level],
+            BatteryLevel::new(90).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_monotonic() {
+        let config = SmoothingConfig {
+            monotonic: true,
+            ..Default::default()
+        };
+        let mut state = SmoothingState::default();
+        // Small rises are held, a rise by the charge step is charging
+        assert_eq!(
+            run(&mut state, &config, &[78, 82, 77, 79, 85]),
+            [78, 78, 77, 77, 85]
+        );
+    }
+
+    #[test]
+    fn test_disabled_by_default() {
+        let config = SmoothingConfig::default();
+        assert!(!config.is_enabled());
+        let mut state = SmoothingState::default();
+        assert_eq!(run(&mut state, &config, &[78, 82, 77]), [78, 82, 77]);
+    }
+}
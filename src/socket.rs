@@ -0,0 +1,315 @@
+//! Daemon socket
+//!
+//! `btmon daemon` keeps its latest scan and hands it out on a Unix socket in
+//! the cache directory, so other invocations answer right away instead of
+//! running a scan of their own. A client connects and reads one JSON
+//! [`ScanCache`]; the daemon closes the connection after writing it. The
+//! answer also carries `backend_restarts`, the number of times the daemon's
+//! watchdog recreated the backend (see [`crate::watchdog`]).
+//!
+//! A second socket, `btmon.events.sock`, pushes changes instead: a client
+//! that connects gets one line of JSON with the latest levels right away and
+//! another each time a scan finds different ones, until it disconnects. The
+//! lines are flat enough for `hs.json.decode`, and carry the daemon's
+//! `session_id` and each device's `seq` (see [`crate::sequence`]):
+//!
+//! ```json
+//! {"scanned_at":"2026-10-15T09:30:00Z","session_id":"19a1f-2f1c","count":1,"devices":[{"name":"Magic Mouse","address":"3c-a6-f6-33-44-55","battery":76,"summary":"Magic Mouse: 76%","seq":1}]}
+//! ```
+
+use crate::cache::ScanCache;
+use crate::output;
+use crate::paths;
+use crate::sequence::Sequences;
+use serde_json::{Value, json};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
+use tracing::debug;
+
+/// Time allowed for the daemon to answer, and for a client to read the
+/// answer
+pub const TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Location of the daemon socket
+pub fn socket_path() -> Option<PathBuf> {
+    paths::cache_dir().map(|dir| dir.join("btmon.sock"))
+}
+
+/// Location of the event socket
+pub fn events_path() -> Option<PathBuf> {
+    paths::cache_dir().map(|dir| dir.join("btmon.events.sock"))
+}
+
+/// The daemon's latest scan and health, shared with the socket threads
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot(Arc<Mutex<State>>);
+
+#[derive(Debug, Default)]
+struct State {
+    scan: Option<Value>,
+    backend_restarts: u64,
+    /// Devices of the last event and its line, to only push changes
+    event: Option<(Value, String)>,
+    sequences: Sequences,
+    /// Clients of the event socket
+    subscribers: Vec<UnixStream>,
+}
+
+impl Snapshot {
+    /// Replace the scan handed out to clients, and push an event to the
+    /// subscribers if the levels changed
+    pub fn update(&self, scan: &ScanCache) -> io::Result<()> {
+        let value = serde_json::to_value(scan)?;
+        let devices: Vec<_> = scan.devices.iter().map(output::shortcuts_device).collect();
+        let devices = serde_json::to_value(devices)?;
+        let mut state = self.lock();
+        state.scan = Some(value);
+        if state
+            .event
+            .as_ref()
+            .is_some_and(|(last, _)| *last == devices)
+        {
+            return Ok(());
+        }
+        state.sequences.update(&scan.devices);
+        let mut numbered = devices.clone();
+        if let Value::Array(numbered) = &mut numbered {
+            for (device, value) in scan.devices.iter().zip(numbered) {
+                if let Value::Object(object) = value {
+                    object.insert("seq".to_string(), state.sequences.seq(&device.name).into());
+                }
+            }
+        }
+        let event = json!({
+            "scanned_at": scan.scanned_at,
+            "session_id": state.sequences.session(),
+            "count": scan.devices.len(),
+            "devices": numbered,
+        });
+        let line = format!("{}\n", serde_json::to_string(&event)?);
+        // Clients that went away, or stopped reading, are dropped
+        state
+            .subscribers
+            .retain_mut(|stream| stream.write_all(line.as_bytes()).is_ok());
+        state.event = Some((devices, line));
+        Ok(())
+    }
+
+    /// Send the latest levels to a client of the event socket and keep it
+    /// for the next changes
+    fn subscribe(&self, mut stream: UnixStream) -> io::Result<()> {
+        let mut state = self.lock();
+        if let Some((_, line)) = &state.event {
+            stream.write_all(line.as_bytes())?;
+        }
+        state.subscribers.push(stream);
+        Ok(())
+    }
+
+    /// Set the number of times the backend was recreated
+    pub fn set_backend_restarts(&self, restarts: u64) {
+        self.lock().backend_restarts = restarts;
+    }
+
+    /// The latest scan and the number of backend restarts, None before the
+    /// first scan
+    pub fn latest(&self) -> Option<(ScanCache, u64)> {
+        let state = self.lock();
+        let scan = serde_json::from_value(state.scan.clone()?).ok()?;
+        Some((scan, state.backend_restarts))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn get(&self) -> Option<String> {
+        let state = self.lock();
+        let mut scan = state.scan.clone()?;
+        if let Value::Object(object) = &mut scan {
+            object.insert(
+                "backend_restarts".to_string(),
+                state.backend_restarts.into(),
+            );
+        }
+        serde_json::to_string(&scan).ok()
+    }
+}
+
+/// Listen on `path`, replacing a socket left behind by a daemon that is no
+/// longer running
+///
+/// Fails if another daemon is answering on it.
+pub fn listen(path: &Path) -> io::Result<UnixListener> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("another daemon is listening on {}", path.display()),
+            ));
+        }
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    // Device names and addresses are only for the user
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Answer every connection with the latest scan on a background thread
+///
+/// Connections made before the first scan finished are closed without an
+/// answer.
+pub fn serve(listener: UnixListener, snapshot: Snapshot) -> io::Result<()> {
+    thread::Builder::new()
+        .name("daemon-socket".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream.and_then(|mut stream| {
+                    stream.set_write_timeout(Some(TIMEOUT))?;
+                    match snapshot.get() {
+                        Some(json) => stream.write_all(json.as_bytes()),
+                        None => Ok(()),
+                    }
+                });
+                if let Err(e) = result {
+                    debug!(error = %e, "Daemon socket connection failed");
+                }
+            }
+        })?;
+    Ok(())
+}
+
+/// Push every change of the levels to the clients of the event socket,
+/// accepting them on a background thread
+///
+/// Clients connecting before the first scan finished get their first line
+/// with it.
+pub fn serve_events(listener: UnixListener, snapshot: Snapshot) -> io::Result<()> {
+    thread::Builder::new()
+        .name("daemon-events".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream.and_then(|stream| {
+                    stream.set_write_timeout(Some(TIMEOUT))?;
+                    snapshot.subscribe(stream)
+                });
+                if let Err(e) = result {
+                    debug!(error = %e, "Daemon event socket connection failed");
+                }
+            }
+        })?;
+    Ok(())
+}
+
+/// Ask the daemon listening on `path` for its latest scan
+pub fn query(path: &Path) -> io::Result<ScanCache> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let mut json = String::new();
+    stream.read_to_string(&mut json)?;
+    if json.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "the daemon hasn't finished its first scan",
+        ));
+    }
+    Ok(serde_json::from_str(&json)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::Device;
+    use chrono::Utc;
+    use std::io::{BufRead, BufReader};
+
+    #[test]
+    fn test_query() {
+        let dir = std::env::temp_dir().join(format!("btmon-socket-{}", std::process::id()));
+        let path = dir.join("btmon.sock");
+        let snapshot = Snapshot::default();
+        serve(listen(&path).unwrap(), snapshot.clone()).unwrap();
+
+        // Nothing to hand out before the first scan
+        assert!(query(&path).is_err());
+
+        snapshot
+            .update(&ScanCache {
+                scanned_at: Utc::now(),
+                devices: vec![Device::test_ble("Keyboard", 76)],
+            })
+            .unwrap();
+        let scan = query(&path).unwrap();
+        assert_eq!(scan.devices[0].name, "Keyboard");
+        snapshot.set_backend_restarts(2);
+        assert!(snapshot.get().unwrap().contains(r#""backend_restarts":2"#));
+        let (latest, restarts) = snapshot.latest().unwrap();
+        assert_eq!((latest.devices.len(), restarts), (1, 2));
+
+        // A second daemon doesn't take over the socket
+        assert_eq!(listen(&path).unwrap_err().kind(), io::ErrorKind::AddrInUse);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_events() {
+        let dir = std::env::temp_dir().join(format!("btmon-events-{}", std::process::id()));
+        let path = dir.join("btmon.events.sock");
+        let snapshot = Snapshot::default();
+        serve_events(listen(&path).unwrap(), snapshot.clone()).unwrap();
+        let scan = |level| ScanCache {
+            scanned_at: Utc::now(),
+            devices: vec![Device::test_ble("Keyboard", level)],
+        };
+        let connect = || {
+            let stream = UnixStream::connect(&path).unwrap();
+            stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+            BufReader::new(stream)
+        };
+        let next = |client: &mut BufReader<UnixStream>| {
+            let mut line = String::new();
+            client.read_line(&mut line).ok()?;
+            serde_json::from_str::<Value>(&line).ok()
+        };
+
+        let mut early = connect();
+        // Wait until the client is subscribed before the first scan
+        while snapshot.lock().subscribers.is_empty() {
+            thread::sleep(Duration::from_millis(5));
+        }
+        snapshot.update(&scan(76)).unwrap();
+        let event = next(&mut early).unwrap();
+        assert_eq!(event["count"], 1);
+        assert_eq!(event["devices"][0]["name"], "Keyboard");
+        assert_eq!(event["devices"][0]["battery"], 76);
+        assert_eq!(event["devices"][0]["seq"], 1);
+
+        // A late client gets the latest levels first, and nobody gets a
+        // scan that changed nothing
+        let mut late = connect();
+        assert_eq!(next(&mut late).unwrap()["devices"][0]["battery"], 76);
+        snapshot.update(&scan(76)).unwrap();
+        snapshot.update(&scan(75)).unwrap();
+        assert_eq!(next(&mut early).unwrap()["devices"][0]["battery"], 75);
+        let event = next(&mut late).unwrap();
+        assert_eq!(event["devices"][0]["seq"], 2);
+        assert_eq!(event["session_id"], snapshot.lock().sequences.session());
+
+        // A client that went away is dropped on the next change
+        drop(early);
+        snapshot.update(&scan(74)).unwrap();
+        assert_eq!(next(&mut late).unwrap()["devices"][0]["battery"], 74);
+        assert_eq!(snapshot.lock().subscribers.len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
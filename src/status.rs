@@ -0,0 +1,178 @@
+//! Machine-readable summary of a run
+//!
+//! With `--status-json`, a single JSON line describing the run is printed on
+//! stderr after the normal output: how many devices were found, which data
+//! sources were queried and how long they took, how many warnings were
+//! logged, and why the exit code is what it is.
+
+use crate::backend::{AdapterStatus, Backend, ClassicReading, GattReading};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// Tracing layer counting warnings and errors
+#[derive(Debug, Clone, Default)]
+pub struct WarningCounter(Arc<AtomicUsize>);
+
+impl WarningCounter {
+    /// Number of warnings and errors logged so far
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl<S: Subscriber> Layer<S> for WarningCounter {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if *event.metadata().level() <= Level::WARN {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Readings and time spent in one data source
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct SourceStats {
+    pub readings: usize,
+    pub duration_ms: u64,
+}
+
+impl SourceStats {
+    fn add(&mut self, readings: usize, elapsed: Duration) {
+        self.readings += readings;
+        self.duration_ms += u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX);
+    }
+}
+
+/// Backend wrapper that times each data source of the inner backend
+pub struct TimedBackend<'a> {
+    inner: &'a mut dyn Backend,
+    sources: BTreeMap<&'static str, SourceStats>,
+}
+
+impl<'a> TimedBackend<'a> {
+    /// Wrap a backend to time its responses
+    pub fn new(inner: &'a mut dyn Backend) -> Self {
+        Self {
+            inner,
+            sources: BTreeMap::new(),
+        }
+    }
+
+    /// Take the statistics of the sources queried so far
+    pub fn into_sources(self) -> BTreeMap<&'static str, SourceStats> {
+        self.sources
+    }
+}
+
+impl Backend for TimedBackend<'_> {
+    fn gatt_readings(&mut self) -> Vec<GattReading> {
+        let start = Instant::now();
+        let readings = self.inner.gatt_readings();
+        self.sources
+            .entry("gatt")
+            .or_default()
+            .add(readings.len(), start.elapsed());
+        readings
+    }
+
+    fn classic_readings(&mut self) -> Vec<ClassicReading> {
+        let start = Instant::now();
+        let readings = self.inner.classic_readings();
+        self.sources
+            .entry("classic")
+            .or_default()
+            .add(readings.len(), start.elapsed());
+        readings
+    }
+
+    fn adapter(&mut self) -> Option<AdapterStatus> {
+        self.inner.adapter()
+    }
+}
+
+/// Summary of a run, printed as one JSON line
+#[derive(Debug, Default, Serialize)]
+pub struct RunStatus {
+    /// Devices in the output
+    pub devices: usize,
+    /// `system` or `replay`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backend: Option<&'static str>,
+    /// Data sources that were queried
+    pub sources: BTreeMap<&'static str, SourceStats>,
+    /// Wall-clock time of the whole run
+    pub duration_ms: u64,
+    /// Warnings and errors logged
+    pub warnings: usize,
+    pub exit_code: u8,
+    /// Why the run failed, empty on success
+    pub failures: Vec<String>,
+}
+
+impl RunStatus {
+    /// Record a reason for failing the run
+    pub fn fail(&mut self, reason: impl Into<String>) {
+        self.failures.push(reason.into());
+        self.exit_code = 1;
+    }
+
+    /// Exit code of the run
+    pub fn exit_code(&self) -> ExitCode {
+        ExitCode::from(self.exit_code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{RawSnapshot, ReplayBackend};
+    use tracing_subscriber::prelude::*;
+
+    #[test]
+    fn test_timed_backend() {
+        let mut replay = ReplayBackend(RawSnapshot {
+            gatt: vec![GattReading {
+                name: "Keyboard".to_string(),
+                battery_level: 76,
+                read_at: None,
+            }],
+            classic: Vec::new(),
+            adapter: None,
+        });
+        let mut timed = TimedBackend::new(&mut replay);
+        timed.gatt_readings();
+        timed.classic_readings();
+
+        let sources = timed.into_sources();
+        assert_eq!(sources["gatt"].readings, 1);
+        assert_eq!(sources["classic"].readings, 0);
+    }
+
+    #[test]
+    fn test_run_status() {
+        let counter = WarningCounter::default();
+        let subscriber = tracing_subscriber::registry().with(counter.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug!("not counted");
+            tracing::warn!("counted");
+            tracing::error!("counted");
+        });
+
+        let mut status = RunStatus {
+            warnings: counter.count(),
+            ..Default::default()
+        };
+        assert_eq!(status.warnings, 2);
+        assert_eq!(status.exit_code(), ExitCode::SUCCESS);
+
+        status.fail("alert delivery failed");
+        let value = serde_json::to_value(&status).unwrap();
+        assert_eq!(value["exit_code"], 1);
+        assert_eq!(value["failures"][0], "alert delivery failed");
+    }
+}
@@ -0,0 +1,157 @@
+//! Output templates for `--template`
+//!
+//! A template is text with `{field}` placeholders, rendered once per device,
+//! e.g. `{name} {battery_level}%`. The fields are those of
+//! [`crate::fields::FIELDS`]; fields a device doesn't have render as nothing. `{{` and `}}` stand for literal braces.
+
+use crate::device::Device;
+use crate::fields::{FIELDS, field_value};
+use serde_json::Value;
+use std::fmt;
+use std::str::FromStr;
+
+/// A piece of a parsed template
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Field(&'static str),
+}
+
+/// A parsed `--template`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+/// Why a template couldn't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    /// A placeholder names no field
+    UnknownField(String),
+    /// A `{` without its `}`, or a lone `}`
+    Unbalanced,
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownField(name) => write!(
+                f,
+                "unknown field '{{{name}}}', expected one of: {}",
+                FIELDS.join(", ")
+            ),
+            Self::Unbalanced => f.write_str("unbalanced braces, use {{ and }} for literal ones"),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+impl FromStr for Template {
+    type Err = TemplateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(TemplateError::Unbalanced),
+                        }
+                    }
+                    let field = FIELDS
+                        .into_iter()
+                        .find(|field| *field == name.trim())
+                        .ok_or(TemplateError::UnknownField(name))?;
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Field(field));
+                }
+                '}' => return Err(TemplateError::Unbalanced),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Self { parts })
+    }
+}
+
+impl Template {
+    /// Render the template for one device
+    pub fn render(&self, device: &Device) -> String {
+        let json = serde_json::to_value(device).unwrap_or(Value::Null);
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => out.push_str(text),
+                Part::Field(field) => match field_value(device, &json, field) {
+                    Value::String(s) => out.push_str(&s),
+                    Value::Null => {}
+                    value => out.push_str(&value.to_string()),
+                },
+            }
+        }
+        out
+    }
+
+    /// Render the template for each device, one per line
+    pub fn render_all(&self, devices: &[Device]) -> String {
+        devices
+            .iter()
+            .map(|device| self.render(device))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::BatteryLevel;
+
+    #[test]
+    fn test_render() {
+        let airpods = Device {
+            vendor: Some("Apple".to_string()),
+            battery_left: BatteryLevel::new(80),
+            battery_right: BatteryLevel::new(90),
+            ..Device::test_classic("AirPods Pro", "a4-c3-37-12-34-56")
+        };
+        let template: Template = "{icon} {name} [{type}/{source}] L{battery_left} R{battery_right} C{battery_case} {{{effective}}}"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            template.render(&airpods),
+            "🎧 AirPods Pro [headphones/classic] L80 R90 C {80}"
+        );
+        let template: Template = "{vendor}: {address}".parse().unwrap();
+        assert_eq!(template.render(&airpods), "Apple: a4-c3-37-12-34-56");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            "{nmae}".parse::<Template>(),
+            Err(TemplateError::UnknownField("nmae".to_string()))
+        );
+        assert_eq!("{name".parse::<Template>(), Err(TemplateError::Unbalanced));
+        assert_eq!("name}".parse::<Template>(), Err(TemplateError::Unbalanced));
+    }
+}
@@ -0,0 +1,309 @@
+//! Device timeline
+//!
+//! `btmon timeline` interleaves what the battery history knows about a
+//! device: the readings of each scan, the connects and disconnects, and the
+//! charge sessions found in the readings, so a flat battery can be traced
+//! back to the session that never finished or the day it wasn't charged.
+//!
+//! A charge session is a run of readings of one component that keeps rising
+//! by at least [`MIN_CHARGE`] points in total; it starts at the reading before
+//! the first rise and ends at the last rise, or where the level falls again.
+
+use crate::device::Component;
+use crate::history::{Connection, Reading};
+use crate::link::LinkEventKind;
+use chrono::{DateTime, Local, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Points a component must gain for a rise to count as a charge session,
+/// so readings wobbling by a point or two aren't taken for one
+pub const MIN_CHARGE: u8 = 5;
+
+/// One line of the timeline
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Entry {
+    /// The device connected
+    Connected {
+        at: DateTime<Utc>,
+        device: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        level: Option<u8>,
+    },
+    /// The levels of one scan
+    Reading {
+        at: DateTime<Utc>,
+        device: String,
+        levels: BTreeMap<Component, u8>,
+    },
+    /// A component charged from `from` to `to`, between `at` and `until`
+    Charge {
+        at: DateTime<Utc>,
+        device: String,
+        component: Component,
+        from: u8,
+        to: u8,
+        until: DateTime<Utc>,
+    },
+    /// The device disconnected
+    Disconnected {
+        at: DateTime<Utc>,
+        device: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        level: Option<u8>,
+    },
+}
+
+impl Entry {
+    /// Time of the entry
+    pub fn at(&self) -> DateTime<Utc> {
+        match self {
+            Self::Connected { at, .. }
+            | Self::Reading { at, .. }
+            | Self::Charge { at, .. }
+            | Self::Disconnected { at, .. } => *at,
+        }
+    }
+
+    /// Order of entries at the same time: a connect before the scan that
+    /// found the device, a disconnect after everything else
+    fn rank(&self) -> u8 {
+        match self {
+            Self::Connected { .. } => 0,
+            Self::Reading { .. } => 1,
+            Self::Charge { .. } => 2,
+            Self::Disconnected { .. } => 3,
+        }
+    }
+}
+
+/// Charge sessions in readings sorted by time
+pub fn charge_sessions(readings: &[Reading]) -> Vec<Entry> {
+    let mut series: BTreeMap<(&str, Component), Vec<&Reading>> = BTreeMap::new();
+    for reading in readings {
+        series
+            .entry((reading.device.as_str(), reading.component))
+            .or_default()
+            .push(reading);
+    }
+
+    let mut sessions = Vec::new();
+    for ((device, component), readings) in series {
+        let session = |start: &Reading, end: &Reading| Entry::Charge {
+            at: start.at,
+            device: device.to_string(),
+            component,
+            from: start.level,
+            to: end.level,
+            until: end.at,
+        };
+        // Start of the current rise and the last reading that rose
+        let mut rise: Option<(&Reading, &Reading)> = None;
+        for pair in readings.windows(2) {
+            let (previous, reading) = (pair[0], pair[1]);
+            if reading.level > previous.level {
+                let start = rise.map_or(previous, |(start, _)| start);
+                rise = Some((start, reading));
+            } else if reading.level < previous.level
+                && let Some((start, end)) = rise.take()
+                && end.level - start.level >= MIN_CHARGE
+            {
+                sessions.push(session(start, end));
+            }
+        }
+        if let Some((start, end)) = rise
+            && end.level - start.level >= MIN_CHARGE
+        {
+            sessions.push(session(start, end));
+        }
+    }
+    sessions
+}
+
+/// Timeline of readings and connection events, both sorted by time
+pub fn build(readings: &[Reading], connections: &[Connection]) -> Vec<Entry> {
+    let mut entries = charge_sessions(readings);
+
+    let mut scans: Vec<Entry> = Vec::new();
+    for reading in readings {
+        match scans.last_mut() {
+            Some(Entry::Reading { at, device, levels })
+                if *at == reading.at && *device == reading.device =>
+            {
+                levels.insert(reading.component, reading.level);
+            }
+            _ => scans.push(Entry::Reading {
+                at: reading.at,
+                device: reading.device.clone(),
+                levels: BTreeMap::from([(reading.component, reading.level)]),
+            }),
+        }
+    }
+    entries.extend(scans);
+
+    entries.extend(connections.iter().map(|connection| {
+        let (at, device, level) = (connection.at, connection.device.clone(), connection.level);
+        match connection.event {
+            LinkEventKind::Connected => Entry::Connected { at, device, level },
+            LinkEventKind::Disconnected => Entry::Disconnected { at, device, level },
+        }
+    }));
+
+    entries.sort_by_key(|entry| (entry.at(), entry.rank()));
+    entries
+}
+
+/// Format the timeline as text, one line per entry
+pub fn format_timeline(entries: &[Entry]) -> String {
+    let mut out = String::new();
+    for entry in entries {
+        let at = entry.at().with_timezone(&Local).format("%Y-%m-%d %H:%M");
+        let _ = write!(out, "{at}  ");
+        match entry {
+            Entry::Connected { device, level, .. } | Entry::Disconnected { device, level, .. } => {
+                let event = if matches!(entry, Entry::Connected { .. }) {
+                    "connected"
+                } else {
+                    "disconnected"
+                };
+                let _ = write!(out, "{device} {event}");
+                if let Some(level) = level {
+                    let _ = write!(out, " at {level}%");
+                }
+            }
+            Entry::Reading { device, levels, .. } => {
+                let _ = write!(out, "{device}:");
+                for (component, level) in levels {
+                    match component.label() {
+                        Some(label) => {
+                            let _ = write!(out, " {label}:{level}%");
+                        }
+                        None => {
+                            let _ = write!(out, " {level}%");
+                        }
+                    }
+                }
+            }
+            Entry::Charge {
+                device,
+                component,
+                from,
+                to,
+                until,
+                ..
+            } => {
+                let until = until.with_timezone(&Local).format("%H:%M");
+                let _ = write!(out, "{device} charged");
+                if let Some(label) = component.label() {
+                    let _ = write!(out, " {label}");
+                }
+                let _ = write!(out, " {from}% to {to}% by {until}");
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(minutes: i64) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2026-10-14T09:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+            + chrono::Duration::minutes(minutes)
+    }
+
+    fn reading(minutes: i64, component: Component, level: u8) -> Reading {
+        Reading {
+            at: at(minutes),
+            device: "AirPods Pro".to_string(),
+            component,
+            level,
+        }
+    }
+
+    #[test]
+    fn test_charge_sessions() {
+        let levels = [50, 48, 60, 70, 70, 85, 84, 85, 86, 40, 95];
+        let readings: Vec<Reading> = levels
+            .iter()
+            .enumerate()
+            .map(|(i, &level)| reading(i as i64 * 10, Component::Case, level))
+            .collect();
+
+        let sessions = charge_sessions(&readings);
+        // 48 to 85, the wobble from 84 to 86 is too small, then 40 to 95
+        assert_eq!(
+            sessions,
+            [
+                Entry::Charge {
+                    at: at(10),
+                    device: "AirPods Pro".to_string(),
+                    component: Component::Case,
+                    from: 48,
+                    to: 85,
+                    until: at(50),
+                },
+                Entry::Charge {
+                    at: at(90),
+                    device: "AirPods Pro".to_string(),
+                    component: Component::Case,
+                    from: 40,
+                    to: 95,
+                    until: at(100),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build() {
+        let readings = [
+            reading(0, Component::Left, 20),
+            reading(0, Component::Right, 30),
+            reading(30, Component::Left, 80),
+            reading(30, Component::Right, 30),
+        ];
+        let connection = |minutes, event, level| Connection {
+            at: at(minutes),
+            device: "AirPods Pro".to_string(),
+            event,
+            level,
+        };
+        let connections = [
+            connection(0, LinkEventKind::Connected, Some(20)),
+            connection(45, LinkEventKind::Disconnected, Some(30)),
+        ];
+
+        let entries = build(&readings, &connections);
+        let kinds: Vec<u8> = entries.iter().map(Entry::rank).collect();
+        assert_eq!(kinds, [0, 1, 2, 1, 3]);
+        assert_eq!(
+            entries[1],
+            Entry::Reading {
+                at: at(0),
+                device: "AirPods Pro".to_string(),
+                levels: BTreeMap::from([(Component::Left, 20), (Component::Right, 30)]),
+            }
+        );
+
+        let text = format_timeline(&entries);
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines[0].ends_with("  AirPods Pro connected at 20%"));
+        assert!(lines[1].ends_with("  AirPods Pro: L:20% R:30%"));
+        assert!(lines[2].contains("  AirPods Pro charged L 20% to 80% by "));
+        assert!(lines[4].ends_with("  AirPods Pro disconnected at 30%"));
+
+        let json = serde_json::to_value(&entries[0]).unwrap();
+        assert_eq!(json["type"], "connected");
+        assert_eq!(
+            serde_json::to_value(&entries[1]).unwrap()["levels"]["left"],
+            20
+        );
+    }
+}
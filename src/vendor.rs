@@ -0,0 +1,151 @@
+//! Manufacturer lookup for addresses and company identifiers
+//!
+//! Two small tables are bundled: IEEE OUI prefixes of vendors that commonly
+//! make Bluetooth accessories, and Bluetooth SIG company identifiers as found
+//! at the start of advertisement manufacturer data. Both are sorted by key so
+//! lookups can binary search; anything not listed resolves to no vendor.
+
+/// IEEE OUI (first three address octets) to vendor name
+const OUI: &[(u32, &str)] = &[
+    (0x00025B, "Cambridge Silicon Radio"),
+    (0x000393, "Apple"),
+    (0x0009BF, "Nintendo"),
+    (0x000A27, "Apple"),
+    (0x000A95, "Apple"),
+    (0x000C8A, "Bose"),
+    (0x000D93, "Apple"),
+    (0x0010FA, "Apple"),
+    (0x001124, "Apple"),
+    (0x00125A, "Microsoft"),
+    (0x0013A9, "Sony"),
+    (0x001451, "Apple"),
+    (0x0016CB, "Apple"),
+    (0x0017AB, "Nintendo"),
+    (0x0017F2, "Apple"),
+    (0x0017FA, "Microsoft"),
+    (0x00191D, "Nintendo"),
+    (0x0019E3, "Apple"),
+    (0x001B63, "Apple"),
+    (0x001B66, "Sennheiser"),
+    (0x001CB3, "Apple"),
+    (0x001D4F, "Apple"),
+    (0x001DBA, "Sony"),
+    (0x001DD8, "Microsoft"),
+    (0x001E52, "Apple"),
+    (0x001EC2, "Apple"),
+    (0x001F20, "Logitech"),
+    (0x001F32, "Nintendo"),
+    (0x001F5B, "Apple"),
+    (0x001FF3, "Apple"),
+    (0x0021E9, "Apple"),
+    (0x002241, "Apple"),
+    (0x002248, "Microsoft"),
+    (0x002312, "Apple"),
+    (0x002332, "Apple"),
+    (0x00236C, "Apple"),
+    (0x0023DF, "Apple"),
+    (0x002436, "Apple"),
+    (0x0024BE, "Sony"),
+    (0x002500, "Apple"),
+    (0x00254B, "Apple"),
+    (0x0025BC, "Apple"),
+    (0x002608, "Apple"),
+    (0x00264A, "Apple"),
+    (0x0026B0, "Apple"),
+    (0x0026BB, "Apple"),
+    (0x0050F2, "Microsoft"),
+    (0x0452C7, "Bose"),
+    (0x281878, "Microsoft"),
+    (0x7C1E52, "Microsoft"),
+];
+
+/// Bluetooth SIG company identifier to vendor name
+const COMPANY: &[(u16, &str)] = &[
+    (0x0000, "Ericsson"),
+    (0x0001, "Nokia"),
+    (0x0002, "Intel"),
+    (0x0006, "Microsoft"),
+    (0x000A, "Qualcomm"),
+    (0x000D, "Texas Instruments"),
+    (0x000F, "Broadcom"),
+    (0x0046, "MediaTek"),
+    (0x004C, "Apple"),
+    (0x0055, "Plantronics"),
+    (0x0057, "Harman"),
+    (0x0059, "Nordic Semiconductor"),
+    (0x005C, "Belkin"),
+    (0x005D, "Realtek"),
+    (0x0067, "GN Netcom"),
+    (0x006B, "Polar"),
+    (0x0075, "Samsung"),
+    (0x0078, "Nike"),
+    (0x0082, "Sennheiser"),
+    (0x0087, "Garmin"),
+    (0x009E, "Bose"),
+    (0x009F, "Suunto"),
+    (0x00E0, "Google"),
+    (0x012D, "Sony"),
+    (0x0171, "Amazon"),
+    (0x01DA, "Logitech"),
+    (0x02E5, "Espressif"),
+    (0x0499, "Ruuvi"),
+    (0x067C, "Tile"),
+];
+
+/// Vendor of a Bluetooth address such as `a4-c3-37-12-34-56`
+///
+/// Returns None for unknown prefixes and for locally administered (random or
+/// private) addresses, which don't carry an OUI.
+pub fn address_vendor(address: &str) -> Option<&'static str> {
+    let mut octets = address
+        .split([':', '-'])
+        .map(|octet| u8::from_str_radix(octet, 16));
+    let mut oui = 0u32;
+    for _ in 0..3 {
+        let octet = octets.next()?.ok()?;
+        oui = (oui << 8) | u32::from(octet);
+    }
+    if oui & 0x02_0000 != 0 {
+        return None;
+    }
+    OUI.binary_search_by_key(&oui, |(key, _)| *key)
+        .ok()
+        .map(|i| OUI[i].1)
+}
+
+/// Vendor of a Bluetooth SIG company identifier
+pub fn company_vendor(id: u16) -> Option<&'static str> {
+    COMPANY
+        .binary_search_by_key(&id, |(key, _)| *key)
+        .ok()
+        .map(|i| COMPANY[i].1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tables_sorted() {
+        assert!(OUI.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(COMPANY.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[test]
+    fn test_address_vendor() {
+        assert_eq!(address_vendor("00-1f-20-12-34-56"), Some("Logitech"));
+        assert_eq!(address_vendor("00:25:00:AA:BB:CC"), Some("Apple"));
+        assert_eq!(address_vendor("12-34-56-78-9a-bc"), None);
+        // Locally administered bit set
+        assert_eq!(address_vendor("02-25-00-aa-bb-cc"), None);
+        assert_eq!(address_vendor("unknown"), None);
+        assert_eq!(address_vendor("00-25"), None);
+    }
+
+    #[test]
+    fn test_company_vendor() {
+        assert_eq!(company_vendor(0x004C), Some("Apple"));
+        assert_eq!(company_vendor(0x067C), Some("Tile"));
+        assert_eq!(company_vendor(0xFFFF), None);
+    }
+}
@@ -0,0 +1,131 @@
+//! Continuous watch mode
+//!
+//! `--watch` repeats the normal run every `--interval`. Ctrl-C doesn't kill
+//! the process in the middle of a pass: the first one stops the loop once the
+//! current pass is done, a second one exits right away.
+//!
+//! Each pass after the first reports how every component's level changed
+//! since the previous one, in the devices' `delta` (see [`Samples`]). NDJSON
+//! records also carry the devices' `seq` and the `session_id` (see
+//! [`Sequences`]).
+
+use crate::device::{BatteryLevel, Component, Device};
+use crate::sequence::Sequences;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Default interval between passes
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often a sleep checks for Ctrl-C
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Set by the SIGINT handler
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_interrupt(_signal: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+    // SAFETY: signal() is async-signal-safe; restoring the default action
+    // lets a second Ctrl-C terminate a pass that hangs.
+    unsafe { libc::signal(libc::SIGINT, libc::SIG_DFL) };
+}
+
+/// Catch Ctrl-C so that the watch loop can stop between passes
+pub fn install_interrupt_handler() {
+    let handler: extern "C" fn(libc::c_int) = on_interrupt;
+    // SAFETY: The handler only stores to an atomic and calls signal().
+    unsafe { libc::signal(libc::SIGINT, handler as libc::sighandler_t) };
+}
+
+/// Whether Ctrl-C was pressed
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Sleep for `duration`, returning false early if Ctrl-C is pressed
+pub fn sleep(duration: Duration) -> bool {
+    let deadline = Instant::now() + duration;
+    loop {
+        if interrupted() {
+            return false;
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return true;
+        }
+        std::thread::sleep(remaining.min(POLL_INTERVAL));
+    }
+}
+
+/// Levels of the previous passes by device name, and the sequence numbers
+/// of the stream
+#[derive(Debug, Default)]
+pub struct Samples {
+    levels: HashMap<String, BTreeMap<Component, BatteryLevel>>,
+    sequences: Sequences,
+}
+
+impl Samples {
+    /// Set the `delta` of each device against its levels in the previous
+    /// pass that saw it, and keep its levels for the next one
+    pub fn apply(&mut self, devices: &mut [Device]) {
+        for device in devices.iter_mut() {
+            let levels: BTreeMap<Component, BatteryLevel> = device.components().collect();
+            if let Some(previous) = self.levels.get(&device.name) {
+                device.delta = levels
+                    .iter()
+                    .filter_map(|(component, level)| {
+                        let previous = previous.get(component)?;
+                        let change =
+                            i16::from(level.as_percentage()) - i16::from(previous.as_percentage());
+                        Some((*component, change))
+                    })
+                    .collect();
+            }
+            self.levels.insert(device.name.clone(), levels);
+        }
+        self.sequences.update(devices);
+    }
+
+    /// Sequence numbers of the devices after the latest pass
+    pub fn sequences(&self) -> &Sequences {
+        &self.sequences
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_samples() {
+        let mut samples = Samples::default();
+        let mut devices = vec![Device::test_ble("Keyboard", 80)];
+        samples.apply(&mut devices);
+        // Nothing to compare the first pass with
+        assert!(devices[0].delta.is_empty());
+
+        let mut devices = vec![Device::test_ble("Keyboard", 77)];
+        samples.apply(&mut devices);
+        assert_eq!(devices[0].delta, BTreeMap::from([(Component::Level, -3)]));
+
+        // A device missing from a pass is compared with the last one it was in
+        samples.apply(&mut []);
+        let mut devices = vec![Device::test_ble("Keyboard", 79)];
+        samples.apply(&mut devices);
+        assert_eq!(devices[0].delta, BTreeMap::from([(Component::Level, 2)]));
+        assert_eq!(samples.sequences().seq("Keyboard"), 3);
+    }
+
+    #[test]
+    fn test_sleep_interrupted() {
+        assert!(sleep(Duration::from_millis(10)));
+
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        let start = Instant::now();
+        assert!(!sleep(Duration::from_secs(10)));
+        assert!(start.elapsed() < POLL_INTERVAL);
+        INTERRUPTED.store(false, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,104 @@
+//! Backend watchdog for the daemon
+//!
+//! A CoreBluetooth session kept for days can go stale after Bluetooth is
+//! toggled or the Mac sleeps: the central manager stays in the unknown or
+//! resetting state and scans come back empty. The daemon reports every scan
+//! to a [`Watchdog`], which asks for the backend to be torn down and created
+//! again once no scan succeeded in a number of intervals.
+
+use crate::backend::{AdapterState, AdapterStatus};
+
+/// Failed scans in a row after which the backend is recreated
+pub const DEFAULT_LIMIT: u32 = 3;
+
+/// Counter of failed scans since the last successful one
+#[derive(Debug)]
+pub struct Watchdog {
+    limit: u32,
+    missed: u32,
+    restarts: u64,
+}
+
+impl Watchdog {
+    /// Recreate the backend after `limit` failed scans in a row
+    pub fn new(limit: u32) -> Self {
+        Self {
+            limit: limit.max(1),
+            missed: 0,
+            restarts: 0,
+        }
+    }
+
+    /// Record the outcome of a scan, returning whether the backend should be
+    /// recreated now
+    pub fn observe(&mut self, succeeded: bool) -> bool {
+        if succeeded {
+            self.missed = 0;
+            return false;
+        }
+        self.missed += 1;
+        if self.missed < self.limit {
+            return false;
+        }
+        self.missed = 0;
+        self.restarts += 1;
+        true
+    }
+
+    /// Failed scans since the last successful one
+    pub fn missed(&self) -> u32 {
+        self.missed
+    }
+
+    /// Times the backend was recreated
+    pub fn restarts(&self) -> u64 {
+        self.restarts
+    }
+}
+
+/// Whether a scan reporting `adapter` (None for a usable one) succeeded
+///
+/// Bluetooth turned off or not allowed is an answer of its own that a new
+/// session wouldn't change; only a session that never got a state, or is
+/// stuck resetting, counts as failed.
+pub fn succeeded(adapter: Option<&AdapterStatus>) -> bool {
+    !adapter.is_some_and(|adapter| {
+        matches!(
+            adapter.state,
+            AdapterState::Unknown | AdapterState::Resetting
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_observe() {
+        let mut watchdog = Watchdog::new(3);
+        assert!(!watchdog.observe(false));
+        assert!(!watchdog.observe(false));
+        assert_eq!(watchdog.missed(), 2);
+        // A successful scan starts the count over
+        assert!(!watchdog.observe(true));
+        assert_eq!(watchdog.missed(), 0);
+
+        assert!(!watchdog.observe(false));
+        assert!(!watchdog.observe(false));
+        assert!(watchdog.observe(false));
+        assert_eq!(watchdog.restarts(), 1);
+        // The new backend gets the full limit again
+        assert!(!watchdog.observe(false));
+        assert_eq!(watchdog.missed(), 1);
+    }
+
+    #[test]
+    fn test_succeeded() {
+        assert!(succeeded(None));
+        assert!(succeeded(Some(&AdapterState::PoweredOff.into())));
+        assert!(succeeded(Some(&AdapterState::Unauthorized.into())));
+        assert!(!succeeded(Some(&AdapterState::Unknown.into())));
+        assert!(!succeeded(Some(&AdapterState::Resetting.into())));
+    }
+}
@@ -0,0 +1,66 @@
+//! SwiftBar and xbar plugin output for `--format xbar`
+//!
+//! Both run an executable from their plugins folder and show its output:
+//! the first line in the menu bar, and the lines after a `---` separator in
+//! its dropdown, with `| key=value` parameters. How often the plugin runs
+//! is part of its file name, e.g. `btmon.5m.sh`.
+
+use crate::alerts::{AlertConfig, Severity};
+use crate::device::Device;
+use crate::locale::Locale;
+use crate::output::{device_icon, format_device_output, format_menubar_title};
+
+/// Format a complete plugin body: the lowest battery in the menu bar, one
+/// dropdown line per device coloured by severity, and a refresh item
+pub fn format_xbar(devices: &[Device], alerts: &AlertConfig, locale: &Locale) -> String {
+    let mut lines = vec![format_menubar_title(devices), "---".to_string()];
+    if devices.is_empty() {
+        lines.push("No devices with battery info".to_string());
+    }
+    for device in devices {
+        // `|` starts the parameters, so it can't appear in the text
+        let text = format!(
+            "{} {}",
+            device_icon(device),
+            format_device_output(device, locale).replace('|', "¦")
+        );
+        let severity = device
+            .effective()
+            .or(device.battery_case)
+            .and_then(|level| alerts.severity(level));
+        lines.push(match severity {
+            Some(Severity::Critical) => format!("{text} | color=red"),
+            Some(Severity::Warning) => format!("{text} | color=orange"),
+            None => text,
+        });
+    }
+    lines.push("---".to_string());
+    lines.push("Refresh | refresh=true".to_string());
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_xbar() {
+        let devices = [
+            Device::test_ble("Magic Keyboard", 76),
+            Device::test_ble("Desk | Mouse", 15),
+        ];
+        assert_eq!(
+            format_xbar(&devices, &AlertConfig::default(), &Locale::default()),
+            "🖱️ 15%\n\
+             ---\n\
+             ⌨️ Magic Keyboard: 76%\n\
+             🖱️ Desk ¦ Mouse: 15% | color=orange\n\
+             ---\n\
+             Refresh | refresh=true"
+        );
+        assert_eq!(
+            format_xbar(&[], &AlertConfig::default(), &Locale::default()),
+            "🔋 –\n---\nNo devices with battery info\n---\nRefresh | refresh=true"
+        );
+    }
+}
@@ -0,0 +1,539 @@
+//! Excel workbook export
+//!
+//! `btmon export --format xlsx` writes the battery history as a workbook
+//! that opens in Excel or Numbers without an import dialog: an overview
+//! sheet with a row per device and component, naming the cells to chart,
+//! then one sheet per device with a row per scan and a column per component.
+//!
+//! A workbook is a zip archive of XML parts. The few parts needed are
+//! written here, and stored in the archive uncompressed, which every reader
+//! accepts.
+
+use crate::device::Component;
+use crate::history::Reading;
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+/// Name of the first sheet
+const OVERVIEW: &str = "Overview";
+
+/// Longest sheet name Excel accepts
+const MAX_SHEET_NAME: usize = 31;
+
+/// Column headers of the overview sheet
+const OVERVIEW_COLUMNS: [&str; 8] = [
+    "Device",
+    "Component",
+    "Readings",
+    "First",
+    "Last",
+    "Latest %",
+    "Lowest %",
+    "Chart range",
+];
+
+/// Serial number of 1970-01-01 in Excel's dates
+const UNIX_EPOCH_SERIAL: f64 = 25_569.0;
+
+/// Style index of date cells in `styles.xml`
+const DATE_STYLE: u8 = 1;
+
+/// Header of a component's column
+fn column_header(component: Component) -> &'static str {
+    match component {
+        Component::Level => "Battery %",
+        Component::Left => "Left %",
+        Component::Right => "Right %",
+        Component::Case => "Case %",
+    }
+}
+
+/// A cell value
+enum Cell {
+    Text(String),
+    Number(f64),
+    Date(NaiveDateTime),
+    Empty,
+}
+
+/// Readings of one device, by scan time and component
+struct DeviceSheet<'a> {
+    device: &'a str,
+    name: String,
+    components: Vec<Component>,
+    scans: BTreeMap<DateTime<Utc>, BTreeMap<Component, u8>>,
+}
+
+/// The workbook of the readings, as the bytes of an `.xlsx` file
+pub fn workbook(readings: &[Reading]) -> Vec<u8> {
+    let sheets = device_sheets(readings);
+
+    let mut overview = vec![
+        OVERVIEW_COLUMNS
+            .iter()
+            .map(|header| Cell::Text(header.to_string()))
+            .collect::<Vec<_>>(),
+    ];
+    for sheet in &sheets {
+        for (index, &component) in sheet.components.iter().enumerate() {
+            let levels: Vec<(DateTime<Utc>, u8)> = sheet
+                .scans
+                .iter()
+                .filter_map(|(at, levels)| Some((*at, *levels.get(&component)?)))
+                .collect();
+            let (Some(first), Some(last)) = (levels.first(), levels.last()) else {
+                continue;
+            };
+            let column = column_name(index + 1);
+            overview.push(vec![
+                Cell::Text(sheet.device.to_string()),
+                Cell::Text(component.key().to_string()),
+                Cell::Number(levels.len() as f64),
+                Cell::Date(local(first.0)),
+                Cell::Date(local(last.0)),
+                Cell::Number(f64::from(last.1)),
+                Cell::Number(f64::from(
+                    levels.iter().map(|l| l.1).min().unwrap_or(last.1),
+                )),
+                Cell::Text(format!(
+                    "'{}'!$A$2:$A${rows},'{}'!${column}$2:${column}${rows}",
+                    sheet.name.replace('\'', "''"),
+                    sheet.name.replace('\'', "''"),
+                    rows = sheet.scans.len() + 1,
+                )),
+            ]);
+        }
+    }
+
+    let names: Vec<&str> = std::iter::once(OVERVIEW)
+        .chain(sheets.iter().map(|sheet| sheet.name.as_str()))
+        .collect();
+    // The content types come first, as some readers expect
+    let mut parts = vec![
+        (
+            "[Content_Types].xml".to_string(),
+            content_types(names.len()),
+        ),
+        ("_rels/.rels".to_string(), ROOT_RELS.to_string()),
+        ("xl/workbook.xml".to_string(), workbook_xml(&names)),
+        (
+            "xl/_rels/workbook.xml.rels".to_string(),
+            workbook_rels(names.len()),
+        ),
+        ("xl/styles.xml".to_string(), STYLES.to_string()),
+        ("xl/worksheets/sheet1.xml".to_string(), worksheet(&overview)),
+    ];
+    for (index, sheet) in sheets.iter().enumerate() {
+        let mut rows = vec![
+            std::iter::once("Time")
+                .chain(sheet.components.iter().map(|&c| column_header(c)))
+                .map(|header| Cell::Text(header.to_string()))
+                .collect::<Vec<_>>(),
+        ];
+        for (at, levels) in &sheet.scans {
+            let mut row = vec![Cell::Date(local(*at))];
+            row.extend(sheet.components.iter().map(|component| {
+                levels
+                    .get(component)
+                    .map_or(Cell::Empty, |&level| Cell::Number(f64::from(level)))
+            }));
+            rows.push(row);
+        }
+        parts.push((
+            format!("xl/worksheets/sheet{}.xml", index + 2),
+            worksheet(&rows),
+        ));
+    }
+
+    let mut zip = Zip::default();
+    for (name, contents) in &parts {
+        zip.add(name, contents.as_bytes());
+    }
+    zip.finish()
+}
+
+/// Group the readings by device, naming each device's sheet
+fn device_sheets(readings: &[Reading]) -> Vec<DeviceSheet<'_>> {
+    let mut devices: BTreeMap<&str, BTreeMap<DateTime<Utc>, BTreeMap<Component, u8>>> =
+        BTreeMap::new();
+    for reading in readings {
+        devices
+            .entry(&reading.device)
+            .or_default()
+            .entry(reading.at)
+            .or_default()
+            .insert(reading.component, reading.level);
+    }
+
+    let mut taken = vec![OVERVIEW.to_lowercase()];
+    devices
+        .into_iter()
+        .map(|(device, scans)| {
+            let components = Component::ALL
+                .into_iter()
+                .filter(|component| scans.values().any(|levels| levels.contains_key(component)))
+                .collect();
+            let name = sheet_name(device, &taken);
+            taken.push(name.to_lowercase());
+            DeviceSheet {
+                device,
+                name,
+                components,
+                scans,
+            }
+        })
+        .collect()
+}
+
+/// A sheet name for `device` that Excel accepts and isn't `taken` (compared
+/// case-insensitively, as Excel does)
+fn sheet_name(device: &str, taken: &[String]) -> String {
+    let base: String = device
+        .chars()
+        .map(|c| match c {
+            '[' | ']' | ':' | '*' | '?' | '/' | '\\' => '_',
+            c => c,
+        })
+        .collect();
+    let base = base.trim_matches('\'').trim();
+    let base = if base.is_empty() { "Device" } else { base };
+
+    (1..)
+        .map(|n| {
+            let suffix = if n == 1 {
+                String::new()
+            } else {
+                format!(" ({n})")
+            };
+            let room = MAX_SHEET_NAME - suffix.chars().count();
+            let mut name: String = base.chars().take(room).collect();
+            name.push_str(&suffix);
+            name
+        })
+        .find(|name| !taken.contains(&name.to_lowercase()))
+        .unwrap_or_default()
+}
+
+/// Time as Excel shows it, in the local time zone
+fn local(at: DateTime<Utc>) -> NaiveDateTime {
+    at.with_timezone(&Local).naive_local()
+}
+
+/// Excel's serial number of a time: days since 1899-12-30
+fn serial(at: NaiveDateTime) -> f64 {
+    at.and_utc().timestamp() as f64 / 86_400.0 + UNIX_EPOCH_SERIAL
+}
+
+/// Column letters of a 0-based column index, e.g. `A` or `AB`
+fn column_name(mut index: usize) -> String {
+    let mut name = Vec::new();
+    loop {
+        name.push(b'A' + (index % 26) as u8);
+        if index < 26 {
+            break;
+        }
+        index = index / 26 - 1;
+    }
+    name.iter().rev().map(|&b| b as char).collect()
+}
+
+/// Escape text for XML content and attributes
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            // Not allowed in XML at all
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// A worksheet part of the rows, with the first row frozen as the header
+fn worksheet(rows: &[Vec<Cell>]) -> String {
+    let mut out = String::from(concat!(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+        "\n",
+        r#"<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main">"#,
+        r#"<sheetViews><sheetView workbookViewId="0"><pane ySplit="1" topLeftCell="A2" activePane="bottomLeft" state="frozen"/></sheetView></sheetViews>"#,
+        r#"<cols><col min="1" max="1" width="20" customWidth="1"/></cols>"#,
+        "<sheetData>"
+    ));
+    for (r, row) in rows.iter().enumerate() {
+        let _ = write!(out, r#"<row r="{}">"#, r + 1);
+        for (c, cell) in row.iter().enumerate() {
+            let reference = format!("{}{}", column_name(c), r + 1);
+            let _ = match cell {
+                Cell::Text(text) => write!(
+                    out,
+                    r#"<c r="{reference}" t="inlineStr"><is><t xml:space="preserve">{}</t></is></c>"#,
+                    escape(text)
+                ),
+                Cell::Number(number) => write!(out, r#"<c r="{reference}"><v>{number}</v></c>"#),
+                Cell::Date(at) => write!(
+                    out,
+                    r#"<c r="{reference}" s="{DATE_STYLE}"><v>{}</v></c>"#,
+                    serial(*at)
+                ),
+                Cell::Empty => Ok(()),
+            };
+        }
+        out.push_str("</row>");
+    }
+    out.push_str("</sheetData></worksheet>");
+    out
+}
+
+/// Content types of the parts of a workbook with `sheets` sheets
+fn content_types(sheets: usize) -> String {
+    let mut out = String::from(concat!(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+        "\n",
+        r#"<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">"#,
+        r#"<Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>"#,
+        r#"<Default Extension="xml" ContentType="application/xml"/>"#,
+        r#"<Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/>"#,
+        r#"<Override PartName="/xl/styles.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.styles+xml"/>"#,
+    ));
+    for sheet in 1..=sheets {
+        let _ = write!(
+            out,
+            r#"<Override PartName="/xl/worksheets/sheet{sheet}.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/>"#
+        );
+    }
+    out.push_str("</Types>");
+    out
+}
+
+/// Relationship of the package to its workbook
+const ROOT_RELS: &str = concat!(
+    r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+    "\n",
+    r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#,
+    r#"<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/>"#,
+    "</Relationships>"
+);
+
+/// The workbook part, listing the sheets by name
+fn workbook_xml(names: &[&str]) -> String {
+    let mut out = String::from(concat!(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+        "\n",
+        r#"<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships">"#,
+        "<sheets>"
+    ));
+    for (index, name) in names.iter().enumerate() {
+        let _ = write!(
+            out,
+            r#"<sheet name="{}" sheetId="{id}" r:id="rId{id}"/>"#,
+            escape(name),
+            id = index + 1
+        );
+    }
+    out.push_str("</sheets></workbook>");
+    out
+}
+
+/// Relationships of the workbook to its sheets and styles
+fn workbook_rels(sheets: usize) -> String {
+    let mut out = String::from(concat!(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+        "\n",
+        r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#,
+    ));
+    for sheet in 1..=sheets {
+        let _ = write!(
+            out,
+            r#"<Relationship Id="rId{sheet}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet{sheet}.xml"/>"#
+        );
+    }
+    let _ = write!(
+        out,
+        r#"<Relationship Id="rId{}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles" Target="styles.xml"/>"#,
+        sheets + 1
+    );
+    out.push_str("</Relationships>");
+    out
+}
+
+/// Styles: the default, and dates as `yyyy-mm-dd hh:mm`
+const STYLES: &str = concat!(
+    r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+    "\n",
+    r#"<styleSheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main">"#,
+    r#"<numFmts count="1"><numFmt numFmtId="164" formatCode="yyyy-mm-dd hh:mm"/></numFmts>"#,
+    r#"<fonts count="1"><font><sz val="11"/><name val="Calibri"/></font></fonts>"#,
+    r#"<fills count="2"><fill><patternFill patternType="none"/></fill><fill><patternFill patternType="gray125"/></fill></fills>"#,
+    r#"<borders count="1"><border><left/><right/><top/><bottom/><diagonal/></border></borders>"#,
+    r#"<cellStyleXfs count="1"><xf numFmtId="0" fontId="0" fillId="0" borderId="0"/></cellStyleXfs>"#,
+    r#"<cellXfs count="2"><xf numFmtId="0" fontId="0" fillId="0" borderId="0" xfId="0"/>"#,
+    r#"<xf numFmtId="164" fontId="0" fillId="0" borderId="0" xfId="0" applyNumberFormat="1"/></cellXfs>"#,
+    r#"<cellStyles count="1"><cellStyle name="Normal" xfId="0" builtinId="0"/></cellStyles>"#,
+    "</styleSheet>"
+);
+
+/// Zip archive with stored (uncompressed) entries
+#[derive(Default)]
+struct Zip {
+    data: Vec<u8>,
+    directory: Vec<u8>,
+    entries: u16,
+}
+
+/// Signatures of the zip records
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_DIRECTORY: u32 = 0x0605_4b50;
+
+/// Zip version 2.0, the first with directories and the one readers expect
+const ZIP_VERSION: u16 = 20;
+
+/// Modification date of every entry, 1980-01-01 in MS-DOS format
+const DOS_DATE: u16 = (1 << 5) | 1;
+
+impl Zip {
+    /// Add a file
+    fn add(&mut self, name: &str, contents: &[u8]) {
+        let offset = self.data.len() as u32;
+        let crc = crc32(contents);
+        let size = contents.len() as u32;
+
+        // The fields the local and central headers share, from the version
+        // needed to the name length
+        let mut common = Vec::new();
+        for value in [ZIP_VERSION, 0, 0, 0, DOS_DATE] {
+            common.extend(value.to_le_bytes());
+        }
+        for value in [crc, size, size] {
+            common.extend(value.to_le_bytes());
+        }
+        common.extend((name.len() as u16).to_le_bytes());
+
+        self.data.extend(LOCAL_HEADER.to_le_bytes());
+        self.data.extend(&common);
+        self.data.extend(0u16.to_le_bytes());
+        self.data.extend(name.as_bytes());
+        self.data.extend(contents);
+
+        self.directory.extend(CENTRAL_HEADER.to_le_bytes());
+        self.directory.extend(ZIP_VERSION.to_le_bytes());
+        self.directory.extend(&common);
+        // Extra field, comment, disk, internal and external attributes
+        for value in [0u16, 0, 0, 0] {
+            self.directory.extend(value.to_le_bytes());
+        }
+        self.directory.extend(0u32.to_le_bytes());
+        self.directory.extend(offset.to_le_bytes());
+        self.directory.extend(name.as_bytes());
+        self.entries += 1;
+    }
+
+    /// The archive's bytes
+    fn finish(mut self) -> Vec<u8> {
+        let offset = self.data.len() as u32;
+        let size = self.directory.len() as u32;
+        self.data.append(&mut self.directory);
+        self.data.extend(END_OF_DIRECTORY.to_le_bytes());
+        for value in [0, 0, self.entries, self.entries] {
+            self.data.extend(value.to_le_bytes());
+        }
+        self.data.extend(size.to_le_bytes());
+        self.data.extend(offset.to_le_bytes());
+        self.data.extend(0u16.to_le_bytes());
+        self.data
+    }
+}
+
+/// CRC-32 (IEEE) of `data`, as zip records it
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(minutes: i64, device: &str, component: Component, level: u8) -> Reading {
+        Reading {
+            at: DateTime::parse_from_rfc3339("2026-10-14T09:00:00Z")
+                .unwrap()
+                .with_timezone(&Utc)
+                + chrono::Duration::minutes(minutes),
+            device: device.to_string(),
+            component,
+            level,
+        }
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_sheet_name() {
+        assert_eq!(sheet_name("AirPods Pro", &[]), "AirPods Pro");
+        assert_eq!(sheet_name("Desk [left]: 2/3", &[]), "Desk _left__ 2_3");
+        assert_eq!(
+            sheet_name("overview", &["overview".to_string()]),
+            "overview (2)"
+        );
+        let long = "A very long Bluetooth keyboard name";
+        assert_eq!(sheet_name(long, &[]).chars().count(), MAX_SHEET_NAME);
+        let taken = [sheet_name(long, &[]).to_lowercase()];
+        assert!(sheet_name(long, &taken).ends_with(" (2)"));
+        assert_eq!(sheet_name("'?'", &[]), "_");
+    }
+
+    #[test]
+    fn test_column_name() {
+        assert_eq!(column_name(0), "A");
+        assert_eq!(column_name(25), "Z");
+        assert_eq!(column_name(26), "AA");
+        assert_eq!(column_name(27 * 26), "AAA");
+    }
+
+    #[test]
+    fn test_workbook() {
+        let readings = [
+            reading(0, "AirPods Pro", Component::Left, 80),
+            reading(0, "AirPods Pro", Component::Right, 90),
+            reading(30, "AirPods Pro", Component::Left, 70),
+            reading(0, "Keyboard <BT>", Component::Level, 55),
+        ];
+        let bytes = workbook(&readings);
+        assert_eq!(&bytes[..4], &LOCAL_HEADER.to_le_bytes());
+
+        // Stored entries, so the parts can be read back as they were written
+        let text = String::from_utf8_lossy(&bytes);
+        assert!(text.contains("[Content_Types].xml"));
+        assert!(text.contains(r#"<sheet name="Keyboard &lt;BT&gt;" sheetId="3" r:id="rId3"/>"#));
+        assert!(text.contains(r#"<t xml:space="preserve">Left %</t>"#));
+        // The right bud wasn't read in the second scan
+        assert!(text.contains(r#"<c r="B3"><v>70</v></c></row>"#));
+        assert!(text.contains("'AirPods Pro'!$A$2:$A$3,'AirPods Pro'!$B$2:$B$3"));
+
+        // The end of the central directory counts every part
+        let end = &bytes[bytes.len() - 22..];
+        assert_eq!(&end[..4], &END_OF_DIRECTORY.to_le_bytes());
+        assert_eq!(u16::from_le_bytes([end[10], end[11]]), 8);
+    }
+}
@@ -0,0 +1,142 @@
+//! YAML output for `--format yaml`
+//!
+//! The output mirrors the JSON output, so values are serialized to JSON
+//! first and the JSON tree is written in YAML block style. Strings that YAML
+//! would read as something else are double-quoted, which uses the same
+//! escapes as JSON.
+
+use serde::Serialize;
+use serde_json::Value;
+
+/// Serialize a value as a YAML document
+pub fn to_string<T: Serialize + ?Sized>(value: &T) -> serde_json::Result<String> {
+    Ok(lines(&serde_json::to_value(value)?).join("\n"))
+}
+
+/// Lines of a node, indented relative to the node itself
+fn lines(value: &Value) -> Vec<String> {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            let mut out = Vec::new();
+            for (key, value) in map {
+                let key = string(key);
+                if is_block(value) {
+                    out.push(format!("{key}:"));
+                    let indent = if value.is_array() { "" } else { "  " };
+                    out.extend(lines(value).into_iter().map(|l| format!("{indent}{l}")));
+                } else {
+                    out.push(format!("{key}: {}", scalar(value)));
+                }
+            }
+            out
+        }
+        Value::Array(items) if !items.is_empty() => {
+            let mut out = Vec::new();
+            for item in items {
+                let mut item_lines = lines(item).into_iter();
+                if let Some(first) = item_lines.next() {
+                    out.push(format!("- {first}"));
+                }
+                out.extend(item_lines.map(|l| format!("  {l}")));
+            }
+            out
+        }
+        _ => vec![scalar(value)],
+    }
+}
+
+/// Whether a value is written as an indented block below its key
+fn is_block(value: &Value) -> bool {
+    match value {
+        Value::Object(map) => !map.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        _ => false,
+    }
+}
+
+/// A value written inline: a scalar or an empty collection
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => string(s),
+        Value::Array(_) => "[]".to_string(),
+        Value::Object(_) => "{}".to_string(),
+    }
+}
+
+/// A string, double-quoted if YAML would read it as anything else
+fn string(s: &str) -> String {
+    let reserved = matches!(
+        s.to_lowercase().as_str(),
+        "" | "~" | "null" | "true" | "false" | "yes" | "no" | "on" | "off" | "y" | "n"
+    );
+    let special_start = s.starts_with([
+        '-', '?', ':', ',', '[', ']', '{', '}', '#', '&', '*', '!', '|', '>', '\'', '"', '%', '@',
+        '`', ' ',
+    ]);
+    // Numbers in any notation, .inf and timestamps
+    let numeric_start = s.starts_with(|c: char| c.is_ascii_digit() || c == '+' || c == '.');
+    let plain = !reserved
+        && !special_start
+        && !numeric_start
+        && !s.ends_with([' ', ':'])
+        && !s.contains(": ")
+        && !s.contains(" #")
+        && !s.chars().any(char::is_control);
+    if plain {
+        s.to_string()
+    } else {
+        serde_json::to_string(s).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_to_string() {
+        let value = json!({
+            "adapter": {"state": "powered_on", "powered": true},
+            "devices": [
+                {"name": "AirPods Pro", "battery_level": null, "battery_left": 80, "raw": {}},
+                {"name": "Desk: Keyboard", "tags": ["a", "b"]}
+            ],
+            "empty": [],
+            "version": "1.0"
+        });
+        assert_eq!(
+            to_string(&value).unwrap(),
+            "adapter:\n  \
+               state: powered_on\n  \
+               powered: true\n\
+             devices:\n\
+             - name: AirPods Pro\n  \
+               battery_level: null\n  \
+               battery_left: 80\n  \
+               raw: {}\n\
+             - name: \"Desk: Keyboard\"\n  \
+               tags:\n  \
+               - a\n  \
+               - b\n\
+             empty: []\n\
+             version: \"1.0\""
+        );
+    }
+
+    #[test]
+    fn test_string() {
+        assert_eq!(string("AirPods Pro"), "AirPods Pro");
+        assert_eq!(string("bc-d0-74-b7-a6-b3"), "bc-d0-74-b7-a6-b3");
+        assert_eq!(string("yes"), "\"yes\"");
+        assert_eq!(string("76"), "\"76\"");
+        assert_eq!(string("2026-10-15T09:30:00Z"), "\"2026-10-15T09:30:00Z\"");
+        assert_eq!(string(".inf"), "\".inf\"");
+        assert_eq!(string("- dash"), "\"- dash\"");
+        assert_eq!(string("line\nbreak"), "\"line\\nbreak\"");
+        assert_eq!(string(""), "\"\"");
+    }
+}